@@ -0,0 +1,332 @@
+// alerts.rs
+
+use crate::interfaces::{OrderBookRead, Price, Side};
+
+/// Identifies a rule in an `AlertEngine`; handed out by `add_rule` in order from 0.
+pub type RuleId = u32;
+
+/// What a rule watches for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertCondition {
+    /// Spread wider than this many ticks (a one-sided or empty book has no spread).
+    SpreadAbove(Price),
+    /// Total-quantity imbalance `(bid - ask) / (bid + ask)` beyond this in either direction.
+    ImbalanceBeyond(f64),
+    /// No level on `side` changed for more than `for_more_than` clock units.
+    Stale { side: Side, for_more_than: u64 },
+    /// Best bid above best ask.
+    Crossed,
+}
+
+/// A condition plus the hysteresis around it: it must hold for `fire_after` clock units before
+/// the alert fires, and stay false for `clear_after` before the alert clears. A condition
+/// flickering faster than `clear_after` fires once and stays active.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlertRule {
+    pub condition: AlertCondition,
+    pub fire_after: u64,
+    pub clear_after: u64,
+}
+
+impl AlertRule {
+    /// Fires as soon as `condition` holds and clears as soon as it does not.
+    pub fn new(condition: AlertCondition) -> Self {
+        AlertRule { condition, fire_after: 0, clear_after: 0 }
+    }
+
+    pub fn fire_after(mut self, duration: u64) -> Self {
+        self.fire_after = duration;
+        self
+    }
+
+    pub fn clear_after(mut self, duration: u64) -> Self {
+        self.clear_after = duration;
+        self
+    }
+}
+
+/// A rule firing (`cleared_at` is `None`) or clearing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub rule_id: RuleId,
+    /// Book clock when the alert fired; a clear repeats the time of the firing it ends.
+    pub fired_at: u64,
+    pub cleared_at: Option<u64>,
+    /// What was observed, for humans: e.g. `spread 12 > 10 ticks`.
+    pub details: String,
+}
+
+type AlertListener = Box<dyn FnMut(&Alert) + Send + Sync>;
+
+/// The registered alert listener. Clones of the engine start without one.
+#[derive(Default)]
+struct AlertCallback(Option<AlertListener>);
+
+impl Clone for AlertCallback {
+    fn clone(&self) -> Self {
+        AlertCallback(None)
+    }
+}
+
+/// What `observe` found while a condition holds. Kept as plain values because it is computed
+/// on every evaluation; formatted into `Alert::details` only when an alert fires.
+#[derive(Debug, Clone, Copy)]
+enum Observation {
+    Spread { spread: Price, ticks: Price },
+    Imbalance { imbalance: f64, limit: f64 },
+    Stale { side: Side, quiet: u64, for_more_than: u64 },
+    Crossed { bid: Price, ask: Price },
+}
+
+impl Observation {
+    fn details(self) -> String {
+        match self {
+            Observation::Spread { spread, ticks } => format!("spread {spread} > {ticks} ticks"),
+            Observation::Imbalance { imbalance, limit } => format!("imbalance {imbalance:+.3} beyond ±{limit}"),
+            Observation::Stale { side, quiet, for_more_than } => format!("{side:?} unchanged for {quiet} > {for_more_than}"),
+            Observation::Crossed { bid, ask } => format!("crossed: bid {bid} > ask {ask}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct RuleState {
+    /// Since when the condition has held, while it does.
+    holding_since: Option<u64>,
+    /// Since when the condition has been false, while the alert is active.
+    clearing_since: Option<u64>,
+    active_since: Option<u64>,
+}
+
+/// Push-style threshold alerts on spread, imbalance, staleness and crossing.
+///
+/// Attach with `OrderBookImpl::enable_alerts`; the book then re-evaluates every rule after each
+/// `apply_update` and depth message, at its clock (`apply_update_at`), and on
+/// `OrderBookImpl::poll_alerts`, which moves the clock without an update so that durations and
+/// staleness come due on a quiet feed. Alerts go to the `on_alert` callback if one is set and
+/// are queued for `take_alerts` otherwise.
+#[derive(Clone, Default)]
+pub struct AlertEngine {
+    rules: Vec<(AlertRule, RuleState)>,
+    /// Book clock of the last change on each side, `[bid, ask]`.
+    last_change: [u64; 2],
+    pending: Vec<Alert>,
+    callback: AlertCallback,
+}
+
+impl AlertEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_rule(&mut self, rule: AlertRule) -> RuleId {
+        self.rules.push((rule, RuleState::default()));
+        (self.rules.len() - 1) as RuleId
+    }
+
+    pub fn rule(&self, id: RuleId) -> Option<&AlertRule> {
+        self.rules.get(id as usize).map(|(rule, _)| rule)
+    }
+
+    /// Whether rule `id` has fired and not cleared since.
+    pub fn is_active(&self, id: RuleId) -> bool {
+        self.rules.get(id as usize).is_some_and(|(_, state)| state.active_since.is_some())
+    }
+
+    /// Delivers alerts to `callback` as they happen instead of queueing them. Replaces any
+    /// previous callback; clones of the engine do not inherit it.
+    pub fn on_alert(&mut self, callback: impl FnMut(&Alert) + Send + Sync + 'static) {
+        self.callback = AlertCallback(Some(Box::new(callback)));
+    }
+
+    /// Alerts queued since the last call, oldest first.
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Starts the staleness clocks of both sides at `now`.
+    pub(crate) fn attach(&mut self, now: u64) {
+        self.last_change = [now; 2];
+    }
+
+    /// Re-evaluates every rule against `book` at clock `now`; `changed` marks the sides the
+    /// triggering update touched, `[bid, ask]`.
+    pub(crate) fn evaluate(&mut self, now: u64, book: &impl OrderBookRead, changed: [bool; 2]) {
+        for side in [Side::Bid, Side::Ask] {
+            if changed[side as usize] {
+                self.last_change[side as usize] = now;
+            }
+        }
+        for id in 0..self.rules.len() {
+            let (rule, mut state) = self.rules[id];
+            let observed = self.observe(rule.condition, now, book);
+            match observed {
+                Some(observation) => {
+                    state.clearing_since = None;
+                    let since = *state.holding_since.get_or_insert(now);
+                    if state.active_since.is_none() && now.saturating_sub(since) >= rule.fire_after {
+                        state.active_since = Some(now);
+                        self.emit(Alert { rule_id: id as RuleId, fired_at: now, cleared_at: None, details: observation.details() });
+                    }
+                }
+                None => {
+                    state.holding_since = None;
+                    if let Some(fired_at) = state.active_since {
+                        let since = *state.clearing_since.get_or_insert(now);
+                        if now.saturating_sub(since) >= rule.clear_after {
+                            state.active_since = None;
+                            state.clearing_since = None;
+                            let details = format!("cleared after {}", now.saturating_sub(fired_at));
+                            self.emit(Alert { rule_id: id as RuleId, fired_at, cleared_at: Some(now), details });
+                        }
+                    }
+                }
+            }
+            self.rules[id].1 = state;
+        }
+    }
+
+    /// What `condition` looks like now, if it holds.
+    fn observe(&self, condition: AlertCondition, now: u64, book: &impl OrderBookRead) -> Option<Observation> {
+        match condition {
+            AlertCondition::SpreadAbove(ticks) => {
+                let spread = book.get_spread().filter(|&spread| spread > ticks)?;
+                Some(Observation::Spread { spread, ticks })
+            }
+            AlertCondition::ImbalanceBeyond(limit) => {
+                let (bid, ask) = (book.get_total_quantity(Side::Bid) as f64, book.get_total_quantity(Side::Ask) as f64);
+                let imbalance = (bid - ask) / (bid + ask);
+                (imbalance.abs() > limit).then_some(Observation::Imbalance { imbalance, limit })
+            }
+            AlertCondition::Stale { side, for_more_than } => {
+                let quiet = now.saturating_sub(self.last_change[side as usize]);
+                (quiet > for_more_than).then_some(Observation::Stale { side, quiet, for_more_than })
+            }
+            AlertCondition::Crossed => {
+                let (bid, ask) = (book.get_best_bid()?, book.get_best_ask()?);
+                (bid > ask).then_some(Observation::Crossed { bid, ask })
+            }
+        }
+    }
+
+    fn emit(&mut self, alert: Alert) {
+        match self.callback.0.as_mut() {
+            Some(callback) => callback(&alert),
+            None => self.pending.push(alert),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::interfaces::Update;
+    use crate::orderbook::OrderBookImpl;
+    use crate::testing::set;
+
+    /// `(rule, fired_at, cleared_at)` of the queued alerts.
+    fn drained(ob: &mut OrderBookImpl) -> Vec<(RuleId, u64, Option<u64>)> {
+        ob.alerts_mut().unwrap().take_alerts().iter().map(|a| (a.rule_id, a.fired_at, a.cleared_at)).collect()
+    }
+
+    #[test]
+    fn wide_spread_fires_after_its_duration_and_clears_with_hysteresis() {
+        let mut ob = OrderBookImpl::new();
+        let mut engine = AlertEngine::new();
+        let wide = engine.add_rule(AlertRule::new(AlertCondition::SpreadAbove(10)).fire_after(100).clear_after(50));
+        ob.enable_alerts(engine);
+        ob.apply_update_at(0, set(10000, 5, Side::Bid));
+        ob.apply_update_at(0, set(10005, 5, Side::Ask));
+
+        // Widens at 10; still within the duration at 109.
+        ob.apply_update_at(10, Update::Remove { price: 10005, side: Side::Ask });
+        ob.apply_update_at(10, set(10020, 5, Side::Ask));
+        ob.poll_alerts(109);
+        assert_eq!(drained(&mut ob), vec![]);
+        ob.poll_alerts(110);
+        let alerts = ob.alerts_mut().unwrap().take_alerts();
+        assert_eq!((alerts[0].rule_id, alerts[0].fired_at, alerts[0].cleared_at), (wide, 110, None));
+        assert_eq!(alerts[0].details, "spread 20 > 10 ticks");
+        assert!(ob.alerts().unwrap().is_active(wide));
+
+        // Narrows briefly, widens again before `clear_after`: no clear, no second firing.
+        ob.apply_update_at(120, set(10008, 5, Side::Ask));
+        ob.apply_update_at(160, Update::Remove { price: 10008, side: Side::Ask });
+        ob.poll_alerts(300);
+        assert_eq!(drained(&mut ob), vec![]);
+
+        ob.apply_update_at(400, set(10008, 5, Side::Ask));
+        ob.poll_alerts(449);
+        assert_eq!(drained(&mut ob), vec![]);
+        ob.poll_alerts(450);
+        let alerts = ob.alerts_mut().unwrap().take_alerts();
+        assert_eq!((alerts[0].rule_id, alerts[0].fired_at, alerts[0].cleared_at), (wide, 110, Some(450)));
+        assert!(!ob.alerts().unwrap().is_active(wide));
+    }
+
+    #[test]
+    fn crossing_and_imbalance_fire_immediately() {
+        let mut ob = OrderBookImpl::new();
+        let mut engine = AlertEngine::new();
+        let crossed = engine.add_rule(AlertRule::new(AlertCondition::Crossed));
+        let imbalance = engine.add_rule(AlertRule::new(AlertCondition::ImbalanceBeyond(0.5)).clear_after(10));
+        ob.enable_alerts(engine);
+
+        ob.apply_update_at(1, set(10000, 10, Side::Bid));
+        // Bid-only: imbalance +1.
+        assert_eq!(drained(&mut ob), vec![(imbalance, 1, None)]);
+        ob.apply_update_at(2, set(9999, 10, Side::Ask));
+        let alerts = ob.alerts_mut().unwrap().take_alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!((alerts[0].rule_id, alerts[0].fired_at), (crossed, 2));
+        assert_eq!(alerts[0].details, "crossed: bid 10000 > ask 9999");
+
+        // Uncrossing clears at once; balanced since 2, the imbalance clears at 12.
+        ob.apply_update_at(3, set(10001, 10, Side::Ask));
+        ob.apply_update_at(3, Update::Remove { price: 9999, side: Side::Ask });
+        assert_eq!(drained(&mut ob), vec![(crossed, 2, Some(3))]);
+        ob.poll_alerts(11);
+        assert_eq!(drained(&mut ob), vec![]);
+        ob.poll_alerts(12);
+        assert_eq!(drained(&mut ob), vec![(imbalance, 1, Some(12))]);
+    }
+
+    #[test]
+    fn clearing_on_a_clock_that_went_backwards() {
+        let mut ob = OrderBookImpl::new();
+        let mut engine = AlertEngine::new();
+        let crossed = engine.add_rule(AlertRule::new(AlertCondition::Crossed));
+        ob.enable_alerts(engine);
+
+        ob.apply_update_at(100, set(10000, 10, Side::Bid));
+        ob.apply_update_at(100, set(9999, 10, Side::Ask));
+        assert_eq!(drained(&mut ob), vec![(crossed, 100, None)]);
+        ob.apply_update_at(50, Update::Remove { price: 9999, side: Side::Ask });
+        let alerts = ob.alerts_mut().unwrap().take_alerts();
+        assert_eq!((alerts[0].rule_id, alerts[0].fired_at, alerts[0].cleared_at), (crossed, 100, Some(50)));
+        assert_eq!(alerts[0].details, "cleared after 0");
+    }
+
+    #[test]
+    fn stale_side_fires_on_a_quiet_feed() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update_at(100, set(10000, 10, Side::Bid));
+        let mut engine = AlertEngine::new();
+        let stale = engine.add_rule(AlertRule::new(AlertCondition::Stale { side: Side::Ask, for_more_than: 50 }));
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let sink = delivered.clone();
+        engine.on_alert(move |alert| sink.lock().unwrap().push((alert.rule_id, alert.fired_at, alert.cleared_at)));
+        ob.enable_alerts(engine);
+
+        // Bid activity does not keep the ask side fresh; trades are not level changes either.
+        ob.apply_update_at(140, set(10000, 12, Side::Bid));
+        ob.apply_update_at(150, Update::Trade { price: 10000, quantity: 1, aggressor: Side::Ask, timestamp: 150 });
+        assert!(delivered.lock().unwrap().is_empty());
+        ob.poll_alerts(151);
+        ob.apply_depth_message(1, &[], &[(10002, 4)]).unwrap();
+        assert_eq!(*delivered.lock().unwrap(), vec![(stale, 151, None), (stale, 151, Some(151))]);
+        // Delivered through the callback, so nothing is queued.
+        assert!(ob.alerts_mut().unwrap().take_alerts().is_empty());
+    }
+}
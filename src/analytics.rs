@@ -0,0 +1,379 @@
+// analytics.rs
+
+use std::fmt;
+use crate::interfaces::{OrderBookRead, Price, Quantity, Side};
+use crate::orderbook::{CAP, OrderBookImpl};
+
+/// Top-of-book summary for dashboards, computed in one go by `OrderBookImpl::stats`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookStats {
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    pub mid: Option<f64>,
+    pub spread: Option<Price>,
+    pub total_bid_quantity: Quantity,
+    pub total_ask_quantity: Quantity,
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+    /// (bid total - ask total) / (bid total + ask total), in [-1, 1]; `None` on an empty book.
+    pub imbalance: Option<f64>,
+}
+
+impl fmt::Display for BookStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let price = |p: Option<Price>| p.map_or_else(|| "-".to_string(), |p| p.to_string());
+        write!(
+            f,
+            "bid {} x {} ({} lvls) | ask {} x {} ({} lvls) | spread {} | imbalance {}",
+            price(self.best_bid), self.total_bid_quantity, self.bid_levels,
+            price(self.best_ask), self.total_ask_quantity, self.ask_levels,
+            price(self.spread),
+            self.imbalance.map_or_else(|| "-".to_string(), |i| format!("{:+.3}", i)),
+        )
+    }
+}
+
+/// Top levels of each side (best first) at book clock `timestamp` and book `version`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "rkyv", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize))]
+pub struct DepthSnapshot {
+    pub timestamp: u64,
+    pub version: u64,
+    pub bids: Vec<(Price, Quantity)>,
+    pub asks: Vec<(Price, Quantity)>,
+}
+
+/// One sample of `OrderBookImpl::impact_curve`: what a marketable order of `quantity` would get.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImpactPoint {
+    pub quantity: Quantity,
+    /// Equals `quantity` unless the opposite side ran out (`truncated`).
+    pub filled: Quantity,
+    /// Volume-weighted fill price; `None` if nothing filled.
+    pub avg_price: Option<f64>,
+    /// Price of the last level reached; `None` if nothing filled.
+    pub worst_price: Option<Price>,
+    /// Cost versus the mid in price units (`avg - mid` for buys, `mid - avg` for sells);
+    /// `None` if nothing filled or the book has no mid.
+    pub slippage: Option<f64>,
+    /// The opposite side holds less than `quantity`; the other fields describe the partial fill.
+    pub truncated: bool,
+}
+
+impl OrderBookImpl {
+    /// Best prices, mid, spread, totals, level counts and imbalance in a single call.
+    /// Level counts are maintained by `apply_update`, so no level array is walked.
+    pub fn stats(&self) -> BookStats {
+        let best_bid = self.get_best_bid();
+        let best_ask = self.get_best_ask();
+        let total_bid_quantity = self.get_total_quantity(Side::Bid);
+        let total_ask_quantity = self.get_total_quantity(Side::Ask);
+        let depth = total_bid_quantity as f64 + total_ask_quantity as f64;
+        BookStats {
+            best_bid,
+            best_ask,
+            mid: best_bid.zip(best_ask).map(|(bid, ask)| (bid as f64 + ask as f64) / 2.0),
+            spread: best_bid.zip(best_ask).map(|(bid, ask)| ask.saturating_sub(bid)),
+            total_bid_quantity,
+            total_ask_quantity,
+            bid_levels: self.level_count(Side::Bid),
+            ask_levels: self.level_count(Side::Ask),
+            imbalance: (depth > 0.0).then(|| (total_bid_quantity as f64 - total_ask_quantity as f64) / depth),
+        }
+    }
+
+    /// Depth-weighted mid over the top `levels` levels of each side.
+    ///
+    /// With `bid_vwap`/`ask_vwap` the volume-weighted prices and `B`/`A` the summed quantities of
+    /// those levels:
+    ///
+    /// `weighted_mid = (bid_vwap * A + ask_vwap * B) / (A + B)`
+    ///
+    /// i.e. each side's price is weighted by the *opposite* side's depth, so heavier bids pull
+    /// the mid towards the ask. With `levels == 1` this is exactly the microprice. `None` if
+    /// either side is empty or `levels == 0`.
+    pub fn get_weighted_mid(&self, levels: usize) -> Option<f64> {
+        let side_vwap = |side: Side| {
+            let (notional, depth) = self
+                .levels(side)
+                .take(levels)
+                .fold((0f64, 0f64), |(n, d), (price, qty)| (n + price as f64 * qty as f64, d + qty as f64));
+            (depth > 0.0).then(|| (notional / depth, depth))
+        };
+        let (bid_vwap, bid_depth) = side_vwap(Side::Bid)?;
+        let (ask_vwap, ask_depth) = side_vwap(Side::Ask)?;
+        Some((bid_vwap * ask_depth + ask_vwap * bid_depth) / (bid_depth + ask_depth))
+    }
+
+    /// Quantity-weighted average price of every level on `side`: where its resting liquidity
+    /// sits, compared with the best. `None` if the side is empty. One pass over the levels in
+    /// 128-bit sums, so it stays exact where the wrapping `get_total_quantity` would not.
+    pub fn center_of_mass(&self, side: Side) -> Option<f64> {
+        if self.level_count(side) == 0 {
+            return None;
+        }
+        let (notional, depth) = self.levels(side).fold((0i128, 0u128), |(notional, depth), (price, qty)| {
+            (notional.saturating_add(price as i128 * qty as i128), depth + qty as u128)
+        });
+        Some(notional as f64 / depth as f64)
+    }
+
+    /// The best `depth` levels per side, stamped with `now()` and `version()`.
+    pub fn depth_snapshot(&self, depth: usize) -> DepthSnapshot {
+        DepthSnapshot {
+            timestamp: self.now(),
+            version: self.version(),
+            bids: self.get_top_levels(Side::Bid, depth),
+            asks: self.get_top_levels(Side::Ask, depth),
+        }
+    }
+
+    /// `depth_snapshot` into `out`, reusing its level buffers.
+    pub fn depth_snapshot_into(&self, depth: usize, out: &mut DepthSnapshot) {
+        out.timestamp = self.now();
+        out.version = self.version();
+        out.bids.clear();
+        out.bids.extend(self.levels(Side::Bid).take(depth));
+        out.asks.clear();
+        out.asks.extend(self.levels(Side::Ask).take(depth));
+    }
+
+    /// Full-depth `depth_snapshot` followed by `clear`, for sampling at interval boundaries.
+    /// The snapshot's version is the pre-clear one.
+    pub fn take_snapshot_and_reset(&mut self) -> DepthSnapshot {
+        let snapshot = self.depth_snapshot(CAP);
+        self.clear();
+        snapshot
+    }
+
+    /// Occupied levels of `side` as two parallel, gap-free columns (best first), ready for
+    /// vectorized processing.
+    pub fn to_columns(&self, side: Side) -> (Vec<Price>, Vec<Quantity>) {
+        let count = self.level_count(side);
+        let mut prices = Vec::with_capacity(count);
+        let mut quantities = Vec::with_capacity(count);
+        for (price, qty) in self.levels(side) {
+            prices.push(price);
+            quantities.push(qty);
+        }
+        (prices, quantities)
+    }
+
+    /// Depth-chart points for `side`: each occupied price best-first with the quantity resting
+    /// at it or better. The last point's quantity equals `get_total_quantity(side)`.
+    pub fn depth_curve(&self, side: Side) -> Vec<(Price, Quantity)> {
+        let mut cumulative: Quantity = 0;
+        let mut curve = Vec::with_capacity(self.level_count(side));
+        curve.extend(self.levels(side).map(|(price, qty)| {
+            cumulative = cumulative.wrapping_add(qty);
+            (price, cumulative)
+        }));
+        curve
+    }
+
+    /// Cash resting on `side`: the sum of `price * qty` over its levels. Negative prices count
+    /// as 0 (the result is unsigned); the sum saturates.
+    pub fn get_notional(&self, side: Side) -> u128 {
+        self.notional_curve(side).last().map_or(0, |(_, notional)| notional)
+    }
+
+    /// The cash view of `depth_curve`: each occupied price best-first with the notional resting
+    /// at it or better (same conventions as `get_notional`).
+    pub fn notional_curve(&self, side: Side) -> impl Iterator<Item = (Price, u128)> + '_ {
+        let mut cumulative: u128 = 0;
+        self.levels(side).map(move |(price, qty)| {
+            cumulative = cumulative.saturating_add(price.max(0) as u128 * qty as u128);
+            (price, cumulative)
+        })
+    }
+
+    /// Fill estimates for a marketable order on `side` (`Bid` = buy, walking the asks) of each
+    /// size in `quantities`, written to `out` in the same order (cleared first).
+    ///
+    /// Ascending sizes are served by a single walk of the opposite side, each picking up where
+    /// the previous stopped; a size smaller than its predecessor restarts the walk, so any order
+    /// is correct but ascending is the fast path. Sizes beyond the available liquidity yield
+    /// `truncated` points rather than being dropped.
+    pub fn impact_curve(&self, side: Side, quantities: &[Quantity], out: &mut Vec<ImpactPoint>) {
+        out.clear();
+        let opposite = match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let mid = self.stats().mid;
+        let mut levels = self.levels(opposite).peekable();
+        // Fully consumed levels so far: their quantity, cash and the last price.
+        let (mut taken, mut cash, mut last): (u128, i128, Option<Price>) = (0, 0, None);
+        let mut previous = 0;
+        for &quantity in quantities {
+            if quantity < previous {
+                levels = self.levels(opposite).peekable();
+                (taken, cash, last) = (0, 0, None);
+            }
+            previous = quantity;
+            let target = quantity as u128;
+            while let Some(&(price, qty)) = levels.peek() {
+                if taken + qty as u128 > target {
+                    break;
+                }
+                taken += qty as u128;
+                cash += price as i128 * qty as i128;
+                last = Some(price);
+                levels.next();
+            }
+            let (filled, fill_cash, worst) = match levels.peek() {
+                Some(&(price, _)) if taken < target => (target, cash + price as i128 * (target - taken) as i128, Some(price)),
+                _ => (taken, cash, last),
+            };
+            let avg_price = (filled > 0).then(|| fill_cash as f64 / filled as f64);
+            let slippage = avg_price.zip(mid).map(|(avg, mid)| match side {
+                Side::Bid => avg - mid,
+                Side::Ask => mid - avg,
+            });
+            out.push(ImpactPoint {
+                quantity,
+                filled: filled as Quantity,
+                avg_price,
+                worst_price: worst,
+                slippage,
+                truncated: filled < target,
+            });
+        }
+    }
+
+    /// Quantity an order on `side` (`Bid` = buy) limited at `limit` could take right now: the
+    /// opposite side's levels at `limit` or better (asks at or below it for a buy, bids at or
+    /// above it for a sell). Only the levels inside the limit are visited. 0 if none are.
+    pub fn fill_size_at_or_better(&self, side: Side, limit: Price) -> Quantity {
+        let levels = match side {
+            Side::Bid => self.levels_in_range(Side::Ask, Price::MIN, limit),
+            Side::Ask => self.levels_in_range(Side::Bid, limit, Price::MAX),
+        };
+        levels.fold(0, |total: Quantity, (_, qty)| total.wrapping_add(qty))
+    }
+
+    /// Cash (price × quantity, summed over the levels walked) for a marketable order on `side`
+    /// (`Bid` = buy, walking the asks) of `quantity`. `None` if the opposite side holds less.
+    pub fn cost_to_fill(&self, side: Side, quantity: Quantity) -> Option<i128> {
+        let opposite = match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let (mut left, mut cash) = (quantity, 0i128);
+        for (price, qty) in self.levels(opposite) {
+            if left == 0 {
+                break;
+            }
+            let take = qty.min(left);
+            cash += price as i128 * take as i128;
+            left -= take;
+        }
+        (left == 0).then_some(cash)
+    }
+
+    /// Round-trip cost at size: the average price of buying `quantity` minus that of selling it
+    /// (both from `cost_to_fill`), rounded down, so it is never narrower than the touch spread.
+    /// `None` for a zero quantity or if either side holds less than `quantity`.
+    pub fn weighted_spread(&self, quantity: Quantity) -> Option<Price> {
+        if quantity == 0 {
+            return None;
+        }
+        let buy = self.cost_to_fill(Side::Bid, quantity)?;
+        let sell = self.cost_to_fill(Side::Ask, quantity)?;
+        Some((buy - sell).div_euclid(quantity as i128) as Price)
+    }
+
+    /// Quantity that must be taken out of `side` to move the mid by at least `ticks` (asks
+    /// push it up, bids down): everything resting closer to the touch than the first level at
+    /// least `2 * ticks` away from the current best, since the mid moves half as far as that best.
+    ///
+    /// `Some(0)` for `ticks <= 0`. `None` if either side is empty, or if `side` runs out before a
+    /// level far enough away is reached (exhausting it leaves no mid at all). Saturates at
+    /// `Quantity::MAX`.
+    pub fn quantity_to_move_mid(&self, side: Side, ticks: i64) -> Option<Quantity> {
+        let (bid, ask) = self.get_best_bid().zip(self.get_best_ask())?;
+        if ticks <= 0 {
+            return Some(0);
+        }
+        let best = match side { Side::Bid => bid, Side::Ask => ask };
+        let distance = ticks.unsigned_abs().saturating_mul(2);
+        let mut consumed: Quantity = 0;
+        for (price, qty) in self.levels(side) {
+            if price.abs_diff(best) >= distance {
+                return Some(consumed);
+            }
+            consumed = consumed.saturating_add(qty);
+        }
+        None
+    }
+
+    /// Quantity a new passive order at `price` on `side` would queue behind: the level's
+    /// resting quantity, or `None` if the level is empty (nobody ahead) or outside the window.
+    /// Together with traded volume at the price this gives a fill-likelihood estimate.
+    pub fn queue_ahead(&self, side: Side, price: Price) -> Option<Quantity> {
+        if !self.in_window(price, side) {
+            return None;
+        }
+        self.get_quantity_at(price, side)
+    }
+
+    /// The liquidity cliff on `side`: the occupied level whose quantity rises most over the
+    /// next occupied level nearer the touch. Ties go to the level nearer the touch. `None` with
+    /// fewer than two levels or when quantity never rises going away from the touch.
+    pub fn steepest_level(&self, side: Side) -> Option<(Price, Quantity)> {
+        let mut levels = self.levels(side);
+        let (_, mut nearer) = levels.next()?;
+        let mut steepest = None;
+        let mut steepest_jump = 0;
+        for (price, quantity) in levels {
+            let jump = quantity.saturating_sub(nearer);
+            if jump > steepest_jump {
+                steepest = Some((price, quantity));
+                steepest_jump = jump;
+            }
+            nearer = quantity;
+        }
+        steepest
+    }
+
+    /// Occupied levels of both sides, closest to the mid first, as `(side, price, qty, distance)`.
+    ///
+    /// `distance` is whole ticks from the mid, rounded down (so with a one-tick spread both
+    /// touches are at distance 0). Ordering uses the exact half-tick distance; on ties the bid
+    /// comes first. With one side empty the other side's best stands in for the mid.
+    pub fn levels_by_distance(&self) -> impl Iterator<Item = (Side, Price, Quantity, u32)> + '_ {
+        // Twice the mid, so half-tick mids stay integral; i128 so extreme prices cannot overflow.
+        let mid2 = match (self.get_best_bid(), self.get_best_ask()) {
+            (Some(bid), Some(ask)) => bid as i128 + ask as i128,
+            (Some(best), None) | (None, Some(best)) => 2 * best as i128,
+            (None, None) => 0,
+        };
+        let dist2 = move |p: Price| (2 * p as i128 - mid2).unsigned_abs();
+        let mut bids = self.levels(Side::Bid).map(move |(p, q)| (Side::Bid, p, q, dist2(p))).peekable();
+        let mut asks = self.levels(Side::Ask).map(move |(p, q)| (Side::Ask, p, q, dist2(p))).peekable();
+        std::iter::from_fn(move || {
+            let next = match (bids.peek(), asks.peek()) {
+                (Some(b), Some(a)) => if b.3 <= a.3 { bids.next() } else { asks.next() },
+                (Some(_), None) => bids.next(),
+                (None, _) => asks.next(),
+            };
+            next.map(|(side, price, qty, dist2)| (side, price, qty, u32::try_from(dist2 / 2).unwrap_or(u32::MAX)))
+        })
+    }
+
+    /// Signed round-trip profit in ticks * quantity for buying `quantity` at `buy_price` and
+    /// selling it at `sell_price` (positive = profit). Saturates instead of overflowing.
+    ///
+    /// The prices are not checked against the book; callers that want to warn about a round
+    /// trip that could not have filled right now ask `round_trip_achievable`.
+    pub fn spread_capture(&self, buy_price: Price, sell_price: Price, quantity: Quantity) -> i64 {
+        let qty = i64::try_from(quantity).unwrap_or(i64::MAX);
+        sell_price.saturating_sub(buy_price).saturating_mul(qty)
+    }
+
+    /// Whether a buy at `buy_price` and a sell at `sell_price` could both fill against the
+    /// current touch (at or better than joining it). An empty side imposes no constraint.
+    pub fn round_trip_achievable(&self, buy_price: Price, sell_price: Price) -> bool {
+        self.get_best_bid().is_none_or(|bid| buy_price >= bid) && self.get_best_ask().is_none_or(|ask| sell_price <= ask)
+    }
+}
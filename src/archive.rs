@@ -0,0 +1,149 @@
+// archive.rs
+
+use rkyv::rancor::Error;
+use rkyv::ser::writer::Buffer;
+use rkyv::util::AlignedVec;
+use rkyv::{Archive, Deserialize, Serialize};
+pub use crate::analytics::ArchivedDepthSnapshot;
+use crate::analytics::DepthSnapshot;
+use crate::interfaces::{Price, Quantity};
+
+/// `snapshot` as an rkyv archive, ready to copy onto the bus.
+pub fn archive_snapshot(snapshot: &DepthSnapshot) -> Result<AlignedVec, Error> {
+    rkyv::to_bytes::<Error>(snapshot)
+}
+
+/// The snapshot archived in `bytes`, validated (bounds, alignment, lengths) but not copied.
+/// `bytes` must be as aligned as `archive_snapshot`'s output (16 bytes is always enough).
+pub fn access_snapshot(bytes: &[u8]) -> Result<&ArchivedDepthSnapshot, Error> {
+    rkyv::access::<ArchivedDepthSnapshot, Error>(bytes)
+}
+
+/// A `DepthSnapshot` cut to at most `N` levels per side, stored in fixed arrays so that every
+/// archive of it has the same size, `ARCHIVED_SIZE`: `24 + 32 * N` bytes (the timestamp and
+/// version, two `u32` lengths, then `N` 16-byte levels per side). The bus can preallocate slots
+/// of that size and `write_into` them without a heap allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct BoundedDepthSnapshot<const N: usize> {
+    pub timestamp: u64,
+    pub version: u64,
+    bid_len: u32,
+    ask_len: u32,
+    /// Best first; entries from `bid_len` on are zero.
+    bids: [(Price, Quantity); N],
+    asks: [(Price, Quantity); N],
+}
+
+impl<const N: usize> BoundedDepthSnapshot<N> {
+    /// Bytes in every archive of this type.
+    pub const ARCHIVED_SIZE: usize = size_of::<ArchivedBoundedDepthSnapshot<N>>();
+
+    /// The best `N` levels of each side of `snapshot`.
+    pub fn from_snapshot(snapshot: &DepthSnapshot) -> Self {
+        let fill = |levels: &[(Price, Quantity)]| {
+            let mut out = [(0, 0); N];
+            let len = levels.len().min(N);
+            out[..len].copy_from_slice(&levels[..len]);
+            (out, len as u32)
+        };
+        let ((bids, bid_len), (asks, ask_len)) = (fill(&snapshot.bids), fill(&snapshot.asks));
+        BoundedDepthSnapshot { timestamp: snapshot.timestamp, version: snapshot.version, bid_len, ask_len, bids, asks }
+    }
+
+    pub fn bids(&self) -> &[(Price, Quantity)] {
+        &self.bids[..self.bid_len as usize]
+    }
+
+    pub fn asks(&self) -> &[(Price, Quantity)] {
+        &self.asks[..self.ask_len as usize]
+    }
+
+    pub fn to_snapshot(&self) -> DepthSnapshot {
+        DepthSnapshot { timestamp: self.timestamp, version: self.version, bids: self.bids().to_vec(), asks: self.asks().to_vec() }
+    }
+
+    /// Archives into the start of `slot`, which must be 16-byte aligned (e.g. an
+    /// `rkyv::util::Align` array) and at least `ARCHIVED_SIZE` long. Returns the bytes written,
+    /// always `ARCHIVED_SIZE`.
+    pub fn write_into(&self, slot: &mut [u8]) -> Result<usize, Error> {
+        Ok(rkyv::api::high::to_bytes_in::<_, Error>(self, Buffer::from(slot))?.len())
+    }
+
+    /// The bounded snapshot archived in `bytes`, validated but not copied.
+    pub fn access(bytes: &[u8]) -> Result<&ArchivedBoundedDepthSnapshot<N>, Error> {
+        rkyv::access::<ArchivedBoundedDepthSnapshot<N>, Error>(bytes)
+    }
+}
+
+impl<const N: usize> ArchivedBoundedDepthSnapshot<N> {
+    /// Bid levels, best first. A length field beyond `N` (a corrupt archive passes validation,
+    /// which checks layout rather than this invariant) reads as `N`.
+    pub fn bids(&self) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        let len = (self.bid_len.to_native() as usize).min(N);
+        self.bids[..len].iter().map(|level| (level.0.to_native(), level.1.to_native()))
+    }
+
+    /// Ask levels, best first; see `bids`.
+    pub fn asks(&self) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        let len = (self.ask_len.to_native() as usize).min(N);
+        self.asks[..len].iter().map(|level| (level.0.to_native(), level.1.to_native()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rkyv::util::Align;
+
+    fn snapshot(levels: i64) -> DepthSnapshot {
+        DepthSnapshot {
+            timestamp: 1_700_000_000,
+            version: 42,
+            bids: (0..levels).map(|i| (9999 - i, 10 + i as u64)).collect(),
+            asks: (0..levels / 2).map(|i| (10001 + i, 20 + i as u64)).collect(),
+        }
+    }
+
+    #[test]
+    fn snapshots_are_read_in_place() {
+        let original = snapshot(30);
+        let bytes = archive_snapshot(&original).unwrap();
+        let archived = access_snapshot(&bytes).unwrap();
+        assert_eq!((archived.timestamp.to_native(), archived.version.to_native()), (1_700_000_000, 42));
+        assert_eq!(archived.bids.len(), 30);
+        assert_eq!((archived.asks[3].0.to_native(), archived.asks[3].1.to_native()), (10004, 23));
+        assert_eq!(rkyv::deserialize::<DepthSnapshot, Error>(archived).unwrap(), original);
+
+        assert!(access_snapshot(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn bounded_archives_have_a_fixed_size() {
+        assert_eq!(BoundedDepthSnapshot::<10>::ARCHIVED_SIZE, 24 + 32 * 10);
+        assert_eq!(BoundedDepthSnapshot::<1>::ARCHIVED_SIZE, 24 + 32);
+
+        let mut slot = Align([0u8; 24 + 32 * 10]);
+        for levels in [0, 4, 10, 25] {
+            let bounded = BoundedDepthSnapshot::<10>::from_snapshot(&snapshot(levels));
+            assert_eq!(bounded.write_into(&mut *slot).unwrap(), BoundedDepthSnapshot::<10>::ARCHIVED_SIZE, "{levels} levels");
+            let archived = BoundedDepthSnapshot::<10>::access(&*slot).unwrap();
+            let expected = snapshot(levels);
+            assert!(archived.bids().eq(expected.bids.iter().copied().take(10)));
+            assert!(archived.asks().eq(expected.asks.iter().copied().take(10)));
+            assert_eq!(archived.version.to_native(), 42);
+            assert_eq!(rkyv::deserialize::<BoundedDepthSnapshot<10>, Error>(archived).unwrap(), bounded);
+        }
+
+        // A slot one byte short is refused rather than overrun.
+        let mut short = Align([0u8; 24 + 32 * 10 - 1]);
+        assert!(BoundedDepthSnapshot::<10>::from_snapshot(&snapshot(3)).write_into(&mut *short).is_err());
+    }
+
+    #[test]
+    fn bounded_snapshots_truncate_to_the_best_levels() {
+        let bounded = BoundedDepthSnapshot::<3>::from_snapshot(&snapshot(8));
+        assert_eq!(bounded.bids(), &[(9999, 10), (9998, 11), (9997, 12)]);
+        assert_eq!(bounded.asks(), &[(10001, 20), (10002, 21), (10003, 22)]);
+        assert_eq!(bounded.to_snapshot().bids.len(), 3);
+    }
+}
@@ -0,0 +1,162 @@
+// arrow.rs
+
+use std::io::Write;
+use std::sync::Arc;
+use arrow_array::{ArrayRef, Int64Array, RecordBatch, UInt8Array, UInt32Array, UInt64Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use crate::analytics::DepthSnapshot;
+use crate::interfaces::{Price, Quantity, Side};
+
+/// Schema of `DepthSnapshot::to_arrow_batch`, one row per level. Columns are appended at the end
+/// only, never renamed, retyped or reordered, so readers may select by name or position:
+///
+/// | column      | type   | content                                                   |
+/// |-------------|--------|-----------------------------------------------------------|
+/// | `timestamp` | UInt64 | `DepthSnapshot::timestamp`, the book clock at capture     |
+/// | `sequence`  | UInt64 | `DepthSnapshot::version`, the book version at capture     |
+/// | `side`      | UInt8  | 0 for bids, 1 for asks (`Side as u8`)                     |
+/// | `level`     | UInt32 | position on its side, 0 = best                            |
+/// | `price`     | Int64  | price in ticks                                            |
+/// | `quantity`  | UInt64 | quantity resting at the level                             |
+///
+/// No column is nullable. Within a batch, rows follow the snapshots in order; within a
+/// snapshot, the bids best first, then the asks best first.
+pub fn depth_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("timestamp", DataType::UInt64, false),
+        Field::new("sequence", DataType::UInt64, false),
+        Field::new("side", DataType::UInt8, false),
+        Field::new("level", DataType::UInt32, false),
+        Field::new("price", DataType::Int64, false),
+        Field::new("quantity", DataType::UInt64, false),
+    ]))
+}
+
+impl DepthSnapshot {
+    /// Every level of `snapshots` as one batch in the `depth_schema` layout.
+    pub fn to_arrow_batch(snapshots: &[DepthSnapshot]) -> RecordBatch {
+        let rows: usize = snapshots.iter().map(|s| s.bids.len() + s.asks.len()).sum();
+        let mut timestamp = Vec::with_capacity(rows);
+        let mut sequence = Vec::with_capacity(rows);
+        let mut side = Vec::with_capacity(rows);
+        let mut level = Vec::with_capacity(rows);
+        let mut price: Vec<Price> = Vec::with_capacity(rows);
+        let mut quantity: Vec<Quantity> = Vec::with_capacity(rows);
+        for snapshot in snapshots {
+            for (levels_side, levels) in [(Side::Bid, &snapshot.bids), (Side::Ask, &snapshot.asks)] {
+                for (position, &(level_price, level_quantity)) in levels.iter().enumerate() {
+                    timestamp.push(snapshot.timestamp);
+                    sequence.push(snapshot.version);
+                    side.push(levels_side as u8);
+                    level.push(position as u32);
+                    price.push(level_price);
+                    quantity.push(level_quantity);
+                }
+            }
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(timestamp)),
+            Arc::new(UInt64Array::from(sequence)),
+            Arc::new(UInt8Array::from(side)),
+            Arc::new(UInt32Array::from(level)),
+            Arc::new(Int64Array::from(price)),
+            Arc::new(UInt64Array::from(quantity)),
+        ];
+        RecordBatch::try_new(depth_schema(), columns).expect("columns match depth_schema")
+    }
+}
+
+/// Appends depth snapshots to an Arrow IPC file (the Feather v2 format), one record batch per
+/// `write` call. The file is only readable once `finish` has written its footer.
+pub struct DepthArrowWriter<W: Write> {
+    writer: FileWriter<W>,
+}
+
+impl<W: Write> DepthArrowWriter<W> {
+    /// Writes the file header and the `depth_schema`.
+    pub fn new(sink: W) -> Result<Self, ArrowError> {
+        Ok(DepthArrowWriter { writer: FileWriter::try_new(sink, &depth_schema())? })
+    }
+
+    pub fn write(&mut self, snapshots: &[DepthSnapshot]) -> Result<(), ArrowError> {
+        self.writer.write(&DepthSnapshot::to_arrow_batch(snapshots))
+    }
+
+    /// Writes the footer and hands back the sink.
+    pub fn finish(mut self) -> Result<W, ArrowError> {
+        self.writer.finish()?;
+        self.writer.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use arrow_array::Array;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Int64Type, UInt8Type, UInt32Type, UInt64Type};
+    use arrow_ipc::reader::FileReader;
+    use crate::interfaces::Update;
+    use crate::orderbook::OrderBookImpl;
+
+    #[test]
+    fn snapshots_round_trip_through_an_ipc_file() {
+        let mut ob = OrderBookImpl::new();
+        let mut first = Vec::new();
+        ob.apply_update_at(100, Update::Set { price: 9999, quantity: 5, side: Side::Bid });
+        ob.apply_update_at(100, Update::Set { price: 10001, quantity: 7, side: Side::Ask });
+        first.push(ob.depth_snapshot(5));
+        ob.apply_update_at(200, Update::Set { price: 9998, quantity: 3, side: Side::Bid });
+        first.push(ob.depth_snapshot(5));
+        ob.apply_update_at(300, Update::Remove { price: 10001, side: Side::Ask });
+        let second = vec![ob.depth_snapshot(1)];
+
+        let mut writer = DepthArrowWriter::new(Vec::new()).unwrap();
+        writer.write(&first).unwrap();
+        writer.write(&second).unwrap();
+        let file = writer.finish().unwrap();
+
+        let reader = FileReader::try_new(Cursor::new(file), None).unwrap();
+        assert_eq!(reader.schema(), depth_schema());
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).collect::<Vec<_>>(), vec![5, 1]);
+
+        let batch = &batches[0];
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let rows: Vec<_> = (0..batch.num_rows())
+            .map(|row| {
+                (
+                    column("timestamp").as_primitive::<UInt64Type>().value(row),
+                    column("sequence").as_primitive::<UInt64Type>().value(row),
+                    column("side").as_primitive::<UInt8Type>().value(row),
+                    column("level").as_primitive::<UInt32Type>().value(row),
+                    column("price").as_primitive::<Int64Type>().value(row),
+                    column("quantity").as_primitive::<UInt64Type>().value(row),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (100, 2, 0, 0, 9999, 5),
+                (100, 2, 1, 0, 10001, 7),
+                (200, 3, 0, 0, 9999, 5),
+                (200, 3, 0, 1, 9998, 3),
+                (200, 3, 1, 0, 10001, 7),
+            ]
+        );
+        assert_eq!(batch.column(0).null_count(), 0);
+
+        let last = &batches[1];
+        assert_eq!(last.column_by_name("timestamp").unwrap().as_primitive::<UInt64Type>().value(0), 300);
+        assert_eq!(last.column_by_name("price").unwrap().as_primitive::<Int64Type>().value(0), 9999);
+    }
+
+    #[test]
+    fn empty_input_gives_an_empty_batch() {
+        let batch = DepthSnapshot::to_arrow_batch(&[]);
+        assert_eq!((batch.num_rows(), batch.num_columns()), (0, 6));
+    }
+}
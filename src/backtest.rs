@@ -0,0 +1,312 @@
+// backtest.rs
+
+use std::collections::BTreeMap;
+use crate::interfaces::{OrderBookRead, OrderBookWrite, Price, Quantity, Side, Update};
+use crate::orderbook::OrderBookImpl;
+
+/// Identifies a hypothetical order in a `FillSimulator`; handed out by `place` in order from 0.
+pub type SimOrderId = u64;
+
+/// How displayed quantity leaving a level without trading (cancels) moves the queue ahead of a
+/// simulated order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CancelPolicy {
+    /// Cancels come from everywhere in the queue alike: the quantity ahead shrinks by its share
+    /// of the level.
+    #[default]
+    ProRata,
+    /// Cancels come from the back of the queue first (the optimistic model: the most recently
+    /// joined quantity is the most likely to leave); only once everything behind the order has
+    /// gone does the quantity ahead shrink.
+    FromBack,
+}
+
+/// A hypothetical resting order and where it sits in the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimOrder {
+    pub id: SimOrderId,
+    pub side: Side,
+    pub price: Price,
+    pub size: Quantity,
+    pub filled: Quantity,
+    /// Timestamp passed to `place`.
+    pub entered_at: u64,
+    /// Displayed quantity estimated to be ahead of the order at its level.
+    pub queue_ahead: Quantity,
+    /// Traded quantity at the level not yet matched by the feed's level update, so that update
+    /// is not mistaken for cancels.
+    unmatched_trades: Quantity,
+}
+
+impl SimOrder {
+    pub fn remaining(&self) -> Quantity {
+        self.size - self.filled
+    }
+}
+
+/// A (partial) fill of a simulated order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimFill {
+    pub order: SimOrderId,
+    pub timestamp: u64,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+    /// What is left of the order after this fill; 0 means it is done and no longer open.
+    pub remaining: Quantity,
+}
+
+/// Passive fills for hypothetical orders, estimated from an L2 feed.
+///
+/// Feed the simulator the same updates and trade prints as the book, in order, through
+/// `apply_update_at`; it keeps its own book and a queue position per order:
+/// - a placed order joins the back of the displayed queue at its price;
+/// - quantity added to the level queues behind it;
+/// - a trade print at the order's price consumes the queue ahead first, then fills the order
+///   (earlier simulated orders at the same level are ahead of later ones); a print through the
+///   price (a sell below a bid, a buy above an ask) fills it completely;
+/// - a level decrease not explained by prints since the previous level update is cancels,
+///   which shrink the queue ahead according to the `CancelPolicy`. A level wiped to 0 therefore
+///   leaves nothing ahead, and a refresh after it queues behind the order.
+///
+/// Orders are not displayed, so they never change the book or the prints; an order placed
+/// through the opposite best rests like any other rather than taking liquidity. Fills
+/// accumulate until `take_fills`.
+#[derive(Clone)]
+pub struct FillSimulator {
+    book: OrderBookImpl,
+    policy: CancelPolicy,
+    orders: BTreeMap<SimOrderId, SimOrder>,
+    next_id: SimOrderId,
+    fills: Vec<SimFill>,
+}
+
+impl FillSimulator {
+    /// An empty book (see `OrderBookImpl::new`) and no orders.
+    pub fn new(policy: CancelPolicy) -> Self {
+        Self::from_book(OrderBookImpl::new(), policy)
+    }
+
+    /// Starts from an existing, possibly preconfigured or populated, book.
+    pub fn from_book(book: OrderBookImpl, policy: CancelPolicy) -> Self {
+        FillSimulator { book, policy, orders: BTreeMap::new(), next_id: 0, fills: Vec::new() }
+    }
+
+    pub fn book(&self) -> &OrderBookImpl {
+        &self.book
+    }
+
+    /// Rests `size` at `price` (in the book's ticks) behind everything displayed there now.
+    /// Panics if `size` is 0.
+    pub fn place(&mut self, timestamp: u64, side: Side, price: Price, size: Quantity) -> SimOrderId {
+        assert!(size > 0, "order size must be positive");
+        let id = self.next_id;
+        self.next_id += 1;
+        let queue_ahead = self.book.get_quantity_at(price, side).unwrap_or(0);
+        self.orders.insert(id, SimOrder { id, side, price, size, filled: 0, entered_at: timestamp, queue_ahead, unmatched_trades: 0 });
+        id
+    }
+
+    /// Withdraws an open order, returning its final state; `None` if it is unknown or already
+    /// filled.
+    pub fn cancel(&mut self, id: SimOrderId) -> Option<SimOrder> {
+        self.orders.remove(&id)
+    }
+
+    pub fn order(&self, id: SimOrderId) -> Option<&SimOrder> {
+        self.orders.get(&id)
+    }
+
+    /// Open orders, oldest first.
+    pub fn open_orders(&self) -> impl Iterator<Item = &SimOrder> {
+        self.orders.values()
+    }
+
+    /// Fills since the last call, in the order they happened.
+    pub fn take_fills(&mut self) -> Vec<SimFill> {
+        std::mem::take(&mut self.fills)
+    }
+
+    /// Applies `update` to the book and moves or fills the orders it concerns.
+    pub fn apply_update_at(&mut self, timestamp: u64, update: Update) {
+        match update {
+            Update::Set { price, side, .. } | Update::SetWithCount { price, side, .. } | Update::Remove { price, side } => {
+                let price = self.book.tick_price(price);
+                let before = self.book.get_quantity_at(price, side).unwrap_or(0);
+                self.book.apply_update(update);
+                self.level_changed(side, price, before);
+            }
+            Update::SetLevels { side, ref levels } => {
+                let before: Vec<_> = levels
+                    .iter()
+                    .map(|&(price, _)| {
+                        let price = self.book.tick_price(price);
+                        (price, self.book.get_quantity_at(price, side).unwrap_or(0))
+                    })
+                    .collect();
+                self.book.apply_update(update);
+                for (price, before) in before {
+                    self.level_changed(side, price, before);
+                }
+            }
+            Update::Trade { price, quantity, aggressor, .. } => {
+                let price = self.book.tick_price(price);
+                self.trade(timestamp, price, quantity, aggressor);
+                self.book.apply_update(update);
+            }
+        }
+    }
+
+    fn level_changed(&mut self, side: Side, price: Price, before: Quantity) {
+        let after = self.book.get_quantity_at(price, side).unwrap_or(0);
+        for order in self.orders.values_mut().filter(|o| o.side == side && o.price == price) {
+            let traded = order.unmatched_trades.min(before.saturating_sub(after));
+            order.unmatched_trades = 0;
+            // The level as it stood once the prints are taken out; what else left was cancelled.
+            let level = before - traded;
+            let cancelled = level.saturating_sub(after);
+            if cancelled > 0 {
+                let ahead = order.queue_ahead.min(level);
+                order.queue_ahead = match self.policy {
+                    CancelPolicy::ProRata => ahead - (cancelled as u128 * ahead as u128 / level as u128) as Quantity,
+                    CancelPolicy::FromBack => ahead - cancelled.saturating_sub(level - ahead),
+                };
+            }
+            order.queue_ahead = order.queue_ahead.min(after);
+        }
+    }
+
+    fn trade(&mut self, timestamp: u64, price: Price, quantity: Quantity, aggressor: Side) {
+        // A buyer lifts asks and a seller hits bids.
+        let side = match aggressor {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let mut own_ahead: Quantity = 0;
+        let mut done = Vec::new();
+        for order in self.orders.values_mut().filter(|o| o.side == side) {
+            let through = match side {
+                Side::Bid => price < order.price,
+                Side::Ask => price > order.price,
+            };
+            let fill = if through {
+                order.remaining()
+            } else if price == order.price {
+                let reached = quantity.saturating_sub(order.queue_ahead.saturating_add(own_ahead));
+                own_ahead = own_ahead.saturating_add(order.remaining());
+                order.queue_ahead = order.queue_ahead.saturating_sub(quantity);
+                order.unmatched_trades = order.unmatched_trades.saturating_add(quantity);
+                reached.min(order.remaining())
+            } else {
+                continue;
+            };
+            if fill == 0 {
+                continue;
+            }
+            order.filled += fill;
+            let remaining = order.remaining();
+            self.fills.push(SimFill { order: order.id, timestamp, side, price: order.price, quantity: fill, remaining });
+            if remaining == 0 {
+                done.push(order.id);
+            }
+        }
+        for id in done {
+            self.orders.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::set;
+
+    fn sell(price: Price, quantity: Quantity) -> Update {
+        Update::Trade { price, quantity, aggressor: Side::Ask, timestamp: 0 }
+    }
+
+    #[test]
+    fn trade_consumes_queue_ahead_then_partially_fills() {
+        let mut sim = FillSimulator::new(CancelPolicy::ProRata);
+        sim.apply_update_at(1, set(9990, 100, Side::Bid));
+        let id = sim.place(2, Side::Bid, 9990, 50);
+        assert_eq!(sim.order(id).unwrap().queue_ahead, 100);
+        // Joins behind the order.
+        sim.apply_update_at(3, set(9990, 130, Side::Bid));
+
+        sim.apply_update_at(4, sell(9990, 120));
+        assert_eq!(
+            sim.take_fills(),
+            vec![SimFill { order: id, timestamp: 4, side: Side::Bid, price: 9990, quantity: 20, remaining: 30 }]
+        );
+        let order = *sim.order(id).unwrap();
+        assert_eq!((order.queue_ahead, order.filled, order.entered_at), (0, 20, 2));
+
+        // The feed's level update for that print is not read as cancels.
+        sim.apply_update_at(5, set(9990, 10, Side::Bid));
+        assert_eq!(sim.order(id).unwrap().queue_ahead, 0);
+
+        sim.apply_update_at(6, sell(9990, 45));
+        assert_eq!(sim.take_fills()[0].quantity, 30);
+        assert!(sim.order(id).is_none());
+        assert_eq!(sim.open_orders().count(), 0);
+    }
+
+    #[test]
+    fn level_wipe_and_refresh_puts_the_refresh_behind() {
+        for policy in [CancelPolicy::ProRata, CancelPolicy::FromBack] {
+            let mut sim = FillSimulator::new(policy);
+            sim.apply_update_at(1, set(10005, 200, Side::Ask));
+            let id = sim.place(2, Side::Ask, 10005, 10);
+            sim.apply_update_at(3, Update::Remove { price: 10005, side: Side::Ask });
+            sim.apply_update_at(4, set(10005, 200, Side::Ask));
+            assert_eq!(sim.order(id).unwrap().queue_ahead, 0, "{policy:?}");
+
+            sim.apply_update_at(5, Update::Trade { price: 10005, quantity: 4, aggressor: Side::Bid, timestamp: 5 });
+            assert_eq!(sim.take_fills()[0].quantity, 4, "{policy:?}");
+            assert_eq!(sim.order(id).unwrap().remaining(), 6);
+        }
+    }
+
+    #[test]
+    fn cancels_follow_the_policy() {
+        for (policy, ahead) in [(CancelPolicy::ProRata, 60), (CancelPolicy::FromBack, 100)] {
+            let mut sim = FillSimulator::new(policy);
+            sim.apply_update_at(1, set(9990, 100, Side::Bid));
+            let id = sim.place(2, Side::Bid, 9990, 5);
+            sim.apply_update_at(3, set(9990, 200, Side::Bid));
+            // 80 of 200 cancelled: pro rata takes 40% off the 100 ahead, from the back leaves it.
+            sim.apply_update_at(4, set(9990, 120, Side::Bid));
+            assert_eq!(sim.order(id).unwrap().queue_ahead, ahead, "{policy:?}");
+            // Past everything behind, from-the-back cancels eat into the queue ahead too.
+            sim.apply_update_at(5, set(9990, 30, Side::Bid));
+            assert!(sim.order(id).unwrap().queue_ahead <= 30, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn prints_through_the_price_fill_completely() {
+        let mut sim = FillSimulator::new(CancelPolicy::ProRata);
+        sim.apply_update_at(1, set(9990, 500, Side::Bid));
+        let first = sim.place(2, Side::Bid, 9990, 10);
+        let second = sim.place(3, Side::Bid, 9990, 10);
+        let above = sim.place(3, Side::Bid, 9995, 10);
+        sim.apply_update_at(4, sell(9989, 1));
+        let fills = sim.take_fills();
+        assert_eq!(fills.iter().map(|f| (f.order, f.quantity)).collect::<Vec<_>>(), vec![(first, 10), (second, 10), (above, 10)]);
+        assert_eq!(sim.open_orders().count(), 0);
+        assert!(sim.cancel(first).is_none());
+    }
+
+    #[test]
+    fn earlier_orders_at_a_level_fill_first() {
+        let mut sim = FillSimulator::new(CancelPolicy::ProRata);
+        sim.apply_update_at(1, set(9990, 10, Side::Bid));
+        let first = sim.place(2, Side::Bid, 9990, 5);
+        let second = sim.place(3, Side::Bid, 9990, 5);
+        sim.apply_update_at(4, sell(9990, 18));
+        let fills = sim.take_fills();
+        assert_eq!(fills.iter().map(|f| (f.order, f.quantity)).collect::<Vec<_>>(), vec![(first, 5), (second, 3)]);
+        assert_eq!(sim.cancel(second).map(|o| o.remaining()), Some(2));
+    }
+}
@@ -1,4 +1,9 @@
-use crate::interfaces::{OrderBook, Side, Update};
+use crate::interfaces::{OrderBookFactory, OrderBookRead, OrderBookWrite, Price, Side, Update};
+use crate::manager::{OrderBookManager, SymbolId};
+use crate::orderbook::OrderBookImpl;
+use crate::reference::BTreeOrderBook;
+use crate::sim::{MarketSimulator, SimConfig};
+use crate::storage::LevelStorage;
 use std::time::Instant;
 
 // ============================================================================
@@ -19,18 +24,44 @@ pub struct BenchmarkResult {
     pub total_operations: usize,
 }
 
+/// How a benchmarked book keeps up with a stream whose price jumps further than its window:
+/// called before each update with the update's price. Part of the timed path, as it would be
+/// in a feed handler.
+pub trait FollowPrice {
+    fn follow(&mut self, price: Price);
+}
+
+impl<S: LevelStorage> FollowPrice for OrderBookImpl<Price, S> {
+    #[inline(always)]
+    fn follow(&mut self, price: Price) {
+        if self.would_recenter(price) {
+            self.recenter_anchor_aligned(price);
+        }
+    }
+}
+
+impl FollowPrice for BTreeOrderBook {
+    fn follow(&mut self, _price: Price) {}
+}
+
 pub struct OrderBookBenchmark;
 
 impl OrderBookBenchmark {
-    /// Run comprehensive benchmark suite
-    pub fn run<T: OrderBook>(name: &str, iterations: usize) -> BenchmarkResult {
+    /// Run comprehensive benchmark suite on a fixed-seed `MarketSimulator` stream, so numbers
+    /// are comparable across runs and implementations. The default stream plus occasional
+    /// jumps of the mid past half a window, so the updates include the recenters they force.
+    pub fn run<T: OrderBookWrite + OrderBookFactory + FollowPrice>(name: &str, iterations: usize) -> BenchmarkResult {
         let mut ob = T::new();
+        let mut sim = MarketSimulator::new(SimConfig { jump_probability: 0.0005, ..SimConfig::default() });
 
-        // Warm up
-        Self::warmup(&mut ob);
+        // Warm up: let the simulated book fill out first
+        for update in sim.by_ref().take(10_000) {
+            ob.apply_update(update);
+        }
 
-        // Benchmark updates
-        let update_timings = Self::benchmark_updates(&mut ob, iterations);
+        // Benchmark updates (generated up front, so the simulator isn't timed)
+        let updates: Vec<Update> = sim.by_ref().take(iterations).collect();
+        let update_timings = Self::benchmark_updates(&mut ob, updates);
 
         // Benchmark spread calculations
         let spread_timings = Self::benchmark_spread(&ob, iterations / 10);
@@ -40,7 +71,7 @@ impl OrderBookBenchmark {
         let best_ask_timings = Self::benchmark_best_ask(&ob, iterations / 10);
 
         // Benchmark random reads
-        let read_timings = Self::benchmark_random_reads(&ob, iterations / 10);
+        let read_timings = Self::benchmark_random_reads(&ob, sim.mid() as Price, iterations / 10);
 
         let avg_update = Self::average(&update_timings);
         let avg_spread = Self::average(&spread_timings);
@@ -65,34 +96,24 @@ impl OrderBookBenchmark {
         }
     }
 
-    fn warmup<T: OrderBook>(ob: &mut T) {
-        // Add some initial levels
-        for i in 0..100 {
-            ob.apply_update(Update::Set {
-                price: 100000 + i * 10,
-                quantity: 100,
-                side: Side::Bid,
-            });
-            ob.apply_update(Update::Set {
-                price: 100100 + i * 10,
-                quantity: 100,
-                side: Side::Ask,
-            });
+    /// Fills `ob` from the start of the default `MarketSimulator` stream, as `run` warms up:
+    /// an uncrossed book, inside the default window, with realistic depth.
+    fn warmup<T: OrderBookWrite>(ob: &mut T) {
+        for update in MarketSimulator::new(SimConfig::default()).take(10_000) {
+            ob.apply_update(update);
         }
     }
 
-    fn benchmark_updates<T: OrderBook>(ob: &mut T, iterations: usize) -> Vec<u64> {
-        let mut timings = Vec::with_capacity(iterations);
-        let base_price = 100000;
-
-        for i in 0..iterations {
-            let update = Update::Set {
-                price: base_price + (i as i64 % 1000) * 10,
-                quantity: 50 + (i as u64 % 200),
-                side: if i % 2 == 0 { Side::Bid } else { Side::Ask },
-            };
+    fn benchmark_updates<T: OrderBookWrite + FollowPrice>(ob: &mut T, updates: Vec<Update>) -> Vec<u64> {
+        let mut timings = Vec::with_capacity(updates.len());
 
+        for update in updates {
             let start = Instant::now();
+            if let Update::Set { price, .. } | Update::SetWithCount { price, .. } | Update::Remove { price, .. } =
+                update
+            {
+                ob.follow(price);
+            }
             ob.apply_update(update);
             let elapsed = start.elapsed().as_nanos() as u64;
 
@@ -102,7 +123,7 @@ impl OrderBookBenchmark {
         timings
     }
 
-    fn benchmark_spread<T: OrderBook>(ob: &T, iterations: usize) -> Vec<u64> {
+    fn benchmark_spread<T: OrderBookRead>(ob: &T, iterations: usize) -> Vec<u64> {
         let mut timings = Vec::with_capacity(iterations);
 
         for _ in 0..iterations {
@@ -115,7 +136,7 @@ impl OrderBookBenchmark {
         timings
     }
 
-    fn benchmark_best_bid<T: OrderBook>(ob: &T, iterations: usize) -> Vec<u64> {
+    fn benchmark_best_bid<T: OrderBookRead>(ob: &T, iterations: usize) -> Vec<u64> {
         let mut timings = Vec::with_capacity(iterations);
 
         for _ in 0..iterations {
@@ -128,7 +149,7 @@ impl OrderBookBenchmark {
         timings
     }
 
-    fn benchmark_best_ask<T: OrderBook>(ob: &T, iterations: usize) -> Vec<u64> {
+    fn benchmark_best_ask<T: OrderBookRead>(ob: &T, iterations: usize) -> Vec<u64> {
         let mut timings = Vec::with_capacity(iterations);
 
         for _ in 0..iterations {
@@ -141,12 +162,11 @@ impl OrderBookBenchmark {
         timings
     }
 
-    fn benchmark_random_reads<T: OrderBook>(ob: &T, iterations: usize) -> Vec<u64> {
+    fn benchmark_random_reads<T: OrderBookRead>(ob: &T, mid: Price, iterations: usize) -> Vec<u64> {
         let mut timings = Vec::with_capacity(iterations);
-        let base_price = 100000;
 
         for i in 0..iterations {
-            let price = base_price + (i as i64 % 500) * 10;
+            let price = mid - 50 + i as i64 % 100;
             let side = if i % 2 == 0 { Side::Bid } else { Side::Ask };
 
             let start = Instant::now();
@@ -158,6 +178,183 @@ impl OrderBookBenchmark {
         timings
     }
 
+    /// What-if cost: fork + 3 modifications + reads, versus clone + the same.
+    /// Returns (avg fork ns, avg clone ns).
+    pub fn run_fork_vs_clone(iterations: usize) -> (f64, f64) {
+        let mut ob = OrderBookImpl::new();
+        Self::warmup(&mut ob);
+        // Pull both touches and requote the bid a tick lower.
+        let (best_bid, best_ask) = (ob.get_best_bid().expect("warmed-up book has bids"), ob.get_best_ask().expect("and asks"));
+        let requoted = best_bid - 1;
+        let what_if = [
+            Update::Remove { price: best_bid, side: Side::Bid },
+            Update::Set { price: requoted, quantity: 40, side: Side::Bid },
+            Update::Remove { price: best_ask, side: Side::Ask },
+        ];
+
+        let mut fork_timings = Vec::with_capacity(iterations);
+        let mut clone_timings = Vec::with_capacity(iterations);
+        for _ in 0..iterations {
+            let start = Instant::now();
+            let mut fork = ob.fork();
+            for update in what_if.iter().cloned() {
+                fork.apply_update(update);
+            }
+            std::hint::black_box((fork.get_best_bid(), fork.get_best_ask(), fork.get_quantity_at(requoted, Side::Bid)));
+            fork_timings.push(start.elapsed().as_nanos() as u64);
+
+            let start = Instant::now();
+            let mut copy = ob.clone();
+            for update in what_if.iter().cloned() {
+                copy.apply_update(update);
+            }
+            std::hint::black_box((copy.get_best_bid(), copy.get_best_ask(), copy.get_quantity_at(requoted, Side::Bid)));
+            clone_timings.push(start.elapsed().as_nanos() as u64);
+        }
+
+        (Self::average(&fork_timings), Self::average(&clone_timings))
+    }
+
+    /// `insert_new` / `modify` against `apply_update` doing the same `Set`s on the warmed-up
+    /// book: new levels on the first 50 empty prices behind each touch (removed again between
+    /// rounds, untimed), then modifications of the best 50 levels per side. Timed per round of
+    /// writes, so the clock's own overhead stays out of the way.
+    /// Returns ((insert_new ns, apply_update ns), (modify ns, apply_update ns)) per write.
+    pub fn run_specialized_writes(rounds: usize) -> ((f64, f64), (f64, f64)) {
+        let mut ob = OrderBookImpl::new();
+        Self::warmup(&mut ob);
+        let interleave = |bids: Vec<Price>, asks: Vec<Price>| -> Vec<(Price, Side)> {
+            bids.into_iter().map(|price| (price, Side::Bid)).zip(asks.into_iter().map(|price| (price, Side::Ask))).flat_map(|(bid, ask)| [bid, ask]).collect()
+        };
+        let occupied = |side: Side| ob.levels(side).map(|(price, _)| price).take(50).collect::<Vec<_>>();
+        let empty = |side: Side, touch: Option<Price>, step: Price| {
+            let touch = touch.expect("warmed-up book has both sides");
+            (1..).map(|k| touch + k * step).filter(|&price| ob.get_quantity_at(price, side).is_none()).take(50).collect::<Vec<_>>()
+        };
+        let new_levels = interleave(empty(Side::Bid, ob.get_best_bid(), -1), empty(Side::Ask, ob.get_best_ask(), 1));
+        let existing = interleave(occupied(Side::Bid), occupied(Side::Ask));
+
+        let mut timings = [[0u64; 2]; 2];
+        for round in 0..rounds {
+            let quantity = 1 + (round as u64 % 50);
+            // Alternate which goes first, so neither always runs on the other's warm cache.
+            let order = if round % 2 == 0 { [(true, 0), (false, 1)] } else { [(false, 1), (true, 0)] };
+            for (specialized, total) in order {
+                let start = Instant::now();
+                for &(price, side) in &new_levels {
+                    if specialized {
+                        ob.insert_new(price, quantity, side);
+                    } else {
+                        ob.apply_update(Update::Set { price, quantity, side });
+                    }
+                }
+                timings[0][total] += start.elapsed().as_nanos() as u64;
+                for &(price, side) in &new_levels {
+                    ob.apply_update(Update::Remove { price, side });
+                }
+
+                let start = Instant::now();
+                for &(price, side) in &existing {
+                    if specialized {
+                        ob.modify(price, quantity, side);
+                    } else {
+                        ob.apply_update(Update::Set { price, quantity, side });
+                    }
+                }
+                timings[1][total] += start.elapsed().as_nanos() as u64;
+            }
+        }
+        std::hint::black_box(ob.get_total_quantity(Side::Bid));
+
+        let per_write = |ns: u64, writes: usize| ns as f64 / (rounds * writes) as f64;
+        let (new, modified) = (new_levels.len(), existing.len());
+        ((per_write(timings[0][0], new), per_write(timings[0][1], new)), (per_write(timings[1][0], modified), per_write(timings[1][1], modified)))
+    }
+
+    /// A burst of `per_symbol` updates for each of `symbols` books, interleaved across
+    /// symbols, through `apply_batch` and then `apply_batch_parallel` (threshold 0, a worker
+    /// per core) on identical managers. Returns (sequential us, parallel us) per batch.
+    pub fn run_manager_batch(symbols: u32, per_symbol: u32, rounds: usize) -> (f64, f64) {
+        let mut sequential = OrderBookManager::new().workers(1);
+        let mut parallel = OrderBookManager::new().parallel_threshold(0);
+        for _ in 0..symbols {
+            sequential.add_book(OrderBookImpl::new());
+            parallel.add_book(OrderBookImpl::new());
+        }
+        let batches: Vec<Vec<(SymbolId, Update)>> = (0..rounds as u32)
+            .map(|round| {
+                (0..per_symbol)
+                    .flat_map(|i| {
+                        (0..symbols).map(move |symbol| {
+                            let side = if (i + symbol) % 2 == 0 { Side::Bid } else { Side::Ask };
+                            let price = 10000 + ((i * 7 + symbol + round) % 40) as i64 - 20;
+                            (symbol, Update::Set { price, quantity: 1 + ((i + round) % 100) as u64, side })
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut timings = [0u64; 2];
+        for batch in &batches {
+            let start = Instant::now();
+            sequential.apply_batch(batch);
+            timings[0] += start.elapsed().as_nanos() as u64;
+            let start = Instant::now();
+            parallel.apply_batch_parallel(batch);
+            timings[1] += start.elapsed().as_nanos() as u64;
+        }
+        let per_batch = |ns: u64| ns as f64 / 1000.0 / rounds as f64;
+        (per_batch(timings[0]), per_batch(timings[1]))
+    }
+
+    /// Reading a `levels`-deep snapshot out of its rkyv archive: validated zero-copy access
+    /// (`access_snapshot`, then a pass over the levels) versus full rkyv deserialization into a
+    /// `DepthSnapshot` and the same pass, and versus decoding the same snapshot from bincode.
+    /// Returns (access ns, rkyv deserialize ns, bincode decode ns) per read.
+    #[cfg(feature = "rkyv")]
+    pub fn run_archive_access(levels: usize, rounds: usize) -> (f64, f64, f64) {
+        use crate::analytics::DepthSnapshot;
+        use crate::archive::{access_snapshot, archive_snapshot};
+        use crate::interfaces::{Price, Quantity};
+        use rkyv::rancor::Error;
+
+        type Encoded = (u64, u64, Vec<(Price, Quantity)>, Vec<(Price, Quantity)>);
+        let config = bincode::config::standard();
+
+        let mut ob = OrderBookImpl::new();
+        Self::warmup(&mut ob);
+        let snapshot = ob.depth_snapshot(levels);
+        let bytes = archive_snapshot(&snapshot).expect("snapshot archives");
+        let encoded = bincode::encode_to_vec((snapshot.timestamp, snapshot.version, &snapshot.bids, &snapshot.asks), config)
+            .expect("snapshot encodes");
+
+        let mut timings = [0u64; 3];
+        for _ in 0..rounds {
+            let start = Instant::now();
+            let archived = access_snapshot(&bytes).expect("archive is valid");
+            let total: u64 = archived.bids.iter().chain(archived.asks.iter()).map(|level| level.1.to_native()).sum();
+            std::hint::black_box(total);
+            timings[0] += start.elapsed().as_nanos() as u64;
+
+            let start = Instant::now();
+            let snapshot = rkyv::from_bytes::<DepthSnapshot, Error>(&bytes).expect("archive is valid");
+            let total: u64 = snapshot.bids.iter().chain(snapshot.asks.iter()).map(|level| level.1).sum();
+            std::hint::black_box(total);
+            timings[1] += start.elapsed().as_nanos() as u64;
+
+            let start = Instant::now();
+            let ((timestamp, version, bids, asks), _): (Encoded, usize) =
+                bincode::decode_from_slice(&encoded, config).expect("encoding is valid");
+            let snapshot = DepthSnapshot { timestamp, version, bids, asks };
+            let total: u64 = snapshot.bids.iter().chain(snapshot.asks.iter()).map(|level| level.1).sum();
+            std::hint::black_box(total);
+            timings[2] += start.elapsed().as_nanos() as u64;
+        }
+        let per_read = |ns: u64| ns as f64 / rounds as f64;
+        (per_read(timings[0]), per_read(timings[1]), per_read(timings[2]))
+    }
+
     fn average(timings: &[u64]) -> f64 {
         timings.iter().sum::<u64>() as f64 / timings.len() as f64
     }
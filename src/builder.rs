@@ -0,0 +1,133 @@
+// builder.rs
+
+use crate::interfaces::{Price, Quantity};
+use crate::orderbook::{AnchorMode, CAP, CrossPolicy, OrderBookImpl};
+
+/// Chainable configuration for `OrderBookImpl`. Unset options keep the `new()` defaults.
+#[derive(Debug, Clone)]
+pub struct OrderBookBuilder {
+    pub(crate) bid_anchor: Price,
+    pub(crate) ask_anchor: Price,
+    pub(crate) tick_size: i64,
+    pub(crate) max_levels: usize,
+    pub(crate) on_cross: CrossPolicy,
+    pub(crate) checksum_depth: usize,
+    pub(crate) anchor_grid: i64,
+    pub(crate) anchor_mode: AnchorMode,
+    pub(crate) max_spread: Option<Price>,
+    pub(crate) qty_divisor: Quantity,
+    pub(crate) price_scale: u32,
+    pub(crate) quote_tick: Price,
+}
+
+impl Default for OrderBookBuilder {
+    fn default() -> Self {
+        OrderBookBuilder { bid_anchor: 10000, ask_anchor: 10000, tick_size: 1, max_levels: CAP, on_cross: CrossPolicy::Allow, checksum_depth: 0, anchor_grid: 256, anchor_mode: AnchorMode::Fixed, max_spread: None, qty_divisor: 1, price_scale: 0, quote_tick: 1 }
+    }
+}
+
+impl OrderBookBuilder {
+    /// Centre of the price window, for both sides.
+    pub fn anchor(mut self, anchor: Price) -> Self {
+        self.bid_anchor = anchor;
+        self.ask_anchor = anchor;
+        self
+    }
+
+    /// Centre of the bid window only. With `ask_anchor` this lets each side's array cover its
+    /// own cluster, so bids and asks may sit up to two windows apart.
+    pub fn bid_anchor(mut self, anchor: Price) -> Self {
+        self.bid_anchor = anchor;
+        self
+    }
+
+    /// Centre of the ask window only; see `bid_anchor`.
+    pub fn ask_anchor(mut self, anchor: Price) -> Self {
+        self.ask_anchor = anchor;
+        self
+    }
+
+    /// Incoming prices are this many units per tick; see `OrderBookImpl::with_tick_divisor`.
+    /// Panics if `tick_size` is not positive.
+    pub fn tick_size(mut self, tick_size: i64) -> Self {
+        assert!(tick_size > 0, "tick size must be positive");
+        self.tick_size = tick_size;
+        self
+    }
+
+    /// Maximum occupied levels per side (capped at `CAP`). Sets opening a level beyond it are
+    /// dropped by `apply_update` and refused by `try_apply_update`.
+    pub fn max_levels(mut self, max_levels: usize) -> Self {
+        self.max_levels = max_levels;
+        self
+    }
+
+    pub fn on_cross(mut self, policy: CrossPolicy) -> Self {
+        self.on_cross = policy;
+        self
+    }
+
+    /// Maintain the rolling top-`depth` checksum from the start (see `enable_checksum`).
+    pub fn checksum_depth(mut self, depth: usize) -> Self {
+        self.checksum_depth = depth;
+        self
+    }
+
+    /// Grid that `recenter_anchor_aligned` snaps the anchor to (default 256 ticks).
+    /// Panics unless `0 < grid <= CAP / 2`, which keeps the requested price inside the window.
+    pub fn anchor_grid(mut self, grid: i64) -> Self {
+        assert!(grid > 0 && grid <= CAP as i64 / 2, "anchor grid must be in 1..=CAP/2");
+        self.anchor_grid = grid;
+        self
+    }
+
+    /// `AnchorMode::FromFirstUpdate` leaves the book unanchored (`BookState::Unanchored`) until
+    /// the first price arrives, for symbols whose price level is not known up front; the
+    /// configured anchors only apply until then. Panics unless its `round_to` is in `1..=CAP/2`.
+    pub fn anchor_mode(mut self, mode: AnchorMode) -> Self {
+        if let AnchorMode::FromFirstUpdate { round_to } = mode {
+            assert!(round_to > 0 && round_to <= CAP as i64 / 2, "anchor rounding must be in 1..=CAP/2");
+        }
+        self.anchor_mode = mode;
+        self
+    }
+
+    /// Refuse removals that would leave the spread wider than `ticks` (a stale or garbled
+    /// quote pulled from the touch): `apply_update` drops them and counts them in
+    /// `OrderBookImpl::spread_rejections`, `try_apply_update` reports
+    /// `OrderBookError::SpreadTooWide`. `Set`s only ever narrow the spread and always pass.
+    pub fn max_spread_ticks(mut self, ticks: Price) -> Self {
+        assert!(ticks >= 0, "max spread must not be negative");
+        self.max_spread = Some(ticks);
+        self
+    }
+
+    /// Base units per whole unit (satoshis per coin, say) for the book's `_scaled` getters;
+    /// quantities are still applied and stored in base units. Fixed for the book's life.
+    /// Panics if `divisor` is 0.
+    pub fn qty_divisor(mut self, divisor: Quantity) -> Self {
+        assert!(divisor > 0, "quantity divisor must be positive");
+        self.qty_divisor = divisor;
+        self
+    }
+
+    /// Decimal places the incoming prices carry (2 for cents), for `OrderBookImpl::format_price`.
+    /// Display only; prices are still applied and stored as integers.
+    pub fn price_scale(mut self, decimals: u32) -> Self {
+        self.price_scale = decimals;
+        self
+    }
+
+    /// The venue's minimum price increment in stored price units, for books whose grid is
+    /// finer than the tick (see `OrderBookImpl::spread_in_ticks_f64`). Unlike `tick_size` it
+    /// leaves incoming prices alone. Panics if `units` is not positive.
+    pub fn quote_tick(mut self, units: Price) -> Self {
+        assert!(units > 0, "quote tick must be positive");
+        self.quote_tick = units;
+        self
+    }
+
+    pub fn build(&self) -> OrderBookImpl {
+        OrderBookImpl::from_builder(self)
+    }
+}
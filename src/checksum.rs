@@ -0,0 +1,219 @@
+// checksum.rs
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use crate::interfaces::{Quantity, Side};
+use crate::orderbook::OrderBookImpl;
+
+/// How the two sides' levels are sequenced in the checksum string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelOrder {
+    /// bid 0, ask 0, bid 1, ask 1, ... (a missing level is skipped, its partner kept).
+    Interleaved,
+    BidsThenAsks,
+    AsksThenBids,
+}
+
+/// How a fixed-point number is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecimalStyle {
+    /// Shortest form: trailing fractional zeros and a bare `.` dropped (`3366.10` -> `3366.1`, `7.0` -> `7`).
+    Trimmed,
+    /// Always exactly `scale` fractional digits.
+    Fixed,
+    /// `Fixed` with the `.` and any leading zeros removed (`0.0500` -> `500`).
+    DigitsOnly,
+}
+
+/// An exchange's checksum recipe: which levels, in what order, rendered how.
+///
+/// The book stores integer ticks; `price_scale` / `qty_scale` are the number of decimal places
+/// those integers carry on the wire (a tick of 0.1 is scale 1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumSpec {
+    pub depth: usize,
+    pub order: LevelOrder,
+    /// Placed between every price and quantity and between levels.
+    pub separator: &'static str,
+    pub price_scale: u32,
+    pub qty_scale: u32,
+    pub style: DecimalStyle,
+}
+
+impl ChecksumSpec {
+    /// OKX: top 25 levels interleaved bid/ask, `price:qty` joined by `:`, shortest decimals.
+    pub fn okx(price_scale: u32, qty_scale: u32) -> Self {
+        ChecksumSpec { depth: 25, order: LevelOrder::Interleaved, separator: ":", price_scale, qty_scale, style: DecimalStyle::Trimmed }
+    }
+}
+
+thread_local! {
+    static BUFFER: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+impl OrderBookImpl {
+    /// CRC32 of the OKX canonical string of the top 25 levels; compare with the `checksum`
+    /// field of OKX book messages (which is this value as an `i32`).
+    pub fn checksum_okx(&self, price_scale: u32, qty_scale: u32) -> u32 {
+        self.checksum_levels(&ChecksumSpec::okx(price_scale, qty_scale))
+    }
+
+    /// CRC32 (IEEE) of the canonical string described by `spec`. The string is built in a
+    /// per-thread buffer that is reused across calls.
+    pub fn checksum_levels(&self, spec: &ChecksumSpec) -> u32 {
+        BUFFER.with(|buffer| {
+            let mut buffer = buffer.borrow_mut();
+            self.checksum_string(spec, &mut buffer);
+            crc32(buffer.as_bytes())
+        })
+    }
+
+    /// Writes the canonical string for `spec` into `out` (cleared first), e.g. to diff against
+    /// the exchange's when checksums disagree.
+    pub fn checksum_string(&self, spec: &ChecksumSpec, out: &mut String) {
+        out.clear();
+        let mut push = |price: i64, qty: Quantity| {
+            if !out.is_empty() {
+                out.push_str(spec.separator);
+            }
+            write_decimal(out, price < 0, price.unsigned_abs(), spec.price_scale, spec.style);
+            out.push_str(spec.separator);
+            write_decimal(out, false, qty, spec.qty_scale, spec.style);
+        };
+        let bids = self.levels(Side::Bid).take(spec.depth);
+        let asks = self.levels(Side::Ask).take(spec.depth);
+        match spec.order {
+            LevelOrder::Interleaved => {
+                let (mut bids, mut asks) = (bids.fuse(), asks.fuse());
+                loop {
+                    let (bid, ask) = (bids.next(), asks.next());
+                    if bid.is_none() && ask.is_none() {
+                        break;
+                    }
+                    for (price, qty) in bid.into_iter().chain(ask) {
+                        push(price, qty);
+                    }
+                }
+            }
+            LevelOrder::BidsThenAsks => bids.chain(asks).for_each(|(price, qty)| push(price, qty)),
+            LevelOrder::AsksThenBids => asks.chain(bids).for_each(|(price, qty)| push(price, qty)),
+        }
+    }
+}
+
+/// Appends `value / 10^scale` (negated if `negative`) in `style`.
+pub(crate) fn write_decimal(out: &mut String, negative: bool, value: u64, scale: u32, style: DecimalStyle) {
+    // Past 10^19 every u64 is purely fractional.
+    let (whole, frac) = match 10u64.checked_pow(scale) {
+        Some(unit) => (value / unit, value % unit),
+        None => (0, value),
+    };
+    if negative && value != 0 {
+        out.push('-');
+    }
+    match style {
+        DecimalStyle::Trimmed => {
+            let _ = write!(out, "{}", whole);
+            if frac != 0 {
+                let digits = format!("{:0width$}", frac, width = scale as usize);
+                let _ = write!(out, ".{}", digits.trim_end_matches('0'));
+            }
+        }
+        DecimalStyle::Fixed if scale == 0 => {
+            let _ = write!(out, "{}", whole);
+        }
+        DecimalStyle::Fixed => {
+            let _ = write!(out, "{}.{:0width$}", whole, frac, width = scale as usize);
+        }
+        DecimalStyle::DigitsOnly => {
+            let _ = write!(out, "{}", value);
+        }
+    }
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32/ISO-HDLC, the zlib / OKX / Kraken variant.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &b| CRC_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::{OrderBookWrite, Update};
+
+    fn book(bids: &[(i64, u64)], asks: &[(i64, u64)]) -> OrderBookImpl {
+        let mut ob = OrderBookImpl::builder().anchor(bids.first().map_or(0, |l| l.0)).build();
+        for &(price, quantity) in bids {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        for &(price, quantity) in asks {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Ask });
+        }
+        ob
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn okx_documented_example() {
+        // The two-level example from the OKX order book checksum docs:
+        // bids 3366.1 x 7, 3366 x 6; asks 3366.8 x 9, 3368 x 8 (tick 0.1, whole lots).
+        let ob = book(&[(33661, 7), (33660, 6)], &[(33668, 9), (33680, 8)]);
+        let mut text = String::new();
+        ob.checksum_string(&ChecksumSpec::okx(1, 0), &mut text);
+        assert_eq!(text, "3366.1:7:3366.8:9:3366:6:3368:8");
+        assert_eq!(ob.checksum_okx(1, 0) as i32, -1881014294);
+    }
+
+    #[test]
+    fn interleaving_continues_past_the_shorter_side() {
+        let ob = book(&[(100, 1), (99, 2), (98, 3)], &[(101, 5)]);
+        let mut text = String::new();
+        ob.checksum_string(&ChecksumSpec::okx(0, 0), &mut text);
+        assert_eq!(text, "100:1:101:5:99:2:98:3");
+    }
+
+    #[test]
+    fn decimal_styles() {
+        let render = |style, value, scale| {
+            let mut out = String::new();
+            write_decimal(&mut out, false, value, scale, style);
+            out
+        };
+        assert_eq!(render(DecimalStyle::Trimmed, 33_661_000, 4), "3366.1");
+        assert_eq!(render(DecimalStyle::Trimmed, 70_000, 4), "7");
+        assert_eq!(render(DecimalStyle::Trimmed, 5, 3), "0.005");
+        assert_eq!(render(DecimalStyle::Fixed, 500, 4), "0.0500");
+        assert_eq!(render(DecimalStyle::DigitsOnly, 500, 4), "500");
+    }
+
+    #[test]
+    fn generic_spec_order_and_depth() {
+        let ob = book(&[(1000, 150), (999, 20)], &[(1001, 5), (1002, 7)]);
+        let spec = ChecksumSpec { depth: 1, order: LevelOrder::AsksThenBids, separator: "", price_scale: 1, qty_scale: 2, style: DecimalStyle::DigitsOnly };
+        let mut text = String::new();
+        ob.checksum_string(&spec, &mut text);
+        assert_eq!(text, "100151000150");
+        assert_eq!(ob.checksum_levels(&spec), crc32(text.as_bytes()));
+    }
+}
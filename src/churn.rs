@@ -0,0 +1,345 @@
+// churn.rs
+
+use std::collections::{HashMap, VecDeque};
+use crate::interfaces::{Price, Quantity, Side};
+
+/// Distance-from-touch band a level change is attributed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChurnBand {
+    /// 0-1 ticks from the best price on the same side (before the change).
+    Touch = 0,
+    /// 2-5 ticks.
+    Near = 1,
+    /// 6 ticks or more.
+    Deep = 2,
+}
+
+impl ChurnBand {
+    fn of(distance: u64) -> Self {
+        match distance {
+            0..=1 => ChurnBand::Touch,
+            2..=5 => ChurnBand::Near,
+            _ => ChurnBand::Deep,
+        }
+    }
+}
+
+/// How far back the rolling sums reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChurnWindow {
+    /// The last `n` level changes.
+    Changes(usize),
+    /// Changes whose book clock (`apply_update_at`) is within this many time units of the latest.
+    Time(u64),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChurnSample {
+    time: u64,
+    side: Side,
+    band: ChurnBand,
+    added: Quantity,
+    cancelled: Quantity,
+}
+
+/// Rolling add/cancel quantities per side and distance band, fed from inside `apply_update`.
+///
+/// A quantity increase is an add. A decrease is a cancel, except for the part explained by a
+/// preceding `Update::Trade` at that price against that side, which is attributed to the trade
+/// and not counted. Only the last few unmatched trade prints are remembered.
+#[derive(Debug, Clone)]
+pub struct ChurnStats {
+    window: ChurnWindow,
+    samples: VecDeque<ChurnSample>,
+    /// [side][band] = (added, cancelled) over the window
+    sums: [[(Quantity, Quantity); 3]; 2],
+    pending_trades: VecDeque<(Side, Price, Quantity)>,
+    level_flips: Option<LevelFlips>,
+}
+
+const MAX_PENDING_TRADES: usize = 16;
+
+/// Times each level appeared or disappeared, kept for `horizon` time units; see
+/// `ChurnStats::with_level_flips`.
+#[derive(Debug, Clone)]
+struct LevelFlips {
+    horizon: u64,
+    /// [side] = flip times by price, oldest first
+    times: [HashMap<Price, VecDeque<u64>>; 2],
+    /// Flips recorded since levels that stopped flipping were last swept out.
+    since_sweep: usize,
+}
+
+const FLIP_SWEEP_EVERY: usize = 4096;
+
+impl ChurnStats {
+    pub fn new(window: ChurnWindow) -> Self {
+        let capacity = match window {
+            ChurnWindow::Changes(n) => n,
+            ChurnWindow::Time(_) => 1024,
+        };
+        ChurnStats {
+            window,
+            samples: VecDeque::with_capacity(capacity),
+            sums: [[(0, 0); 3]; 2],
+            pending_trades: VecDeque::with_capacity(MAX_PENDING_TRADES),
+            level_flips: None,
+        }
+    }
+
+    /// Also records, per price level, when it flipped between empty and occupied, keeping the
+    /// last `horizon` time units (book clock) of flips for `flip_count` and `churning_levels`.
+    /// Quote stuffing shows up as a level set and removed over and over; ordinary size changes
+    /// on a resting level are not flips.
+    pub fn with_level_flips(mut self, horizon: u64) -> Self {
+        self.level_flips = Some(LevelFlips { horizon, times: [HashMap::new(), HashMap::new()], since_sweep: 0 });
+        self
+    }
+
+    /// Times the level at `price` on `side` was set or removed within `window` of `now` (and
+    /// within the horizon). 0 without `with_level_flips`.
+    pub fn flip_count(&self, side: Side, price: Price, now: u64, window: u64) -> u32 {
+        let Some(flips) = &self.level_flips else { return 0 };
+        let since = now.saturating_sub(window.min(flips.horizon));
+        flips.times[side as usize].get(&price).map_or(0, |times| times.iter().rev().take_while(|&&time| time >= since).count() as u32)
+    }
+
+    /// Levels that flipped at least `min_flips` times within `window` of `now`, most flips first
+    /// (ties by side, then price).
+    pub fn churning_levels(&self, now: u64, window: u64, min_flips: u32) -> Vec<(Side, Price, u32)> {
+        let Some(flips) = &self.level_flips else { return Vec::new() };
+        let mut levels: Vec<(Side, Price, u32)> = [Side::Bid, Side::Ask]
+            .into_iter()
+            .flat_map(|side| flips.times[side as usize].keys().map(move |&price| (side, price)))
+            .map(|(side, price)| (side, price, self.flip_count(side, price, now, window)))
+            .filter(|&(.., count)| count > 0 && count >= min_flips)
+            .collect();
+        levels.sort_by_key(|&(side, price, count)| (std::cmp::Reverse(count), side as u8, price));
+        levels
+    }
+
+    /// Quantity added on `side` within `band` over the window.
+    pub fn added(&self, side: Side, band: ChurnBand) -> Quantity {
+        self.sums[side as usize][band as usize].0
+    }
+
+    /// Quantity cancelled (not traded away) on `side` within `band` over the window.
+    pub fn cancelled(&self, side: Side, band: ChurnBand) -> Quantity {
+        self.sums[side as usize][band as usize].1
+    }
+
+    /// added / cancelled, `None` when nothing was cancelled.
+    pub fn add_cancel_ratio(&self, side: Side, band: ChurnBand) -> Option<f64> {
+        let (added, cancelled) = self.sums[side as usize][band as usize];
+        (cancelled > 0).then(|| added as f64 / cancelled as f64)
+    }
+
+    /// Remembers a trade so the resting side's matching decrease is not read as a cancel.
+    pub(crate) fn record_trade(&mut self, price: Price, quantity: Quantity, aggressor: Side) {
+        let resting = match aggressor { Side::Bid => Side::Ask, Side::Ask => Side::Bid };
+        if self.pending_trades.len() == MAX_PENDING_TRADES {
+            self.pending_trades.pop_front();
+        }
+        self.pending_trades.push_back((resting, price, quantity));
+    }
+
+    pub(crate) fn record_change(&mut self, time: u64, side: Side, price: Price, touch: Option<Price>, old: Quantity, new: Quantity) {
+        if old == new {
+            return;
+        }
+        if let Some(flips) = self.level_flips.as_mut()
+            && (old == 0 || new == 0)
+        {
+            flips.record(time, side, price);
+        }
+        let distance = match (touch, side) {
+            (None, _) => 0,
+            (Some(best), Side::Bid) => best.saturating_sub(price).max(0) as u64,
+            (Some(best), Side::Ask) => price.saturating_sub(best).max(0) as u64,
+        };
+        let (added, cancelled) = if new > old { (new - old, 0) } else { (0, self.net_of_trades(side, price, old - new)) };
+        let sample = ChurnSample { time, side, band: ChurnBand::of(distance), added, cancelled };
+
+        let sum = &mut self.sums[side as usize][sample.band as usize];
+        sum.0 = sum.0.saturating_add(added);
+        sum.1 = sum.1.saturating_add(cancelled);
+        self.samples.push_back(sample);
+        self.evict(time);
+    }
+
+    fn net_of_trades(&mut self, side: Side, price: Price, decrease: Quantity) -> Quantity {
+        let mut left = decrease;
+        for trade in self.pending_trades.iter_mut().filter(|(s, p, _)| *s == side && *p == price) {
+            let matched = trade.2.min(left);
+            trade.2 -= matched;
+            left -= matched;
+        }
+        self.pending_trades.retain(|(_, _, qty)| *qty > 0);
+        left
+    }
+
+    fn evict(&mut self, now: u64) {
+        loop {
+            let expired = match (self.window, self.samples.front()) {
+                (_, None) => false,
+                (ChurnWindow::Changes(n), Some(_)) => self.samples.len() > n,
+                (ChurnWindow::Time(span), Some(oldest)) => now.saturating_sub(oldest.time) > span,
+            };
+            if !expired {
+                return;
+            }
+            if let Some(old) = self.samples.pop_front() {
+                let sum = &mut self.sums[old.side as usize][old.band as usize];
+                sum.0 = sum.0.saturating_sub(old.added);
+                sum.1 = sum.1.saturating_sub(old.cancelled);
+            }
+        }
+    }
+}
+
+impl LevelFlips {
+    fn record(&mut self, time: u64, side: Side, price: Price) {
+        let since = time.saturating_sub(self.horizon);
+        let times = self.times[side as usize].entry(price).or_default();
+        while times.front().is_some_and(|&oldest| oldest < since) {
+            times.pop_front();
+        }
+        times.push_back(time);
+
+        self.since_sweep += 1;
+        if self.since_sweep >= FLIP_SWEEP_EVERY {
+            self.since_sweep = 0;
+            for levels in &mut self.times {
+                levels.retain(|_, times| times.back().is_some_and(|&latest| latest >= since));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::{OrderBookRead, OrderBookWrite, Update};
+    use crate::orderbook::OrderBookImpl;
+    use crate::testing::set;
+
+    #[test]
+    fn classifies_adds_cancels_and_trades_by_band() {
+        let mut ob = OrderBookImpl::new();
+        ob.enable_churn(ChurnStats::new(ChurnWindow::Changes(100)));
+        ob.apply_update(set(10000, 50, Side::Bid)); // empty side: touch band
+        ob.apply_update(set(9999, 20, Side::Bid)); // 1 tick: touch
+        ob.apply_update(set(9996, 30, Side::Bid)); // 4 ticks: near
+        ob.apply_update(set(9990, 40, Side::Bid)); // 10 ticks: deep
+        ob.apply_update(set(9990, 10, Side::Bid)); // deep cancel of 30
+        ob.apply_update(Update::Remove { price: 9996, side: Side::Bid }); // near cancel of 30
+
+        // A 15-lot sell hits the best bid; the following decrease is a fill, not a cancel.
+        ob.apply_update(Update::Trade { price: 10000, quantity: 15, aggressor: Side::Ask, timestamp: 0 });
+        ob.apply_update(set(10000, 30, Side::Bid)); // 20 less: 15 traded, 5 cancelled
+
+        let churn = ob.churn().unwrap();
+        assert_eq!(churn.added(Side::Bid, ChurnBand::Touch), 70);
+        assert_eq!(churn.cancelled(Side::Bid, ChurnBand::Touch), 5);
+        assert_eq!(churn.added(Side::Bid, ChurnBand::Near), 30);
+        assert_eq!(churn.cancelled(Side::Bid, ChurnBand::Near), 30);
+        assert_eq!(churn.added(Side::Bid, ChurnBand::Deep), 40);
+        assert_eq!(churn.cancelled(Side::Bid, ChurnBand::Deep), 30);
+        assert_eq!(churn.add_cancel_ratio(Side::Bid, ChurnBand::Touch), Some(14.0));
+        assert_eq!(churn.add_cancel_ratio(Side::Ask, ChurnBand::Touch), None);
+    }
+
+    #[test]
+    fn windows_roll_off_old_changes() {
+        let mut ob = OrderBookImpl::new();
+        ob.enable_churn(ChurnStats::new(ChurnWindow::Changes(2)));
+        ob.apply_update(set(10001, 5, Side::Ask));
+        ob.apply_update(set(10001, 7, Side::Ask));
+        ob.apply_update(set(10001, 7, Side::Ask)); // no change, not a sample
+        ob.apply_update(set(10001, 4, Side::Ask));
+        let churn = ob.churn().unwrap();
+        assert_eq!((churn.added(Side::Ask, ChurnBand::Touch), churn.cancelled(Side::Ask, ChurnBand::Touch)), (2, 3));
+
+        let mut ob = OrderBookImpl::new();
+        ob.enable_churn(ChurnStats::new(ChurnWindow::Time(100)));
+        ob.apply_update_at(0, set(10001, 5, Side::Ask));
+        ob.apply_update_at(50, set(10002, 5, Side::Ask));
+        ob.apply_update_at(160, set(10003, 5, Side::Ask));
+        // Only the change at t=160 (2 ticks behind the 10001 touch) is still inside the window.
+        assert_eq!(ob.churn().unwrap().added(Side::Ask, ChurnBand::Near), 5);
+        assert_eq!(ob.churn().unwrap().added(Side::Ask, ChurnBand::Touch), 0);
+    }
+
+    #[test]
+    fn saturated_sums_roll_off_without_underflow() {
+        let mut ob = OrderBookImpl::new();
+        ob.enable_churn(ChurnStats::new(ChurnWindow::Changes(1)));
+        ob.apply_update(set(10000, u64::MAX, Side::Bid));
+        ob.apply_update(set(9999, u64::MAX, Side::Bid)); // saturates, then rolls the first off
+        ob.apply_update(set(9998, 1, Side::Bid));
+        assert_eq!(ob.churn().unwrap().added(Side::Bid, ChurnBand::Touch), 0);
+    }
+
+    #[test]
+    fn toggled_levels_show_a_rising_churn_rate() {
+        let mut ob = OrderBookImpl::new();
+        ob.enable_churn(ChurnStats::new(ChurnWindow::Changes(16)).with_level_flips(1_000));
+        ob.apply_update_at(0, set(9990, 500, Side::Bid));
+        for i in 0..10 {
+            ob.apply_update_at(i * 10, set(10005, 100 + i, Side::Ask)); // resized, never emptied
+        }
+        assert_eq!(ob.churn_rate(Side::Ask, 10005, 1_000), 1);
+
+        let mut rates = Vec::new();
+        for i in 0..20 {
+            ob.apply_update_at(100 + i * 10, set(10002, 1, Side::Ask));
+            ob.apply_update_at(105 + i * 10, Update::Remove { price: 10002, side: Side::Ask });
+            rates.push(ob.churn_rate(Side::Ask, 10002, 1_000));
+        }
+        assert!(rates.windows(2).all(|pair| pair[1] > pair[0]), "{rates:?}");
+        assert_eq!(ob.churn_rate(Side::Ask, 10002, 1_000), 40);
+        // Only the last 50 time units (the clock is at 295): the flips at 245..=295.
+        assert_eq!(ob.churn_rate(Side::Ask, 10002, 50), 11);
+        assert_eq!(ob.churn_rate(Side::Bid, 10002, 1_000), 0);
+
+        let flagged = ob.churn().unwrap().churning_levels(ob.now(), 1_000, 5);
+        assert_eq!(flagged, [(Side::Ask, 10002, 40)]);
+
+        // Flips older than the horizon roll off.
+        ob.apply_update_at(2_000, set(9991, 5, Side::Bid));
+        assert_eq!(ob.churn_rate(Side::Ask, 10002, 5_000), 0);
+
+        let mut plain = OrderBookImpl::new();
+        plain.enable_churn(ChurnStats::new(ChurnWindow::Changes(16)));
+        plain.apply_update(set(10002, 1, Side::Ask));
+        assert_eq!(plain.churn_rate(Side::Ask, 10002, 1_000), 0);
+    }
+
+    #[test]
+    fn recentering_is_not_churn() {
+        let mut ob = OrderBookImpl::new();
+        ob.enable_churn(ChurnStats::new(ChurnWindow::Changes(64)).with_level_flips(1_000));
+        ob.apply_update_at(0, set(9990, 10, Side::Bid));
+        ob.apply_update_at(0, set(10010, 20, Side::Ask));
+        for i in 0..3 {
+            ob.apply_update_at(10 + i, set(10005, 1, Side::Ask));
+            ob.apply_update_at(10 + i, Update::Remove { price: 10005, side: Side::Ask });
+        }
+        let observed = |ob: &OrderBookImpl| {
+            let churn = ob.churn().unwrap();
+            (
+                [ob.churn_rate(Side::Bid, 9990, 1_000), ob.churn_rate(Side::Ask, 10010, 1_000), ob.churn_rate(Side::Ask, 10005, 1_000)],
+                churn.added(Side::Bid, ChurnBand::Touch),
+                churn.added(Side::Ask, ChurnBand::Touch),
+                churn.cancelled(Side::Ask, ChurnBand::Touch),
+            )
+        };
+        let expected = observed(&ob);
+        assert_eq!(expected.0, [1, 1, 6]);
+
+        ob.recenter_anchor(10_200);
+        assert_eq!(ob.get_best_bid(), Some(9990));
+        assert_eq!(observed(&ob), expected);
+        assert!(ob.churn().unwrap().churning_levels(ob.now(), 1_000, 2).iter().all(|&(.., flips)| flips == 6));
+    }
+}
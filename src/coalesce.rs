@@ -0,0 +1,208 @@
+// coalesce.rs
+
+use crate::interfaces::{OrderBookWrite, Price, Side, Update};
+use crate::orderbook::CAP;
+
+const EMPTY: u32 = u32::MAX;
+
+/// Buffers a burst of updates and collapses those to the same level, so `drain_into` applies
+/// only the last `Set`/`Remove` per (side, price). The feed has no delta updates, so within a
+/// level the last write always wins; a `Set` followed by a `Remove` drains as the `Remove`.
+///
+/// Levels drain in the order they were first touched; trades are passed through in arrival
+/// order, uncoalesced. The final levels match applying the burst one by one on a book with
+/// `CrossPolicy::Allow`, no level limit and a tick size of 1, for prices inside the book's
+/// window (each of those makes the outcome depend on intermediate states). `undo_last` afterwards undoes only the last drained update.
+/// A `SetLevels` is split into its levels, each coalesced (and counted by `pushed`) like a `Set`.
+///
+/// The index is an open-addressing table keyed by the level's ring slot (price mod `CAP`) and
+/// side, with linear probing; the table and the pending list keep their capacity across drains,
+/// so a steady burst size allocates nothing.
+#[derive(Debug, Clone)]
+pub struct UpdateCoalescer {
+    pending: Vec<Update>,
+    /// Positions in `pending`, `EMPTY` if free. Length is a power of two, at least twice the
+    /// number of coalesced levels.
+    table: Vec<u32>,
+    levels: usize,
+    pushed: u64,
+}
+
+impl Default for UpdateCoalescer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UpdateCoalescer {
+    pub fn new() -> Self {
+        UpdateCoalescer { pending: Vec::new(), table: vec![EMPTY; 64], levels: 0, pushed: 0 }
+    }
+
+    pub fn push(&mut self, update: Update) {
+        let (side, price) = match update {
+            Update::Set { price, side, .. } | Update::SetWithCount { price, side, .. } | Update::Remove { price, side } => (side, price),
+            Update::Trade { .. } => {
+                self.pushed += 1;
+                self.pending.push(update);
+                return;
+            }
+            Update::SetLevels { side, levels } => {
+                for (price, quantity) in levels {
+                    self.push(Update::Set { price, quantity, side });
+                }
+                return;
+            }
+        };
+        self.pushed += 1;
+        let mut slot = Self::hash(side, price) & (self.table.len() - 1);
+        loop {
+            let position = self.table[slot];
+            if position == EMPTY {
+                break;
+            }
+            if Self::key(&self.pending[position as usize]) == Some((side, price)) {
+                self.pending[position as usize] = update;
+                return;
+            }
+            slot = (slot + 1) & (self.table.len() - 1);
+        }
+        self.table[slot] = self.pending.len() as u32;
+        self.pending.push(update);
+        self.levels += 1;
+        if self.levels * 2 > self.table.len() {
+            self.grow();
+        }
+    }
+
+    /// Applies the buffered updates to `book` and empties the buffer. Returns how many were
+    /// applied.
+    pub fn drain_into(&mut self, book: &mut impl OrderBookWrite) -> usize {
+        let applied = self.pending.len();
+        for update in self.pending.drain(..) {
+            book.apply_update(update);
+        }
+        if self.levels > 0 {
+            self.table.fill(EMPTY);
+            self.levels = 0;
+        }
+        applied
+    }
+
+    /// Updates waiting for `drain_into`.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Updates pushed since construction, coalesced or not.
+    pub fn pushed(&self) -> u64 {
+        self.pushed
+    }
+
+    fn key(update: &Update) -> Option<(Side, Price)> {
+        match *update {
+            Update::Set { price, side, .. } | Update::SetWithCount { price, side, .. } | Update::Remove { price, side } => {
+                Some((side, price))
+            }
+            Update::Trade { .. } | Update::SetLevels { .. } => None,
+        }
+    }
+
+    #[inline(always)]
+    fn hash(side: Side, price: Price) -> usize {
+        // Consecutive prices land in consecutive slots; bids and asks at the same price are
+        // kept apart by flipping the top bit of the ring index.
+        ((price as usize) & (CAP - 1)) ^ ((side as usize) << 11)
+    }
+
+    fn grow(&mut self) {
+        let len = self.table.len() * 2;
+        self.table.clear();
+        self.table.resize(len, EMPTY);
+        for (position, update) in self.pending.iter().enumerate() {
+            if let Some((side, price)) = Self::key(update) {
+                let mut slot = Self::hash(side, price) & (len - 1);
+                while self.table[slot] != EMPTY {
+                    slot = (slot + 1) & (len - 1);
+                }
+                self.table[slot] = position as u32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smallvec::smallvec;
+    use crate::interfaces::OrderBookRead;
+    use crate::orderbook::OrderBookImpl;
+    use crate::sim::SimRng;
+    use crate::testing::{random_price, random_side, random_update};
+
+    #[test]
+    fn last_write_per_level_wins() {
+        let mut coalescer = UpdateCoalescer::new();
+        coalescer.push(Update::Set { price: 100, quantity: 5, side: Side::Bid });
+        coalescer.push(Update::Set { price: 101, quantity: 2, side: Side::Ask });
+        coalescer.push(Update::Set { price: 100, quantity: 9, side: Side::Bid });
+        coalescer.push(Update::Set { price: 100, quantity: 4, side: Side::Ask });
+        coalescer.push(Update::Remove { price: 101, side: Side::Ask });
+        assert_eq!(coalescer.len(), 3);
+        assert_eq!(coalescer.pushed(), 5);
+
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 101, quantity: 7, side: Side::Ask });
+        assert_eq!(coalescer.drain_into(&mut ob), 3);
+        assert!(coalescer.is_empty());
+        assert_eq!(ob.get_quantity_at(100, Side::Bid), Some(9));
+        assert_eq!(ob.get_quantity_at(100, Side::Ask), Some(4));
+        assert_eq!(ob.get_quantity_at(101, Side::Ask), None);
+    }
+
+    #[test]
+    fn set_levels_coalesce_per_level() {
+        let mut coalescer = UpdateCoalescer::new();
+        coalescer.push(Update::Set { price: 10000, quantity: 5, side: Side::Bid });
+        coalescer.push(Update::SetLevels { side: Side::Bid, levels: smallvec![(10000, 7), (9999, 3), (9998, 1)] });
+        coalescer.push(Update::Remove { price: 9998, side: Side::Bid });
+        assert_eq!(coalescer.len(), 3);
+        assert_eq!(coalescer.pushed(), 5);
+
+        let mut ob = OrderBookImpl::new();
+        coalescer.drain_into(&mut ob);
+        assert_eq!(ob.get_top_levels(Side::Bid, 5), vec![(10000, 7), (9999, 3)]);
+    }
+
+    #[test]
+    fn coalesced_bursts_match_sequential_application() {
+        let mut rng = SimRng::new(0x2545_f491_4f6c_dd1d);
+        let mut coalescer = UpdateCoalescer::new();
+        let mut coalesced = OrderBookImpl::new();
+        let mut sequential = OrderBookImpl::new();
+        for _ in 0..500 {
+            // Narrow and wide price ranges, so bursts both collide heavily and force growth; the
+            // wide one also puts bids and asks 2048 apart into the same hash slot.
+            let (span, burst) = if rng.below(2) == 0 { (16, rng.below(64)) } else { (3000, rng.below(400)) };
+            for _ in 0..burst {
+                let update = if rng.below(8) == 0 {
+                    Update::Trade { price: random_price(&mut rng, 10000, span), quantity: 1, aggressor: random_side(&mut rng), timestamp: 0 }
+                } else {
+                    random_update(&mut rng, 10000, span)
+                };
+                sequential.apply_update(update.clone());
+                coalescer.push(update);
+            }
+            coalescer.drain_into(&mut coalesced);
+            for side in [Side::Bid, Side::Ask] {
+                assert!(coalesced.levels(side).eq(sequential.levels(side)), "{side:?} levels differ");
+                assert_eq!(coalesced.get_total_quantity(side), sequential.get_total_quantity(side));
+            }
+            assert_eq!(coalesced.get_bbo().map(|b| (b.bid_price, b.ask_price)), sequential.get_bbo().map(|b| (b.bid_price, b.ask_price)));
+        }
+    }
+}
@@ -0,0 +1,247 @@
+// consolidated.rs
+
+use crate::interfaces::{OrderBookRead, OrderBookWrite, Price, Quantity, Side, Update};
+use crate::orderbook::OrderBookImpl;
+
+/// Identifies the venue a component book belongs to.
+pub type VenueId = u16;
+
+/// One merged price level with the per-venue breakdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsolidatedLevel {
+    pub price: Price,
+    pub quantity: Quantity,
+    pub venues: Vec<(VenueId, Quantity)>,
+}
+
+/// A bid on one venue at or above an ask on another: an arbitrage or latency signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrossedMarket {
+    pub bid_venue: VenueId,
+    pub bid: Price,
+    pub ask_venue: VenueId,
+    pub ask: Price,
+}
+
+/// Consolidated view over one book per venue.
+///
+/// Updates are routed by venue id. The merged touch and any cross between venues are cached
+/// and only recomputed when a component's best bid or ask actually moves, so
+/// `best_bid`/`best_ask`/`crossed` are O(1). On equal prices the venue registered first wins
+/// the touch.
+pub struct ConsolidatedBook {
+    venues: Vec<VenueId>,
+    books: Vec<OrderBookImpl>,
+    touches: Vec<(Option<Price>, Option<Price>)>,
+    best_bid: Option<(Price, VenueId)>,
+    best_ask: Option<(Price, VenueId)>,
+    crossed: Option<CrossedMarket>,
+}
+
+impl ConsolidatedBook {
+    pub fn new(venues: &[VenueId]) -> Self {
+        ConsolidatedBook {
+            venues: venues.to_vec(),
+            books: venues.iter().map(|_| OrderBookImpl::new()).collect(),
+            touches: vec![(None, None); venues.len()],
+            best_bid: None,
+            best_ask: None,
+            crossed: None,
+        }
+    }
+
+    /// Routes `update` to `venue`'s book. Returns false (and drops it) for an unknown venue.
+    pub fn apply_update(&mut self, venue: VenueId, update: Update) -> bool {
+        let Some(slot) = self.slot(venue) else { return false };
+        let book = &mut self.books[slot];
+        book.apply_update(update);
+
+        let touch = (book.get_best_bid(), book.get_best_ask());
+        if touch != self.touches[slot] {
+            self.touches[slot] = touch;
+            self.refresh_touch();
+        }
+        true
+    }
+
+    pub fn venue(&self, venue: VenueId) -> Option<&OrderBookImpl> {
+        self.slot(venue).map(|slot| &self.books[slot])
+    }
+
+    /// Best bid across venues and the venue quoting it.
+    #[inline(always)]
+    pub fn best_bid(&self) -> Option<(Price, VenueId)> {
+        self.best_bid
+    }
+
+    /// Best ask across venues and the venue quoting it.
+    #[inline(always)]
+    pub fn best_ask(&self) -> Option<(Price, VenueId)> {
+        self.best_ask
+    }
+
+    /// Reports a cross-venue crossed market: some venue's best bid at or above another venue's
+    /// best ask, even when neither is the merged touch. With several, the widest cross.
+    pub fn crossed(&self) -> Option<CrossedMarket> {
+        self.crossed
+    }
+
+    /// Top `n` merged levels, summing quantities at equal prices across venues.
+    pub fn get_top_levels(&self, side: Side, n: usize) -> Vec<(Price, Quantity)> {
+        self.merged_levels(side, n).into_iter().map(|level| (level.price, level.quantity)).collect()
+    }
+
+    /// Like `get_top_levels`, keeping the per-venue breakdown of every merged level.
+    pub fn get_top_levels_by_venue(&self, side: Side, n: usize) -> Vec<ConsolidatedLevel> {
+        self.merged_levels(side, n)
+    }
+
+    /// Saturates, like the merged level quantities.
+    pub fn get_total_quantity(&self, side: Side) -> Quantity {
+        self.books.iter().fold(0, |total, book| total.saturating_add(book.get_total_quantity(side)))
+    }
+
+    fn slot(&self, venue: VenueId) -> Option<usize> {
+        self.venues.iter().position(|v| *v == venue)
+    }
+
+    fn refresh_touch(&mut self) {
+        let mut best_bid: Option<(Price, VenueId)> = None;
+        let mut best_ask: Option<(Price, VenueId)> = None;
+        // The best ask on any venue but `best_ask`'s, to cross that venue's own bid against.
+        let mut runner_up_ask: Option<(Price, VenueId)> = None;
+        for (&venue, &(bid, ask)) in self.venues.iter().zip(&self.touches) {
+            if let Some(bid) = bid && best_bid.is_none_or(|(best, _)| bid > best) {
+                best_bid = Some((bid, venue));
+            }
+            if let Some(ask) = ask {
+                if best_ask.is_none_or(|(best, _)| ask < best) {
+                    runner_up_ask = best_ask;
+                    best_ask = Some((ask, venue));
+                } else if runner_up_ask.is_none_or(|(runner_up, _)| ask < runner_up) {
+                    runner_up_ask = Some((ask, venue));
+                }
+            }
+        }
+
+        let mut crossed: Option<CrossedMarket> = None;
+        for (&bid_venue, &(bid, _)) in self.venues.iter().zip(&self.touches) {
+            let other_ask = if best_ask.is_some_and(|(_, venue)| venue == bid_venue) { runner_up_ask } else { best_ask };
+            if let (Some(bid), Some((ask, ask_venue))) = (bid, other_ask)
+                && bid >= ask
+                && crossed.is_none_or(|widest| bid - ask > widest.bid - widest.ask)
+            {
+                crossed = Some(CrossedMarket { bid_venue, bid, ask_venue, ask });
+            }
+        }
+        self.best_bid = best_bid;
+        self.best_ask = best_ask;
+        self.crossed = crossed;
+    }
+
+    /// k-way merge of the venues' best-first level iterators.
+    fn merged_levels(&self, side: Side, n: usize) -> Vec<ConsolidatedLevel> {
+        let better = |a: Price, b: Price| match side {
+            Side::Bid => a > b,
+            Side::Ask => a < b,
+        };
+        let mut heads: Vec<_> = self.books.iter().map(|book| book.levels(side).peekable()).collect();
+        let mut merged = Vec::with_capacity(n.min(64));
+        while merged.len() < n {
+            let Some(price) = heads
+                .iter_mut()
+                .filter_map(|head| head.peek().map(|&(price, _)| price))
+                .reduce(|best, price| if better(price, best) { price } else { best })
+            else {
+                break;
+            };
+            let mut level = ConsolidatedLevel { price, quantity: 0, venues: Vec::new() };
+            for (head, &venue) in heads.iter_mut().zip(&self.venues) {
+                if let Some((_, qty)) = head.next_if(|&(p, _)| p == price) {
+                    level.quantity = level.quantity.saturating_add(qty);
+                    level.venues.push((venue, qty));
+                }
+            }
+            merged.push(level);
+        }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::set;
+
+    #[test]
+    fn merged_touch_and_levels() {
+        let mut book = ConsolidatedBook::new(&[1, 2, 3]);
+        assert!(book.apply_update(1, set(9998, 10, Side::Bid)));
+        assert!(book.apply_update(2, set(9999, 5, Side::Bid)));
+        assert!(book.apply_update(3, set(9999, 7, Side::Bid)));
+        assert!(book.apply_update(1, set(10002, 4, Side::Ask)));
+        assert!(book.apply_update(3, set(10001, 6, Side::Ask)));
+        assert!(!book.apply_update(9, set(10001, 6, Side::Ask)));
+
+        assert_eq!(book.best_bid(), Some((9999, 2)));
+        assert_eq!(book.best_ask(), Some((10001, 3)));
+        assert_eq!(book.get_top_levels(Side::Bid, 5), vec![(9999, 12), (9998, 10)]);
+        assert_eq!(
+            book.get_top_levels_by_venue(Side::Bid, 1),
+            vec![ConsolidatedLevel { price: 9999, quantity: 12, venues: vec![(2, 5), (3, 7)] }]
+        );
+        assert_eq!(book.get_total_quantity(Side::Ask), 10);
+
+        // Venue 2 pulls its bid: the cached touch moves to venue 3 at the same price.
+        book.apply_update(2, Update::Remove { price: 9999, side: Side::Bid });
+        assert_eq!(book.best_bid(), Some((9999, 3)));
+        assert_eq!(book.crossed(), None);
+    }
+
+    #[test]
+    fn totals_saturate_across_venues() {
+        let mut book = ConsolidatedBook::new(&[1, 2]);
+        book.apply_update(1, set(9999, u64::MAX, Side::Bid));
+        book.apply_update(2, set(9999, u64::MAX, Side::Bid));
+        assert_eq!(book.get_total_quantity(Side::Bid), u64::MAX);
+        assert_eq!(book.get_top_levels(Side::Bid, 1), vec![(9999, u64::MAX)]);
+    }
+
+    #[test]
+    fn detects_cross_venue_crossed_market() {
+        let mut book = ConsolidatedBook::new(&[1, 2]);
+        book.apply_update(1, set(10000, 1, Side::Bid));
+        book.apply_update(1, set(10003, 1, Side::Ask));
+        book.apply_update(2, set(9997, 1, Side::Bid));
+        book.apply_update(2, set(10001, 1, Side::Ask));
+        assert_eq!(book.crossed(), None);
+
+        book.apply_update(1, set(10002, 1, Side::Bid));
+        assert_eq!(book.crossed(), Some(CrossedMarket { bid_venue: 1, bid: 10002, ask_venue: 2, ask: 10001 }));
+    }
+
+    #[test]
+    fn detects_a_cross_away_from_the_merged_touch() {
+        // Venue 1 is crossed on its own (a stale side, say) and holds both merged bests.
+        let mut book = ConsolidatedBook::new(&[1, 2, 3]);
+        book.apply_update(1, set(10005, 1, Side::Bid));
+        book.apply_update(1, set(10001, 1, Side::Ask));
+        book.apply_update(2, set(9998, 1, Side::Bid));
+        book.apply_update(3, set(10010, 1, Side::Ask));
+        assert_eq!((book.best_bid(), book.best_ask()), (Some((10005, 1)), Some((10001, 1))));
+        // A venue against itself is not a cross between venues.
+        assert_eq!(book.crossed(), None);
+
+        // Venue 3's ask drops below venue 1's bid, with both merged bests still on venue 1.
+        book.apply_update(3, set(10003, 1, Side::Ask));
+        book.apply_update(3, Update::Remove { price: 10010, side: Side::Ask });
+        assert_eq!(book.crossed(), Some(CrossedMarket { bid_venue: 1, bid: 10005, ask_venue: 3, ask: 10003 }));
+
+        // Venue 1's bid falls back; venue 2's crosses both other asks and reports the wider.
+        book.apply_update(1, Update::Remove { price: 10005, side: Side::Bid });
+        book.apply_update(1, set(9990, 1, Side::Bid));
+        book.apply_update(2, set(10004, 1, Side::Bid));
+        assert_eq!(book.best_ask(), Some((10001, 1)));
+        assert_eq!(book.crossed(), Some(CrossedMarket { bid_venue: 2, bid: 10004, ask_venue: 1, ask: 10001 }));
+    }
+}
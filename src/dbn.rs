@@ -0,0 +1,415 @@
+// dbn.rs
+
+use std::collections::HashMap;
+use std::mem::offset_of;
+use crate::feed::ParseError;
+use crate::interfaces::{Price, Quantity, Side, Update};
+use crate::mbp::{BidAskPair, Mbp10Record, RecordHeader, UNDEF_PRICE, price_from_fixed};
+
+/// Databento MBO record (one order event), 56 bytes, `repr(C)`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MboRecord {
+    pub hd: RecordHeader,
+    pub order_id: u64,
+    /// 1e-9 fixed point.
+    pub price: i64,
+    pub size: u32,
+    pub flags: u8,
+    pub channel_id: u8,
+    /// `'A'`dd, `'C'`ancel, `'M'`odify, `'R'` clear, `'T'`rade, `'F'`ill or `'N'`one.
+    pub action: u8,
+    /// `'A'`sk, `'B'`id or `'N'`one; the aggressor for trades.
+    pub side: u8,
+    pub ts_recv: u64,
+    pub ts_in_delta: i32,
+    pub sequence: u32,
+}
+
+/// Databento MBP-1 record: `Mbp10Record` with a single level, 80 bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mbp1Record {
+    pub hd: RecordHeader,
+    pub price: i64,
+    pub size: u32,
+    pub action: u8,
+    pub side: u8,
+    pub flags: u8,
+    pub depth: u8,
+    pub ts_recv: u64,
+    pub ts_in_delta: i32,
+    pub sequence: u32,
+    pub levels: [BidAskPair; 1],
+}
+
+/// `'B'` / `'A'` / `'N'`; anything else is an error at `offset`.
+fn side_code(code: u8, offset: usize) -> Result<Option<Side>, ParseError> {
+    match code {
+        b'B' => Ok(Some(Side::Bid)),
+        b'A' => Ok(Some(Side::Ask)),
+        b'N' => Ok(None),
+        _ => Err(ParseError { offset, reason: "invalid side" }),
+    }
+}
+
+fn book_price(px: i64, price_scale: i64, offset: usize) -> Result<Price, ParseError> {
+    price_from_fixed(px, price_scale).ok_or(ParseError { offset, reason: "price not on the tick grid" })
+}
+
+/// Turns one instrument's MBO records into level `Update`s by keeping every live order and the
+/// per-level totals they add up to. Level changes are emitted as `Update::SetWithCount`, the
+/// count being the live orders resting at the level.
+///
+/// Error offsets are field offsets within the record. Trades become `Update::Trade` (timestamp
+/// `ts_event`) unless their side is `'N'`, which leaves no aggressor to report. Fills are
+/// ignored: in MBO the book change arrives as the cancel or modify that follows. A modify of an
+/// unknown order is an add; a cancel of one is ignored, as when replay starts mid-session.
+#[derive(Debug, Clone)]
+pub struct MboDecoder {
+    price_scale: i64,
+    orders: HashMap<u64, (Side, Price, Quantity)>,
+    /// Per level: total size and live orders.
+    levels: [HashMap<Price, (Quantity, u32)>; 2],
+}
+
+impl MboDecoder {
+    /// `price_scale` is the value of one book price unit in 1e-9 units, as in `RecordMeta`.
+    pub fn new(price_scale: i64) -> Self {
+        MboDecoder { price_scale, orders: HashMap::new(), levels: [HashMap::new(), HashMap::new()] }
+    }
+
+    /// Live orders currently tracked.
+    pub fn order_count(&self) -> usize {
+        self.orders.len()
+    }
+
+    /// Appends the updates `record` implies to `out`. On error nothing is appended or changed.
+    pub fn decode(&mut self, record: &MboRecord, out: &mut Vec<Update>) -> Result<(), ParseError> {
+        let side = side_code(record.side, offset_of!(MboRecord, side))?;
+        let size = record.size as Quantity;
+        match record.action {
+            b'A' | b'M' => {
+                let Some(side) = side else {
+                    return Err(ParseError { offset: offset_of!(MboRecord, side), reason: "order without a side" });
+                };
+                let price = book_price(record.price, self.price_scale, offset_of!(MboRecord, price))?;
+                if record.action == b'A' && self.orders.contains_key(&record.order_id) {
+                    return Err(ParseError { offset: offset_of!(MboRecord, order_id), reason: "duplicate order id" });
+                }
+                if let Some((old_side, old_price, old_size)) = self.orders.insert(record.order_id, (side, price, size)) {
+                    // Zero-size orders were never counted at their level.
+                    self.level_sub(old_side, old_price, old_size, old_size > 0, out);
+                }
+                self.level_add(side, price, size, out);
+            }
+            b'C' => {
+                if let Some(order) = self.orders.get_mut(&record.order_id) {
+                    let (side, price, live) = *order;
+                    let cancelled = size.min(live);
+                    order.2 = live - cancelled;
+                    let gone = order.2 == 0;
+                    if gone {
+                        self.orders.remove(&record.order_id);
+                    }
+                    self.level_sub(side, price, cancelled, gone && live > 0, out);
+                }
+            }
+            b'T' => {
+                if let Some(aggressor) = side {
+                    let price = book_price(record.price, self.price_scale, offset_of!(MboRecord, price))?;
+                    out.push(Update::Trade { price, quantity: size, aggressor, timestamp: record.hd.ts_event });
+                }
+            }
+            b'R' => {
+                self.orders.clear();
+                for side in [Side::Bid, Side::Ask] {
+                    out.extend(self.levels[side as usize].drain().map(|(price, _)| Update::Remove { price, side }));
+                }
+            }
+            b'F' | b'N' => {}
+            _ => return Err(ParseError { offset: offset_of!(MboRecord, action), reason: "unknown action" }),
+        }
+        Ok(())
+    }
+
+    fn level_add(&mut self, side: Side, price: Price, size: Quantity, out: &mut Vec<Update>) {
+        if size == 0 {
+            return;
+        }
+        let (quantity, count) = self.levels[side as usize].entry(price).or_insert((0, 0));
+        *quantity = quantity.saturating_add(size);
+        *count += 1;
+        out.push(Update::SetWithCount { price, quantity: *quantity, order_count: *count, side });
+    }
+
+    /// `leaves`: the order no longer rests at the level, so it stops counting towards it.
+    fn level_sub(&mut self, side: Side, price: Price, size: Quantity, leaves: bool, out: &mut Vec<Update>) {
+        let levels = &mut self.levels[side as usize];
+        let Some((quantity, count)) = levels.get_mut(&price) else { return };
+        *quantity = quantity.saturating_sub(size);
+        if leaves {
+            *count = count.saturating_sub(1);
+        }
+        if *quantity > 0 {
+            out.push(Update::SetWithCount { price, quantity: *quantity, order_count: *count, side });
+        } else {
+            levels.remove(&price);
+            out.push(Update::Remove { price, side });
+        }
+    }
+}
+
+/// Turns one instrument's MBP-1 or MBP-10 records into level `Update`s by diffing each record's
+/// levels against the previous record's.
+///
+/// A level that vanishes from the record is removed only if it was better than the record's
+/// worst level (or the side now has fewer than the record's depth); otherwise it may just have
+/// dropped below the visible depth and is left as it was. Levels are set with
+/// `Update::SetWithCount`, carrying the record's order counts. Trades become `Update::Trade` as
+/// with `MboDecoder`; `'R'` removes every level seen so far.
+#[derive(Debug, Clone)]
+pub struct MbpDecoder {
+    price_scale: i64,
+    /// (price, quantity, order count) per side, as in the last record.
+    visible: [Vec<(Price, Quantity, u32)>; 2],
+}
+
+impl MbpDecoder {
+    /// `price_scale` is the value of one book price unit in 1e-9 units, as in `RecordMeta`.
+    pub fn new(price_scale: i64) -> Self {
+        MbpDecoder { price_scale, visible: [Vec::new(), Vec::new()] }
+    }
+
+    pub fn decode_mbp1(&mut self, record: &Mbp1Record, out: &mut Vec<Update>) -> Result<(), ParseError> {
+        let event = (record.action, record.side, record.price, record.size, record.hd.ts_event);
+        self.decode(event, &record.levels, offset_of!(Mbp1Record, levels), out)
+    }
+
+    pub fn decode_mbp10(&mut self, record: &Mbp10Record, out: &mut Vec<Update>) -> Result<(), ParseError> {
+        let event = (record.action, record.side, record.price, record.size, record.hd.ts_event);
+        self.decode(event, &record.levels, offset_of!(Mbp10Record, levels), out)
+    }
+
+    /// Shared by both record types, whose fields up to the levels are laid out alike.
+    fn decode(
+        &mut self,
+        (action, side, px, size, ts_event): (u8, u8, i64, u32, u64),
+        levels: &[BidAskPair],
+        levels_at: usize,
+        out: &mut Vec<Update>,
+    ) -> Result<(), ParseError> {
+        let side = side_code(side, offset_of!(Mbp10Record, side))?;
+        match action {
+            b'A' | b'C' | b'M' | b'T' | b'F' | b'N' => {}
+            b'R' => {
+                for side in [Side::Bid, Side::Ask] {
+                    out.extend(self.visible[side as usize].drain(..).map(|(price, _, _)| Update::Remove { price, side }));
+                }
+                return Ok(());
+            }
+            _ => return Err(ParseError { offset: offset_of!(Mbp10Record, action), reason: "unknown action" }),
+        }
+
+        // Convert everything before emitting anything, so an error leaves `out` untouched.
+        let mut fresh: [Vec<(Price, Quantity, u32)>; 2] = [Vec::with_capacity(levels.len()), Vec::with_capacity(levels.len())];
+        for (i, level) in levels.iter().enumerate() {
+            let at = levels_at + i * size_of::<BidAskPair>();
+            for (side, px, sz, ct, field) in [
+                (Side::Bid, level.bid_px, level.bid_sz, level.bid_ct, offset_of!(BidAskPair, bid_px)),
+                (Side::Ask, level.ask_px, level.ask_sz, level.ask_ct, offset_of!(BidAskPair, ask_px)),
+            ] {
+                if sz > 0 && px != UNDEF_PRICE {
+                    fresh[side as usize].push((book_price(px, self.price_scale, at + field)?, sz as Quantity, ct));
+                }
+            }
+        }
+        let trade = match (action, side) {
+            (b'T', Some(aggressor)) => {
+                let price = book_price(px, self.price_scale, offset_of!(Mbp10Record, price))?;
+                Some(Update::Trade { price, quantity: size as Quantity, aggressor, timestamp: ts_event })
+            }
+            _ => None,
+        };
+
+        out.extend(trade);
+        for side in [Side::Bid, Side::Ask] {
+            let (old, new) = (&self.visible[side as usize], &fresh[side as usize]);
+            let worst = (new.len() == levels.len()).then(|| new[new.len() - 1].0);
+            for &(price, _, _) in old {
+                let inside = match (worst, side) {
+                    (None, _) => true,
+                    (Some(worst), Side::Bid) => price > worst,
+                    (Some(worst), Side::Ask) => price < worst,
+                };
+                if inside && !new.iter().any(|&(p, _, _)| p == price) {
+                    out.push(Update::Remove { price, side });
+                }
+            }
+            for &(price, quantity, order_count) in new {
+                if !old.contains(&(price, quantity, order_count)) {
+                    out.push(Update::SetWithCount { price, quantity, order_count, side });
+                }
+            }
+        }
+        self.visible = fresh;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::{OrderBookRead, OrderBookWrite};
+    use crate::mbp::RecordMeta;
+    use crate::orderbook::OrderBookImpl;
+
+    // ES-style 0.25 tick: book price 20_001 is 5000.25.
+    const SCALE: i64 = 250_000_000;
+
+    fn mbo(order_id: u64, action: u8, side: u8, px: i64, size: u32) -> MboRecord {
+        MboRecord {
+            hd: RecordHeader { length: 14, rtype: 0xA0, publisher_id: 1, instrument_id: 7, ts_event: 42 },
+            order_id,
+            price: px,
+            size,
+            flags: 0,
+            channel_id: 0,
+            action,
+            side,
+            ts_recv: 43,
+            ts_in_delta: 0,
+            sequence: 0,
+        }
+    }
+
+    #[test]
+    fn layouts_match_the_schema() {
+        assert_eq!(size_of::<MboRecord>(), 56);
+        assert_eq!(offset_of!(MboRecord, action), 38);
+        assert_eq!(size_of::<Mbp1Record>(), 80);
+        assert_eq!(offset_of!(Mbp1Record, levels), offset_of!(Mbp10Record, levels));
+    }
+
+    #[test]
+    fn mbo_actions_map_to_level_updates() {
+        let mut decoder = MboDecoder::new(SCALE);
+        let mut out = Vec::new();
+        let records = [
+            mbo(1, b'A', b'B', 5_000_250_000_000, 10),
+            mbo(2, b'A', b'B', 5_000_250_000_000, 5),
+            mbo(3, b'A', b'A', 5_000_500_000_000, 8),
+            mbo(1, b'C', b'B', 5_000_250_000_000, 4),
+            mbo(2, b'M', b'B', 5_000_000_000_000, 5),
+            mbo(0, b'T', b'A', 5_000_250_000_000, 3),
+            mbo(1, b'F', b'B', 5_000_250_000_000, 3),
+            mbo(9, b'C', b'A', 5_000_500_000_000, 1),
+        ];
+        for record in &records {
+            decoder.decode(record, &mut out).unwrap();
+        }
+        let set = |price, quantity, order_count, side| Update::SetWithCount { price, quantity, order_count, side };
+        let expected = [
+            set(20_001, 10, 1, Side::Bid),
+            set(20_001, 15, 2, Side::Bid),
+            set(20_002, 8, 1, Side::Ask),
+            set(20_001, 11, 2, Side::Bid),
+            set(20_001, 6, 1, Side::Bid),
+            set(20_000, 5, 1, Side::Bid),
+            Update::Trade { price: 20_001, quantity: 3, aggressor: Side::Ask, timestamp: 42 },
+        ];
+        assert_eq!(out, expected);
+        assert_eq!(decoder.order_count(), 3);
+
+        out.clear();
+        decoder.decode(&mbo(0, b'R', b'N', UNDEF_PRICE, 0), &mut out).unwrap();
+        assert_eq!(out.len(), 3);
+        assert_eq!(decoder.order_count(), 0);
+    }
+
+    #[test]
+    fn mbo_rejects_bad_records_with_field_offsets() {
+        let mut decoder = MboDecoder::new(SCALE);
+        let mut out = Vec::new();
+        let err = decoder.decode(&mbo(1, b'X', b'B', 0, 1), &mut out).unwrap_err();
+        assert_eq!(err, ParseError { offset: 38, reason: "unknown action" });
+        let err = decoder.decode(&mbo(1, b'A', b'B', 5_000_100_000_000, 1), &mut out).unwrap_err();
+        assert_eq!(err, ParseError { offset: 24, reason: "price not on the tick grid" });
+        assert_eq!(decoder.decode(&mbo(1, b'A', b'Q', 0, 1), &mut out).unwrap_err().offset, 39);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn mbp10_records_round_trip_through_a_book() {
+        let meta = RecordMeta { price_scale: SCALE, ..RecordMeta::default() };
+        let mut source = OrderBookImpl::new();
+        let mut replica = OrderBookImpl::new();
+        let mut decoder = MbpDecoder::new(SCALE);
+        let mut out = Vec::new();
+        let mut sync = |source: &OrderBookImpl, replica: &mut OrderBookImpl| {
+            out.clear();
+            decoder.decode_mbp10(&source.to_mbp10(&meta), &mut out).unwrap();
+            for update in out.drain(..) {
+                replica.apply_update(update);
+            }
+            assert_eq!(replica.get_top_levels(Side::Bid, 10), source.get_top_levels(Side::Bid, 10));
+            assert_eq!(replica.get_top_levels(Side::Ask, 10), source.get_top_levels(Side::Ask, 10));
+        };
+
+        for i in 0..12 {
+            source.apply_update(Update::Set { price: 9990 - i, quantity: 1 + i as u64, side: Side::Bid });
+            source.apply_update(Update::Set { price: 10010 + i, quantity: 2, side: Side::Ask });
+        }
+        sync(&source, &mut replica);
+        source.apply_update(Update::Remove { price: 9985, side: Side::Bid });
+        source.apply_update(Update::Set { price: 10010, quantity: 9, side: Side::Ask });
+        sync(&source, &mut replica);
+        source.apply_update(Update::Set { price: 9995, quantity: 1, side: Side::Bid });
+        sync(&source, &mut replica);
+    }
+
+    #[test]
+    fn mbp1_trade_and_touch() {
+        let mut decoder = MbpDecoder::new(SCALE);
+        let mut out = Vec::new();
+        let record = Mbp1Record {
+            hd: RecordHeader { length: 20, rtype: 0x01, publisher_id: 1, instrument_id: 7, ts_event: 99 },
+            price: 5_000_250_000_000,
+            size: 2,
+            action: b'T',
+            side: b'B',
+            flags: 0,
+            depth: 0,
+            ts_recv: 100,
+            ts_in_delta: 0,
+            sequence: 1,
+            levels: [BidAskPair { bid_px: 5_000_000_000_000, ask_px: 5_000_250_000_000, bid_sz: 4, ask_sz: 6, bid_ct: 1, ask_ct: 2 }],
+        };
+        decoder.decode_mbp1(&record, &mut out).unwrap();
+        let expected = [
+            Update::Trade { price: 20_001, quantity: 2, aggressor: Side::Bid, timestamp: 99 },
+            Update::SetWithCount { price: 20_000, quantity: 4, order_count: 1, side: Side::Bid },
+            Update::SetWithCount { price: 20_001, quantity: 6, order_count: 2, side: Side::Ask },
+        ];
+        assert_eq!(out, expected);
+
+        // The ask level moves up: the old touch is gone (the side is within depth 1 of it).
+        let mut next = record;
+        next.action = b'C';
+        next.levels[0].ask_px = 5_000_500_000_000;
+        out.clear();
+        decoder.decode_mbp1(&next, &mut out).unwrap();
+        let expected = [
+            Update::Remove { price: 20_001, side: Side::Ask },
+            Update::SetWithCount { price: 20_002, quantity: 6, order_count: 2, side: Side::Ask },
+        ];
+        assert_eq!(out, expected);
+
+        // Only the order count changes: the level is set again.
+        let mut joined = next;
+        joined.levels[0].bid_ct = 3;
+        out.clear();
+        decoder.decode_mbp1(&joined, &mut out).unwrap();
+        assert_eq!(out, [Update::SetWithCount { price: 20_000, quantity: 4, order_count: 3, side: Side::Bid }]);
+    }
+}
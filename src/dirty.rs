@@ -0,0 +1,62 @@
+// dirty.rs
+
+use crate::interfaces::Side;
+use crate::orderbook::CAP;
+
+/// Ring slots changed since the last `OrderBookImpl::take_dirty_levels`, deduplicated by a
+/// per-side bitmap. Holds at most `limit` slots; one more and it gives up and only remembers
+/// that everything must be refreshed.
+#[derive(Debug, Clone)]
+pub(crate) struct DirtyLevels {
+    limit: usize,
+    slots: Vec<(Side, usize)>,
+    marked: [[u64; CAP / 64]; 2],
+    overflowed: bool,
+}
+
+impl DirtyLevels {
+    pub(crate) fn new(limit: usize) -> Self {
+        DirtyLevels { limit, slots: Vec::with_capacity(limit.min(CAP * 2)), marked: [[0; CAP / 64]; 2], overflowed: false }
+    }
+
+    #[inline(always)]
+    pub(crate) fn mark(&mut self, side: Side, index: usize) {
+        if self.overflowed {
+            return;
+        }
+        let word = &mut self.marked[side as usize][index / 64];
+        let bit = 1u64 << (index % 64);
+        if *word & bit != 0 {
+            return;
+        }
+        if self.slots.len() == self.limit {
+            self.overflow();
+            return;
+        }
+        *word |= bit;
+        self.slots.push((side, index));
+    }
+
+    /// Every level may have changed (a clear or recenter): the next poll needs a full refresh.
+    pub(crate) fn overflow(&mut self) {
+        self.overflowed = true;
+        self.slots.clear();
+        self.marked = [[0; CAP / 64]; 2];
+    }
+
+    pub(crate) fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    pub(crate) fn slots(&self) -> &[(Side, usize)] {
+        &self.slots
+    }
+
+    pub(crate) fn reset(&mut self) {
+        for &(side, index) in &self.slots {
+            self.marked[side as usize][index / 64] = 0;
+        }
+        self.slots.clear();
+        self.overflowed = false;
+    }
+}
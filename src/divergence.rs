@@ -0,0 +1,145 @@
+// divergence.rs
+
+use std::fmt;
+use crate::interfaces::{OrderBookRead, Price, Quantity, Side};
+use crate::orderbook::OrderBookImpl;
+
+/// Where two books disagree on one side, restricted to each book's top-N levels.
+/// "left" is the book `compare` was called on, "right" is the other one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SideDivergence {
+    pub side: Side,
+    pub only_in_left: Vec<(Price, Quantity)>,
+    pub only_in_right: Vec<(Price, Quantity)>,
+    /// (price, left quantity, right quantity)
+    pub quantity_mismatches: Vec<(Price, Quantity, Quantity)>,
+    /// (left best, right best) when they differ
+    pub best_mismatch: Option<(Option<Price>, Option<Price>)>,
+    /// (left total, right total) when they differ
+    pub total_mismatch: Option<(Quantity, Quantity)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DivergenceReport {
+    pub bids: SideDivergence,
+    pub asks: SideDivergence,
+}
+
+impl SideDivergence {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_left.is_empty()
+            && self.only_in_right.is_empty()
+            && self.quantity_mismatches.is_empty()
+            && self.best_mismatch.is_none()
+            && self.total_mismatch.is_none()
+    }
+}
+
+impl DivergenceReport {
+    /// True when the books agree on everything that was compared.
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+}
+
+/// Compares two books of any implementation through the trait's read methods.
+pub fn compare(left: &impl OrderBookRead, right: &impl OrderBookRead, top_n: usize) -> DivergenceReport {
+    DivergenceReport {
+        bids: compare_side(left, right, Side::Bid, top_n),
+        asks: compare_side(left, right, Side::Ask, top_n),
+    }
+}
+
+/// Signed change in the quantity resting at `price` on `side` from `before` to `after` (a level
+/// absent from a book counts as 0), for attributing flow to adds versus cancels. Saturates at
+/// the `i64` range.
+pub fn level_delta(before: &impl OrderBookRead, after: &impl OrderBookRead, side: Side, price: Price) -> i64 {
+    let old = before.get_quantity_at(price, side).unwrap_or(0) as i128;
+    let new = after.get_quantity_at(price, side).unwrap_or(0) as i128;
+    (new - old).clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+fn compare_side(left: &impl OrderBookRead, right: &impl OrderBookRead, side: Side, top_n: usize) -> SideDivergence {
+    let mut report = SideDivergence {
+        side,
+        only_in_left: Vec::new(),
+        only_in_right: Vec::new(),
+        quantity_mismatches: Vec::new(),
+        best_mismatch: None,
+        total_mismatch: None,
+    };
+
+    let left_levels = left.get_top_levels(side, top_n);
+    let right_levels = right.get_top_levels(side, top_n);
+
+    for &(price, qty) in &left_levels {
+        match right.get_quantity_at(price, side) {
+            None => report.only_in_left.push((price, qty)),
+            Some(other) if other != qty => report.quantity_mismatches.push((price, qty, other)),
+            Some(_) => {}
+        }
+    }
+    for &(price, qty) in &right_levels {
+        match left.get_quantity_at(price, side) {
+            None => report.only_in_right.push((price, qty)),
+            // Only report mismatches the left scan could not see (level outside left's top-N).
+            Some(other) if other != qty && !left_levels.iter().any(|(p, _)| *p == price) => {
+                report.quantity_mismatches.push((price, other, qty))
+            }
+            Some(_) => {}
+        }
+    }
+
+    let (left_best, right_best) = match side {
+        Side::Bid => (left.get_best_bid(), right.get_best_bid()),
+        Side::Ask => (left.get_best_ask(), right.get_best_ask()),
+    };
+    if left_best != right_best {
+        report.best_mismatch = Some((left_best, right_best));
+    }
+    let (left_total, right_total) = (left.get_total_quantity(side), right.get_total_quantity(side));
+    if left_total != right_total {
+        report.total_mismatch = Some((left_total, right_total));
+    }
+    report
+}
+
+impl OrderBookImpl {
+    /// Divergence between this book and `other` over the top `top_n` levels per side.
+    pub fn compare(&self, other: &impl OrderBookRead, top_n: usize) -> DivergenceReport {
+        compare(self, other, top_n)
+    }
+}
+
+impl fmt::Display for SideDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.side { Side::Bid => "bids", Side::Ask => "asks" };
+        if self.is_empty() {
+            return writeln!(f, "  {}: in sync", name);
+        }
+        writeln!(f, "  {}:", name)?;
+        if let Some((l, r)) = self.best_mismatch {
+            writeln!(f, "    best:  left={:?} right={:?}", l, r)?;
+        }
+        if let Some((l, r)) = self.total_mismatch {
+            writeln!(f, "    total: left={} right={}", l, r)?;
+        }
+        for (price, qty) in &self.only_in_left {
+            writeln!(f, "    {} only in left  (qty {})", price, qty)?;
+        }
+        for (price, qty) in &self.only_in_right {
+            writeln!(f, "    {} only in right (qty {})", price, qty)?;
+        }
+        for (price, l, r) in &self.quantity_mismatches {
+            writeln!(f, "    {} qty left={} right={}", price, l, r)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DivergenceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Book divergence:")?;
+        write!(f, "{}{}", self.bids, self.asks)
+    }
+}
@@ -0,0 +1,89 @@
+// error.rs
+
+use std::fmt;
+use crate::interfaces::{Price, Quantity, Side};
+use crate::filter::FilterViolation;
+use crate::orderbook::CrossedUpdate;
+
+/// Crate-wide error for the fallible APIs (`TryOrderBook` and friends).
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderBookError {
+    /// The price cannot be represented in the book's current window around `anchor`.
+    OutOfWindow { price: Price, anchor: Price },
+    /// A sequenced message arrived out of order.
+    SequenceGap { expected: u64, received: u64 },
+    /// Applying the update would overflow a quantity total.
+    Overflow { side: Side, total: Quantity, added: Quantity },
+    /// Raw input could not be decoded into an update.
+    Decode { offset: usize, reason: &'static str },
+    /// A new level refused because the side already holds `max_levels` levels.
+    LevelLimit { side: Side, max_levels: usize },
+    /// A crossing update refused under `CrossPolicy::Reject`.
+    Crossed(CrossedUpdate),
+    /// The query needs a level on `side`, which has none.
+    EmptySide { side: Side },
+    /// The query is meaningless on a crossed book (best bid above best ask).
+    CrossedBook { bid: Price, ask: Price },
+    /// Refused by the book's `UpdateFilter` as a likely garbled update.
+    Implausible(FilterViolation),
+    /// A removal refused because it would widen the spread past `OrderBookBuilder::max_spread_ticks`.
+    SpreadTooWide { spread: Price, max: Price },
+    /// Levels from `low` to `high` don't fit in one window (at most `max` ticks apart).
+    SpanTooWide { low: Price, high: Price, max: u64 },
+    /// A level given with no quantity where only occupied levels are accepted.
+    ZeroQuantity { side: Side, price: Price },
+}
+
+impl fmt::Display for OrderBookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderBookError::OutOfWindow { price, anchor } => {
+                write!(f, "price {} is outside the book window around anchor {}", price, anchor)
+            }
+            OrderBookError::SequenceGap { expected, received } => {
+                write!(f, "sequence gap: expected {}, received {}", expected, received)
+            }
+            OrderBookError::Overflow { side, total, added } => {
+                write!(f, "{:?} total {} overflows when adding {}", side, total, added)
+            }
+            OrderBookError::LevelLimit { side, max_levels } => {
+                write!(f, "{:?} side already holds the maximum of {} levels", side, max_levels)
+            }
+            OrderBookError::Decode { offset, reason } => write!(f, "decode error at byte {}: {}", offset, reason),
+            OrderBookError::Crossed(crossed) => write!(f, "rejected crossing update: {}", crossed),
+            OrderBookError::EmptySide { side } => write!(f, "{:?} side is empty", side),
+            OrderBookError::CrossedBook { bid, ask } => write!(f, "book is crossed: bid {} above ask {}", bid, ask),
+            OrderBookError::Implausible(violation) => write!(f, "implausible update: {}", violation),
+            OrderBookError::SpreadTooWide { spread, max } => {
+                write!(f, "update would widen the spread to {} ticks, above the limit {}", spread, max)
+            }
+            OrderBookError::SpanTooWide { low, high, max } => {
+                write!(f, "levels {} to {} span more than the window's {} ticks", low, high, max)
+            }
+            OrderBookError::ZeroQuantity { side, price } => write!(f, "{:?} level {} has zero quantity", side, price),
+        }
+    }
+}
+
+impl std::error::Error for OrderBookError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OrderBookError::Crossed(crossed) => Some(crossed),
+            OrderBookError::Implausible(violation) => Some(violation),
+            _ => None,
+        }
+    }
+}
+
+impl From<CrossedUpdate> for OrderBookError {
+    fn from(crossed: CrossedUpdate) -> Self {
+        OrderBookError::Crossed(crossed)
+    }
+}
+
+impl From<FilterViolation> for OrderBookError {
+    fn from(violation: FilterViolation) -> Self {
+        OrderBookError::Implausible(violation)
+    }
+}
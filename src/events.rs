@@ -0,0 +1,248 @@
+// events.rs
+
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use futures_core::Stream;
+use crate::analytics::DepthSnapshot;
+use crate::interfaces::{Bbo, OrderBookWrite, Update};
+use crate::orderbook::OrderBookImpl;
+use crate::publisher::{DepthPublisher, DepthSink};
+
+/// Single-value mailbox shared by the publisher and one stream: a newer value overwrites an
+/// unread one, so a slow consumer costs one value of memory, never a queue.
+struct Slot<T> {
+    latest: Option<T>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+type Shared<T> = Arc<Mutex<Slot<T>>>;
+
+fn publish<T>(slot: &Shared<T>, value: T) {
+    let mut slot = slot.lock().unwrap();
+    slot.latest = Some(value);
+    if let Some(waker) = slot.waker.take() {
+        waker.wake();
+    }
+}
+
+fn close<T>(slot: &Shared<T>) {
+    let mut slot = slot.lock().unwrap();
+    slot.closed = true;
+    if let Some(waker) = slot.waker.take() {
+        waker.wake();
+    }
+}
+
+/// A depth stream's end of the `DepthPublisher`.
+struct DepthSlot(Shared<DepthSnapshot>);
+
+impl DepthSink for DepthSlot {
+    fn publish(&mut self, snapshot: &DepthSnapshot) {
+        publish(&self.0, snapshot.clone());
+    }
+}
+
+/// Wraps a book and pushes its changes to async consumers.
+///
+/// Every mutation goes through `BookEvents`, which publishes as part of the update itself (BBO
+/// changes via the book's `on_bbo_change` callback, depth right after the update); streams never
+/// poll the book. Backpressure is coalesce-to-latest: each stream holds at most one pending
+/// value, overwritten by newer ones, so a consumer that falls behind sees the latest state and
+/// skips the intermediate ones. Streams end once the `BookEvents` is dropped and the pending
+/// value (if any) has been read. Reads go through `Deref`.
+pub struct BookEvents {
+    book: OrderBookImpl,
+    bbo_subscribers: Arc<Mutex<Vec<Shared<Bbo>>>>,
+    depth_subscribers: Vec<DepthPublisher<DepthSlot>>,
+}
+
+impl BookEvents {
+    /// Takes over `book`'s BBO callback slot (any callback set before is replaced).
+    pub fn new(mut book: OrderBookImpl) -> Self {
+        let bbo_subscribers: Arc<Mutex<Vec<Shared<Bbo>>>> = Arc::default();
+        let subscribers = Arc::clone(&bbo_subscribers);
+        book.on_bbo_change(move |bbo| {
+            let Some(bbo) = bbo else { return };
+            let mut subscribers = subscribers.lock().unwrap();
+            subscribers.retain(|slot| Arc::strong_count(slot) > 1);
+            for slot in subscribers.iter() {
+                publish(slot, bbo);
+            }
+        });
+        BookEvents { book, bbo_subscribers, depth_subscribers: Vec::new() }
+    }
+
+    /// Yields the BBO each time the touch (prices or quantities) changes. Moments when a side
+    /// is empty have no BBO and are not reported.
+    pub fn bbo_stream(&self) -> BboStream {
+        let slot = Arc::new(Mutex::new(Slot { latest: None, waker: None, closed: false }));
+        self.bbo_subscribers.lock().unwrap().push(Arc::clone(&slot));
+        BboStream { slot }
+    }
+
+    /// Yields the top `depth` levels per side whenever they change, at most once per
+    /// `min_interval` of book time. A change inside the interval is held back and delivered by
+    /// the first update (or `advance_clock`) after the interval has passed; see `DepthPublisher`.
+    pub fn depth_stream(&mut self, depth: usize, min_interval: Duration) -> DepthStream {
+        let slot = Arc::new(Mutex::new(Slot { latest: None, waker: None, closed: false }));
+        let min_interval = min_interval.as_nanos().min(u64::MAX as u128) as u64;
+        self.depth_subscribers.push(DepthPublisher::new(depth, min_interval, DepthSlot(Arc::clone(&slot))));
+        DepthStream { slot }
+    }
+
+    pub fn apply_update(&mut self, update: Update) {
+        self.book.apply_update(update);
+        self.publish_depth();
+    }
+
+    /// Like `OrderBookImpl::apply_update_at`; `timestamp` (nanoseconds) drives the depth throttle.
+    pub fn apply_update_at(&mut self, timestamp: u64, update: Update) {
+        self.book.apply_update_at(timestamp, update);
+        self.publish_depth();
+    }
+
+    /// Moves the clock forward without an update, releasing depth snapshots held back by the throttle.
+    pub fn advance_clock(&mut self, timestamp: u64) {
+        self.book.set_clock(timestamp);
+        self.publish_depth();
+    }
+
+    pub fn into_inner(mut self) -> OrderBookImpl {
+        self.book.remove_bbo_callback();
+        std::mem::take(&mut self.book)
+    }
+
+    fn publish_depth(&mut self) {
+        let now = self.book.now();
+        self.depth_subscribers.retain(|sub| Arc::strong_count(&sub.sink().0) > 1);
+        for sub in &mut self.depth_subscribers {
+            sub.maybe_publish(&self.book, now);
+        }
+    }
+}
+
+impl Deref for BookEvents {
+    type Target = OrderBookImpl;
+
+    fn deref(&self) -> &OrderBookImpl {
+        &self.book
+    }
+}
+
+impl Drop for BookEvents {
+    fn drop(&mut self) {
+        for slot in self.bbo_subscribers.lock().unwrap().iter() {
+            close(slot);
+        }
+        for sub in &self.depth_subscribers {
+            close(&sub.sink().0);
+        }
+    }
+}
+
+fn poll_slot<T>(slot: &Shared<T>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+    let mut slot = slot.lock().unwrap();
+    if let Some(value) = slot.latest.take() {
+        Poll::Ready(Some(value))
+    } else if slot.closed {
+        Poll::Ready(None)
+    } else {
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// See `BookEvents::bbo_stream`.
+pub struct BboStream {
+    slot: Shared<Bbo>,
+}
+
+impl Stream for BboStream {
+    type Item = Bbo;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bbo>> {
+        poll_slot(&self.slot, cx)
+    }
+}
+
+/// See `BookEvents::depth_stream`.
+pub struct DepthStream {
+    slot: Shared<DepthSnapshot>,
+}
+
+impl Stream for DepthStream {
+    type Item = DepthSnapshot;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<DepthSnapshot>> {
+        poll_slot(&self.slot, cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::Side;
+    use crate::testing::set;
+
+    fn poll<S: Stream + Unpin>(stream: &mut S) -> Poll<Option<S::Item>> {
+        Pin::new(stream).poll_next(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn slow_bbo_consumer_sees_only_the_latest_touch() {
+        let mut events = BookEvents::new(OrderBookImpl::new());
+        let mut stream = events.bbo_stream();
+        events.apply_update(Update::Set { price: 10010, quantity: 5, side: Side::Ask });
+        assert!(poll(&mut stream).is_pending(), "no BBO while the bid side is empty");
+
+        for price in 9900..10000 {
+            events.apply_update(set(price, 1, Side::Bid));
+        }
+        let Poll::Ready(Some(bbo)) = poll(&mut stream) else { panic!("expected a BBO") };
+        assert_eq!(bbo, events.get_bbo().unwrap());
+        assert_eq!(bbo.bid_price, 9999);
+        assert!(poll(&mut stream).is_pending(), "intermediate touches are coalesced away");
+
+        // Changes below the touch do not wake BBO consumers.
+        events.apply_update(set(9000, 1, Side::Bid));
+        assert!(poll(&mut stream).is_pending());
+    }
+
+    #[test]
+    fn depth_stream_is_throttled_and_coalesced() {
+        let mut events = BookEvents::new(OrderBookImpl::new());
+        let mut stream = events.depth_stream(2, Duration::from_nanos(100));
+
+        events.apply_update_at(0, set(9990, 1, Side::Bid));
+        let Poll::Ready(Some(first)) = poll(&mut stream) else { panic!() };
+        assert_eq!(first.bids, vec![(9990, 1)]);
+
+        // Inside the interval: held back, then delivered as one coalesced snapshot.
+        events.apply_update_at(10, set(9991, 2, Side::Bid));
+        events.apply_update_at(20, set(9992, 3, Side::Bid));
+        assert!(poll(&mut stream).is_pending());
+        events.advance_clock(100);
+        let Poll::Ready(Some(second)) = poll(&mut stream) else { panic!() };
+        assert_eq!((second.timestamp, second.bids), (100, vec![(9992, 3), (9991, 2)]));
+
+        // Beyond the top 2 nothing changes, so nothing is sent.
+        events.apply_update_at(300, set(9980, 1, Side::Bid));
+        assert!(poll(&mut stream).is_pending());
+    }
+
+    #[test]
+    fn streams_end_when_the_book_goes_away() {
+        let mut events = BookEvents::new(OrderBookImpl::new());
+        let mut bbo = events.bbo_stream();
+        let mut depth = events.depth_stream(1, Duration::ZERO);
+        events.apply_update(set(9990, 1, Side::Bid));
+        drop(events);
+        assert!(matches!(poll(&mut depth), Poll::Ready(Some(_))));
+        assert!(matches!(poll(&mut depth), Poll::Ready(None)));
+        assert!(matches!(poll(&mut bbo), Poll::Ready(None)));
+    }
+}
@@ -0,0 +1,198 @@
+// feed.rs
+
+use std::fmt;
+use crate::error::OrderBookError;
+use crate::interfaces::{OrderBookWrite, Side, Update};
+use crate::orderbook::OrderBookImpl;
+
+/// Input that could not be decoded. `offset` is relative to the slice handed to the parser
+/// (`replay_from_bytes` rebases it onto the whole buffer).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "parse error at byte {}: {}", self.offset, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for OrderBookError {
+    fn from(err: ParseError) -> Self {
+        OrderBookError::Decode { offset: err.offset, reason: err.reason }
+    }
+}
+
+/// Decodes one wire format into `Update`s, one message at a time, straight from borrowed bytes.
+pub trait FeedParser {
+    /// Decodes the message at the start of `data` (never empty) and returns it with the number
+    /// of bytes it occupied (at least 1).
+    fn parse(&mut self, data: &[u8]) -> Result<(Update, usize), ParseError>;
+}
+
+/// Fixed 26-byte little-endian records, the crate's own log format:
+///
+/// | bytes  | field                                           |
+/// |--------|-------------------------------------------------|
+/// | 0      | kind: 0 = Set, 1 = Remove, 2 = Trade, 3 = SetWithCount, 4 = SetLevels |
+/// | 1      | side (aggressor for trades): 0 = Bid, 1 = Ask          |
+/// | 2..10  | price, `i64` (ignored for SetLevels)                   |
+/// | 10..18 | quantity, `u64` (ignored for Remove); SetLevels: number of levels |
+/// | 18..26 | Trade: timestamp, `u64`; SetWithCount: order count, `u64` (low 32 bits); else ignored |
+///
+/// A SetLevels record is followed by its levels, 16 bytes each: price `i64`, quantity `u64`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BinaryFeedParser;
+
+impl BinaryFeedParser {
+    pub const RECORD_LEN: usize = 26;
+
+    /// Appends `update` in this format.
+    pub fn encode(update: &Update, out: &mut Vec<u8>) {
+        let (kind, side, price, quantity, timestamp) = match *update {
+            Update::SetLevels { side, ref levels } => (4, side, 0, levels.len() as u64, 0),
+            Update::Set { price, quantity, side } => (0u8, side, price, quantity, 0),
+            Update::Remove { price, side } => (1, side, price, 0, 0),
+            Update::Trade { price, quantity, aggressor, timestamp } => (2, aggressor, price, quantity, timestamp),
+            Update::SetWithCount { price, quantity, order_count, side } => (3, side, price, quantity, order_count as u64),
+        };
+        out.push(kind);
+        out.push(side as u8);
+        out.extend_from_slice(&price.to_le_bytes());
+        out.extend_from_slice(&quantity.to_le_bytes());
+        out.extend_from_slice(&timestamp.to_le_bytes());
+        if let Update::SetLevels { levels, .. } = update {
+            for &(price, quantity) in levels {
+                out.extend_from_slice(&price.to_le_bytes());
+                out.extend_from_slice(&quantity.to_le_bytes());
+            }
+        }
+    }
+}
+
+impl FeedParser for BinaryFeedParser {
+    fn parse(&mut self, data: &[u8]) -> Result<(Update, usize), ParseError> {
+        let Some(record) = data.get(..Self::RECORD_LEN) else {
+            return Err(ParseError { offset: data.len(), reason: "truncated record" });
+        };
+        let side = match record[1] {
+            0 => Side::Bid,
+            1 => Side::Ask,
+            _ => return Err(ParseError { offset: 1, reason: "invalid side" }),
+        };
+        let word = |at: usize| <[u8; 8]>::try_from(&record[at..at + 8]).unwrap();
+        let price = i64::from_le_bytes(word(2));
+        let quantity = u64::from_le_bytes(word(10));
+        let update = match record[0] {
+            0 => Update::Set { price, quantity, side },
+            1 => Update::Remove { price, side },
+            2 => Update::Trade { price, quantity, aggressor: side, timestamp: u64::from_le_bytes(word(18)) },
+            3 => Update::SetWithCount { price, quantity, order_count: u64::from_le_bytes(word(18)) as u32, side },
+            4 => {
+                let len = usize::try_from(quantity).ok().and_then(|count| count.checked_mul(16)).and_then(|len| len.checked_add(Self::RECORD_LEN));
+                let Some(body) = len.and_then(|len| data.get(Self::RECORD_LEN..len)) else {
+                    return Err(ParseError { offset: data.len(), reason: "truncated record" });
+                };
+                let word = |at: usize| <[u8; 8]>::try_from(&body[at..at + 8]).unwrap();
+                let levels = (0..body.len()).step_by(16).map(|at| (i64::from_le_bytes(word(at)), u64::from_le_bytes(word(at + 8)))).collect();
+                return Ok((Update::SetLevels { side, levels }, Self::RECORD_LEN + body.len()));
+            }
+            _ => return Err(ParseError { offset: 0, reason: "unknown message kind" }),
+        };
+        Ok((update, Self::RECORD_LEN))
+    }
+}
+
+impl OrderBookImpl {
+    /// Decodes every message in `data` with `parser` and applies it; returns how many were applied.
+    ///
+    /// Nothing is allocated per message but the level list of a `SetLevels` longer than a
+    /// `BulkLevels` holds inline, so `data` can be a memory-mapped log. On a parse error
+    /// the messages before it stay applied and the error's offset points into `data`.
+    pub fn replay_from_bytes(&mut self, data: &[u8], parser: &mut impl FeedParser) -> Result<usize, ParseError> {
+        let mut offset = 0;
+        let mut applied = 0;
+        while offset < data.len() {
+            let (update, consumed) = parser
+                .parse(&data[offset..])
+                .map_err(|err| ParseError { offset: offset + err.offset, reason: err.reason })?;
+            self.apply_update(update);
+            applied += 1;
+            offset += consumed.max(1);
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smallvec::smallvec;
+    use crate::interfaces::{BulkLevels, OrderBookRead};
+
+    #[test]
+    fn replays_an_encoded_log() {
+        let mut log = Vec::new();
+        for update in [
+            Update::Set { price: 9990, quantity: 10, side: Side::Bid },
+            Update::Set { price: 9995, quantity: 20, side: Side::Bid },
+            Update::Set { price: 10005, quantity: 30, side: Side::Ask },
+            Update::Trade { price: 10005, quantity: 5, aggressor: Side::Bid, timestamp: 7 },
+            Update::SetWithCount { price: 10005, quantity: 25, order_count: 3, side: Side::Ask },
+            Update::Remove { price: 9995, side: Side::Bid },
+        ] {
+            BinaryFeedParser::encode(&update, &mut log);
+        }
+
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.replay_from_bytes(&log, &mut BinaryFeedParser), Ok(6));
+        assert_eq!(ob.get_best_bid(), Some(9990));
+        assert_eq!(ob.get_quantity_at(10005, Side::Ask), Some(25));
+        assert_eq!(ob.get_quantity_at(9995, Side::Bid), None);
+        assert_eq!(ob.get_total_quantity(Side::Bid), 10);
+
+        let (update, _) = BinaryFeedParser.parse(&log[4 * BinaryFeedParser::RECORD_LEN..]).unwrap();
+        assert_eq!(update, Update::SetWithCount { price: 10005, quantity: 25, order_count: 3, side: Side::Ask });
+    }
+
+    #[test]
+    fn set_levels_records_carry_their_levels() {
+        let mut log = Vec::new();
+        let levels: BulkLevels = smallvec![(9990, 10), (9995, 20), (9990, 0)];
+        BinaryFeedParser::encode(&Update::SetLevels { side: Side::Bid, levels: levels.clone() }, &mut log);
+        BinaryFeedParser::encode(&Update::Set { price: 10005, quantity: 30, side: Side::Ask }, &mut log);
+        assert_eq!(log.len(), 2 * BinaryFeedParser::RECORD_LEN + 3 * 16);
+
+        let (update, consumed) = BinaryFeedParser.parse(&log).unwrap();
+        assert_eq!(consumed, BinaryFeedParser::RECORD_LEN + 3 * 16);
+        assert_eq!(update, Update::SetLevels { side: Side::Bid, levels });
+
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.replay_from_bytes(&log, &mut BinaryFeedParser), Ok(2));
+        assert_eq!(ob.get_top_levels(Side::Bid, 5), vec![(9995, 20)]);
+        assert_eq!(ob.get_best_ask(), Some(10005));
+
+        // A level count running past the end of the data.
+        let err = BinaryFeedParser.parse(&log[..BinaryFeedParser::RECORD_LEN + 40]).unwrap_err();
+        assert_eq!(err.reason, "truncated record");
+    }
+
+    #[test]
+    fn reports_the_offset_of_a_bad_record() {
+        let mut log = Vec::new();
+        BinaryFeedParser::encode(&Update::Set { price: 9990, quantity: 10, side: Side::Bid }, &mut log);
+        BinaryFeedParser::encode(&Update::Set { price: 9991, quantity: 10, side: Side::Bid }, &mut log);
+        log[BinaryFeedParser::RECORD_LEN + 1] = 9;
+        log.extend_from_slice(&[0; 3]);
+
+        let mut ob = OrderBookImpl::new();
+        let err = ob.replay_from_bytes(&log, &mut BinaryFeedParser).unwrap_err();
+        assert_eq!(err, ParseError { offset: BinaryFeedParser::RECORD_LEN + 1, reason: "invalid side" });
+        assert_eq!(ob.get_best_bid(), Some(9990));
+        assert_eq!(OrderBookError::from(err), OrderBookError::Decode { offset: 27, reason: "invalid side" });
+    }
+}
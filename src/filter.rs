@@ -0,0 +1,123 @@
+// filter.rs
+
+use std::fmt;
+use crate::interfaces::{Price, Quantity, Side, Update};
+
+/// Plausibility limits for incoming updates, checked by `try_apply_update` once installed with
+/// `OrderBookImpl::set_update_filter`. Catches garbled packets (a 9e18 quantity, a price a
+/// million ticks out) before they poison totals for the rest of the session. Limits are set
+/// chainably; `UpdateFilter::default()` has none.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateFilter {
+    max_quantity: Option<Quantity>,
+    max_mid_distance: Option<u64>,
+    max_total_change: Option<Quantity>,
+    rejected: FilterRejections,
+}
+
+/// Updates refused so far, per limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FilterRejections {
+    pub quantity: u64,
+    pub mid_distance: u64,
+    pub total_change: u64,
+}
+
+/// Which limit an update broke. Carried by `OrderBookError::Implausible`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterViolation {
+    Quantity { quantity: Quantity, max: Quantity },
+    /// `mid_x2` is twice the mid, so half-tick mids stay exact.
+    MidDistance { price: Price, mid_x2: i128, max: u64 },
+    TotalChange { side: Side, change: Quantity, max: Quantity },
+}
+
+impl fmt::Display for FilterViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterViolation::Quantity { quantity, max } => write!(f, "quantity {} above the limit {}", quantity, max),
+            FilterViolation::MidDistance { price, mid_x2, max } => {
+                write!(f, "price {} more than {} ticks from the mid {}", price, max, *mid_x2 as f64 / 2.0)
+            }
+            FilterViolation::TotalChange { side, change, max } => {
+                write!(f, "{:?} level change {} above the limit {}", side, change, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FilterViolation {}
+
+impl UpdateFilter {
+    /// Largest quantity a `Set` or `Trade` may carry.
+    pub fn max_quantity(mut self, max: Quantity) -> Self {
+        self.max_quantity = Some(max);
+        self
+    }
+
+    /// Furthest a `Set` or `Trade` price may be from the mid, in ticks. Not checked while the
+    /// book has no mid (either side empty).
+    pub fn max_mid_distance(mut self, ticks: u64) -> Self {
+        self.max_mid_distance = Some(ticks);
+        self
+    }
+
+    /// Largest change one `Set` or `Remove` may make to its level (and so to the side's total).
+    pub fn max_total_change(mut self, max: Quantity) -> Self {
+        self.max_total_change = Some(max);
+        self
+    }
+
+    pub fn rejections(&self) -> FilterRejections {
+        self.rejected
+    }
+
+    /// Checks `update` (already snapped to ticks) against the limits, counting a rejection.
+    /// `mid_x2` is twice the current mid, `resting` the quantity now at the update's level.
+    pub(crate) fn check(&mut self, update: &Update, mid_x2: Option<i128>, resting: Quantity) -> Result<(), FilterViolation> {
+        let result = self.violation(update, mid_x2, resting);
+        match result {
+            Err(FilterViolation::Quantity { .. }) => self.rejected.quantity += 1,
+            Err(FilterViolation::MidDistance { .. }) => self.rejected.mid_distance += 1,
+            Err(FilterViolation::TotalChange { .. }) => self.rejected.total_change += 1,
+            Ok(()) => {}
+        }
+        result
+    }
+
+    fn violation(&self, update: &Update, mid_x2: Option<i128>, resting: Quantity) -> Result<(), FilterViolation> {
+        let (price, quantity, level) = match *update {
+            Update::Set { price, quantity, side } | Update::SetWithCount { price, quantity, side, .. } => {
+                (price, quantity, Some(side))
+            }
+            Update::Remove { side, .. } => {
+                return self.check_change(side, resting, 0);
+            }
+            Update::Trade { price, quantity, .. } => (price, quantity, None),
+            // `try_apply_update` checks a bulk update's levels one by one, as `Set`s.
+            Update::SetLevels { .. } => return Ok(()),
+        };
+        if let Some(max) = self.max_quantity
+            && quantity > max
+        {
+            return Err(FilterViolation::Quantity { quantity, max });
+        }
+        if let (Some(max), Some(mid_x2)) = (self.max_mid_distance, mid_x2)
+            && (2 * price as i128 - mid_x2).unsigned_abs() > 2 * max as u128
+        {
+            return Err(FilterViolation::MidDistance { price, mid_x2, max });
+        }
+        match level {
+            Some(side) => self.check_change(side, resting, quantity),
+            None => Ok(()),
+        }
+    }
+
+    fn check_change(&self, side: Side, resting: Quantity, quantity: Quantity) -> Result<(), FilterViolation> {
+        let change = resting.abs_diff(quantity);
+        match self.max_total_change {
+            Some(max) if change > max => Err(FilterViolation::TotalChange { side, change, max }),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// fork.rs
+
+use crate::interfaces::{OrderBookRead, Price, Quantity, Side, Update};
+use crate::orderbook::OrderBookImpl;
+
+/// Copy-on-write view over an `OrderBookImpl` for what-if analysis.
+///
+/// The parent's arrays are borrowed immutably; every modification goes into a small overlay
+/// of `(side, price, quantity)` entries that the read accessors consult before the parent.
+/// Forks are meant to be short-lived (a handful of modifications) and are never written back.
+/// Prices are expected to fall inside the parent's window.
+pub struct BookFork<'a> {
+    parent: &'a OrderBookImpl,
+    overlay: Vec<(Side, Price, Quantity)>,
+    totals: [Quantity; 2],
+}
+
+impl OrderBookImpl {
+    /// Cheap fork sharing this book's arrays; see `BookFork`.
+    pub fn fork(&self) -> BookFork<'_> {
+        BookFork {
+            parent: self,
+            overlay: Vec::new(),
+            totals: [self.get_total_quantity(Side::Bid), self.get_total_quantity(Side::Ask)],
+        }
+    }
+}
+
+impl<'a> BookFork<'a> {
+    pub fn apply_update(&mut self, update: Update) {
+        let (price, quantity, side) = match update {
+            Update::Set { price, quantity, side } | Update::SetWithCount { price, quantity, side, .. } => (price, quantity, side),
+            Update::Remove { price, side } => (price, 0, side),
+            Update::Trade { .. } => return,
+            Update::SetLevels { side, levels } => {
+                for (price, quantity) in levels {
+                    self.apply_update(Update::Set { price, quantity, side });
+                }
+                return;
+            }
+        };
+        let old = self.get_quantity_at(price, side).unwrap_or(0);
+        let total = &mut self.totals[side as usize];
+        *total = total.wrapping_sub(old).wrapping_add(quantity);
+
+        match self.overlay.iter_mut().find(|(s, p, _)| *s == side && *p == price) {
+            Some(entry) => entry.2 = quantity,
+            None => self.overlay.push((side, price, quantity)),
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_quantity_at(&self, price: Price, side: Side) -> Option<Quantity> {
+        match self.overlay.iter().find(|(s, p, _)| *s == side && *p == price) {
+            Some(&(_, _, qty)) => if qty > 0 { Some(qty) } else { None },
+            None => self.parent.get_quantity_at(price, side),
+        }
+    }
+
+    pub fn get_best_bid(&self) -> Option<Price> {
+        self.levels(Side::Bid).next().map(|(price, _)| price)
+    }
+
+    pub fn get_best_ask(&self) -> Option<Price> {
+        self.levels(Side::Ask).next().map(|(price, _)| price)
+    }
+
+    pub fn get_spread(&self) -> Option<Price> {
+        Some(self.get_best_ask()?.saturating_sub(self.get_best_bid()?))
+    }
+
+    pub fn get_top_levels(&self, side: Side, n: usize) -> Vec<(Price, Quantity)> {
+        self.levels(side).take(n).collect()
+    }
+
+    #[inline(always)]
+    pub fn get_total_quantity(&self, side: Side) -> Quantity {
+        self.totals[side as usize]
+    }
+
+    /// Occupied levels best-first: the parent's levels with overlay overrides applied,
+    /// merged with overlay entries at prices the parent leaves empty.
+    pub fn levels(&self, side: Side) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        let better = move |a: Price, b: Price| match side {
+            Side::Bid => a > b,
+            Side::Ask => a < b,
+        };
+        let mut added: Vec<(Price, Quantity)> = self
+            .overlay
+            .iter()
+            .filter(|(s, p, q)| *s == side && *q > 0 && self.parent.get_quantity_at(*p, side).is_none())
+            .map(|&(_, p, q)| (p, q))
+            .collect();
+        added.sort_unstable_by(|a, b| if better(a.0, b.0) { std::cmp::Ordering::Less } else { std::cmp::Ordering::Greater });
+
+        let mut parent = self
+            .parent
+            .levels(side)
+            .filter_map(move |(price, qty)| match self.overlay.iter().find(|(s, p, _)| *s == side && *p == price) {
+                Some(&(_, _, q)) => if q > 0 { Some((price, q)) } else { None },
+                None => Some((price, qty)),
+            })
+            .peekable();
+        let mut added = added.into_iter().peekable();
+
+        std::iter::from_fn(move || match (parent.peek(), added.peek()) {
+            (Some(&(p, _)), Some(&(a, _))) => if better(a, p) { added.next() } else { parent.next() },
+            (Some(_), None) => parent.next(),
+            (None, _) => added.next(),
+        })
+    }
+}
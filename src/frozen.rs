@@ -0,0 +1,151 @@
+// frozen.rs
+
+use crate::interfaces::{OrderBookRead, Price, Quantity, Side};
+use crate::orderbook::OrderBookImpl;
+
+/// An immutable copy of a book's levels, laid out for reading: each side is one dense array of
+/// `(price, quantity)`, best first, so a price lookup is a binary search and the top `n` levels
+/// are a slice of the first `n` entries.
+///
+/// Memory is proportional to the occupied levels rather than the window, which makes a frozen
+/// book cheap to keep around and to hand to read-heavy consumers on other threads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrozenBook {
+    /// Descending price.
+    bids: Box<[(Price, Quantity)]>,
+    /// Ascending price.
+    asks: Box<[(Price, Quantity)]>,
+    totals: [Quantity; 2],
+    version: u64,
+    timestamp: u64,
+}
+
+impl OrderBookImpl {
+    /// Gives up the book for a `FrozenBook` of its current levels, version and clock.
+    pub fn freeze(self) -> FrozenBook {
+        FrozenBook {
+            bids: self.levels(Side::Bid).collect(),
+            asks: self.levels(Side::Ask).collect(),
+            totals: [self.get_total_quantity(Side::Bid), self.get_total_quantity(Side::Ask)],
+            version: self.version(),
+            timestamp: self.now(),
+        }
+    }
+}
+
+impl FrozenBook {
+    /// Every level of `side`, best first.
+    #[inline(always)]
+    pub fn levels(&self, side: Side) -> &[(Price, Quantity)] {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    /// The best `n` levels of `side` (all of them if it has fewer), without copying.
+    #[inline(always)]
+    pub fn top(&self, side: Side, n: usize) -> &[(Price, Quantity)] {
+        let levels = self.levels(side);
+        &levels[..n.min(levels.len())]
+    }
+
+    pub fn level_count(&self, side: Side) -> usize {
+        self.levels(side).len()
+    }
+
+    /// The book's version when it was frozen.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The book's clock when it was frozen.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Position of `price` in `side`'s levels (`Ok`), or where it would go (`Err`), in best
+    /// first order.
+    #[inline(always)]
+    fn search(&self, price: Price, side: Side) -> Result<usize, usize> {
+        match side {
+            Side::Bid => self.bids.binary_search_by(|&(level, _)| price.cmp(&level)),
+            Side::Ask => self.asks.binary_search_by(|&(level, _)| level.cmp(&price)),
+        }
+    }
+}
+
+impl OrderBookRead for FrozenBook {
+    fn get_spread(&self) -> Option<Price> {
+        Some(self.get_best_ask()?.saturating_sub(self.get_best_bid()?))
+    }
+
+    #[inline(always)]
+    fn get_best_bid(&self) -> Option<Price> {
+        self.bids.first().map(|&(price, _)| price)
+    }
+
+    #[inline(always)]
+    fn get_best_ask(&self) -> Option<Price> {
+        self.asks.first().map(|&(price, _)| price)
+    }
+
+    #[inline(always)]
+    fn get_quantity_at(&self, price: Price, side: Side) -> Option<Quantity> {
+        self.search(price, side).ok().map(|position| self.levels(side)[position].1)
+    }
+
+    fn get_top_levels(&self, side: Side, n: usize) -> Vec<(Price, Quantity)> {
+        self.top(side, n).to_vec()
+    }
+
+    fn get_total_quantity(&self, side: Side) -> Quantity {
+        self.totals[side as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::{OrderBookWrite, Update};
+    use crate::sim::SimRng;
+    use crate::testing::random_update;
+
+    #[test]
+    fn frozen_queries_match_the_live_book() {
+        let mut rng = SimRng::new(0x6a09_e667_f3bc_c908);
+        let mut live = OrderBookImpl::new();
+        for round in 0..2000u64 {
+            live.apply_update_at(round, random_update(&mut rng, 10000, 600));
+        }
+        let frozen = live.clone().freeze();
+
+        assert_eq!((frozen.version(), frozen.timestamp()), (live.version(), live.now()));
+        assert_eq!(frozen.get_spread(), live.get_spread());
+        assert_eq!((frozen.get_best_bid(), frozen.get_best_ask()), (live.get_best_bid(), live.get_best_ask()));
+        for side in [Side::Bid, Side::Ask] {
+            assert_eq!(frozen.level_count(side), live.level_count(side));
+            assert_eq!(frozen.get_total_quantity(side), live.get_total_quantity(side));
+            for n in [0, 1, 5, 100, 10_000] {
+                assert_eq!(frozen.get_top_levels(side, n), live.get_top_levels(side, n), "{side:?} top {n}");
+            }
+            for price in 10000 - 310..10000 + 310 {
+                assert_eq!(frozen.get_quantity_at(price, side), live.get_quantity_at(price, side), "{side:?} {price}");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_and_one_sided_books_freeze() {
+        let empty = OrderBookImpl::new().freeze();
+        assert_eq!((empty.get_best_bid(), empty.get_spread()), (None, None));
+        assert!(empty.top(Side::Ask, 3).is_empty());
+
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 9990, quantity: 4, side: Side::Bid });
+        let frozen = ob.freeze();
+        assert_eq!(frozen.get_spread(), None);
+        assert_eq!(frozen.top(Side::Bid, 3), &[(9990, 4)]);
+        assert_eq!(frozen.get_quantity_at(9991, Side::Bid), None);
+    }
+}
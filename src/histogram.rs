@@ -0,0 +1,313 @@
+// histogram.rs
+
+use crate::feed::ParseError;
+use crate::interfaces::{Bbo, Side};
+
+/// Fixed-bucket histogram of `u64` values with log-spaced buckets.
+///
+/// Values below `2^bits` each get a bucket of their own; above that, every power-of-two range
+/// is split into `2^bits` equal buckets, so a bucket is never wider than `2^-bits` of its lower
+/// bound. All buckets are allocated up front and `record` is a few shifts and an increment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    bits: u32,
+    counts: Box<[u64]>,
+    total: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    /// `bits` sub-bucket bits per power of two. Panics above 8.
+    pub fn new(bits: u32) -> Self {
+        assert!(bits <= 8, "at most 8 sub-bucket bits");
+        Histogram { bits, counts: vec![0; (65 - bits as usize) << bits].into_boxed_slice(), total: 0, min: u64::MAX, max: 0 }
+    }
+
+    #[inline(always)]
+    pub fn record(&mut self, value: u64) {
+        let index = self.bucket_of(value);
+        self.counts[index] += 1;
+        self.total += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Values recorded.
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        (self.total > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        (self.total > 0).then_some(self.max)
+    }
+
+    /// Non-empty buckets as `(lower, upper_exclusive, count)`, lowest first.
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+        self.counts.iter().enumerate().filter(|&(_, &count)| count > 0).map(|(index, &count)| {
+            let (lower, width) = self.bucket_range(index);
+            (lower, lower.saturating_add(width), count)
+        })
+    }
+
+    /// The value below which `p` percent of the recorded values fall (`p` clamped to 0..=100),
+    /// each bucket's count taken as spread evenly over its range and the result clamped to the
+    /// recorded min and max. `None` if nothing was recorded.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if self.total == 0 {
+            return None;
+        }
+        let target = p.clamp(0.0, 100.0) / 100.0 * self.total as f64;
+        let mut below = 0u64;
+        for (index, &count) in self.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            if (below + count) as f64 >= target {
+                let (lower, width) = self.bucket_range(index);
+                let fraction = (target - below as f64) / count as f64;
+                let value = lower as f64 + fraction * width as f64;
+                return Some(value.clamp(self.min as f64, self.max as f64));
+            }
+            below += count;
+        }
+        Some(self.max as f64)
+    }
+
+    pub fn reset(&mut self) {
+        self.counts.fill(0);
+        self.total = 0;
+        self.min = u64::MAX;
+        self.max = 0;
+    }
+
+    #[inline(always)]
+    fn bucket_of(&self, value: u64) -> usize {
+        let sub = 1u64 << self.bits;
+        if value < sub {
+            return value as usize;
+        }
+        let exponent = 63 - value.leading_zeros();
+        let shift = exponent - self.bits;
+        (((shift as u64 + 1) << self.bits) + ((value >> shift) - sub)) as usize
+    }
+
+    /// `(lower, width)` of bucket `index`.
+    fn bucket_range(&self, index: usize) -> (u64, u64) {
+        let sub = 1usize << self.bits;
+        if index < sub {
+            return (index as u64, 1);
+        }
+        let shift = (index >> self.bits) - 1;
+        (((sub + (index & (sub - 1))) as u64) << shift, 1 << shift)
+    }
+}
+
+/// Distribution of the spread (in ticks) and of each side's touch size, recorded by the book on
+/// every BBO change once installed with `OrderBookImpl::enable_histograms`.
+///
+/// Only two-sided books are sampled; a crossed book (negative spread) is counted in `crossed`
+/// instead of the spread histogram, though its touch sizes are recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramStats {
+    spread: Histogram,
+    touch: [Histogram; 2],
+    crossed: u64,
+}
+
+impl HistogramStats {
+    /// `bits` as in `Histogram::new`, for all three histograms.
+    pub fn new(bits: u32) -> Self {
+        HistogramStats { spread: Histogram::new(bits), touch: [Histogram::new(bits), Histogram::new(bits)], crossed: 0 }
+    }
+
+    pub fn spread(&self) -> &Histogram {
+        &self.spread
+    }
+
+    pub fn touch_size(&self, side: Side) -> &Histogram {
+        &self.touch[side as usize]
+    }
+
+    /// BBO changes seen with the book crossed.
+    pub fn crossed(&self) -> u64 {
+        self.crossed
+    }
+
+    #[inline(always)]
+    pub(crate) fn record(&mut self, bbo: &Bbo) {
+        match bbo.ask_price.checked_sub(bbo.bid_price) {
+            Some(spread) if spread >= 0 => self.spread.record(spread as u64),
+            _ => self.crossed += 1,
+        }
+        self.touch[Side::Bid as usize].record(bbo.bid_quantity);
+        self.touch[Side::Ask as usize].record(bbo.ask_quantity);
+    }
+
+    /// Count, extremes and p50 / p90 / p99 of each histogram, for end-of-day reports.
+    pub fn summary(&self) -> HistogramSummary {
+        HistogramSummary {
+            spread: SeriesSummary::of(&self.spread),
+            bid_size: SeriesSummary::of(&self.touch[Side::Bid as usize]),
+            ask_size: SeriesSummary::of(&self.touch[Side::Ask as usize]),
+            crossed: self.crossed,
+        }
+    }
+
+    /// Starts a new session.
+    pub fn reset(&mut self) {
+        self.spread.reset();
+        self.touch.iter_mut().for_each(Histogram::reset);
+        self.crossed = 0;
+    }
+}
+
+/// One histogram reduced to a few numbers; all zero when it is empty.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SeriesSummary {
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl SeriesSummary {
+    fn of(histogram: &Histogram) -> Self {
+        let percentile = |p| histogram.percentile(p).unwrap_or(0.0);
+        SeriesSummary {
+            count: histogram.count(),
+            min: histogram.min().unwrap_or(0),
+            max: histogram.max().unwrap_or(0),
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+        }
+    }
+}
+
+/// `HistogramStats::summary`, with a fixed-size binary form for storing reports.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HistogramSummary {
+    pub spread: SeriesSummary,
+    pub bid_size: SeriesSummary,
+    pub ask_size: SeriesSummary,
+    pub crossed: u64,
+}
+
+impl HistogramSummary {
+    /// Three series of six little-endian 8-byte fields (count, min, max, p50, p90, p99; the
+    /// percentiles as `f64` bits), then `crossed`.
+    pub const ENCODED_LEN: usize = 3 * 48 + 8;
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        for series in [&self.spread, &self.bid_size, &self.ask_size] {
+            for word in [series.count, series.min, series.max, series.p50.to_bits(), series.p90.to_bits(), series.p99.to_bits()] {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&self.crossed.to_le_bytes());
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, ParseError> {
+        let Some(data) = data.get(..Self::ENCODED_LEN) else {
+            return Err(ParseError { offset: data.len(), reason: "truncated summary" });
+        };
+        let word = |i: usize| u64::from_le_bytes(data[i * 8..i * 8 + 8].try_into().unwrap());
+        let series = |first: usize| SeriesSummary {
+            count: word(first),
+            min: word(first + 1),
+            max: word(first + 2),
+            p50: f64::from_bits(word(first + 3)),
+            p90: f64::from_bits(word(first + 4)),
+            p99: f64::from_bits(word(first + 5)),
+        };
+        Ok(HistogramSummary { spread: series(0), bid_size: series(6), ask_size: series(12), crossed: word(18) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_exact_then_log_spaced() {
+        let mut histogram = Histogram::new(2);
+        for value in [0, 1, 3, 4, 5, 7, 8, 9, 11, 100, u64::MAX] {
+            histogram.record(value);
+        }
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (0, 1, 1),
+                (1, 2, 1),
+                (3, 4, 1),
+                (4, 5, 1),
+                (5, 6, 1),
+                (7, 8, 1),
+                (8, 10, 2),
+                (10, 12, 1),
+                (96, 112, 1),
+                (7 << 61, u64::MAX, 1),
+            ]
+        );
+        assert_eq!(histogram.count(), 11);
+        assert_eq!((histogram.min(), histogram.max()), (Some(0), Some(u64::MAX)));
+    }
+
+    #[test]
+    fn percentiles_interpolate_within_buckets() {
+        let mut histogram = Histogram::new(1);
+        assert_eq!(histogram.percentile(50.0), None);
+        // Buckets of width 1 below 2, then [2,3) [3,4) [4,6) [6,8) [8,12) ...
+        for value in [1, 1, 4, 5, 8, 8, 8, 11] {
+            histogram.record(value);
+        }
+        // 25% = 2 of 8 values: the top of bucket [1,2).
+        assert_eq!(histogram.percentile(25.0), Some(2.0));
+        // 50% = 4 values: both of [4,6)'s, its upper edge.
+        assert_eq!(histogram.percentile(50.0), Some(6.0));
+        // 75% = 6 values: two of [8,12)'s four, halfway across it.
+        assert_eq!(histogram.percentile(75.0), Some(10.0));
+        // Clamped to the recorded extremes.
+        assert_eq!(histogram.percentile(0.0), Some(1.0));
+        assert_eq!(histogram.percentile(100.0), Some(11.0));
+
+        histogram.reset();
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(histogram.buckets().count(), 0);
+    }
+
+    #[test]
+    fn stats_record_bbos_and_summaries_round_trip() {
+        let mut stats = HistogramStats::new(3);
+        let bbo = |bid_price, bid_quantity, ask_price, ask_quantity| Bbo { bid_price, bid_quantity, ask_price, ask_quantity, version: 0 };
+        stats.record(&bbo(100, 5, 101, 7));
+        stats.record(&bbo(100, 5, 102, 9));
+        stats.record(&bbo(100, 3, 101, 9));
+        stats.record(&bbo(102, 3, 101, 9));
+        assert_eq!(stats.spread().buckets().collect::<Vec<_>>(), vec![(1, 2, 2), (2, 3, 1)]);
+        assert_eq!(stats.crossed(), 1);
+        assert_eq!(stats.touch_size(Side::Bid).count(), 4);
+        assert_eq!(stats.touch_size(Side::Ask).buckets().collect::<Vec<_>>(), vec![(7, 8, 1), (9, 10, 3)]);
+
+        let summary = stats.summary();
+        assert_eq!(summary.spread.count, 3);
+        assert_eq!((summary.spread.min, summary.spread.max), (1, 2));
+        assert_eq!(summary.bid_size.p50, 4.0);
+        let mut bytes = Vec::new();
+        summary.encode(&mut bytes);
+        assert_eq!(bytes.len(), HistogramSummary::ENCODED_LEN);
+        assert_eq!(HistogramSummary::decode(&bytes), Ok(summary));
+        assert_eq!(HistogramSummary::decode(&bytes[1..]).unwrap_err().reason, "truncated summary");
+
+        stats.reset();
+        assert_eq!(stats.summary(), HistogramSummary::default());
+    }
+}
@@ -5,6 +5,9 @@
 // The fastest implementation wins!
 // Target: Sub-nanosecond operations where possible
 
+use smallvec::SmallVec;
+use crate::error::OrderBookError;
+
 /// Price is represented as an integer where 1 unit = 10^-4
 /// Example: 12345 represents a price of 1.2345
 pub type Price = i64;
@@ -12,6 +15,10 @@ pub type Price = i64;
 /// Quantity in the orderbook
 pub type Quantity = u64;
 
+/// The levels of an `Update::SetLevels`: up to 8 (a typical depth message's worth) are held
+/// inline, so most bulk updates allocate nothing.
+pub type BulkLevels = SmallVec<[(Price, Quantity); 8]>;
+
 /// Side of the order
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Side {
@@ -20,7 +27,7 @@ pub enum Side {
 }
 
 /// Order book update operation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Update {
     /// Add or update a price level (price, quantity, side)
     /// If quantity is 0, this level should be removed
@@ -30,21 +37,59 @@ pub enum Update {
         side: Side,
     },
 
+    /// `Set` plus the number of orders resting at the level, for feeds that publish it.
+    /// Books built without the `order-counts` feature apply it as a plain `Set`.
+    SetWithCount {
+        price: Price,
+        quantity: Quantity,
+        order_count: u32,
+        side: Side,
+    },
+
     /// Remove a price level completely
     Remove { price: Price, side: Side },
+
+    /// Several levels of one side at once, as exchange depth messages group them: the same as
+    /// a `Set` per entry in order (quantity 0 removes the level), but the book fixes its best
+    /// index once at the end and notifies the BBO callback once.
+    SetLevels {
+        side: Side,
+        levels: BulkLevels,
+    },
+
+    /// A trade print. Leaves the levels alone (the feed sends those separately);
+    /// `aggressor` is the side that initiated it (Bid = buyer lifted the offer).
+    Trade {
+        price: Price,
+        quantity: Quantity,
+        aggressor: Side,
+        timestamp: u64,
+    },
 }
 
-/// The main trait that students must implement
-pub trait OrderBook: Send + Sync {
-    /// Create a new orderbook instance
-    fn new() -> Self
-    where
-        Self: Sized;
+/// Top of book: best price and the quantity resting there, on both sides
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bbo {
+    pub bid_price: Price,
+    pub bid_quantity: Quantity,
+    pub ask_price: Price,
+    pub ask_quantity: Quantity,
+    /// Book version this was read at (see `OrderBookImpl::version`).
+    pub version: u64,
+}
 
-    /// Apply an update to the orderbook
-    /// This is the HOT PATH - optimize heavily!
-    fn apply_update(&mut self, update: Update);
+impl Bbo {
+    /// Same prices and quantities, whatever the versions.
+    pub fn same_touch(&self, other: &Bbo) -> bool {
+        (self.bid_price, self.bid_quantity, self.ask_price, self.ask_quantity)
+            == (other.bid_price, other.bid_quantity, other.ask_price, other.ask_quantity)
+    }
+}
 
+/// The main traits that students must implement: `OrderBookRead` for the getters and
+/// `OrderBookWrite` for construction and updates. Code that only inspects a book (strategies,
+/// analytics) should take `OrderBookRead`, so handing it a book cannot let it mutate one.
+pub trait OrderBookRead: Send + Sync {
     /// Get the current spread (best_ask - best_bid)
     /// Returns None if either side is empty
     /// This is also HOT PATH
@@ -67,3 +112,37 @@ pub trait OrderBook: Send + Sync {
     /// Get total quantity across all levels for a side
     fn get_total_quantity(&self, side: Side) -> Quantity;
 }
+
+/// Mutation, on top of the getters. Like `OrderBookRead` this is object safe, so a
+/// `Box<dyn OrderBookWrite>` can pick its implementation at runtime; construction lives in
+/// `OrderBookFactory`.
+pub trait OrderBookWrite: OrderBookRead {
+    /// Apply an update to the orderbook
+    /// This is the HOT PATH - optimize heavily!
+    fn apply_update(&mut self, update: Update);
+}
+
+/// Construction of a default book, for generic code (`T::new()`). Separate from the book traits
+/// so they stay object safe.
+pub trait OrderBookFactory {
+    /// Create a new orderbook instance
+    fn new() -> Self
+    where
+        Self: Sized;
+}
+
+/// The trait from before the read / write split, kept so existing `T: OrderBook` bounds still
+/// compile: every `OrderBookWrite + OrderBookFactory` is an `OrderBook`. Calling methods on a concrete book needs
+/// `OrderBookRead` / `OrderBookWrite` in scope, since a subtrait does not bring those along.
+#[deprecated(note = "bound on `OrderBookRead` or `OrderBookWrite` instead")]
+pub trait OrderBook: OrderBookWrite + OrderBookFactory {}
+
+#[allow(deprecated)]
+impl<T: OrderBookWrite + OrderBookFactory + ?Sized> OrderBook for T {}
+
+/// Fallible semantics on top of `OrderBookWrite`, for generic code that must not lose
+/// updates silently.
+pub trait TryOrderBook: OrderBookWrite {
+    /// Apply an update, or explain why it cannot be applied. On `Err` the book is unchanged.
+    fn try_apply_update(&mut self, update: Update) -> Result<(), OrderBookError>;
+}
@@ -0,0 +1,72 @@
+// ladder.rs
+
+use crate::interfaces::{OrderBookRead, Price, Quantity, Side};
+use crate::orderbook::{CAP, OrderBookImpl};
+
+/// One DOM row: a single price with whatever rests on either side (zero if nothing).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LadderRow {
+    pub price: Price,
+    pub bid_qty: Quantity,
+    pub ask_qty: Quantity,
+}
+
+impl OrderBookImpl {
+    /// Fills `out` with consecutive price rows from `center + half_depth` down to
+    /// `center - half_depth`, one tick apart, empty prices included.
+    ///
+    /// Rows outside both sides' windows are left out, so near the edge `out` holds fewer than
+    /// `2 * half_depth + 1` rows. `out` is cleared first and its allocation reused.
+    pub fn get_ladder(&self, center: Price, half_depth: usize, out: &mut Vec<LadderRow>) {
+        out.clear();
+        let half = i64::try_from(half_depth).unwrap_or(i64::MAX);
+        let (lo, hi) = (center.saturating_sub(half), center.saturating_add(half));
+        // Only walk prices inside a window, so a huge `half_depth` costs at most two windows of rows.
+        let mut spans = [Side::Bid, Side::Ask].map(|side| {
+            let anchor = self.anchor(side);
+            (anchor.saturating_sub(CAP as i64 / 2).max(lo), anchor.saturating_add(CAP as i64 / 2 - 1).min(hi))
+        });
+        spans.sort_by_key(|&(_, top)| std::cmp::Reverse(top));
+        let mut next = hi;
+        for (bottom, top) in spans {
+            for price in (bottom..=top.min(next)).rev() {
+                let (bid_in, ask_in) = (self.in_window(price, Side::Bid), self.in_window(price, Side::Ask));
+                let qty = |side, inside: bool| if inside { self.get_quantity_at(price, side).unwrap_or(0) } else { 0 };
+                out.push(LadderRow { price, bid_qty: qty(Side::Bid, bid_in), ask_qty: qty(Side::Ask, ask_in) });
+            }
+            next = next.min(bottom.saturating_sub(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::{OrderBookWrite, Update};
+    use crate::orderbook::CAP;
+
+    #[test]
+    fn ladder_spans_the_spread() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 9998, quantity: 7, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9999, quantity: 3, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10002, quantity: 4, side: Side::Ask });
+
+        let mut rows = Vec::new();
+        ob.get_ladder(10000, 2, &mut rows);
+        let row = |price, bid_qty, ask_qty| LadderRow { price, bid_qty, ask_qty };
+        assert_eq!(rows, vec![row(10002, 0, 4), row(10001, 0, 0), row(10000, 0, 0), row(9999, 3, 0), row(9998, 7, 0)]);
+        assert!(rows.windows(2).all(|w| w[0].price - w[1].price == 1));
+    }
+
+    #[test]
+    fn ladder_is_clamped_to_the_window() {
+        let ob = OrderBookImpl::new();
+        let top = 10000 + CAP as i64 / 2 - 1;
+        let mut rows = vec![LadderRow { price: 0, bid_qty: 1, ask_qty: 1 }];
+        ob.get_ladder(top, 3, &mut rows);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].price, top);
+        assert_eq!(rows[3].price, top - 3);
+    }
+}
@@ -0,0 +1,48 @@
+pub mod alerts;
+pub mod analytics;
+#[cfg(feature = "rkyv")]
+pub mod archive;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod backtest;
+pub mod benchmarks;
+pub mod builder;
+pub mod checksum;
+pub mod coalesce;
+pub mod churn;
+pub mod consolidated;
+pub mod dbn;
+pub mod dirty;
+pub mod divergence;
+pub mod error;
+#[cfg(feature = "async")]
+pub mod events;
+pub mod feed;
+pub mod filter;
+pub mod fork;
+pub mod frozen;
+pub mod histogram;
+pub mod interfaces;
+pub mod ladder;
+pub mod manager;
+pub mod mbp;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod orderbook;
+pub mod persist;
+pub mod price;
+pub mod profile;
+pub mod publisher;
+#[cfg(feature = "proto")]
+pub mod proto;
+pub mod reference;
+pub mod recorder;
+pub mod sim;
+pub mod replication;
+pub mod state_ring;
+pub mod storage;
+pub mod tape;
+#[doc(hidden)]
+pub mod testing;
+pub mod timed;
+pub mod watch;
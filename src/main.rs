@@ -1,8 +1,4 @@
-use crate::{benchmarks::OrderBookBenchmark, orderbook::OrderBookImpl};
-
-mod benchmarks;
-mod interfaces;
-mod orderbook;
+use rust_3::{benchmarks::OrderBookBenchmark, orderbook::OrderBookImpl};
 
 // Objective: Complete the orderbook implementation at ./orderbook.rs and run this file to see how fast it is. Faster implementation wins !
 
@@ -16,6 +12,29 @@ fn main() {
     let result = OrderBookBenchmark::run::<OrderBookImpl>("OrderBook", 100_000);
     OrderBookBenchmark::print_results(&result);
 
+    let (fork_ns, clone_ns) = OrderBookBenchmark::run_fork_vs_clone(10_000);
+    println!("  What-if (3 modifications + reads):");
+    println!("    Fork:  {:.2} ns", fork_ns);
+    println!("    Clone: {:.2} ns", clone_ns);
+
+    let ((insert_ns, insert_set_ns), (modify_ns, modify_set_ns)) = OrderBookBenchmark::run_specialized_writes(10_000);
+    println!("  New level:      insert_new {:.2} ns, apply_update {:.2} ns", insert_ns, insert_set_ns);
+    println!("  Existing level: modify {:.2} ns, apply_update {:.2} ns", modify_ns, modify_set_ns);
+
+    let (sequential_us, parallel_us) = OrderBookBenchmark::run_manager_batch(500, 20, 50);
+    println!("  Manager batch (500 symbols x 20 updates):");
+    println!("    Sequential: {:.2} us", sequential_us);
+    println!("    Parallel:   {:.2} us", parallel_us);
+
+    #[cfg(feature = "rkyv")]
+    {
+        let (access_ns, deserialize_ns, bincode_ns) = OrderBookBenchmark::run_archive_access(50, 10_000);
+        println!("  Archived snapshot read (50 levels):");
+        println!("    Validated access: {:.2} ns", access_ns);
+        println!("    Deserialize:      {:.2} ns", deserialize_ns);
+        println!("    bincode decode:   {:.2} ns", bincode_ns);
+    }
+
     println!("\n Competition Goal: Achieve sub-nanosecond operations!");
     println!(" Tips:");
     println!("   - Use cache-friendly data structures");
@@ -31,12 +50,24 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
-    use crate::{
-        interfaces::{OrderBook, Side, Update},
-        orderbook::OrderBookImpl,
+    use std::collections::BTreeMap;
+    use smallvec::smallvec;
+    use rust_3::{
+        analytics::ImpactPoint,
+        divergence::level_delta,
+        error::OrderBookError,
+        filter::{FilterRejections, FilterViolation, UpdateFilter},
+        histogram::HistogramStats,
+        interfaces::{Bbo, BulkLevels, OrderBookFactory, OrderBookRead, OrderBookWrite, Side, TryOrderBook, Update},
+        orderbook::{AnchorMode, ApplySummary, BookState, CAP, CrossPolicy, CrossedUpdate, FUZZ_RECORD_LEN, LiquidityState, OrderBookImpl},
+        profile::ProfileBucket,
+        reference::BTreeOrderBook,
+        sim::SimRng,
+        testing::{random_price, random_side, random_update},
+        watch::{LevelEvent, MAX_WATCHES},
     };
 
-    fn test_basic_operations<T: OrderBook>() {
+    fn test_basic_operations<T: OrderBookWrite + OrderBookFactory>() {
         let mut ob = T::new();
 
         // Add bids
@@ -69,7 +100,7 @@ mod tests {
         assert_eq!(ob.get_quantity_at(10000, Side::Bid), Some(100));
     }
 
-    fn test_updates_and_removes<T: OrderBook>() {
+    fn test_updates_and_removes<T: OrderBookWrite + OrderBookFactory>() {
         let mut ob = T::new();
 
         ob.apply_update(Update::Set {
@@ -113,4 +144,2291 @@ mod tests {
         test_basic_operations::<OrderBookImpl>();
         test_updates_and_removes::<OrderBookImpl>();
     }
+
+    #[test]
+    fn test_reference_implementation() {
+        test_basic_operations::<BTreeOrderBook>();
+        test_updates_and_removes::<BTreeOrderBook>();
+    }
+
+    #[test]
+    fn test_raw_side_index_maps_back_to_price() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 10005, quantity: 70, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9990, quantity: 30, side: Side::Bid });
+
+        let (slots, anchor) = ob.raw_side(Side::Bid);
+        let mut found: Vec<(i64, u64)> = slots
+            .iter()
+            .enumerate()
+            .filter(|(_, q)| **q > 0)
+            .map(|(i, q)| {
+                let offset = i as i64;
+                let price = if offset >= (CAP / 2) as i64 { anchor + offset - CAP as i64 } else { anchor + offset };
+                (price, *q)
+            })
+            .collect();
+        found.sort();
+
+        assert_eq!(found, vec![(9990, 30), (10005, 70)]);
+        assert!(ob.raw_side(Side::Ask).0.iter().all(|q| *q == 0));
+    }
+
+    #[test]
+    fn test_undo_last_restores_previous_state() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 10000, quantity: 100, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10010, quantity: 50, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10020, quantity: 40, side: Side::Ask });
+
+        // Removing the best bid forces a rescan; undo must bring the old best back without one.
+        ob.apply_update(Update::Remove { price: 10010, side: Side::Bid });
+        assert_eq!(ob.get_best_bid(), Some(10000));
+        assert!(ob.undo_last());
+        assert_eq!(ob.get_best_bid(), Some(10010));
+        assert_eq!(ob.get_quantity_at(10010, Side::Bid), Some(50));
+        assert_eq!(ob.get_total_quantity(Side::Bid), 150);
+
+        // Only one level of undo.
+        assert!(!ob.undo_last());
+        assert_eq!(ob.get_best_bid(), Some(10010));
+
+        ob.apply_update(Update::Set { price: 10020, quantity: 90, side: Side::Ask });
+        assert!(ob.undo_last());
+        assert_eq!(ob.get_quantity_at(10020, Side::Ask), Some(40));
+        assert_eq!(ob.get_total_quantity(Side::Ask), 40);
+    }
+
+    #[test]
+    fn test_nothing_to_undo_after_a_refused_update() {
+        let dropped_by_cap = |ob: &mut OrderBookImpl| {
+            ob.apply_update(Update::Set { price: 9990, quantity: 50, side: Side::Bid });
+            ob.apply_update(Update::Set { price: 9995, quantity: 10, side: Side::Bid });
+        };
+        let crossing = |ob: &mut OrderBookImpl| {
+            ob.apply_update(Update::Set { price: 9970, quantity: 50, side: Side::Bid });
+            ob.apply_update(Update::Set { price: 10020, quantity: 10, side: Side::Bid });
+        };
+        let blowing_the_spread = |ob: &mut OrderBookImpl| {
+            ob.apply_update(Update::Set { price: 9990, quantity: 50, side: Side::Bid });
+            ob.apply_update(Update::Remove { price: 10000, side: Side::Bid });
+        };
+        let configs: [fn() -> OrderBookImpl; 3] = [
+            || OrderBookImpl::builder().max_levels(2).build(),
+            || OrderBookImpl::with_cross_policy(CrossPolicy::Reject),
+            || OrderBookImpl::builder().max_spread_ticks(5).build(),
+        ];
+        let refusals: [fn(&mut OrderBookImpl); 3] = [dropped_by_cap, crossing, blowing_the_spread];
+        for (make, refused) in configs.into_iter().zip(refusals) {
+            let mut ob = make();
+            ob.apply_update(Update::Set { price: 10000, quantity: 100, side: Side::Bid });
+            ob.apply_update(Update::Set { price: 10002, quantity: 100, side: Side::Ask });
+            refused(&mut ob);
+            let levels = ob.get_top_levels(Side::Bid, 5);
+            // The update before the refused one stays applied.
+            assert!(!ob.undo_last());
+            assert_eq!(ob.get_top_levels(Side::Bid, 5), levels);
+        }
+    }
+
+    #[test]
+    fn test_incremental_checksum_matches_full_recompute() {
+        let mut ob = OrderBookImpl::new();
+        ob.enable_checksum(5);
+        for i in 0..40i64 {
+            let side = if i % 2 == 0 { Side::Bid } else { Side::Ask };
+            let price = if side == Side::Bid { 9990 - (i * 7) % 30 } else { 10010 + (i * 11) % 30 };
+            ob.apply_update(Update::Set { price, quantity: 10 + i as u64, side });
+            if i % 5 == 0 {
+                ob.apply_update(Update::Remove { price, side });
+            }
+            assert_eq!(ob.current_checksum(), ob.top_levels_checksum(5));
+        }
+
+        // Levels straddling the anchor, and clearing the best repeatedly.
+        ob.apply_update(Update::Set { price: 10002, quantity: 5, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10004, quantity: 5, side: Side::Ask });
+        for price in [10002, 9990, 9989, 9988] {
+            ob.apply_update(Update::Remove { price, side: Side::Bid });
+            assert_eq!(ob.current_checksum(), ob.top_levels_checksum(5));
+        }
+        assert!(ob.undo_last());
+        assert_eq!(ob.current_checksum(), ob.top_levels_checksum(5));
+        assert_ne!(ob.current_checksum(), ob.top_levels_checksum(4));
+    }
+
+    #[test]
+    fn test_fork_reads_match_cloned_book() {
+        let mut ob = OrderBookImpl::new();
+        for i in 0..20i64 {
+            ob.apply_update(Update::Set { price: 9995 - i * 3, quantity: 10 + i as u64, side: Side::Bid });
+            ob.apply_update(Update::Set { price: 10005 + i * 3, quantity: 20 + i as u64, side: Side::Ask });
+        }
+        let what_if = [
+            Update::Remove { price: 9995, side: Side::Bid },
+            Update::Set { price: 9993, quantity: 7, side: Side::Bid },
+            Update::Set { price: 10001, quantity: 3, side: Side::Ask },
+            Update::Set { price: 10008, quantity: 0, side: Side::Ask },
+            Update::Set { price: 9992, quantity: 99, side: Side::Bid },
+        ];
+
+        let mut fork = ob.fork();
+        let mut copy = ob.clone();
+        for update in what_if {
+            fork.apply_update(update.clone());
+            copy.apply_update(update);
+            assert_eq!(fork.get_best_bid(), copy.get_best_bid());
+            assert_eq!(fork.get_best_ask(), copy.get_best_ask());
+            assert_eq!(fork.get_spread(), copy.get_spread());
+            for side in [Side::Bid, Side::Ask] {
+                assert_eq!(fork.get_top_levels(side, 30), copy.get_top_levels(side, 30));
+                assert_eq!(fork.get_total_quantity(side), copy.get_total_quantity(side));
+            }
+        }
+        for price in 9930..10070 {
+            assert_eq!(fork.get_quantity_at(price, Side::Bid), copy.get_quantity_at(price, Side::Bid));
+            assert_eq!(fork.get_quantity_at(price, Side::Ask), copy.get_quantity_at(price, Side::Ask));
+        }
+
+        // The parent is untouched.
+        assert_eq!(ob.get_best_bid(), Some(9995));
+        assert_eq!(ob.get_quantity_at(10001, Side::Ask), None);
+    }
+
+    #[test]
+    fn test_nth_level() {
+        let mut ob = OrderBookImpl::new();
+        // Spread the bids across the anchor and over several bitmap words.
+        for (i, price) in [10130, 10003, 9999, 9900, 9700].into_iter().enumerate() {
+            ob.apply_update(Update::Set { price, quantity: 10 * (i as u64 + 1), side: Side::Bid });
+        }
+        ob.apply_update(Update::Set { price: 10200, quantity: 5, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 10600, quantity: 6, side: Side::Ask });
+
+        assert_eq!(ob.nth_level(Side::Bid, 0), Some((10130, 10)));
+        assert_eq!(ob.nth_level(Side::Bid, 2), Some((9999, 30)));
+        assert_eq!(ob.nth_level(Side::Bid, 4), Some((9700, 50)));
+        assert_eq!(ob.nth_level(Side::Bid, 5), None);
+        assert_eq!(ob.nth_level(Side::Ask, 1), Some((10600, 6)));
+        assert_eq!(ob.nth_level(Side::Ask, 2), None);
+
+        ob.apply_update(Update::Remove { price: 10003, side: Side::Bid });
+        assert_eq!(ob.nth_level(Side::Bid, 1), Some((9999, 30)));
+        for n in 0..4 {
+            assert_eq!(ob.nth_level(Side::Bid, n), ob.get_top_levels(Side::Bid, 10).get(n).copied());
+        }
+    }
+
+    #[test]
+    fn test_divergence_report() {
+        let mut primary = OrderBookImpl::new();
+        let mut backup = BTreeOrderBook::new();
+        for i in 0..10i64 {
+            for update in [
+                Update::Set { price: 9990 - i, quantity: 100 + i as u64, side: Side::Bid },
+                Update::Set { price: 10010 + i, quantity: 200 + i as u64, side: Side::Ask },
+            ] {
+                primary.apply_update(update.clone());
+                backup.apply_update(update);
+            }
+        }
+        assert!(primary.compare(&backup, 5).is_empty());
+
+        // Seed: a missed remove of the best bid, a stale ask quantity, and an extra deep ask.
+        primary.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        backup.apply_update(Update::Set { price: 10011, quantity: 7, side: Side::Ask });
+        backup.apply_update(Update::Set { price: 10040, quantity: 1, side: Side::Ask });
+
+        let report = primary.compare(&backup, 5);
+        assert_eq!(report.bids.only_in_right, vec![(9990, 100)]);
+        assert!(report.bids.only_in_left.is_empty());
+        assert_eq!(report.bids.best_mismatch, Some((Some(9989), Some(9990))));
+        assert_eq!(report.bids.total_mismatch, Some((1045 - 100, 1045)));
+        assert_eq!(report.asks.quantity_mismatches, vec![(10011, 201, 7)]);
+        assert_eq!(report.asks.best_mismatch, None);
+        // 10040 is beyond both books' top 5, so only the totals reveal it.
+        assert!(report.asks.only_in_right.is_empty());
+        assert_eq!(report.asks.total_mismatch, Some((2045, 2045 - 201 + 7 + 1)));
+
+        let text = report.to_string();
+        assert!(text.contains("9990 only in right"));
+        assert!(text.contains("10011 qty left=201 right=7"));
+    }
+
+    #[test]
+    fn test_divergence_report_on_overflowing_totals() {
+        let mut primary = OrderBookImpl::new();
+        let mut backup = BTreeOrderBook::new();
+        for update in [
+            Update::Set { price: 10010, quantity: u64::MAX, side: Side::Ask },
+            Update::Set { price: 10011, quantity: 3, side: Side::Ask },
+        ] {
+            primary.apply_update(update.clone());
+            backup.apply_update(update);
+        }
+        // Both totals wrap the same way, so nothing diverges (and nothing panics).
+        assert_eq!(backup.get_total_quantity(Side::Ask), 2);
+        assert_eq!(primary.get_total_quantity(Side::Ask), 2);
+        assert!(primary.compare(&backup, 5).is_empty());
+    }
+
+    #[test]
+    fn test_level_delta() {
+        let mut before = OrderBookImpl::new();
+        for (price, quantity) in [(9990, 50), (9995, 30), (9998, 10)] {
+            before.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        before.apply_update(Update::Set { price: 10002, quantity: 40, side: Side::Ask });
+        let mut after = before.clone();
+        after.apply_update(Update::Set { price: 9990, quantity: 75, side: Side::Bid });
+        after.apply_update(Update::Set { price: 9995, quantity: 12, side: Side::Bid });
+        after.apply_update(Update::Remove { price: 9998, side: Side::Bid });
+        after.apply_update(Update::Set { price: 9999, quantity: 8, side: Side::Bid });
+
+        assert_eq!(level_delta(&before, &after, Side::Bid, 9990), 25);
+        assert_eq!(level_delta(&before, &after, Side::Bid, 9995), -18);
+        assert_eq!(level_delta(&before, &after, Side::Bid, 9998), -10);
+        assert_eq!(level_delta(&before, &after, Side::Bid, 9999), 8);
+        assert_eq!(level_delta(&after, &before, Side::Bid, 9999), -8);
+        // Untouched, empty in both, and the same price on the other side.
+        assert_eq!(level_delta(&before, &after, Side::Ask, 10002), 0);
+        assert_eq!(level_delta(&before, &after, Side::Bid, 9980), 0);
+        assert_eq!(level_delta(&before, &after, Side::Ask, 9990), 0);
+
+        // Any pair of read implementations works, and huge swings saturate.
+        let mut reference = BTreeOrderBook::new();
+        reference.apply_update(Update::Set { price: 10002, quantity: u64::MAX, side: Side::Ask });
+        assert_eq!(level_delta(&before, &reference, Side::Ask, 10002), i64::MAX);
+        assert_eq!(level_delta(&reference, &before, Side::Ask, 10002), i64::MIN);
+    }
+
+    fn seed_two_sided(ob: &mut OrderBookImpl) {
+        for (price, side) in [(9990, Side::Bid), (9995, Side::Bid), (10005, Side::Ask), (10010, Side::Ask), (10020, Side::Ask)] {
+            ob.apply_update(Update::Set { price, quantity: 100, side });
+        }
+    }
+
+    #[test]
+    fn test_crossing_set_rejected() {
+        let mut ob = OrderBookImpl::with_cross_policy(CrossPolicy::Reject);
+        seed_two_sided(&mut ob);
+
+        let crossing = Update::Set { price: 10005, quantity: 50, side: Side::Bid };
+        assert_eq!(
+            ob.try_apply_update(crossing.clone()),
+            Err(OrderBookError::Crossed(CrossedUpdate { price: 10005, side: Side::Bid, opposite_best: 10005 }))
+        );
+        ob.apply_update(crossing);
+        assert_eq!(ob.get_best_bid(), Some(9995));
+        assert_eq!(ob.get_quantity_at(10005, Side::Bid), None);
+
+        // Non-crossing updates and removals still go through.
+        assert_eq!(ob.try_apply_update(Update::Set { price: 10004, quantity: 5, side: Side::Bid }), Ok(()));
+        assert_eq!(ob.try_apply_update(Update::Set { price: 10004, quantity: 0, side: Side::Bid }), Ok(()));
+        assert_eq!(ob.get_best_bid(), Some(9995));
+    }
+
+    #[test]
+    fn test_crossing_set_auto_resolved() {
+        let mut ob = OrderBookImpl::with_cross_policy(CrossPolicy::AutoResolve);
+        seed_two_sided(&mut ob);
+
+        ob.apply_update(Update::Set { price: 10010, quantity: 70, side: Side::Bid });
+        assert_eq!(ob.get_best_bid(), Some(10010));
+        assert_eq!(ob.get_best_ask(), Some(10020));
+        assert_eq!(ob.get_quantity_at(10005, Side::Ask), None);
+        assert_eq!(ob.get_total_quantity(Side::Ask), 100);
+
+        ob.apply_update(Update::Set { price: 9990, quantity: 10, side: Side::Ask });
+        assert_eq!(ob.get_best_bid(), None);
+        assert_eq!(ob.get_best_ask(), Some(9990));
+        assert_eq!(ob.get_spread(), None);
+    }
+
+    #[test]
+    fn test_volume_profile_alignment() {
+        let mut ob = OrderBookImpl::new();
+        // Anchor is 10000: bids straddle it, asks sit above, one hole band in between.
+        ob.apply_update(Update::Set { price: 9993, quantity: 4, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9999, quantity: 6, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10001, quantity: 1, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10019, quantity: 3, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 10021, quantity: 2, side: Side::Ask });
+
+        let bucket = |low, bid_quantity, ask_quantity| ProfileBucket { low, high: low + 9, bid_quantity, ask_quantity };
+        assert_eq!(
+            ob.volume_profile(10),
+            vec![bucket(9990, 10, 0), bucket(10000, 1, 0), bucket(10010, 0, 3), bucket(10020, 0, 2)]
+        );
+
+        // Bands are pinned to the anchor, not to the book's edges.
+        ob.apply_update(Update::Remove { price: 9993, side: Side::Bid });
+        assert_eq!(ob.volume_profile(10)[0], bucket(9990, 6, 0));
+        assert_eq!(ob.volume_profile(7)[0], ProfileBucket { low: 9993, high: 9999, bid_quantity: 6, ask_quantity: 0 });
+
+        assert!(OrderBookImpl::new().volume_profile(10).is_empty());
+        assert!(ob.volume_profile(0).is_empty());
+    }
+
+    #[test]
+    fn test_volume_profile_window_edges() {
+        let mut ob = OrderBookImpl::new();
+        let low_edge = 10000 - CAP as i64 / 2;
+        let high_edge = 10000 + CAP as i64 / 2 - 1;
+        ob.apply_update(Update::Set { price: low_edge, quantity: 5, side: Side::Bid });
+        ob.apply_update(Update::Set { price: high_edge, quantity: 8, side: Side::Ask });
+
+        let profile = ob.volume_profile(1024);
+        assert_eq!(profile.len(), 4);
+        assert_eq!(profile.first(), Some(&ProfileBucket { low: 10000 - 2048, high: 10000 - 1025, bid_quantity: 5, ask_quantity: 0 }));
+        assert_eq!(profile.last(), Some(&ProfileBucket { low: 10000 + 1024, high: 10000 + 2047, bid_quantity: 0, ask_quantity: 8 }));
+        assert!(profile[1..3].iter().all(|b| b.bid_quantity == 0 && b.ask_quantity == 0));
+    }
+
+    #[test]
+    fn test_spread_capture_round_trips() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 9998, quantity: 100, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10002, quantity: 100, side: Side::Ask });
+
+        // Bought on the bid, sold on the ask: earn the 4-tick spread on 25 lots.
+        assert!(ob.round_trip_achievable(9998, 10002));
+        assert_eq!(ob.spread_capture(9998, 10002, 25), 100);
+
+        // Lifted the ask and hit the bid: pay the spread.
+        assert!(ob.round_trip_achievable(10002, 9998));
+        assert_eq!(ob.spread_capture(10002, 9998, 25), -100);
+
+        // A sell above the offer could not have filled.
+        assert!(!ob.round_trip_achievable(9998, 10010));
+        assert_eq!(ob.spread_capture(9998, 10010, 1), 12);
+    }
+
+    #[test]
+    fn test_hot_layout_is_cache_line_aligned() {
+        let ob = OrderBookImpl::new();
+        assert_eq!(std::mem::align_of::<OrderBookImpl>(), 64);
+        let base = &ob as *const OrderBookImpl as usize;
+        // Line 0 holds the written scalars, line 1 the config; the arrays start on line 2.
+        let (bids, _) = ob.raw_side(Side::Bid);
+        let (asks, _) = ob.raw_side(Side::Ask);
+        assert_eq!((bids.as_ptr() as usize - base), 128);
+        assert_eq!(asks.as_ptr() as usize % 64, 0);
+    }
+
+    #[test]
+    fn test_stats_match_individual_accessors() {
+        let mut ob = OrderBookImpl::new();
+        for (price, quantity, side) in [(9990, 30, Side::Bid), (9995, 20, Side::Bid), (10003, 10, Side::Ask), (10004, 40, Side::Ask), (10009, 50, Side::Ask)] {
+            ob.apply_update(Update::Set { price, quantity, side });
+        }
+        let stats = ob.stats();
+        assert_eq!(stats.best_bid, ob.get_best_bid());
+        assert_eq!(stats.best_ask, ob.get_best_ask());
+        assert_eq!(stats.spread, ob.get_spread());
+        assert_eq!(stats.mid, Some(9999.0));
+        assert_eq!(stats.total_bid_quantity, ob.get_total_quantity(Side::Bid));
+        assert_eq!(stats.total_ask_quantity, ob.get_total_quantity(Side::Ask));
+        assert_eq!(stats.bid_levels, ob.get_top_levels(Side::Bid, CAP).len());
+        assert_eq!(stats.ask_levels, 3);
+        assert_eq!(stats.imbalance, Some((50.0 - 100.0) / 150.0));
+        assert_eq!(stats.to_string(), "bid 9995 x 50 (2 lvls) | ask 10003 x 100 (3 lvls) | spread 8 | imbalance -0.333");
+
+        let empty = OrderBookImpl::new().stats();
+        assert_eq!((empty.mid, empty.imbalance, empty.bid_levels), (None, None, 0));
+    }
+
+    #[test]
+    fn test_tick_divisor_snaps_sub_tick_prices() {
+        // Feed quotes in 1/100 of a tick.
+        let mut ob = OrderBookImpl::with_tick_divisor(100);
+        ob.apply_update(Update::Set { price: 1_000_000, quantity: 5, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 1_000_037, quantity: 7, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 1_000_099, quantity: 9, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 1_000_100, quantity: 4, side: Side::Ask });
+
+        // All three bids land on tick 10000; the last write wins, as for any Set.
+        assert_eq!(ob.get_top_levels(Side::Bid, 10), vec![(10000, 9)]);
+        assert_eq!(ob.get_best_ask(), Some(10001));
+        assert_eq!(ob.get_spread(), Some(1));
+
+        ob.apply_update(Update::Remove { price: 1_000_050, side: Side::Bid });
+        assert_eq!(ob.get_best_bid(), None);
+
+        // Half a tick rounds down, not to nearest.
+        let mut half = OrderBookImpl::with_tick_divisor(10);
+        half.apply_update(Update::Set { price: 99_995, quantity: 1, side: Side::Ask });
+        assert_eq!(half.get_best_ask(), Some(9999));
+    }
+
+    #[test]
+    fn test_levels_by_distance_from_mid() {
+        let mut ob = OrderBookImpl::new();
+        // Mid is 10000: bids at 1, 3 and 6 ticks away, asks at 2, 3 and 4.
+        for (price, side) in [(9999, Side::Bid), (9997, Side::Bid), (9994, Side::Bid), (10002, Side::Ask), (10003, Side::Ask), (10004, Side::Ask)] {
+            ob.apply_update(Update::Set { price, quantity: 1, side });
+        }
+        ob.apply_update(Update::Remove { price: 10002, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 10001, quantity: 1, side: Side::Ask });
+
+        let order: Vec<(Side, i64, u32)> = ob.levels_by_distance().map(|(side, price, _, dist)| (side, price, dist)).collect();
+        assert_eq!(
+            order,
+            vec![
+                (Side::Bid, 9999, 1),
+                (Side::Ask, 10001, 1),
+                (Side::Bid, 9997, 3),
+                (Side::Ask, 10003, 3),
+                (Side::Ask, 10004, 4),
+                (Side::Bid, 9994, 6),
+            ]
+        );
+        assert!(order.windows(2).all(|w| w[0].2 <= w[1].2));
+        assert_eq!(OrderBookImpl::new().levels_by_distance().count(), 0);
+    }
+
+    fn test_fallible_overflow<T: TryOrderBook + OrderBookFactory>() {
+        let mut ob = T::new();
+        assert_eq!(ob.try_apply_update(Update::Set { price: 10000, quantity: u64::MAX - 5, side: Side::Ask }), Ok(()));
+        assert_eq!(
+            ob.try_apply_update(Update::Set { price: 10001, quantity: 10, side: Side::Ask }),
+            Err(OrderBookError::Overflow { side: Side::Ask, total: u64::MAX - 5, added: 10 })
+        );
+        // Replacing the same level is not an overflow.
+        assert_eq!(ob.try_apply_update(Update::Set { price: 10000, quantity: u64::MAX, side: Side::Ask }), Ok(()));
+        assert_eq!(ob.get_total_quantity(Side::Ask), u64::MAX);
+    }
+
+    #[test]
+    fn test_try_order_book_errors() {
+        test_fallible_overflow::<OrderBookImpl>();
+        test_fallible_overflow::<BTreeOrderBook>();
+
+        let mut ob = OrderBookImpl::new();
+        let err = ob.try_apply_update(Update::Set { price: 20000, quantity: 1, side: Side::Bid }).unwrap_err();
+        assert_eq!(err, OrderBookError::OutOfWindow { price: 20000, anchor: 10000 });
+        assert_eq!(err.to_string(), "price 20000 is outside the book window around anchor 10000");
+        assert_eq!(ob.get_best_bid(), None);
+
+        let crossed: OrderBookError = CrossedUpdate { price: 1, side: Side::Ask, opposite_best: 2 }.into();
+        assert!(std::error::Error::source(&crossed).is_some());
+    }
+
+    #[test]
+    fn test_apply_update_bbo() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.apply_update_bbo(Update::Set { price: 9999, quantity: 10, side: Side::Bid }), None);
+        let bbo = ob.apply_update_bbo(Update::Set { price: 10001, quantity: 20, side: Side::Ask });
+        assert_eq!(bbo, Some(Bbo { bid_price: 9999, bid_quantity: 10, ask_price: 10001, ask_quantity: 20, version: 2 }));
+        assert_eq!(bbo, ob.get_bbo());
+
+        let bbo = ob.apply_update_bbo(Update::Set { price: 10000, quantity: 5, side: Side::Bid });
+        assert_eq!(bbo.map(|b| (b.bid_price, b.bid_quantity)), Some((10000, 5)));
+        assert_eq!(bbo, ob.get_bbo());
+
+        assert_eq!(ob.apply_update_bbo(Update::Remove { price: 10001, side: Side::Ask }), None);
+        assert_eq!(ob.get_bbo(), None);
+    }
+
+    #[test]
+    fn test_emptiness_follows_level_counts() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!((ob.level_count(Side::Bid), ob.level_count(Side::Ask)), (0, 0));
+
+        ob.apply_update(Update::Set { price: 9999, quantity: 4, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9999, quantity: 6, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9998, quantity: 1, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10001, quantity: 2, side: Side::Ask });
+        assert_eq!((ob.level_count(Side::Bid), ob.level_count(Side::Ask)), (2, 1));
+
+        // Removing an absent level or zeroing an empty one must not move the counters.
+        ob.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9990, quantity: 0, side: Side::Bid });
+        assert_eq!(ob.level_count(Side::Bid), 2);
+
+        ob.apply_update(Update::Remove { price: 10001, side: Side::Ask });
+        assert_eq!(ob.level_count(Side::Ask), 0);
+        assert_eq!((ob.get_best_ask(), ob.get_spread(), ob.get_bbo()), (None, None, None));
+        assert!(ob.undo_last());
+        assert_eq!(ob.level_count(Side::Ask), 1);
+        assert_eq!(ob.get_spread(), Some(2));
+
+        ob.apply_update(Update::Set { price: 9999, quantity: 0, side: Side::Bid });
+        ob.apply_update(Update::Remove { price: 9998, side: Side::Bid });
+        assert_eq!(ob.level_count(Side::Bid), 0);
+        assert_eq!(ob.get_best_bid(), None);
+
+        // A new level on an empty side becomes the best immediately.
+        ob.apply_update(Update::Set { price: 9000, quantity: 1, side: Side::Bid });
+        assert_eq!(ob.get_best_bid(), Some(9000));
+    }
+
+    #[test]
+    fn test_builder_options_take_effect() {
+        let mut ob = OrderBookImpl::builder()
+            .anchor(50_000)
+            .tick_size(5)
+            .max_levels(2)
+            .on_cross(CrossPolicy::Reject)
+            .checksum_depth(3)
+            .build();
+        assert_eq!(ob.anchor(Side::Bid), 50_000);
+        assert_eq!(ob.anchor(Side::Ask), 50_000);
+
+        // Tick size 5: 250_003 snaps to tick 50_000, inside the window around the new anchor.
+        ob.apply_update(Update::Set { price: 250_003, quantity: 10, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 249_990, quantity: 10, side: Side::Bid });
+        assert_eq!(ob.get_top_levels(Side::Bid, 5), vec![(50_000, 10), (49_998, 10)]);
+
+        // max_levels: a third bid level is refused, updating an existing one is fine.
+        assert_eq!(
+            ob.try_apply_update(Update::Set { price: 249_980, quantity: 1, side: Side::Bid }),
+            Err(OrderBookError::LevelLimit { side: Side::Bid, max_levels: 2 })
+        );
+        ob.apply_update(Update::Set { price: 249_980, quantity: 1, side: Side::Bid });
+        assert_eq!(ob.level_count(Side::Bid), 2);
+        assert_eq!(ob.try_apply_update(Update::Set { price: 249_990, quantity: 4, side: Side::Bid }), Ok(()));
+
+        // Cross policy and checksum.
+        assert!(matches!(
+            ob.try_apply_update(Update::Set { price: 250_000, quantity: 1, side: Side::Ask }),
+            Err(OrderBookError::Crossed(_))
+        ));
+        assert_eq!(ob.current_checksum(), ob.top_levels_checksum(3));
+        assert_ne!(ob.current_checksum(), 0);
+    }
+
+    #[test]
+    fn test_to_columns_matches_top_levels() {
+        let mut ob = OrderBookImpl::new();
+        for (i, price) in [10003, 9990, 10001, 9995, 10010].into_iter().enumerate() {
+            ob.apply_update(Update::Set { price, quantity: i as u64 + 1, side: Side::Bid });
+        }
+        let (prices, quantities) = ob.to_columns(Side::Bid);
+        assert_eq!(prices.len(), quantities.len());
+        let pairs: Vec<(i64, u64)> = prices.into_iter().zip(quantities).collect();
+        assert_eq!(pairs, ob.get_top_levels(Side::Bid, CAP));
+        assert_eq!(pairs[0], (10010, 5));
+        assert_eq!(ob.to_columns(Side::Ask), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_weighted_mid() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.get_weighted_mid(1), None);
+        ob.apply_update(Update::Set { price: 9999, quantity: 100, side: Side::Bid });
+        assert_eq!(ob.get_weighted_mid(1), None);
+        ob.apply_update(Update::Set { price: 10001, quantity: 300, side: Side::Ask });
+
+        // N = 1 is the microprice: (bid * ask_qty + ask * bid_qty) / (bid_qty + ask_qty).
+        let microprice = (9999.0 * 300.0 + 10001.0 * 100.0) / 400.0;
+        assert_eq!(ob.get_weighted_mid(1), Some(microprice));
+        assert_eq!(ob.get_weighted_mid(5), Some(microprice));
+
+        // Deep bid liquidity pulls the mid up, deep ask liquidity pushes it down.
+        ob.apply_update(Update::Set { price: 9998, quantity: 500, side: Side::Bid });
+        let with_bids = ob.get_weighted_mid(2).unwrap();
+        assert!(with_bids > microprice);
+        assert_eq!(ob.get_weighted_mid(1), Some(microprice));
+        ob.apply_update(Update::Set { price: 10002, quantity: 2000, side: Side::Ask });
+        assert!(ob.get_weighted_mid(2).unwrap() < with_bids);
+    }
+
+    #[test]
+    fn test_per_side_anchors_cover_distant_clusters() {
+        let mut ob = OrderBookImpl::builder().bid_anchor(1_000).ask_anchor(20_000).build();
+        assert_eq!(ob.anchor(Side::Bid), 1_000);
+        assert_eq!(ob.anchor(Side::Ask), 20_000);
+
+        for (price, quantity) in [(990, 5), (1_000, 7), (-1_000, 1)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        for (price, quantity) in [(20_010, 3), (20_000, 4), (22_000, 2)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Ask });
+        }
+        assert!(20_000 - 1_000 > CAP as i64);
+        assert_eq!(ob.get_best_bid(), Some(1_000));
+        assert_eq!(ob.get_best_ask(), Some(20_000));
+        assert_eq!(ob.get_spread(), Some(19_000));
+        assert_eq!(ob.get_top_levels(Side::Bid, 5), vec![(1_000, 7), (990, 5), (-1_000, 1)]);
+        assert_eq!(ob.get_top_levels(Side::Ask, 5), vec![(20_000, 4), (20_010, 3), (22_000, 2)]);
+        assert_eq!(ob.get_quantity_at(22_000, Side::Ask), Some(2));
+        assert_eq!(ob.get_quantity_at(22_000, Side::Bid), None);
+        let bbo = ob.get_bbo().unwrap();
+        assert_eq!((bbo.bid_price, bbo.ask_price), (1_000, 20_000));
+
+        // The ask cluster is outside the bid window and vice versa.
+        assert!(!ob.in_window(20_000, Side::Bid));
+        assert!(!ob.in_window(1_000, Side::Ask));
+        assert_eq!(
+            ob.try_apply_update(Update::Set { price: 20_000, quantity: 1, side: Side::Bid }),
+            Err(OrderBookError::OutOfWindow { price: 20_000, anchor: 1_000 })
+        );
+
+        ob.apply_update(Update::Remove { price: 1_000, side: Side::Bid });
+        assert_eq!(ob.get_best_bid(), Some(990));
+    }
+
+    #[test]
+    fn test_recenter_side_keeps_the_other_side() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 9_990, quantity: 5, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10_010, quantity: 6, side: Side::Ask });
+
+        ob.recenter_side(Side::Ask, 15_000);
+        assert_eq!(ob.anchor(Side::Bid), 10_000);
+        assert_eq!(ob.clear_count(), 0, "a recenter is not a clear");
+        assert_eq!(ob.get_best_bid(), Some(9_990));
+        // 10010 is more than half a window below the new ask anchor, so it is dropped.
+        assert_eq!(ob.get_best_ask(), None);
+
+        ob.apply_update(Update::Set { price: 16_000, quantity: 2, side: Side::Ask });
+        assert_eq!(ob.get_best_ask(), Some(16_000));
+        assert_eq!(ob.get_best_bid(), Some(9_990));
+    }
+
+    #[test]
+    fn test_depth_message_moves_both_bests_once() {
+        use std::sync::{Arc, Mutex};
+
+        let mut ob = OrderBookImpl::new();
+        seed_two_sided(&mut ob);
+        let (bid, ask) = (ob.get_best_bid().unwrap(), ob.get_best_ask().unwrap());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        ob.on_bbo_change(move |bbo| sink.lock().unwrap().push(bbo));
+
+        // Pull the old touch on both sides and add a new, better level on each.
+        let bids = [(bid, 0), (bid + 1, 4)];
+        let asks = [(ask, 0), (ask - 1, 6), (ask + 50, 1)];
+        let summary = ob.apply_depth_message(7, &bids, &asks).unwrap();
+        assert_eq!(summary, ApplySummary { bids_changed: 2, asks_changed: 3, bbo_moved: true, crossed: false });
+        assert_eq!(ob.get_best_bid(), Some(bid + 1));
+        assert_eq!(ob.get_best_ask(), Some(ask - 1));
+        assert_eq!(*seen.lock().unwrap(), vec![ob.get_bbo()]);
+        assert_eq!(ob.last_sequence(), Some(7));
+
+        // Re-sending the same quantities changes nothing and fires nothing.
+        let summary = ob.apply_depth_message(8, &bids, &[]).unwrap();
+        assert_eq!(summary, ApplySummary::default());
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_depth_message_rejects_gaps_and_out_of_window_atomically() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_depth_message(1, &[(9_999, 5)], &[(10_001, 5)]).unwrap();
+        assert_eq!(
+            ob.apply_depth_message(3, &[(9_998, 1)], &[]),
+            Err(OrderBookError::SequenceGap { expected: 2, received: 3 })
+        );
+        assert_eq!(
+            ob.apply_depth_message(2, &[(9_998, 1)], &[(50_000, 1)]),
+            Err(OrderBookError::OutOfWindow { price: 50_000, anchor: 10_000 })
+        );
+        // Neither failure applied anything or advanced the sequence.
+        assert_eq!(ob.get_quantity_at(9_998, Side::Bid), None);
+        assert_eq!(ob.last_sequence(), Some(1));
+
+        let summary = ob.apply_depth_message(2, &[(10_002, 3)], &[]).unwrap();
+        assert!(summary.crossed);
+        assert_eq!(ob.get_best_bid(), Some(10_002));
+
+        ob.reset_sequence();
+        assert!(ob.apply_depth_message(100, &[], &[]).is_ok());
+    }
+
+    #[test]
+    fn test_depth_curve_accumulates_to_total() {
+        let mut ob = OrderBookImpl::new();
+        assert!(ob.depth_curve(Side::Bid).is_empty());
+        seed_two_sided(&mut ob);
+        ob.apply_update(Update::Set { price: 10007, quantity: 25, side: Side::Ask });
+
+        for side in [Side::Bid, Side::Ask] {
+            let curve = ob.depth_curve(side);
+            assert_eq!(curve.len(), ob.level_count(side));
+            assert!(curve.windows(2).all(|w| w[0].1 <= w[1].1));
+            assert_eq!(curve.last().unwrap().1, ob.get_total_quantity(side));
+        }
+        assert_eq!(ob.depth_curve(Side::Ask), vec![(10005, 100), (10007, 125), (10010, 225), (10020, 325)]);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics_count_updates_recenters_and_scans() {
+        use rust_3::orderbook::BookMetrics;
+
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.metrics(), BookMetrics::default());
+        ob.apply_update(Update::Set { price: 9980, quantity: 1, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9990, quantity: 1, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10000, quantity: 1, side: Side::Bid });
+        // Emptying the best bid promotes 9990 and walks 9989 -> 9980 for the new second: ten
+        // slots examined.
+        ob.apply_update(Update::Remove { price: 10000, side: Side::Bid });
+        assert_eq!(ob.metrics(), BookMetrics { updates: 4, recenters: 0, scan_steps: 10 });
+
+        ob.recenter_anchor(10500);
+        ob.recenter_anchor(10500);
+        ob.recenter_side(Side::Ask, 11000);
+        let metrics = ob.metrics();
+        assert_eq!(metrics.recenters, 2);
+        assert_eq!(metrics.updates, 4);
+    }
+
+    #[test]
+    fn test_version_counts_only_real_changes() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.version(), 0);
+        ob.apply_update(Update::Set { price: 9990, quantity: 5, side: Side::Bid });
+        let v = ob.version();
+        assert_eq!(v, 1);
+
+        // No-op Set, Remove of an absent level and zero-quantity Set of an absent level.
+        ob.apply_update(Update::Set { price: 9990, quantity: 5, side: Side::Bid });
+        ob.apply_update(Update::Remove { price: 9980, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9980, quantity: 0, side: Side::Bid });
+        ob.apply_update(Update::Trade { price: 9990, quantity: 1, aggressor: Side::Ask, timestamp: 0 });
+        assert!(!ob.changed_since(v));
+
+        ob.apply_update(Update::Set { price: 9990, quantity: 6, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10010, quantity: 6, side: Side::Ask });
+        assert_eq!(ob.version(), 3);
+        assert_eq!(ob.get_bbo().unwrap().version, 3);
+        assert!(ob.undo_last());
+        assert_eq!(ob.version(), 4);
+
+        ob.recenter_anchor(10100);
+        assert_eq!(ob.version(), 5);
+        ob.clear();
+        assert_eq!(ob.version(), 0);
+    }
+
+    #[test]
+    fn test_quantity_to_move_mid() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.quantity_to_move_mid(Side::Ask, 1), None);
+        // Bid 9998 / ask 10000: mid 9999.
+        ob.apply_update(Update::Set { price: 9998, quantity: 50, side: Side::Bid });
+        for (price, quantity) in [(10000, 10), (10001, 20), (10002, 30), (10006, 40)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Ask });
+        }
+        assert_eq!(ob.quantity_to_move_mid(Side::Ask, 0), Some(0));
+        // One tick of mid needs the ask at 10002 or beyond to become the best.
+        let needed = ob.quantity_to_move_mid(Side::Ask, 1).unwrap();
+        assert_eq!(needed, 30);
+        assert_eq!(ob.quantity_to_move_mid(Side::Ask, 2), Some(60));
+        assert_eq!(ob.quantity_to_move_mid(Side::Ask, 3), Some(60));
+        assert_eq!(ob.quantity_to_move_mid(Side::Ask, 4), None);
+        assert_eq!(ob.quantity_to_move_mid(Side::Bid, 1), None);
+
+        // Simulate the sweep: consume `needed` from the best ask up.
+        let mid_before = (ob.get_best_bid().unwrap() + ob.get_best_ask().unwrap()) as f64 / 2.0;
+        let mut left = needed;
+        for (price, qty) in ob.get_top_levels(Side::Ask, 10) {
+            if left == 0 {
+                break;
+            }
+            let take = qty.min(left);
+            left -= take;
+            ob.apply_update(Update::Set { price, quantity: qty - take, side: Side::Ask });
+        }
+        let mid_after = (ob.get_best_bid().unwrap() + ob.get_best_ask().unwrap()) as f64 / 2.0;
+        assert_eq!(mid_after - mid_before, 1.0);
+    }
+
+    #[test]
+    fn test_quantity_to_move_mid_saturates() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 9999, quantity: 1, side: Side::Bid });
+        for price in [10001, 10002, 10010] {
+            ob.apply_update(Update::Set { price, quantity: u64::MAX, side: Side::Ask });
+        }
+        assert_eq!(ob.quantity_to_move_mid(Side::Ask, 1), Some(u64::MAX));
+        assert_eq!(ob.quantity_to_move_mid(Side::Ask, 4), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_take_snapshot_and_reset() {
+        let mut ob = OrderBookImpl::new();
+        seed_two_sided(&mut ob);
+        ob.apply_update_at(42, Update::Set { price: 9995, quantity: 7, side: Side::Bid });
+        let version = ob.version();
+
+        let snapshot = ob.take_snapshot_and_reset();
+        assert_eq!((snapshot.timestamp, snapshot.version), (42, version));
+        assert_eq!(snapshot.bids, vec![(9995, 7), (9990, 100)]);
+        assert_eq!(snapshot.asks, vec![(10005, 100), (10010, 100), (10020, 100)]);
+
+        assert_eq!(ob.get_bbo(), None);
+        assert_eq!(ob.level_count(Side::Bid) + ob.level_count(Side::Ask), 0);
+        assert_eq!(ob.get_total_quantity(Side::Ask), 0);
+        assert!(ob.depth_snapshot(5).bids.is_empty());
+    }
+
+    fn degenerate_books() -> Vec<(&'static str, OrderBookImpl)> {
+        let book = |levels: &[(i64, Side)]| {
+            let mut ob = OrderBookImpl::new();
+            for &(price, side) in levels {
+                ob.apply_update(Update::Set { price, quantity: 10, side });
+            }
+            ob
+        };
+        vec![
+            ("empty", book(&[])),
+            ("bid-only", book(&[(9990, Side::Bid)])),
+            ("ask-only", book(&[(10010, Side::Ask)])),
+            ("two-sided", book(&[(9990, Side::Bid), (10010, Side::Ask)])),
+            ("locked", book(&[(10000, Side::Bid), (10000, Side::Ask)])),
+            ("crossed", book(&[(10005, Side::Bid), (9995, Side::Ask)])),
+        ]
+    }
+
+    #[test]
+    fn test_accessors_are_total_on_degenerate_books() {
+        use rust_3::checksum::ChecksumSpec;
+
+        for (state, mut ob) in degenerate_books() {
+            let (bid, ask) = (ob.get_best_bid(), ob.get_best_ask());
+            let expected_spread = match state {
+                "empty" | "bid-only" | "ask-only" => None,
+                "two-sided" => Some(20),
+                "locked" => Some(0),
+                "crossed" => Some(-10),
+                _ => unreachable!(),
+            };
+            assert_eq!(ob.get_spread(), expected_spread, "{state}");
+            match ob.try_spread() {
+                Ok(spread) => assert_eq!(Some(spread), expected_spread, "{state}"),
+                Err(OrderBookError::EmptySide { side }) => {
+                    assert!(expected_spread.is_none(), "{state}");
+                    assert!(ob.level_count(side) == 0, "{state}");
+                }
+                Err(OrderBookError::CrossedBook { bid: b, ask: a }) => {
+                    assert_eq!((state, Some(b), Some(a)), ("crossed", bid, ask));
+                }
+                Err(other) => panic!("{state}: unexpected {other}"),
+            }
+            assert_eq!(ob.get_bbo().is_some(), bid.is_some() && ask.is_some(), "{state}");
+            let stats = ob.stats();
+            assert_eq!(stats.mid.is_some(), expected_spread.is_some(), "{state}");
+            assert_eq!(stats.spread, expected_spread, "{state}");
+            assert_eq!(ob.get_weighted_mid(1).is_some(), expected_spread.is_some(), "{state}");
+            assert_eq!(ob.get_weighted_mid(0), None, "{state}");
+
+            for side in [Side::Bid, Side::Ask] {
+                let count = ob.level_count(side);
+                assert_eq!(ob.levels(side).count(), count, "{state}");
+                assert_eq!(ob.get_top_levels(side, usize::MAX).len(), count, "{state}");
+                assert_eq!(ob.get_top_levels(side, 0), vec![], "{state}");
+                assert_eq!(ob.nth_level(side, usize::MAX), None, "{state}");
+                assert_eq!(ob.depth_curve(side).last().map(|p| p.1), (count > 0).then(|| ob.get_total_quantity(side)));
+                assert_eq!(ob.to_columns(side).0.len(), count, "{state}");
+                for ticks in [i64::MIN, -1, 0, 1, i64::MAX] {
+                    let moved = ob.quantity_to_move_mid(side, ticks);
+                    assert!(moved.is_none() || expected_spread.is_some(), "{state}");
+                }
+                for price in [i64::MIN, -1, 0, i64::MAX] {
+                    assert_eq!(ob.get_quantity_at(price, side), None, "{state}");
+                    assert!(!ob.in_window(price, side), "{state}");
+                }
+            }
+
+            let levels = ob.level_count(Side::Bid) + ob.level_count(Side::Ask);
+            assert_eq!(ob.levels_by_distance().count(), levels, "{state}");
+            assert_eq!(ob.volume_profile(1).iter().map(|b| b.bid_quantity + b.ask_quantity).sum::<u64>(), 10 * levels as u64);
+            // Huge bands: one below and one at/above the anchor at most.
+            assert!(ob.volume_profile(usize::MAX).len() <= levels.min(2), "{state}");
+            let mut rows = Vec::new();
+            ob.get_ladder(10000, usize::MAX, &mut rows);
+            assert_eq!(rows.len(), CAP, "{state}");
+            ob.get_ladder(i64::MIN, 5, &mut rows);
+            assert!(rows.is_empty(), "{state}");
+            assert_eq!(ob.depth_snapshot(usize::MAX).bids.len(), ob.level_count(Side::Bid));
+            assert_eq!(ob.top_levels_checksum(usize::MAX), ob.top_levels_checksum(CAP));
+            ob.checksum_levels(&ChecksumSpec::okx(40, 40));
+            assert!(ob.compare(&ob.clone(), usize::MAX).is_empty(), "{state}");
+            assert_eq!(ob.fork().get_spread(), expected_spread, "{state}");
+            let _ = ob.spread_capture(i64::MIN, i64::MAX, u64::MAX);
+            ob.undo_last();
+            assert!(!ob.undo_last(), "{state}");
+            ob.clear();
+            assert_eq!(ob.try_spread(), Err(OrderBookError::EmptySide { side: Side::Bid }));
+        }
+    }
+
+    #[test]
+    fn test_apply_update_totals_wrap_instead_of_panicking() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 9990, quantity: u64::MAX, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9991, quantity: 2, side: Side::Bid });
+        assert_eq!(ob.get_total_quantity(Side::Bid), 1);
+        ob.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        assert_eq!(ob.get_total_quantity(Side::Bid), 2);
+    }
+
+    fn round_trip<P: rust_3::price::PriceInt>(anchor: P, below: P, above: P) {
+        let mut ob = OrderBookImpl::<P>::with_anchor(anchor);
+        ob.set_level(below, 7, Side::Bid);
+        ob.set_level(anchor, 3, Side::Bid);
+        ob.set_level(above, 5, Side::Ask);
+        assert_eq!(ob.quantity_at(below, Side::Bid), Some(7));
+        assert_eq!(ob.quantity_at(anchor, Side::Bid), Some(3));
+        assert_eq!(ob.quantity_at(above, Side::Ask), Some(5));
+        assert_eq!(ob.best_price(Side::Bid), Some(anchor));
+        assert_eq!(ob.best_price(Side::Ask), Some(above));
+        assert_eq!(ob.levels(Side::Bid).collect::<Vec<_>>(), vec![(anchor, 3), (below, 7)]);
+
+        ob.set_level(anchor, 0, Side::Bid);
+        assert_eq!(ob.quantity_at(anchor, Side::Bid), None);
+        assert_eq!(ob.best_price(Side::Bid), Some(below));
+        assert_eq!(ob.nth_level(Side::Bid, 0), Some((below, 7)));
+        assert_eq!(ob.level_count(Side::Bid), 1);
+    }
+
+    #[test]
+    fn test_price_int_round_trips() {
+        round_trip::<i32>(0, -100, 100);
+        round_trip::<i64>(-5, -2000, 2000);
+        round_trip::<u32>(100, 1, 2147);
+        round_trip::<u64>(1 << 40, (1 << 40) - 2048, (1 << 40) + 2047);
+        // Unsigned window straddling zero on the wrapped side: the ring only sees distances.
+        round_trip::<u32>(u32::MAX - 10, u32::MAX - 50, 5);
+    }
+
+    #[test]
+    fn test_window_boundary_prices() {
+        // The window is [anchor - 2048, anchor + 2047].
+        let mut ob = OrderBookImpl::new();
+        assert!(ob.in_window(7952, Side::Bid) && ob.in_window(12047, Side::Bid));
+        assert!(!ob.in_window(7951, Side::Bid) && !ob.in_window(12048, Side::Bid));
+        assert_eq!(
+            ob.try_apply_update(Update::Set { price: 12048, quantity: 1, side: Side::Ask }),
+            Err(OrderBookError::OutOfWindow { price: 12048, anchor: 10000 })
+        );
+
+        ob.apply_update(Update::Set { price: 7952, quantity: 4, side: Side::Bid });
+        assert_eq!(ob.get_best_bid(), Some(7952));
+        assert_eq!(ob.get_top_levels(Side::Bid, 1), vec![(7952, 4)]);
+        assert_eq!(ob.get_quantity_at(7952, Side::Bid), Some(4));
+        ob.apply_update(Update::Set { price: 12047, quantity: 6, side: Side::Ask });
+        assert_eq!(ob.get_best_ask(), Some(12047));
+        assert_eq!(ob.nth_level(Side::Ask, 0), Some((12047, 6)));
+
+        // A new best more than half a window away from the old one is still recognised.
+        ob.apply_update(Update::Set { price: 12000, quantity: 1, side: Side::Bid });
+        assert_eq!(ob.get_best_bid(), Some(12000));
+        ob.apply_update(Update::Set { price: 7960, quantity: 1, side: Side::Ask });
+        assert_eq!(ob.get_best_ask(), Some(7960));
+        ob.apply_update(Update::Remove { price: 12000, side: Side::Bid });
+        assert_eq!(ob.get_best_bid(), Some(7952));
+        ob.apply_update(Update::Remove { price: 7960, side: Side::Ask });
+        assert_eq!(ob.get_best_ask(), Some(12047));
+        assert_eq!(ob.levels(Side::Ask).collect::<Vec<_>>(), vec![(12047, 6)]);
+    }
+
+    #[test]
+    fn test_would_recenter() {
+        let mut ob = OrderBookImpl::new();
+        for price in [10000, 9000, 11000, 7952, 12047] {
+            assert!(!ob.would_recenter(price), "{price}");
+        }
+        for price in [7951, 12048, 20000, -5, i64::MIN, i64::MAX] {
+            assert!(ob.would_recenter(price), "{price}");
+        }
+
+        // With the sides anchored apart, a price outside either window counts.
+        ob.recenter_side(Side::Ask, 11000);
+        assert!(!ob.would_recenter(10500));
+        assert!(ob.would_recenter(8500));
+        assert!(ob.would_recenter(12500));
+    }
+
+    #[test]
+    fn test_queue_ahead() {
+        let mut ob = OrderBookImpl::new();
+        seed_two_sided(&mut ob);
+        assert_eq!(ob.queue_ahead(Side::Bid, 9995), Some(100));
+        assert_eq!(ob.queue_ahead(Side::Ask, 10010), Some(100));
+        assert_eq!(ob.queue_ahead(Side::Bid, 9993), None);
+        assert_eq!(ob.queue_ahead(Side::Ask, 9995), None);
+        // Out of the window, even though the slot it would alias is occupied.
+        assert_eq!(ob.queue_ahead(Side::Bid, 9995 + 4096), None);
+    }
+
+    #[test]
+    fn test_aggregate_levels() {
+        let mut ob = OrderBookImpl::new();
+        for (price, quantity, side) in [
+            (9999, 5, Side::Bid), (9997, 3, Side::Bid), (9995, 2, Side::Bid), (9990, 1, Side::Bid),
+            (10001, 4, Side::Ask), (10004, 1, Side::Ask), (10005, 2, Side::Ask), (10012, 7, Side::Ask),
+        ] {
+            ob.apply_update(Update::Set { price, quantity, side });
+        }
+        assert_eq!(ob.aggregate_levels(Side::Bid, 5, 3), vec![(9999, 10), (9994, 1), (9989, 0)]);
+        assert_eq!(ob.aggregate_levels(Side::Ask, 5, 3), vec![(10000, 5), (10005, 2), (10010, 7)]);
+        assert_eq!(ob.aggregate_levels(Side::Ask, 5, 1), vec![(10000, 5)]);
+
+        // Alignment is absolute: moving the touch inside its band changes nothing but quantities.
+        ob.apply_update(Update::Remove { price: 9999, side: Side::Bid });
+        assert_eq!(ob.aggregate_levels(Side::Bid, 5, 2), vec![(9999, 5), (9994, 1)]);
+
+        // The window ends at 7952: the band [7900, 7999] is partial and the last one returned.
+        let mut edge = OrderBookImpl::new();
+        edge.apply_update(Update::Set { price: 8050, quantity: 1, side: Side::Bid });
+        edge.apply_update(Update::Set { price: 7960, quantity: 2, side: Side::Bid });
+        assert_eq!(edge.aggregate_levels(Side::Bid, 100, 10), vec![(8099, 1), (7999, 2)]);
+
+        assert!(edge.aggregate_levels(Side::Ask, 5, 3).is_empty());
+        assert!(edge.aggregate_levels(Side::Bid, 0, 3).is_empty());
+    }
+
+    #[test]
+    fn test_notional_curve() {
+        let mut ob = OrderBookImpl::new();
+        seed_two_sided(&mut ob);
+        ob.apply_update(Update::Set { price: 10005, quantity: 3, side: Side::Ask });
+        let curve: Vec<(i64, u128)> = ob.notional_curve(Side::Ask).collect();
+        assert_eq!(curve, vec![(10005, 30_015), (10010, 1_031_015), (10020, 2_033_015)]);
+        assert_eq!(curve.last().unwrap().1, ob.get_notional(Side::Ask));
+        assert_eq!(ob.notional_curve(Side::Bid).last().unwrap().1, ob.get_notional(Side::Bid));
+        assert_eq!(ob.get_notional(Side::Bid), 9995 * 100 + 9990 * 100);
+
+        ob.clear();
+        assert_eq!(ob.notional_curve(Side::Bid).count(), 0);
+        assert_eq!(ob.get_notional(Side::Bid), 0);
+    }
+
+    /// One independent walk per size: the reference `impact_curve` must agree with.
+    fn single_impact(ob: &OrderBookImpl, side: Side, quantity: u64) -> (u64, Option<f64>, Option<i64>) {
+        let opposite = if side == Side::Bid { Side::Ask } else { Side::Bid };
+        let (mut filled, mut cash, mut worst) = (0u64, 0i128, None);
+        for (price, qty) in ob.levels(opposite) {
+            if filled == quantity {
+                break;
+            }
+            let take = qty.min(quantity - filled);
+            filled += take;
+            cash += price as i128 * take as i128;
+            worst = Some(price);
+        }
+        (filled, (filled > 0).then(|| cash as f64 / filled as f64), worst)
+    }
+
+    #[test]
+    fn test_impact_curve_matches_single_walks() {
+        let mut ob = OrderBookImpl::new();
+        seed_two_sided(&mut ob);
+        ob.apply_update(Update::Set { price: 10005, quantity: 30, side: Side::Ask });
+        let mid = ob.stats().mid.unwrap();
+        let sizes = [0, 1, 30, 31, 100, 130, 230, 231, 1000, 50, 130];
+        let mut out = Vec::new();
+        for side in [Side::Bid, Side::Ask] {
+            ob.impact_curve(side, &sizes, &mut out);
+            assert_eq!(out.len(), sizes.len());
+            for (point, &size) in out.iter().zip(&sizes) {
+                let (filled, avg, worst) = single_impact(&ob, side, size);
+                assert_eq!((point.quantity, point.filled, point.worst_price), (size, filled, worst), "{side:?} {size}");
+                assert_eq!(point.avg_price, avg, "{side:?} {size}");
+                assert_eq!(point.truncated, filled < size);
+                let expected_slippage = avg.map(|avg| if side == Side::Bid { avg - mid } else { mid - avg });
+                assert_eq!(point.slippage, expected_slippage);
+            }
+        }
+        ob.impact_curve(Side::Bid, &[130], &mut out);
+        assert_eq!(out[0].worst_price, Some(10010));
+        assert_eq!(out[0].avg_price, Some((10005.0 * 30.0 + 10010.0 * 100.0) / 130.0));
+        assert!(!out[0].truncated);
+
+        ob.clear();
+        ob.impact_curve(Side::Bid, &[5], &mut out);
+        assert_eq!(out, vec![ImpactPoint { quantity: 5, filled: 0, avg_price: None, worst_price: None, slippage: None, truncated: true }]);
+    }
+
+    #[test]
+    fn test_recenter_anchor_aligned_is_stable() {
+        let mut ob = OrderBookImpl::new();
+        seed_two_sided(&mut ob);
+        ob.recenter_anchor_aligned(10_100);
+        assert_eq!(ob.anchor(Side::Bid), 9984);
+        assert_eq!(ob.anchor(Side::Ask), 9984);
+        let version = ob.version();
+        for near in [10_001, 10_150, 10_239, 9984] {
+            ob.recenter_anchor_aligned(near);
+            assert_eq!(ob.anchor(Side::Bid), 9984, "{near}");
+        }
+        assert_eq!(ob.version(), version, "no-op recenters leave the book alone");
+        assert_eq!(ob.get_best_bid(), Some(9995));
+
+        ob.recenter_anchor_aligned(10_240);
+        assert_eq!(ob.anchor(Side::Ask), 10_240);
+
+        let mut coarse = OrderBookImpl::builder().anchor_grid(1000).build();
+        coarse.recenter_anchor_aligned(-1);
+        assert_eq!(coarse.anchor(Side::Bid), -1000);
+    }
+
+    #[test]
+    fn test_recentering_targets_the_reference_price() {
+        let mut ob = OrderBookImpl::new();
+        seed_two_sided(&mut ob);
+        assert_eq!(ob.recenter_to_reference(), (0, 0));
+        assert_eq!(ob.anchor(Side::Bid), 10_000);
+
+        // Activity is expected around 10_900: the recenter forced by 10_100 lands on the grid
+        // multiple nearest that (11_008) rather than just below 10_100 (9_984).
+        ob.set_reference_price(10_900);
+        assert_eq!(ob.recenter_anchor_aligned(10_100), (0, 0));
+        assert_eq!((ob.anchor(Side::Bid), ob.anchor(Side::Ask)), (11_008, 11_008));
+        assert!(ob.in_window(10_100, Side::Bid));
+        assert_eq!((ob.get_best_bid(), ob.get_best_ask()), (Some(9995), Some(10005)));
+
+        // A reference too far away still leaves the forcing price inside the window.
+        ob.set_reference_price(20_000);
+        ob.recenter_anchor_aligned(10_100);
+        assert_eq!(ob.anchor(Side::Bid), 12_032);
+        assert!(ob.in_window(10_100, Side::Ask));
+        ob.set_reference_price(0);
+        ob.recenter_anchor_aligned(10_100);
+        assert_eq!(ob.anchor(Side::Bid), 8_192);
+        assert!(ob.in_window(10_100, Side::Ask));
+
+        // In a quiet period the window can be moved onto the reference directly.
+        ob.set_reference_price(10_900);
+        ob.recenter_to_reference();
+        assert_eq!(ob.anchor(Side::Ask), 11_008);
+        let version = ob.version();
+        ob.recenter_to_reference();
+        assert_eq!(ob.version(), version);
+
+        ob.clear_reference_price();
+        assert_eq!(ob.reference_price(), None);
+        ob.recenter_anchor_aligned(10_100);
+        assert_eq!(ob.anchor(Side::Bid), 9_984);
+    }
+
+    #[test]
+    fn test_update_filter_rejects_garbled_updates() {
+        let mut ob = OrderBookImpl::new();
+        ob.set_update_filter(UpdateFilter::default().max_quantity(1_000_000).max_mid_distance(500).max_total_change(600_000));
+        let set = |price, quantity, side| Update::Set { price, quantity, side };
+
+        // No mid yet: a far first price is not distance-checked.
+        assert_eq!(ob.try_apply_update(set(9000, 10, Side::Bid)), Ok(()));
+        assert_eq!(ob.try_apply_update(set(10001, 10, Side::Ask)), Ok(()));
+        // Large but plausible: at the limits, near the mid.
+        assert_eq!(ob.try_apply_update(set(10000, 600_000, Side::Ask)), Ok(()));
+        assert_eq!(ob.try_apply_update(set(10000, 1_000_000, Side::Ask)), Ok(()));
+        assert_eq!(ob.try_apply_update(set(9501, 5, Side::Bid)), Ok(()));
+
+        assert_eq!(
+            ob.try_apply_update(set(9995, 9_000_000_000_000_000_000, Side::Bid)),
+            Err(OrderBookError::Implausible(FilterViolation::Quantity { quantity: 9_000_000_000_000_000_000, max: 1_000_000 }))
+        );
+        // Mid is 9750.5 now (best bid 9501, best ask 10000): 9000 is 750.5 ticks away.
+        assert_eq!(
+            ob.try_apply_update(set(9000, 20, Side::Bid)),
+            Err(OrderBookError::Implausible(FilterViolation::MidDistance { price: 9000, mid_x2: 19_501, max: 500 }))
+        );
+        assert!(matches!(
+            ob.try_apply_update(Update::Trade { price: 12_000, quantity: 1, aggressor: Side::Bid, timestamp: 0 }),
+            Err(OrderBookError::Implausible(FilterViolation::MidDistance { .. }))
+        ));
+        assert_eq!(
+            ob.try_apply_update(Update::Remove { price: 10000, side: Side::Ask }),
+            Err(OrderBookError::Implausible(FilterViolation::TotalChange { side: Side::Ask, change: 1_000_000, max: 600_000 }))
+        );
+
+        assert_eq!(ob.get_quantity_at(10000, Side::Ask), Some(1_000_000));
+        assert_eq!(ob.get_quantity_at(9000, Side::Bid), Some(10));
+        assert_eq!(ob.get_total_quantity(Side::Bid), 15);
+        assert_eq!(
+            ob.update_filter().unwrap().rejections(),
+            FilterRejections { quantity: 1, mid_distance: 2, total_change: 1 }
+        );
+
+        // The infallible path is not filtered.
+        ob.apply_update(Update::Remove { price: 10000, side: Side::Ask });
+        assert_eq!(ob.get_quantity_at(10000, Side::Ask), None);
+        assert!(ob.remove_update_filter().is_some());
+        assert_eq!(ob.try_apply_update(set(9995, 9_000_000_000_000_000_000, Side::Bid)), Ok(()));
+    }
+
+    #[test]
+    fn test_quote_quantities() {
+        let mut book = OrderBookImpl::new();
+        let levels = [(9990, 3u64, Side::Bid), (9995, 7, Side::Bid), (10010, 4, Side::Ask), (10020, 11, Side::Ask)];
+        for &(price, quantity, side) in &levels {
+            book.apply_update(Update::Set { price, quantity, side });
+        }
+        for &(price, quantity, side) in &levels {
+            assert_eq!(book.get_quote_quantity_at(price, side), Some(price as i128 * quantity as i128));
+        }
+        assert_eq!(book.get_quote_quantity_at(9991, Side::Bid), None);
+        assert_eq!(book.get_total_notional(Side::Bid), 9990 * 3 + 9995 * 7);
+        assert_eq!(book.get_total_notional(Side::Ask), 10010 * 4 + 10020 * 11);
+
+        book.apply_update(Update::Set { price: 9995, quantity: 2, side: Side::Bid });
+        book.apply_update(Update::Remove { price: 10010, side: Side::Ask });
+        assert_eq!(book.get_total_notional(Side::Bid), 9990 * 3 + 9995 * 2);
+        assert_eq!(book.get_total_notional(Side::Ask), 10020 * 11);
+
+        book.undo_last();
+        assert_eq!(book.get_total_notional(Side::Ask), 10010 * 4 + 10020 * 11);
+        book.recenter_anchor(10500);
+        let mut rebuilt = OrderBookImpl::new();
+        rebuilt.recenter_anchor(10500);
+        for (price, quantity) in book.levels(Side::Bid) {
+            rebuilt.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        assert_eq!(book.get_total_notional(Side::Bid), rebuilt.get_total_notional(Side::Bid));
+        book.clear();
+        assert_eq!(book.get_total_notional(Side::Ask), 0);
+    }
+
+    #[test]
+    fn test_steepest_level() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.steepest_level(Side::Bid), None);
+        for (price, quantity) in [(9999, 10), (9997, 12), (9996, 8), (9993, 40), (9990, 45), (9980, 5)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        // 8 -> 40 at 9993 beats 40 -> 45 and 10 -> 12; the gap at 9995/9994 does not matter.
+        assert_eq!(ob.steepest_level(Side::Bid), Some((9993, 40)));
+
+        for (price, quantity) in [(10001, 30), (10002, 20), (10005, 10)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Ask });
+        }
+        assert_eq!(ob.steepest_level(Side::Ask), None);
+        ob.apply_update(Update::Set { price: 10007, quantity: 25, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 10009, quantity: 40, side: Side::Ask });
+        // Jumps of 15 at 10007 and 10009 tie; the nearer one wins.
+        assert_eq!(ob.steepest_level(Side::Ask), Some((10007, 25)));
+    }
+
+    #[test]
+    fn test_dirty_levels() {
+        let mut ob = OrderBookImpl::new();
+        let mut dirty = Vec::new();
+        ob.apply_update(Update::Set { price: 9990, quantity: 5, side: Side::Bid });
+        assert!(!ob.take_dirty_levels(&mut dirty));
+        assert!(dirty.is_empty());
+
+        ob.enable_dirty_tracking(4);
+        ob.apply_update(Update::Set { price: 9995, quantity: 3, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10005, quantity: 8, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 9995, quantity: 4, side: Side::Bid });
+        ob.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10005, quantity: 8, side: Side::Ask });
+        assert!(!ob.take_dirty_levels(&mut dirty));
+        assert_eq!(dirty, vec![(Side::Bid, 9995, 4), (Side::Ask, 10005, 8), (Side::Bid, 9990, 0)]);
+        assert!(!ob.take_dirty_levels(&mut dirty));
+        assert!(dirty.is_empty());
+
+        // Exactly the limit still fits; one more distinct level trips the full refresh.
+        for price in 10001..10005 {
+            ob.apply_update(Update::Set { price, quantity: 1, side: Side::Ask });
+        }
+        ob.apply_update(Update::Set { price: 10001, quantity: 2, side: Side::Ask });
+        assert!(!ob.take_dirty_levels(&mut dirty));
+        assert_eq!(dirty.len(), 4);
+        for price in 10001..10006 {
+            ob.apply_update(Update::Remove { price, side: Side::Ask });
+        }
+        assert!(ob.take_dirty_levels(&mut dirty));
+        assert!(dirty.is_empty());
+
+        // The flag is cleared by the poll; tracking resumes from there.
+        ob.apply_update(Update::Set { price: 9994, quantity: 6, side: Side::Bid });
+        assert!(!ob.take_dirty_levels(&mut dirty));
+        assert_eq!(dirty, vec![(Side::Bid, 9994, 6)]);
+
+        ob.recenter_anchor(10100);
+        assert!(ob.take_dirty_levels(&mut dirty));
+        ob.undo_last();
+        ob.apply_update(Update::Set { price: 10090, quantity: 1, side: Side::Bid });
+        ob.undo_last();
+        assert!(!ob.take_dirty_levels(&mut dirty));
+        assert_eq!(dirty, vec![(Side::Bid, 10090, 0)]);
+    }
+
+    #[test]
+    fn test_max_spread_clamp() {
+        let mut ob = OrderBookImpl::builder().max_spread_ticks(10).build();
+        for (price, side) in [(9998, Side::Bid), (9990, Side::Bid), (9980, Side::Bid), (10002, Side::Ask), (10030, Side::Ask)] {
+            ob.apply_update(Update::Set { price, quantity: 5, side });
+        }
+        // Pulling the best bid leaves 10002 - 9990 = 12 > 10.
+        assert_eq!(
+            ob.try_apply_update(Update::Remove { price: 9998, side: Side::Bid }),
+            Err(OrderBookError::SpreadTooWide { spread: 12, max: 10 })
+        );
+        assert_eq!(ob.spread_rejections(), 0);
+        ob.apply_update(Update::Set { price: 9998, quantity: 0, side: Side::Bid });
+        assert_eq!(ob.get_best_bid(), Some(9998));
+        assert_eq!(ob.spread_rejections(), 1);
+
+        // A bid inside the spread narrows it, after which the same kind of removal passes.
+        assert_eq!(ob.try_apply_update(Update::Set { price: 9999, quantity: 3, side: Side::Bid }), Ok(()));
+        assert_eq!(ob.try_apply_update(Update::Remove { price: 9999, side: Side::Bid }), Ok(()));
+        ob.apply_update(Update::Set { price: 9993, quantity: 1, side: Side::Bid });
+        assert_eq!(ob.try_apply_update(Update::Remove { price: 9998, side: Side::Bid }), Ok(()));
+        assert_eq!(ob.get_spread(), Some(9));
+
+        // Behind the touch, or with the next level gone entirely, the clamp does not apply.
+        assert_eq!(ob.try_apply_update(Update::Remove { price: 9980, side: Side::Bid }), Ok(()));
+        ob.apply_update(Update::Remove { price: 10002, side: Side::Ask });
+        assert_eq!(ob.get_best_ask(), Some(10002));
+        ob.apply_update(Update::Remove { price: 10030, side: Side::Ask });
+        ob.apply_update(Update::Remove { price: 10002, side: Side::Ask });
+        assert_eq!(ob.get_best_ask(), None);
+    }
+
+    #[test]
+    fn test_watch_levels() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 9990, quantity: 5, side: Side::Bid });
+        let bid = ob.watch_level(Side::Bid, 9990).unwrap();
+        let ask = ob.watch_level(Side::Ask, 10010).unwrap();
+        let far = ob.watch_level(Side::Bid, 12500).unwrap();
+        let event = |watch_id, side, price, old_qty, new_qty| LevelEvent { watch_id, side, price, old_qty, new_qty };
+
+        ob.apply_update(Update::Set { price: 9990, quantity: 7, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9990, quantity: 7, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9991, quantity: 1, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10010, quantity: 2, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10010, quantity: 4, side: Side::Ask });
+        ob.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        ob.undo_last();
+        assert_eq!(
+            ob.take_level_events(),
+            vec![
+                event(bid, Side::Bid, 9990, 5, 7),
+                event(ask, Side::Ask, 10010, 0, 4),
+                event(bid, Side::Bid, 9990, 7, 0),
+                event(bid, Side::Bid, 9990, 0, 7),
+            ]
+        );
+        assert!(ob.take_level_events().is_empty());
+
+        assert!(ob.unwatch(ask));
+        assert!(!ob.unwatch(ask));
+        ob.apply_update(Update::Remove { price: 10010, side: Side::Ask });
+        assert!(ob.take_level_events().is_empty());
+
+        // Moving the bid window up drops 9990 and brings the far watch into range.
+        ob.recenter_side(Side::Bid, 12100);
+        assert_eq!(ob.take_level_events(), vec![event(bid, Side::Bid, 9990, 7, 0)]);
+        ob.apply_update(Update::Set { price: 12500, quantity: 3, side: Side::Bid });
+        ob.clear();
+        assert_eq!(ob.take_level_events(), vec![event(far, Side::Bid, 12500, 0, 3), event(far, Side::Bid, 12500, 3, 0)]);
+    }
+
+    #[test]
+    fn test_watch_limit() {
+        let mut ob = OrderBookImpl::new();
+        let ids: Vec<_> = (0..MAX_WATCHES as i64).map(|i| ob.watch_level(Side::Bid, 9900 + i).unwrap()).collect();
+        assert_eq!(ob.watch_level(Side::Ask, 10010), None);
+
+        // Unwatching one makes room for exactly one more.
+        assert!(ob.unwatch(ids[10]));
+        let ask = ob.watch_level(Side::Ask, 10010).unwrap();
+        assert!(!ids.contains(&ask));
+        assert_eq!(ob.watch_level(Side::Ask, 10011), None);
+    }
+
+    #[test]
+    fn test_twa_spread() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.twa_spread(), None);
+        ob.enable_twa_spread();
+        ob.apply_update_at(100, Update::Set { price: 9998, quantity: 1, side: Side::Bid });
+        ob.apply_update_at(150, Update::Set { price: 10002, quantity: 1, side: Side::Ask });
+        assert_eq!(ob.twa_spread(), None);
+        // Spread 4 for 100, then 2 for 300, then 4 again for 100; the one-sided 50 does not count.
+        ob.apply_update_at(250, Update::Set { price: 10000, quantity: 1, side: Side::Ask });
+        ob.apply_update_at(550, Update::Remove { price: 10000, side: Side::Ask });
+        ob.apply_update_at(550, Update::Set { price: 9996, quantity: 1, side: Side::Bid });
+        ob.apply_update_at(650, Update::Remove { price: 9998, side: Side::Bid });
+        let expected = (4.0 * 100.0 + 2.0 * 300.0 + 4.0 * 100.0) / 500.0;
+        assert!((ob.twa_spread().unwrap() - expected).abs() < 1e-12);
+
+        // The last removal left a spread of 6.
+        ob.reset_twa();
+        assert_eq!(ob.twa_spread(), None);
+        ob.apply_update_at(700, Update::Trade { price: 10002, quantity: 1, aggressor: Side::Bid, timestamp: 700 });
+        assert_eq!(ob.twa_spread(), Some(6.0));
+    }
+
+    #[test]
+    fn test_levels_raw() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.levels_raw(Side::Ask).best_index(), None);
+        ob.recenter_side(Side::Ask, 10500);
+        for (price, quantity) in [(10001, 4), (10003, 9), (12000, 2)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Ask });
+        }
+        let raw = ob.levels_raw(Side::Ask);
+        assert_eq!(raw.anchor(), 10500);
+        assert_eq!(raw.best_index().map(|i| raw.index_to_price(i)), Some(10001));
+        let mut occupied: Vec<_> = (0..CAP).filter(|&i| raw.quantities()[i] > 0).map(|i| (raw.index_to_price(i), raw.quantities()[i])).collect();
+        occupied.sort();
+        assert_eq!(occupied, ob.levels(Side::Ask).collect::<Vec<_>>());
+        assert_eq!(raw.quantities().iter().sum::<u64>(), ob.get_total_quantity(Side::Ask));
+        assert_eq!(raw.price_to_index(12000).map(|i| raw.quantities()[i]), Some(2));
+        assert_eq!(raw.price_to_index(12548), None);
+        assert_eq!(raw.price_to_index(8452), Some(CAP / 2));
+    }
+
+    #[test]
+    fn test_top_levels_array() {
+        let mut ob = OrderBookImpl::new();
+        for (price, quantity) in [(10001, 3), (10004, 1), (10002, 8), (10010, 5), (10007, 2)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Ask });
+        }
+        let (levels, filled) = ob.top_levels_array::<3>(Side::Ask);
+        assert_eq!(filled, 3);
+        assert_eq!(levels.to_vec(), ob.get_top_levels(Side::Ask, 3));
+
+        ob.apply_update(Update::Set { price: 9990, quantity: 6, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9995, quantity: 4, side: Side::Bid });
+        let (levels, filled) = ob.top_levels_array::<8>(Side::Bid);
+        assert_eq!(filled, 2);
+        assert_eq!(levels[..filled].to_vec(), ob.get_top_levels(Side::Bid, 8));
+        assert!(levels[filled..].iter().all(|&level| level == (0, 0)));
+        assert_eq!(ob.top_levels_array::<0>(Side::Bid).1, 0);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_pre_split_trait_bound_still_compiles() {
+        use rust_3::interfaces::OrderBook;
+
+        fn touch<T: OrderBook>(ob: &mut T) -> Option<i64> {
+            ob.apply_update(Update::Set { price: 9990, quantity: 1, side: Side::Bid });
+            ob.apply_update(Update::Set { price: 9994, quantity: 1, side: Side::Ask });
+            ob.get_spread()
+        }
+        fn spread_of(ob: &dyn OrderBook) -> Option<i64> {
+            ob.get_spread()
+        }
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(touch(&mut ob), Some(4));
+        assert_eq!(touch(&mut BTreeOrderBook::new()), Some(4));
+        assert_eq!(spread_of(&ob), Some(4));
+    }
+
+    #[test]
+    fn test_buffer_until_snapshot_replays_late_deltas() {
+        let bid = |price, quantity| Update::Set { price, quantity, side: Side::Bid };
+        let mut ob = OrderBookImpl::new();
+        ob.buffer_until_snapshot();
+        assert!(ob.is_buffering());
+        // Deltas 101..=105 stream in (105 ahead of 104) before the snapshot lands.
+        for (seq, update) in [(101, bid(9990, 1)), (102, bid(9991, 2)), (103, bid(9990, 0)), (105, bid(9993, 5)), (104, bid(9991, 7))] {
+            assert_eq!(ob.apply_sequenced(seq, update), Ok(()));
+        }
+        assert_eq!(ob.get_best_bid(), None);
+
+        // The snapshot was taken as of 103: it already has 101..=103 in it.
+        ob.apply_update(bid(9991, 2));
+        ob.apply_update(bid(9985, 9));
+        assert_eq!(ob.set_snapshot_baseline(103), Ok(2));
+        assert!(!ob.is_buffering());
+        assert_eq!(ob.get_top_levels(Side::Bid, 5), vec![(9993, 5), (9991, 7), (9985, 9)]);
+        assert_eq!(ob.last_sequence(), Some(105));
+
+        // Live from here on, gap-checked.
+        assert_eq!(ob.apply_sequenced(107, bid(9994, 1)), Err(OrderBookError::SequenceGap { expected: 106, received: 107 }));
+        assert_eq!(ob.apply_sequenced(106, bid(9994, 1)), Ok(()));
+        assert_eq!(ob.get_best_bid(), Some(9994));
+
+        // A delta lost during the next resync surfaces as a gap at replay.
+        ob.buffer_until_snapshot();
+        ob.apply_sequenced(201, bid(9980, 1)).unwrap();
+        ob.apply_sequenced(203, bid(9981, 1)).unwrap();
+        assert_eq!(ob.set_snapshot_baseline(199), Err(OrderBookError::SequenceGap { expected: 200, received: 201 }));
+        assert_eq!(ob.get_quantity_at(9980, Side::Bid), None);
+        assert!(!ob.is_buffering());
+    }
+
+    #[test]
+    fn test_books_behind_trait_objects() {
+        fn make_book(reference: bool) -> Box<dyn OrderBookWrite> {
+            if reference { Box::new(BTreeOrderBook::new()) } else { Box::new(OrderBookImpl::new()) }
+        }
+        let mut books: Vec<Box<dyn OrderBookRead>> = Vec::new();
+        for reference in [false, true] {
+            let mut book = make_book(reference);
+            book.apply_update(Update::Set { price: 9995, quantity: 3, side: Side::Bid });
+            book.apply_update(Update::Set { price: 10004, quantity: 8, side: Side::Ask });
+            book.apply_update(Update::Set { price: 10002, quantity: 1, side: Side::Ask });
+            books.push(book);
+        }
+        books.push(Box::new(OrderBookImpl::default()));
+        for book in &books[..2] {
+            assert_eq!(book.get_spread(), Some(7));
+            assert_eq!(book.get_top_levels(Side::Ask, 2), vec![(10002, 1), (10004, 8)]);
+            assert_eq!(book.get_total_quantity(Side::Ask), 9);
+        }
+        assert_eq!(books[2].get_best_bid(), None);
+    }
+
+    #[test]
+    fn test_center_of_mass() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.center_of_mass(Side::Bid), None);
+        let bids = [(9999, 2u64), (9995, 10), (9980, 30), (9900, 1)];
+        for (price, quantity) in bids {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        let weighted = bids.iter().map(|&(p, q)| p as f64 * q as f64).sum::<f64>() / bids.iter().map(|&(_, q)| q as f64).sum::<f64>();
+        assert!((ob.center_of_mass(Side::Bid).unwrap() - weighted).abs() < 1e-9);
+        // Bulk of the bids sits well behind the best.
+        assert!(ob.center_of_mass(Side::Bid).unwrap() < 9990.0);
+
+        ob.apply_update(Update::Remove { price: 9980, side: Side::Bid });
+        assert_eq!(ob.center_of_mass(Side::Bid), Some((9999.0 * 2.0 + 9995.0 * 10.0 + 9900.0) / 13.0));
+        assert_eq!(ob.center_of_mass(Side::Ask), None);
+    }
+
+    #[test]
+    fn test_center_of_mass_past_a_wrapped_total() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 10000, quantity: u64::MAX, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9998, quantity: u64::MAX, side: Side::Bid });
+        assert_eq!(ob.get_total_quantity(Side::Bid), u64::MAX - 1);
+        assert_eq!(ob.center_of_mass(Side::Bid), Some(9999.0));
+
+        // The total wraps to exactly 0, yet the side is not empty.
+        ob.apply_update(Update::Set { price: 9998, quantity: 1, side: Side::Bid });
+        assert_eq!(ob.get_total_quantity(Side::Bid), 0);
+        assert_eq!(ob.center_of_mass(Side::Bid), Some(10000.0));
+    }
+
+    #[test]
+    #[cfg(feature = "order-counts")]
+    fn test_order_counts() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::SetWithCount { price: 9990, quantity: 10, order_count: 3, side: Side::Bid });
+        ob.apply_update(Update::SetWithCount { price: 9995, quantity: 4, order_count: 1, side: Side::Bid });
+        ob.apply_update(Update::SetWithCount { price: 10005, quantity: 7, order_count: 2, side: Side::Ask });
+        assert_eq!(ob.get_order_count_at(9990, Side::Bid), Some(3));
+        assert_eq!(ob.get_order_count_at(9991, Side::Bid), None);
+        assert_eq!(ob.get_top_levels_with_counts(Side::Bid, 5), vec![(9995, 4, 1), (9990, 10, 3)]);
+
+        // A plain Set changes the quantity but keeps the count.
+        ob.apply_update(Update::Set { price: 9990, quantity: 12, side: Side::Bid });
+        assert_eq!(ob.get_order_count_at(9990, Side::Bid), Some(3));
+
+        // Removing a level drops its count; undo brings it back.
+        ob.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        assert_eq!(ob.get_order_count_at(9990, Side::Bid), None);
+        assert!(ob.undo_last());
+        assert_eq!(ob.get_order_count_at(9990, Side::Bid), Some(3));
+        ob.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9990, quantity: 1, side: Side::Bid });
+        assert_eq!(ob.get_order_count_at(9990, Side::Bid), Some(0));
+
+        // Recentering moves counts with their levels.
+        ob.recenter_anchor(10500);
+        assert_eq!(ob.get_top_levels_with_counts(Side::Ask, 5), vec![(10005, 7, 2)]);
+        assert_eq!(ob.get_order_count_at(9995, Side::Bid), Some(1));
+
+        ob.clear();
+        ob.apply_update(Update::Set { price: 10005, quantity: 7, side: Side::Ask });
+        assert_eq!(ob.get_order_count_at(10005, Side::Ask), Some(0));
+    }
+
+    #[test]
+    fn test_is_better() {
+        let ob = OrderBookImpl::new();
+        let anchor = ob.anchor(Side::Bid);
+        assert!(ob.is_better(Side::Bid, anchor + 1, anchor - 1));
+        assert!(!ob.is_better(Side::Bid, anchor - 1, anchor + 1));
+        assert!(ob.is_better(Side::Ask, anchor - 1, anchor + 1));
+        assert!(!ob.is_better(Side::Ask, anchor + 1, anchor - 1));
+        assert!(!ob.is_better(Side::Bid, anchor, anchor));
+        assert!(!ob.is_better(Side::Ask, anchor, anchor));
+        // Out-of-window prices alias in-window slots but still compare by price.
+        assert!(ob.is_better(Side::Bid, anchor + 5000, anchor + 5000 - CAP as i64 + 1));
+
+        // An unsigned window wrapping past zero: 3 is above u32::MAX - 3.
+        let ob = OrderBookImpl::<u32>::with_anchor(0);
+        assert!(ob.is_better(Side::Bid, 3, u32::MAX - 3));
+        assert!(ob.is_better(Side::Ask, u32::MAX - 3, 3));
+        assert!(!ob.is_better(Side::Ask, 3, u32::MAX - 3));
+    }
+
+    #[test]
+    fn test_anchor_from_first_update() {
+        let mut ob = OrderBookImpl::builder().anchor_mode(AnchorMode::FromFirstUpdate { round_to: 100 }).build();
+        assert_eq!(ob.book_state(), BookState::Unanchored);
+        assert_eq!(ob.get_best_bid(), None);
+
+        // Neither a removal, a trade nor an empty Set places the windows.
+        ob.apply_update(Update::Remove { price: 50_000, side: Side::Bid });
+        ob.apply_update(Update::Trade { price: 50_000, quantity: 1, aggressor: Side::Ask, timestamp: 0 });
+        ob.apply_update(Update::Set { price: 50_000, quantity: 0, side: Side::Bid });
+        assert_eq!(ob.book_state(), BookState::Unanchored);
+
+        // Far outside the default window, yet accepted: the book anchors on it first.
+        assert_eq!(ob.try_apply_update(Update::Set { price: 123_456, quantity: 4, side: Side::Bid }), Ok(()));
+        assert_eq!(ob.anchor(Side::Bid), 123_500);
+        assert_eq!(ob.anchor(Side::Ask), 123_500);
+        assert_eq!(ob.get_quantity_at(123_456, Side::Bid), Some(4));
+        assert_eq!(ob.book_state(), BookState::OneSided);
+
+        // From here on it behaves like any anchored book.
+        ob.apply_update(Update::Set { price: 123_460, quantity: 2, side: Side::Ask });
+        assert_eq!(ob.anchor(Side::Ask), 123_500);
+        assert!(!ob.in_window(130_000, Side::Ask));
+        assert_eq!(ob.book_state(), BookState::Normal);
+        ob.clear();
+        assert_eq!(ob.book_state(), BookState::Empty);
+    }
+
+    #[test]
+    fn test_anchor_from_first_snapshot() {
+        let mut ob = OrderBookImpl::builder().anchor_mode(AnchorMode::FromFirstUpdate { round_to: 10 }).build();
+        // A message that opens no level leaves the book unanchored.
+        ob.apply_depth_message(1, &[(10_000, 0)], &[]).unwrap();
+        assert_eq!(ob.book_state(), BookState::Unanchored);
+
+        let bids = [(89_990, 5), (89_995, 3)];
+        let asks = [(90_010, 2), (90_005, 0), (90_020, 7)];
+        ob.apply_depth_message(2, &bids, &asks).unwrap();
+        // Mid of 89_995 and 90_010, rounded to the nearest 10.
+        assert_eq!(ob.anchor(Side::Bid), 90_000);
+        assert_eq!(ob.get_top_levels(Side::Bid, 5), vec![(89_995, 3), (89_990, 5)]);
+        assert_eq!(ob.get_top_levels(Side::Ask, 5), vec![(90_010, 2), (90_020, 7)]);
+        assert_eq!(ob.book_state(), BookState::Normal);
+
+        // A snapshot wider than one window is refused whole, and the book stays unanchored.
+        let mut ob = OrderBookImpl::builder().anchor_mode(AnchorMode::FromFirstUpdate { round_to: 1 }).build();
+        let err = ob.apply_depth_message(1, &[(90_000, 1)], &[(90_000 + CAP as i64, 1)]).unwrap_err();
+        assert!(matches!(err, OrderBookError::OutOfWindow { .. }));
+        assert_eq!(ob.book_state(), BookState::Unanchored);
+
+        let mut locked = OrderBookImpl::new();
+        locked.apply_update(Update::Set { price: 10_000, quantity: 1, side: Side::Bid });
+        locked.apply_update(Update::Set { price: 10_000, quantity: 1, side: Side::Ask });
+        assert_eq!(locked.book_state(), BookState::Locked);
+        locked.apply_update(Update::Set { price: 10_001, quantity: 1, side: Side::Bid });
+        assert_eq!(locked.book_state(), BookState::Crossed);
+    }
+
+    #[test]
+    fn test_liquidity_state() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.liquidity_state(), LiquidityState::Empty);
+        ob.apply_update(Update::Set { price: 9990, quantity: 5, side: Side::Bid });
+        assert_eq!(ob.liquidity_state(), LiquidityState::BidOnly);
+        ob.apply_update(Update::Set { price: 10010, quantity: 3, side: Side::Ask });
+        assert_eq!(ob.liquidity_state(), LiquidityState::TwoSided);
+        ob.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        assert_eq!(ob.liquidity_state(), LiquidityState::AskOnly);
+        ob.apply_update(Update::Remove { price: 10010, side: Side::Ask });
+        assert_eq!(ob.liquidity_state(), LiquidityState::Empty);
+    }
+
+    #[test]
+    fn test_histograms_record_bbo_changes() {
+        let mut ob = OrderBookImpl::new();
+        ob.enable_histograms(HistogramStats::new(3));
+        ob.apply_update(Update::Set { price: 9998, quantity: 4, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10002, quantity: 6, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 9999, quantity: 2, side: Side::Bid });
+        // Away from the touch: no BBO change, nothing recorded.
+        ob.apply_update(Update::Set { price: 9990, quantity: 9, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10001, quantity: 6, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 10001, quantity: 8, side: Side::Ask });
+
+        let stats = ob.histograms().unwrap();
+        assert_eq!(stats.spread().buckets().collect::<Vec<_>>(), vec![(2, 3, 2), (3, 4, 1), (4, 5, 1)]);
+        assert_eq!(stats.touch_size(Side::Bid).buckets().collect::<Vec<_>>(), vec![(2, 3, 3), (4, 5, 1)]);
+        assert_eq!(stats.touch_size(Side::Ask).buckets().collect::<Vec<_>>(), vec![(6, 7, 3), (8, 9, 1)]);
+        assert_eq!(stats.spread().percentile(50.0), Some(3.0));
+
+        let stats = ob.disable_histograms().unwrap();
+        ob.apply_update(Update::Remove { price: 10001, side: Side::Ask });
+        assert!(ob.histograms().is_none());
+        assert_eq!(stats.spread().count(), 4);
+    }
+
+    #[test]
+    fn test_second_best() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 9990, quantity: 5, side: Side::Bid });
+        assert_eq!(ob.second_best(Side::Bid), None);
+        ob.apply_update(Update::Set { price: 9980, quantity: 2, side: Side::Bid });
+        assert_eq!(ob.second_best(Side::Bid), Some((9980, 2)));
+
+        // Changes behind the best: a better second, a quantity change, a worse level.
+        ob.apply_update(Update::Set { price: 9985, quantity: 7, side: Side::Bid });
+        assert_eq!(ob.second_best(Side::Bid), Some((9985, 7)));
+        ob.apply_update(Update::Set { price: 9985, quantity: 1, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9970, quantity: 3, side: Side::Bid });
+        assert_eq!(ob.second_best(Side::Bid), Some((9985, 1)));
+        assert_eq!(ob.get_best_bid(), Some(9990));
+
+        // Removing the second promotes the third; removing the best promotes the second.
+        ob.apply_update(Update::Remove { price: 9985, side: Side::Bid });
+        assert_eq!(ob.second_best(Side::Bid), Some((9980, 2)));
+        ob.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        assert_eq!(ob.get_best_bid(), Some(9980));
+        assert_eq!(ob.second_best(Side::Bid), Some((9970, 3)));
+        assert!(ob.undo_last());
+        assert_eq!(ob.second_best(Side::Bid), Some((9980, 2)));
+
+        // A new best pushes the old one into second place.
+        ob.apply_update(Update::Set { price: 10010, quantity: 4, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 10005, quantity: 6, side: Side::Ask });
+        assert_eq!(ob.second_best(Side::Ask), Some((10010, 4)));
+        ob.apply_update(Update::Remove { price: 10005, side: Side::Ask });
+        assert_eq!(ob.second_best(Side::Ask), None);
+    }
+
+    #[test]
+    fn test_second_best_matches_nth_level() {
+        let mut rng = SimRng::new(0x9e37_79b9_7f4a_7c15);
+        let mut ob = OrderBookImpl::new();
+        let mut seq = 0;
+        for round in 0..20_000 {
+            match rng.below(10) {
+                0..=7 => ob.apply_update(random_update(&mut rng, 10000, 80)),
+                8 => {
+                    ob.undo_last();
+                }
+                _ => {
+                    let price = random_price(&mut rng, 10000, 80);
+                    let levels = [(price, rng.below(3)), (price + 1, rng.below(3))];
+                    seq += 1;
+                    ob.apply_depth_message(seq, &levels, &levels).unwrap();
+                }
+            }
+            for side in [Side::Bid, Side::Ask] {
+                assert_eq!(ob.second_best(side), ob.nth_level(side, 1), "round {round}, {side:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_scaled_quantities_round_half_up() {
+        let mut ob = OrderBookImpl::builder().qty_divisor(100).build();
+        assert_eq!(ob.qty_divisor(), 100);
+        ob.apply_update(Update::Set { price: 9990, quantity: 149, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9989, quantity: 150, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9988, quantity: 49, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9987, quantity: 300, side: Side::Bid });
+        assert_eq!(ob.top_levels_scaled(Side::Bid, 4), vec![(9990, 1), (9989, 2), (9988, 0), (9987, 3)]);
+        // 648 base units: 6.48 rounds to 6.
+        assert_eq!(ob.get_total_quantity(Side::Bid), 648);
+        assert_eq!(ob.get_total_quantity_scaled(Side::Bid), 6);
+        ob.apply_update(Update::Set { price: 9988, quantity: 51, side: Side::Bid });
+        assert_eq!(ob.get_total_quantity_scaled(Side::Bid), 7);
+        assert_eq!(ob.top_levels_scaled(Side::Bid, 4)[2], (9988, 1));
+
+        // Odd divisor: 1/3 rounds down, 2/3 up.
+        let mut ob = OrderBookImpl::builder().qty_divisor(3).build();
+        ob.apply_update(Update::Set { price: 10010, quantity: 4, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 10011, quantity: 5, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 10012, quantity: u64::MAX - 10, side: Side::Ask });
+        assert_eq!(ob.top_levels_scaled(Side::Ask, 3), vec![(10010, 1), (10011, 2), (10012, u64::MAX / 3 - 3)]);
+
+        // Without a divisor the scaled getters are the raw ones.
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 9990, quantity: 7, side: Side::Bid });
+        assert_eq!(ob.get_total_quantity_scaled(Side::Bid), 7);
+    }
+
+    #[test]
+    fn test_recenter_reports_dropped_quantity() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 8000, quantity: 5, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 8500, quantity: 7, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 9990, quantity: 11, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 10010, quantity: 3, side: Side::Ask });
+        ob.apply_update(Update::Set { price: 12000, quantity: 9, side: Side::Ask });
+
+        // New window [9952, 13999]: both deep bids go, the asks survive.
+        assert_eq!(ob.recenter_anchor(12000), (12, 0));
+        assert_eq!(ob.get_total_quantity(Side::Bid), 11);
+        assert_eq!(ob.get_total_quantity(Side::Ask), 12);
+        // A no-op recenter drops nothing.
+        assert_eq!(ob.recenter_anchor(12000), (0, 0));
+        assert_eq!(ob.recenter_side(Side::Ask, 9000), (0, 9));
+        assert_eq!(ob.get_total_quantity(Side::Ask), 3);
+    }
+
+    #[test]
+    fn test_set_levels_matches_individual_sets() {
+        let mut rng = SimRng::new(0x1234_5678_9abc_def1);
+        let configs: [fn() -> OrderBookImpl; 4] = [
+            OrderBookImpl::new,
+            || OrderBookImpl::builder().max_levels(12).build(),
+            || OrderBookImpl::builder().tick_size(5).build(),
+            || OrderBookImpl::builder().on_cross(CrossPolicy::Reject).build(),
+        ];
+        for (round, config) in configs.into_iter().enumerate() {
+            let (mut bulk, mut single, mut reference) = (config(), config(), BTreeOrderBook::new());
+            for _ in 0..2000 {
+                let side = random_side(&mut rng);
+                let centre = match side { Side::Bid => 9990, Side::Ask => 10010 };
+                let levels: BulkLevels = (0..rng.below(10)).map(|_| (centre - 30 + rng.below(60) as i64, rng.below(4) * rng.below(50))).collect();
+                for &(price, quantity) in &levels {
+                    single.apply_update(Update::Set { price, quantity, side });
+                }
+                reference.apply_update(Update::SetLevels { side, levels: levels.clone() });
+                bulk.apply_update(Update::SetLevels { side, levels });
+                for side in [Side::Bid, Side::Ask] {
+                    assert!(bulk.levels(side).eq(single.levels(side)), "config {round}: {side:?} levels differ");
+                    assert_eq!(bulk.get_total_quantity(side), single.get_total_quantity(side));
+                    assert_eq!(bulk.second_best(side), single.second_best(side));
+                    if round == 0 {
+                        assert_eq!(bulk.get_top_levels(side, 100), reference.get_top_levels(side, 100));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_levels_notifies_bbo_once() {
+        use std::sync::{Arc, Mutex};
+
+        let mut ob = OrderBookImpl::new();
+        seed_two_sided(&mut ob);
+        let bid = ob.get_best_bid().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        ob.on_bbo_change(move |bbo| sink.lock().unwrap().push(bbo));
+
+        // Three touch changes in one message, one notification with the final state.
+        let levels = smallvec![(bid + 1, 4), (bid + 2, 6), (bid + 2, 0), (bid, 0)];
+        ob.apply_update(Update::SetLevels { side: Side::Bid, levels });
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].map(|bbo| (bbo.bid_price, bbo.bid_quantity)), Some((bid + 1, 4)));
+        assert!(!ob.undo_last());
+        drop(seen);
+
+        // The fallible path refuses the whole message if one level is outside the window.
+        let levels = smallvec![(bid + 3, 1), (bid - 5000, 1)];
+        let err = ob.try_apply_update(Update::SetLevels { side: Side::Bid, levels }).unwrap_err();
+        assert!(matches!(err, OrderBookError::OutOfWindow { .. }));
+        assert_eq!(ob.get_best_bid(), Some(bid + 1));
+    }
+
+    #[test]
+    fn test_format_price() {
+        let cents = OrderBookImpl::builder().price_scale(2).build();
+        assert_eq!(cents.price_scale(), 2);
+        assert_eq!(cents.format_price(10000), "100.00");
+        assert_eq!(cents.format_price(10005), "100.05");
+        assert_eq!(cents.format_price(7), "0.07");
+        assert_eq!(cents.format_price(-150), "-1.50");
+
+        // Quoted in 1/10000, stored in ticks of 0.0005.
+        let fine = OrderBookImpl::builder().price_scale(4).tick_size(5).build();
+        assert_eq!(fine.format_price(2000), "1.0000");
+        assert_eq!(fine.format_price(2001), "1.0005");
+        assert_eq!(fine.format_price(0), "0.0000");
+
+        assert_eq!(OrderBookImpl::new().format_price(10000), "10000");
+    }
+
+    #[test]
+    fn test_worst_levels() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!((ob.get_worst_bid(), ob.get_book_span(Side::Bid)), (None, None));
+        // Bids either side of the anchor so the scan crosses the ring's wrap point.
+        for price in [12000, 10000, 9990, 8000] {
+            ob.apply_update(Update::Set { price, quantity: 1, side: Side::Bid });
+        }
+        for price in [10001, 10005, 10050] {
+            ob.apply_update(Update::Set { price, quantity: 1, side: Side::Ask });
+        }
+        assert_eq!(ob.get_worst_bid(), Some(8000));
+        assert_eq!(ob.get_worst_ask(), Some(10050));
+        assert_eq!(ob.get_book_span(Side::Bid), Some(4000));
+        assert_eq!(ob.get_book_span(Side::Ask), Some(49));
+
+        // Removing a middle level leaves the worst alone; removing the worst finds the next.
+        ob.apply_update(Update::Remove { price: 10005, side: Side::Ask });
+        assert_eq!(ob.get_worst_ask(), Some(10050));
+        ob.apply_update(Update::Remove { price: 10050, side: Side::Ask });
+        assert_eq!(ob.get_worst_ask(), Some(10001));
+        assert_eq!(ob.get_book_span(Side::Ask), Some(0));
+        ob.apply_update(Update::Remove { price: 8000, side: Side::Bid });
+        assert_eq!(ob.get_worst_bid(), Some(9990));
+        ob.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        ob.apply_update(Update::Remove { price: 10000, side: Side::Bid });
+        assert_eq!(ob.get_worst_bid(), Some(12000));
+        ob.apply_update(Update::Remove { price: 10001, side: Side::Ask });
+        assert_eq!((ob.get_worst_ask(), ob.get_book_span(Side::Ask)), (None, None));
+    }
+
+    #[test]
+    fn test_levels_in_range() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.levels_in_range(Side::Bid, 0, 20000).count(), 0);
+        for (price, quantity) in [(12000, 1), (10000, 2), (9990, 3), (9985, 4), (9950, 5), (8000, 6)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        for (price, quantity) in [(10001, 1), (10003, 2), (10010, 3), (11000, 4)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Ask });
+        }
+        let bands = [(9985, 10000), (9986, 9989), (9000, 12000), (i64::MIN, i64::MAX), (10002, 10010), (10011, 10000), (11000, 11000)];
+        for side in [Side::Bid, Side::Ask] {
+            for (low, high) in bands {
+                let expected: Vec<_> = ob.get_top_levels(side, CAP).into_iter().filter(|&(price, _)| (low..=high).contains(&price)).collect();
+                assert_eq!(ob.levels_in_range(side, low, high).collect::<Vec<_>>(), expected, "{side:?} [{low}, {high}]");
+            }
+        }
+        assert_eq!(ob.levels_in_range(Side::Bid, 9950, 9990).collect::<Vec<_>>(), vec![(9990, 3), (9985, 4), (9950, 5)]);
+    }
+
+    #[test]
+    fn test_btreemap_round_trip() {
+        let bids = BTreeMap::from([(50_000, 3), (49_990, 7), (48_000, 1)]);
+        let asks = BTreeMap::from([(50_001, 2), (52_095, 9)]);
+        let ob = OrderBookImpl::try_from((&bids, &asks)).unwrap();
+        assert_eq!(ob.get_best_bid(), Some(50_000));
+        assert_eq!(ob.get_total_quantity(Side::Ask), 11);
+        assert_eq!(ob.to_maps(), (bids.clone(), asks.clone()));
+
+        // Book -> maps -> book keeps every level.
+        let mut ob = OrderBookImpl::new();
+        for (price, quantity, side) in [(9000, 4, Side::Bid), (9999, 1, Side::Bid), (10000, 2, Side::Ask), (12047, 8, Side::Ask)] {
+            ob.apply_update(Update::Set { price, quantity, side });
+        }
+        let (bids, asks) = ob.to_maps();
+        let copy = OrderBookImpl::try_from((&bids, &asks)).unwrap();
+        for side in [Side::Bid, Side::Ask] {
+            assert!(copy.levels(side).eq(ob.levels(side)));
+        }
+
+        let empty = BTreeMap::new();
+        assert_eq!(OrderBookImpl::try_from((&empty, &empty)).unwrap().book_state(), BookState::Empty);
+        let wide = BTreeMap::from([(10_000, 1), (10_000 + CAP as i64, 1)]);
+        assert!(matches!(OrderBookImpl::try_from((&wide, &empty)), Err(OrderBookError::SpanTooWide { low: 10_000, .. })));
+        let widest = BTreeMap::from([(10_000, 1), (10_000 + CAP as i64 - 1, 1)]);
+        assert_eq!(OrderBookImpl::try_from((&empty, &widest)).unwrap().to_maps().1, widest);
+        let zero = BTreeMap::from([(10_000, 0)]);
+        assert_eq!(
+            OrderBookImpl::try_from((&bids, &zero)).err(),
+            Some(OrderBookError::ZeroQuantity { side: Side::Ask, price: 10_000 })
+        );
+    }
+
+    #[test]
+    fn test_market_eq_ignores_anchors() {
+        let mut low = OrderBookImpl::builder().anchor(9000).build();
+        let mut high = OrderBookImpl::builder().bid_anchor(10500).ask_anchor(11000).build();
+        for (price, quantity, side) in [(9990, 5, Side::Bid), (9995, 2, Side::Bid), (10001, 4, Side::Ask), (10020, 1, Side::Ask)] {
+            low.apply_update(Update::Set { price, quantity, side });
+            high.apply_update(Update::Set { price, quantity, side });
+        }
+        assert_ne!(low.anchor(Side::Ask), high.anchor(Side::Ask));
+        assert!(low.market_eq(&high) && high.market_eq(&low));
+
+        // A recenter that drops nothing keeps the market.
+        let before = low.clone();
+        low.recenter_anchor(10200);
+        assert!(low.market_eq(&before));
+
+        low.apply_update(Update::Set { price: 10020, quantity: 2, side: Side::Ask });
+        assert!(!low.market_eq(&high));
+        low.apply_update(Update::Set { price: 10020, quantity: 1, side: Side::Ask });
+        low.apply_update(Update::Set { price: 9980, quantity: 1, side: Side::Bid });
+        assert!(!low.market_eq(&high));
+        assert!(OrderBookImpl::new().market_eq(&OrderBookImpl::builder().anchor(0).build()));
+    }
+
+    #[test]
+    fn test_book_into_iterator() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!((&ob).into_iter().len(), 0);
+        for (price, quantity, side) in [(9990, 5, Side::Bid), (9995, 2, Side::Bid), (12047, 3, Side::Bid), (10001, 4, Side::Ask), (10020, 1, Side::Ask)] {
+            ob.apply_update(Update::Set { price, quantity, side });
+        }
+        let mut expected: Vec<_> = ob.get_top_levels(Side::Ask, usize::MAX).into_iter().map(|(p, q)| (Side::Ask, p, q)).collect();
+        expected.extend(ob.get_top_levels(Side::Bid, usize::MAX).into_iter().map(|(p, q)| (Side::Bid, p, q)));
+
+        let mut iter = ob.into_iter();
+        assert_eq!(iter.len(), 5);
+        iter.next();
+        assert_eq!(iter.size_hint(), (4, Some(4)));
+        let all: Vec<_> = (&ob).into_iter().collect();
+        assert_eq!(all, expected);
+        assert_eq!(all.capacity(), 5);
+        let mut bid_total = 0;
+        for (side, _, quantity) in &ob {
+            if side == Side::Bid {
+                bid_total += quantity;
+            }
+        }
+        assert_eq!(bid_total, ob.get_total_quantity(Side::Bid));
+    }
+
+    #[test]
+    fn test_specialized_writes_match_apply_update() {
+        let mut rng = SimRng::new(0x6a09_e667_f3bc_c908);
+        let configs: [fn() -> OrderBookImpl; 4] = [
+            OrderBookImpl::new,
+            || OrderBookImpl::builder().max_levels(20).build(),
+            || OrderBookImpl::builder().on_cross(CrossPolicy::Reject).checksum_depth(5).build(),
+            || {
+                let mut ob = OrderBookImpl::new();
+                ob.on_bbo_change(|_| {});
+                ob
+            },
+        ];
+        for (config, make) in configs.iter().enumerate() {
+            let (mut fast, mut general) = (make(), make());
+            for round in 0..5_000 {
+                let side = random_side(&mut rng);
+                let price = random_price(&mut rng, 10000, 60);
+                let quantity = rng.below(8);
+                // Mostly right about whether the level is occupied, sometimes wrong.
+                let occupied = general.get_quantity_at(price, side).is_some() != (rng.below(8) == 0);
+                match rng.below(10) {
+                    0..=5 => {
+                        if occupied { fast.modify(price, quantity, side) } else { fast.insert_new(price, quantity, side) }
+                        general.apply_update(Update::Set { price, quantity, side });
+                    }
+                    6..=8 => {
+                        fast.apply_update(Update::Remove { price, side });
+                        general.apply_update(Update::Remove { price, side });
+                    }
+                    _ => assert_eq!(fast.undo_last(), general.undo_last()),
+                }
+                for side in [Side::Bid, Side::Ask] {
+                    assert!(fast.levels(side).eq(general.levels(side)), "config {config}, round {round}");
+                    assert_eq!(fast.get_total_quantity(side), general.get_total_quantity(side));
+                    assert_eq!(fast.get_total_notional(side), general.get_total_notional(side));
+                    assert_eq!(fast.second_best(side), general.second_best(side));
+                }
+                assert_eq!(fast.get_bbo(), general.get_bbo(), "config {config}, round {round}");
+                assert_eq!(fast.current_checksum(), general.current_checksum());
+            }
+        }
+    }
+
+    #[test]
+    fn test_fill_size_at_or_better() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.fill_size_at_or_better(Side::Bid, 10_010), 0);
+        for (price, quantity) in [(10001, 3), (10003, 5), (10006, 2)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Ask });
+        }
+        for (price, quantity) in [(9999, 4), (9995, 6)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        // Limits inside the range stop at the last level no worse than the limit.
+        assert_eq!(ob.fill_size_at_or_better(Side::Bid, 10003), 8);
+        assert_eq!(ob.fill_size_at_or_better(Side::Bid, 10005), 8);
+        assert_eq!(ob.fill_size_at_or_better(Side::Ask, 9996), 4);
+        assert_eq!(ob.fill_size_at_or_better(Side::Ask, 9995), 10);
+        // Beyond the far end everything fills; short of the touch nothing does.
+        assert_eq!(ob.fill_size_at_or_better(Side::Bid, 20_000), 10);
+        assert_eq!(ob.fill_size_at_or_better(Side::Ask, 0), 10);
+        assert_eq!(ob.fill_size_at_or_better(Side::Bid, 10000), 0);
+        assert_eq!(ob.fill_size_at_or_better(Side::Ask, 10000), 0);
+    }
+
+    #[test]
+    fn test_weighted_spread() {
+        let mut ob = OrderBookImpl::new();
+        for (price, quantity) in [(10001, 3), (10003, 5), (10006, 2)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Ask });
+        }
+        for (price, quantity) in [(9999, 4), (9995, 6)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        assert_eq!(ob.cost_to_fill(Side::Bid, 5), Some(3 * 10001 + 2 * 10003));
+        assert_eq!(ob.cost_to_fill(Side::Ask, 11), None);
+
+        // Within the touch sizes it is the touch spread; it widens as size walks the book.
+        let touch = ob.get_spread().unwrap();
+        assert_eq!(ob.weighted_spread(1), Some(touch));
+        assert_eq!(ob.weighted_spread(3), Some(touch));
+        // Buy 5: (3 * 10001 + 2 * 10003) / 5 = 10001.8; sell 5: (4 * 9999 + 9995) / 5 = 9998.2.
+        assert_eq!(ob.weighted_spread(5), Some(3));
+        // Buy 10: 100030 / 10; sell 10: 99966 / 10.
+        assert_eq!(ob.weighted_spread(10), Some(6));
+        let mut previous = touch;
+        for quantity in 1..=10 {
+            let spread = ob.weighted_spread(quantity).unwrap();
+            assert!(spread >= previous, "size {quantity}");
+            previous = spread;
+        }
+        assert_eq!(ob.weighted_spread(11), None);
+        assert_eq!(ob.weighted_spread(0), None);
+    }
+
+    #[test]
+    fn test_spread_in_ticks_f64() {
+        // Prices in 1e-4 units on a venue quoting in steps of 0.0025: a 0.0060 spread is 2.4 ticks.
+        let mut ob = OrderBookImpl::builder().quote_tick(25).build();
+        assert_eq!(ob.quote_tick(), 25);
+        ob.apply_update(Update::Set { price: 10000, quantity: 5, side: Side::Bid });
+        assert_eq!(ob.spread_in_ticks_f64(), None);
+        ob.apply_update(Update::Set { price: 10060, quantity: 5, side: Side::Ask });
+        assert_eq!(ob.spread_in_ticks_f64(), Some(2.4));
+        assert_eq!(ob.get_spread(), Some(60));
+
+        // With the default tick it is just the spread.
+        let mut plain = OrderBookImpl::new();
+        plain.apply_update(Update::Set { price: 10000, quantity: 5, side: Side::Bid });
+        plain.apply_update(Update::Set { price: 10003, quantity: 5, side: Side::Ask });
+        assert_eq!(plain.spread_in_ticks_f64(), Some(3.0));
+    }
+
+    #[test]
+    fn test_remove_many() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut ob = OrderBookImpl::new();
+        let mut expected = OrderBookImpl::new();
+        let bbo_changes = Arc::new(AtomicUsize::new(0));
+        let counter = bbo_changes.clone();
+        ob.on_bbo_change(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        for (price, quantity) in [(9990, 5), (9995, 7), (9998, 2), (10000, 4)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Bid });
+            expected.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        ob.apply_update(Update::Set { price: 10002, quantity: 3, side: Side::Ask });
+        expected.apply_update(Update::Set { price: 10002, quantity: 3, side: Side::Ask });
+        let calls = bbo_changes.load(Ordering::Relaxed);
+
+        // The best, a deeper level, two empty prices, an out-of-window one, and a repeat.
+        let prices = [10000, 9995, 9997, 10001, 10000 + 5000, 9995];
+        assert_eq!(ob.remove_many(Side::Bid, &prices), 2);
+        for price in [10000, 9995] {
+            expected.apply_update(Update::Remove { price, side: Side::Bid });
+        }
+        assert!(ob.levels(Side::Bid).eq(expected.levels(Side::Bid)));
+        assert_eq!(ob.get_best_bid(), Some(9998));
+        assert_eq!(ob.get_total_quantity(Side::Bid), 7);
+        assert_eq!(ob.second_best(Side::Bid), Some((9990, 5)));
+        assert_eq!(ob.get_top_levels(Side::Ask, 5), vec![(10002, 3)]);
+        assert_eq!(bbo_changes.load(Ordering::Relaxed), calls + 1);
+
+        assert_eq!(ob.remove_many(Side::Bid, &[9997, 10001]), 0);
+        assert_eq!(bbo_changes.load(Ordering::Relaxed), calls + 1);
+        assert_eq!(ob.remove_many(Side::Bid, &[9998, 9990]), 2);
+        assert_eq!(ob.get_best_bid(), None);
+        assert_eq!(ob.get_total_quantity(Side::Bid), 0);
+    }
+
+    #[test]
+    fn test_fuzz_apply_keeps_invariants() {
+        let mut rng = SimRng::new(0x9e37_79b9_7f4a_7c15);
+        let configs: [fn() -> OrderBookImpl; 4] = [
+            OrderBookImpl::new,
+            || OrderBookImpl::builder().checksum_depth(10).max_levels(50).build(),
+            || OrderBookImpl::with_cross_policy(CrossPolicy::AutoResolve),
+            || OrderBookImpl::builder().max_spread_ticks(20).build(),
+        ];
+        for make in configs {
+            let mut ob = make();
+            ob.verify_invariants().unwrap();
+            for _ in 0..300 {
+                // Raw bytes, with the offsets pulled near the anchor half the time so the
+                // window fills up rather than aliasing everywhere.
+                let len = rng.below(20 * FUZZ_RECORD_LEN as u64) as usize;
+                let mut data: Vec<u8> = (0..len).map(|_| rng.below(256) as u8).collect();
+                for record in data.chunks_exact_mut(FUZZ_RECORD_LEN) {
+                    if rng.below(2) == 0 {
+                        record[2] = if record[1] & 0x80 == 0 { 0 } else { 0xff };
+                    }
+                }
+                ob.fuzz_apply(&data);
+                if let Err(broken) = ob.verify_invariants() {
+                    panic!("{broken} after {data:?}");
+                }
+            }
+            assert!(ob.level_count(Side::Bid) + ob.level_count(Side::Ask) > 0);
+        }
+
+        // A partial record is ignored.
+        let mut ob = OrderBookImpl::new();
+        ob.fuzz_apply(&[0, 0, 0, 5, 0, 0]);
+        assert_eq!(ob.version(), 0);
+        ob.fuzz_apply(&[0b100, 3, 0, 5, 0, 0, 0]);
+        assert_eq!(ob.get_quantity_at(ob.anchor(Side::Ask) + 3, Side::Ask), Some(5));
+    }
+
+    #[test]
+    fn test_fold_levels() {
+        let mut ob = OrderBookImpl::new();
+        assert_eq!(ob.fold_levels(Side::Bid, 7, |acc, _, _| acc + 1), 7);
+        // Bids either side of the anchor, so the fold wraps from slot 0 to the top of the ring.
+        for (price, quantity) in [(9990, 4), (9995, 1), (10000, 6), (12047, 2)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        for (price, quantity) in [(10001, 3), (10002, 5), (10010, 9)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Ask });
+        }
+        ob.apply_update(Update::Set { price: 10002, quantity: 0, side: Side::Ask });
+        for side in [Side::Bid, Side::Ask] {
+            assert_eq!(ob.fold_levels(side, 0, |total, _, quantity| total + quantity), ob.get_total_quantity(side));
+        }
+        let visited = ob.fold_levels(Side::Ask, Vec::new(), |mut seen, price, quantity| {
+            seen.push((price, quantity));
+            seen
+        });
+        assert_eq!(visited, vec![(10001, 3), (10010, 9)]);
+        let notional = ob.fold_levels(Side::Bid, 0i64, |sum, price, quantity| sum + price * quantity as i64);
+        assert_eq!(notional, 12047 * 2 + 10000 * 6 + 9995 + 9990 * 4);
+    }
+
+    #[test]
+    fn test_total_notional_matches_recomputation() {
+        let mut rng = SimRng::new(0xd1b5_4a32_d192_ed03);
+        let mut ob = OrderBookImpl::new();
+        let mut seq = 0;
+        for round in 0..20_000 {
+            let side = random_side(&mut rng);
+            // The anchor drifts, so recenters drop levels from whichever end falls out.
+            let anchor = ob.anchor(side);
+            let price = random_price(&mut rng, anchor, 4000);
+            match rng.below(40) {
+                0..=19 => ob.apply_update(Update::Set { price, quantity: 1 + rng.below(1_000_000), side }),
+                20..=29 => ob.apply_update(Update::Remove { price, side }),
+                30..=32 => ob.apply_update(Update::Trade { price, quantity: 1 + rng.below(5), aggressor: side, timestamp: 0 }),
+                33 => {
+                    ob.undo_last();
+                }
+                34 => {
+                    let levels = (0..rng.below(5) as i64).map(|i| (price + i, rng.below(100))).collect();
+                    ob.apply_update(Update::SetLevels { side, levels });
+                }
+                35 => {
+                    let levels = [(price, rng.below(3)), (price + 1, rng.below(3))];
+                    seq += 1;
+                    let (bids, asks): (&[_], &[_]) = if side == Side::Bid { (&levels, &[]) } else { (&[], &levels) };
+                    ob.apply_depth_message(seq, bids, asks).unwrap();
+                }
+                36..=37 => {
+                    ob.recenter_anchor(anchor - 1500 + rng.below(3000) as i64);
+                }
+                38 => {
+                    ob.recenter_side(side, anchor - 1500 + rng.below(3000) as i64);
+                }
+                _ if rng.below(10) == 0 => ob.clear(),
+                _ => {}
+            }
+            for side in [Side::Bid, Side::Ask] {
+                let expected = ob.fold_levels(side, 0i128, |sum, price, quantity| sum + price as i128 * quantity as i128);
+                assert_eq!(ob.get_total_notional(side), expected, "round {round}, {side:?}");
+            }
+        }
+    }
 }
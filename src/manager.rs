@@ -0,0 +1,197 @@
+// manager.rs
+
+use std::num::NonZeroUsize;
+use crate::interfaces::{OrderBookWrite, Update};
+use crate::orderbook::OrderBookImpl;
+
+/// Identifies a book in an `OrderBookManager`; handed out by `add_book` in order from 0.
+pub type SymbolId = u32;
+
+/// One book per symbol, with batch application that spreads a burst over worker threads.
+///
+/// Books are independent, so `apply_batch_parallel` only has to keep each book's own updates in
+/// order: it groups the batch by symbol (a counting sort, arrival order kept within a symbol)
+/// and hands each worker a contiguous run of books with about the same number of updates.
+/// Every book ends up exactly as with `apply_batch`; the order in which different books see
+/// their updates (and so the order their BBO callbacks fire) is unspecified.
+pub struct OrderBookManager {
+    books: Vec<OrderBookImpl>,
+    parallel_threshold: usize,
+    workers: usize,
+}
+
+impl Default for OrderBookManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderBookManager {
+    /// No books yet; one worker per available core and a parallel threshold of 4096 updates.
+    pub fn new() -> Self {
+        let workers = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        OrderBookManager { books: Vec::new(), parallel_threshold: 4096, workers }
+    }
+
+    /// Batches smaller than `updates` are applied on the calling thread by
+    /// `apply_batch_parallel`, where spawning workers would cost more than it saves.
+    pub fn parallel_threshold(mut self, updates: usize) -> Self {
+        self.parallel_threshold = updates;
+        self
+    }
+
+    /// Threads `apply_batch_parallel` may use (at least 1).
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    pub fn add_book(&mut self, book: OrderBookImpl) -> SymbolId {
+        self.books.push(book);
+        (self.books.len() - 1) as SymbolId
+    }
+
+    pub fn book(&self, symbol: SymbolId) -> Option<&OrderBookImpl> {
+        self.books.get(symbol as usize)
+    }
+
+    pub fn book_mut(&mut self, symbol: SymbolId) -> Option<&mut OrderBookImpl> {
+        self.books.get_mut(symbol as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.books.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.books.is_empty()
+    }
+
+    /// Applies `updates` in order on the calling thread. Updates for an unknown symbol are
+    /// dropped; returns how many were applied.
+    pub fn apply_batch(&mut self, updates: &[(SymbolId, Update)]) -> usize {
+        let mut applied = 0;
+        for (symbol, update) in updates {
+            if let Some(book) = self.books.get_mut(*symbol as usize) {
+                book.apply_update(update.clone());
+                applied += 1;
+            }
+        }
+        applied
+    }
+
+    /// `apply_batch` spread over up to `workers` scoped threads, each owning a disjoint run of
+    /// books. Falls back to `apply_batch` below the parallel threshold or with one worker.
+    pub fn apply_batch_parallel(&mut self, updates: &[(SymbolId, Update)]) -> usize {
+        let workers = self.workers.min(self.books.len());
+        if updates.len() < self.parallel_threshold || workers < 2 {
+            return self.apply_batch(updates);
+        }
+
+        // starts[b]..starts[b + 1] is book b's run in `order`, which holds batch positions.
+        let books = self.books.len();
+        let mut starts = vec![0usize; books + 1];
+        for &(symbol, _) in updates {
+            if (symbol as usize) < books {
+                starts[symbol as usize + 1] += 1;
+            }
+        }
+        for book in 0..books {
+            starts[book + 1] += starts[book];
+        }
+        let applied = starts[books];
+        let mut order = vec![0u32; applied];
+        let mut fill = starts.clone();
+        for (position, &(symbol, _)) in updates.iter().enumerate() {
+            if (symbol as usize) < books {
+                order[fill[symbol as usize]] = position as u32;
+                fill[symbol as usize] += 1;
+            }
+        }
+
+        let per_worker = applied.div_ceil(workers).max(1);
+        std::thread::scope(|scope| {
+            let (order, starts) = (&order, &starts);
+            let mut rest = &mut self.books[..];
+            let mut first = 0;
+            while !rest.is_empty() {
+                let mut end = first + 1;
+                while end < books && starts[end] - starts[first] < per_worker {
+                    end += 1;
+                }
+                let (chunk, tail) = std::mem::take(&mut rest).split_at_mut(end - first);
+                rest = tail;
+                let (base, positions) = (first, &order[starts[first]..starts[end]]);
+                first = end;
+                if positions.is_empty() {
+                    continue;
+                }
+                scope.spawn(move || {
+                    for &position in positions {
+                        let (symbol, update) = &updates[position as usize];
+                        chunk[*symbol as usize - base].apply_update(update.clone());
+                    }
+                });
+            }
+        });
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::{OrderBookRead, Side};
+    use crate::sim::SimRng;
+    use crate::testing::random_update;
+
+    fn burst(symbols: u32, per_symbol: u32) -> Vec<(SymbolId, Update)> {
+        let mut rng = SimRng::new(0x3c6e_f372_fe94_f82b);
+        (0..symbols * per_symbol)
+            .map(|_| {
+                let symbol = rng.below(symbols as u64) as SymbolId;
+                (symbol, random_update(&mut rng, 10000, 40))
+            })
+            .collect()
+    }
+
+    fn manager(symbols: u32, workers: usize) -> OrderBookManager {
+        let mut manager = OrderBookManager::new().workers(workers).parallel_threshold(0);
+        for _ in 0..symbols {
+            manager.add_book(OrderBookImpl::new());
+        }
+        manager
+    }
+
+    #[test]
+    fn parallel_batches_match_sequential_application() {
+        for (symbols, workers) in [(50, 4), (3, 8), (1, 2), (7, 3)] {
+            let mut updates = burst(symbols, 40);
+            // Unknown symbols are dropped by both paths.
+            updates.push((symbols, Update::Set { price: 10000, quantity: 1, side: Side::Bid }));
+            let (mut parallel, mut sequential) = (manager(symbols, workers), manager(symbols, 1));
+            for _ in 0..3 {
+                assert_eq!(parallel.apply_batch_parallel(&updates), sequential.apply_batch(&updates));
+            }
+            for symbol in 0..symbols {
+                let (a, b) = (parallel.book(symbol).unwrap(), sequential.book(symbol).unwrap());
+                for side in [Side::Bid, Side::Ask] {
+                    assert!(a.levels(side).eq(b.levels(side)), "symbol {symbol}, {side:?}");
+                }
+                assert_eq!(a.get_bbo(), b.get_bbo());
+            }
+        }
+    }
+
+    #[test]
+    fn small_batches_stay_on_the_calling_thread() {
+        let mut manager = OrderBookManager::new().workers(4).parallel_threshold(100);
+        let symbol = manager.add_book(OrderBookImpl::new());
+        manager.add_book(OrderBookImpl::new());
+        let caller = std::thread::current().id();
+        manager.book_mut(symbol).unwrap().on_bbo_change(move |_| assert_eq!(std::thread::current().id(), caller));
+        let updates = [(symbol, Update::Set { price: 10000, quantity: 5, side: Side::Bid })];
+        assert_eq!(manager.apply_batch_parallel(&updates), 1);
+        assert_eq!(manager.book(symbol).unwrap().get_best_bid(), Some(10000));
+    }
+}
@@ -0,0 +1,195 @@
+// mbp.rs
+
+use crate::interfaces::{Price, Side};
+use crate::orderbook::OrderBookImpl;
+
+/// Databento's null price (`UNDEF_PRICE`): an absent level or an unset price field.
+pub const UNDEF_PRICE: i64 = i64::MAX;
+/// `rtype` of an MBP-10 record.
+pub const RTYPE_MBP10: u8 = 0x0A;
+/// `flags` bit marking the last record of an event.
+pub const F_LAST: u8 = 1 << 7;
+/// `flags` bit marking a record built from a snapshot rather than an incremental update.
+pub const F_SNAPSHOT: u8 = 1 << 5;
+
+/// Common DBN record header: 16 bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordHeader {
+    /// Record length in 4-byte words.
+    pub length: u8,
+    pub rtype: u8,
+    pub publisher_id: u16,
+    pub instrument_id: u32,
+    pub ts_event: u64,
+}
+
+/// One depth level of both sides. Prices are fixed-point in units of 1e-9.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BidAskPair {
+    pub bid_px: i64,
+    pub ask_px: i64,
+    pub bid_sz: u32,
+    pub ask_sz: u32,
+    pub bid_ct: u32,
+    pub ask_ct: u32,
+}
+
+impl BidAskPair {
+    /// Both sides absent: `UNDEF_PRICE`, zero size and count.
+    pub const EMPTY: BidAskPair = BidAskPair { bid_px: UNDEF_PRICE, ask_px: UNDEF_PRICE, bid_sz: 0, ask_sz: 0, bid_ct: 0, ask_ct: 0 };
+}
+
+/// Databento MBP-10 record in its published 368-byte layout (`repr(C)`, native endianness), so a
+/// slice of these can be written out as DBN record bodies as-is.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mbp10Record {
+    pub hd: RecordHeader,
+    /// Price of the triggering event, `UNDEF_PRICE` for a plain book export.
+    pub price: i64,
+    pub size: u32,
+    /// `'A'`dd, `'C'`ancel, `'M'`odify, `'R'` clear, `'T'`rade, `'F'`ill or `'N'`one.
+    pub action: u8,
+    /// `'A'`sk, `'B'`id or `'N'`one.
+    pub side: u8,
+    pub flags: u8,
+    /// Level of the triggering event.
+    pub depth: u8,
+    pub ts_recv: u64,
+    pub ts_in_delta: i32,
+    pub sequence: u32,
+    /// Best level first.
+    pub levels: [BidAskPair; 10],
+}
+
+/// The parts of an `Mbp10Record` that the book does not know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RecordMeta {
+    pub publisher_id: u16,
+    pub instrument_id: u32,
+    pub ts_event: u64,
+    pub ts_recv: u64,
+    pub ts_in_delta: i32,
+    pub sequence: u32,
+    pub flags: u8,
+    /// Value of one book price unit in 1e-9 units (e.g. 10_000_000 for a 0.01 tick). Scaled
+    /// prices saturate.
+    pub price_scale: i64,
+}
+
+/// Inverse of the scaling in `to_mbp10`: a 1e-9 fixed-point price as book units of
+/// `price_scale` each. `None` for `UNDEF_PRICE`, a non-positive scale, or a price between ticks.
+pub fn price_from_fixed(px: i64, price_scale: i64) -> Option<Price> {
+    if px == UNDEF_PRICE || price_scale <= 0 || px % price_scale != 0 {
+        return None;
+    }
+    Some(px / price_scale)
+}
+
+impl OrderBookImpl {
+    /// The top 10 levels of each side as an MBP-10 record with no triggering event (action and
+    /// side `'N'`, price `UNDEF_PRICE`). Missing levels are padded with `BidAskPair::EMPTY`;
+    /// sizes saturate at `u32::MAX`. Order counts are the book's (see `get_order_count_at`);
+    /// without the `order-counts` feature they are 0.
+    pub fn to_mbp10(&self, meta: &RecordMeta) -> Mbp10Record {
+        let mut levels = [BidAskPair::EMPTY; 10];
+        let scale = |price: Price| price.saturating_mul(meta.price_scale);
+        let size = |quantity: u64| u32::try_from(quantity).unwrap_or(u32::MAX);
+        for (level, (price, quantity)) in levels.iter_mut().zip(self.levels(Side::Bid)) {
+            level.bid_px = scale(price);
+            level.bid_sz = size(quantity);
+            level.bid_ct = self.slot_count(Side::Bid, self.price_to_index(price, Side::Bid));
+        }
+        for (level, (price, quantity)) in levels.iter_mut().zip(self.levels(Side::Ask)) {
+            level.ask_px = scale(price);
+            level.ask_sz = size(quantity);
+            level.ask_ct = self.slot_count(Side::Ask, self.price_to_index(price, Side::Ask));
+        }
+        Mbp10Record {
+            hd: RecordHeader {
+                length: (size_of::<Mbp10Record>() / 4) as u8,
+                rtype: RTYPE_MBP10,
+                publisher_id: meta.publisher_id,
+                instrument_id: meta.instrument_id,
+                ts_event: meta.ts_event,
+            },
+            price: UNDEF_PRICE,
+            size: 0,
+            action: b'N',
+            side: b'N',
+            flags: meta.flags,
+            depth: 0,
+            ts_recv: meta.ts_recv,
+            ts_in_delta: meta.ts_in_delta,
+            sequence: meta.sequence,
+            levels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::offset_of;
+    use crate::interfaces::{OrderBookWrite, Update};
+
+    #[test]
+    fn layout_matches_the_schema() {
+        assert_eq!(size_of::<RecordHeader>(), 16);
+        assert_eq!(size_of::<BidAskPair>(), 32);
+        assert_eq!(size_of::<Mbp10Record>(), 368);
+        assert_eq!(offset_of!(Mbp10Record, price), 16);
+        assert_eq!(offset_of!(Mbp10Record, ts_recv), 32);
+        assert_eq!(offset_of!(Mbp10Record, sequence), 44);
+        assert_eq!(offset_of!(Mbp10Record, levels), 48);
+    }
+
+    #[test]
+    fn golden_record() {
+        let mut ob = OrderBookImpl::new();
+        for (price, quantity) in [(9999, 5), (9998, 7)] {
+            ob.apply_update(Update::Set { price, quantity, side: Side::Bid });
+        }
+        ob.apply_update(Update::Set { price: 10001, quantity: 1 << 40, side: Side::Ask });
+        let meta = RecordMeta {
+            publisher_id: 1,
+            instrument_id: 42,
+            ts_event: 1_000,
+            ts_recv: 1_010,
+            ts_in_delta: 3,
+            sequence: 77,
+            flags: F_LAST | F_SNAPSHOT,
+            price_scale: 10_000_000,
+        };
+
+        let mut levels = [BidAskPair::EMPTY; 10];
+        levels[0] = BidAskPair { bid_px: 99_990_000_000, ask_px: 100_010_000_000, bid_sz: 5, ask_sz: u32::MAX, bid_ct: 0, ask_ct: 0 };
+        levels[1] = BidAskPair { bid_px: 99_980_000_000, ask_px: UNDEF_PRICE, bid_sz: 7, ask_sz: 0, bid_ct: 0, ask_ct: 0 };
+        let expected = Mbp10Record {
+            hd: RecordHeader { length: 92, rtype: 0x0A, publisher_id: 1, instrument_id: 42, ts_event: 1_000 },
+            price: i64::MAX,
+            size: 0,
+            action: b'N',
+            side: b'N',
+            flags: 0xA0,
+            depth: 0,
+            ts_recv: 1_010,
+            ts_in_delta: 3,
+            sequence: 77,
+            levels,
+        };
+        assert_eq!(ob.to_mbp10(&meta), expected);
+        assert_eq!(OrderBookImpl::new().to_mbp10(&meta).levels, [BidAskPair::EMPTY; 10]);
+    }
+
+    #[test]
+    fn fixed_prices_convert_back_on_tick_only() {
+        assert_eq!(price_from_fixed(99_990_000_000, 10_000_000), Some(9999));
+        assert_eq!(price_from_fixed(-250_000_000, 250_000_000), Some(-1));
+        assert_eq!(price_from_fixed(99_995_000_000, 10_000_000_000), None);
+        assert_eq!(price_from_fixed(UNDEF_PRICE, 1), None);
+        assert_eq!(price_from_fixed(100, 0), None);
+    }
+}
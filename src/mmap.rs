@@ -0,0 +1,402 @@
+// mmap.rs
+
+use std::fs::OpenOptions;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use memmap2::{MmapMut, MmapRaw};
+use crate::interfaces::{OrderBookRead, OrderBookWrite, Price, Quantity, Side, Update};
+use crate::orderbook::{CAP, OrderBookImpl};
+
+/// File layout of an `MmapOrderBook`: an `MmapHeader` padded to one page, then the bid slots,
+/// then the ask slots, each `CAP` native-endian `u64` quantities in the book's own slot order
+/// (see `OrderBookImpl::raw_side`). Files are only meaningful on the machine that wrote them.
+pub const MMAP_MAGIC: [u8; 8] = *b"RSOBMMAP";
+pub const MMAP_LAYOUT_VERSION: u32 = 1;
+const HEADER_LEN: usize = 4096;
+const SIDE_LEN: usize = CAP * size_of::<Quantity>();
+pub const MMAP_FILE_LEN: usize = HEADER_LEN + 2 * SIDE_LEN;
+const NO_BEST: u64 = u64::MAX;
+
+/// The fixed fields at the start of the file, refreshed after every write. Everything but the
+/// magic, layout, capacity and anchors can be rebuilt from the slots.
+#[repr(C)]
+struct MmapHeader {
+    magic: [u8; 8],
+    layout_version: u32,
+    cap: u32,
+    /// 1 once `close` has run; 0 while a process has the file open.
+    clean: u32,
+    _reserved: u32,
+    /// Writes since the file was created.
+    generation: u64,
+    anchors: [Price; 2],
+    totals: [Quantity; 2],
+    counts: [u32; 2],
+    /// Best slot per side, `NO_BEST` when the side is empty.
+    best: [u64; 2],
+    version: u64,
+}
+
+/// One side's slots inside the mapping. The bid and ask halves never overlap, so each may be
+/// borrowed mutably on its own. Cloning copies the slots into an anonymous mapping, detached
+/// from the file (a cloned book is an independent copy).
+pub struct MmapLevels {
+    _map: Arc<MmapRaw>,
+    slots: NonNull<[Quantity; CAP]>,
+}
+
+// The pointer is into `_map`, which lives as long as this value, and only this value reaches
+// its half of the mapping.
+unsafe impl Send for MmapLevels {}
+unsafe impl Sync for MmapLevels {}
+
+impl MmapLevels {
+    fn new(map: &Arc<MmapRaw>, offset: usize) -> Self {
+        assert!(offset + SIDE_LEN <= map.len());
+        // The mapping is page aligned and `offset` a multiple of 8.
+        let slots = NonNull::new(unsafe { map.as_mut_ptr().add(offset) } as *mut [Quantity; CAP]).expect("mapping is not null");
+        MmapLevels { _map: map.clone(), slots }
+    }
+}
+
+impl Deref for MmapLevels {
+    type Target = [Quantity; CAP];
+
+    #[inline(always)]
+    fn deref(&self) -> &[Quantity; CAP] {
+        unsafe { self.slots.as_ref() }
+    }
+}
+
+impl DerefMut for MmapLevels {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut [Quantity; CAP] {
+        unsafe { self.slots.as_mut() }
+    }
+}
+
+impl Clone for MmapLevels {
+    fn clone(&self) -> Self {
+        let map = Arc::new(MmapRaw::from(MmapMut::map_anon(SIDE_LEN).expect("anonymous mapping for a cloned side")));
+        let mut copy = MmapLevels::new(&map, 0);
+        copy.copy_from_slice(&**self);
+        copy
+    }
+}
+
+/// A book whose slots and header live in a memory-mapped file, so a restarted process can
+/// `open` the state the previous one left instead of rebuilding it from the feed.
+///
+/// Writes land in the mapping directly; the header (anchors, totals, counts, best indices,
+/// version and a generation counter) is refreshed after each one. The kernel keeps the pages
+/// when the process dies, so nothing is lost to a crash; `flush` is needed only against losing
+/// the machine.
+///
+/// **Single writer.** Exactly one `MmapOrderBook` may have a file open at a time, in one process.
+/// Nothing enforces this: two writers, or a reader in another process, see torn state.
+///
+/// A process that dies (or drops the book without calling `close`) leaves the file marked as
+/// not cleanly closed, and the next `open` rebuilds the header fields from the slots; see
+/// `recovered`. A crash in the middle of a single update leaves that update either applied to
+/// its slot or not, so the recovered book is consistent, but the feed has to resync from the
+/// sequence it last knows was applied.
+pub struct MmapOrderBook {
+    book: OrderBookImpl<Price, MmapLevels>,
+    map: Arc<MmapRaw>,
+    generation: u64,
+    recovered: bool,
+}
+
+impl MmapOrderBook {
+    /// Creates (or truncates) `path` as an empty book centred on 10000, like
+    /// `OrderBookImpl::new`. `cap` is the slot count per side and must be `CAP`, the window
+    /// every book is compiled with; it is stored so that a build with another `CAP` refuses
+    /// the file.
+    pub fn create(path: impl AsRef<Path>, cap: usize) -> io::Result<Self> {
+        Self::create_at(path, cap, 10000)
+    }
+
+    /// `create` with both windows centred on `anchor`.
+    pub fn create_at(path: impl AsRef<Path>, cap: usize, anchor: Price) -> io::Result<Self> {
+        if cap != CAP {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("capacity must be {CAP}, got {cap}")));
+        }
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.set_len(MMAP_FILE_LEN as u64)?;
+        let map = Arc::new(MmapRaw::map_raw(&file)?);
+        let book = OrderBookImpl::with_storage(anchor, MmapLevels::new(&map, HEADER_LEN), MmapLevels::new(&map, HEADER_LEN + SIDE_LEN));
+        let mut created = MmapOrderBook { book, map, generation: 0, recovered: false };
+        let header = created.header_mut();
+        header.magic = MMAP_MAGIC;
+        header.layout_version = MMAP_LAYOUT_VERSION;
+        header.cap = CAP as u32;
+        header.clean = 0;
+        created.sync_header();
+        created.map.flush()?;
+        Ok(created)
+    }
+
+    /// Reopens a file made by `create`. Refuses, as `InvalidData`, a file that is too short or
+    /// has the wrong magic, layout version or capacity. If the file was closed cleanly its header
+    /// must agree with its slots (anything else is corruption and refused too); if not, the
+    /// derived fields are rebuilt from the slots and `recovered` reports it.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len();
+        if len < MMAP_FILE_LEN as u64 {
+            return Err(invalid(format!("file is {len} bytes, a mapped book needs {MMAP_FILE_LEN}")));
+        }
+        let map = Arc::new(MmapRaw::map_raw(&file)?);
+        let header = unsafe { &*(map.as_ptr() as *const MmapHeader) };
+        if header.magic != MMAP_MAGIC {
+            return Err(invalid("not a mapped order book file".to_string()));
+        }
+        if header.layout_version != MMAP_LAYOUT_VERSION {
+            return Err(invalid(format!("layout version {} is not supported (this build reads {MMAP_LAYOUT_VERSION})", header.layout_version)));
+        }
+        if header.cap != CAP as u32 {
+            return Err(invalid(format!("file holds {} slots per side, this build uses {CAP}", header.cap)));
+        }
+        let clean = header.clean == 1;
+        let (anchors, version, generation) = (header.anchors, header.version, header.generation);
+        let book = OrderBookImpl::from_slots(anchors, MmapLevels::new(&map, HEADER_LEN), MmapLevels::new(&map, HEADER_LEN + SIDE_LEN), version);
+        let mut opened = MmapOrderBook { book, map, generation, recovered: !clean };
+        if clean && !opened.header_matches() {
+            return Err(invalid("header disagrees with the levels of a cleanly closed file".to_string()));
+        }
+        opened.header_mut().clean = 0;
+        opened.sync_header();
+        Ok(opened)
+    }
+
+    /// Whether `open` found the file not cleanly closed and rebuilt its header from the slots.
+    pub fn recovered(&self) -> bool {
+        self.recovered
+    }
+
+    /// Writes applied to this file since it was created, across processes.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The book itself, for everything beyond the `OrderBookRead` methods.
+    pub fn book(&self) -> &OrderBookImpl<Price, MmapLevels> {
+        &self.book
+    }
+
+    /// `apply_update` with the book clock set to `timestamp`; see `OrderBookImpl::apply_update_at`.
+    pub fn apply_update_at(&mut self, timestamp: u64, update: Update) {
+        self.book.apply_update_at(timestamp, update);
+        self.record_write();
+    }
+
+    /// Writes the mapping back to the file, for durability beyond the process (power loss).
+    pub fn flush(&self) -> io::Result<()> {
+        self.map.flush()
+    }
+
+    /// Flushes and marks the file cleanly closed, so the next `open` trusts its header.
+    pub fn close(mut self) -> io::Result<()> {
+        self.sync_header();
+        self.map.flush()?;
+        self.header_mut().clean = 1;
+        self.map.flush_range(0, HEADER_LEN)
+    }
+
+    fn header_mut(&mut self) -> &mut MmapHeader {
+        // The header page is never reached through the `MmapLevels`.
+        unsafe { &mut *(self.map.as_mut_ptr() as *mut MmapHeader) }
+    }
+
+    fn record_write(&mut self) {
+        self.generation += 1;
+        self.sync_header();
+    }
+
+    /// Copies the generation and the derived fields into the header.
+    fn sync_header(&mut self) {
+        let generation = self.generation;
+        let fields = self.header_fields();
+        let header = self.header_mut();
+        header.generation = generation;
+        (header.anchors, header.totals, header.counts, header.best, header.version) = fields;
+    }
+
+    fn header_fields(&self) -> ([Price; 2], [Quantity; 2], [u32; 2], [u64; 2], u64) {
+        let book = &self.book;
+        let sides = [Side::Bid, Side::Ask];
+        (
+            sides.map(|side| book.anchor(side)),
+            sides.map(|side| book.get_total_quantity(side)),
+            sides.map(|side| book.level_count(side) as u32),
+            sides.map(|side| book.levels_raw(side).best_index().map_or(NO_BEST, |index| index as u64)),
+            book.version(),
+        )
+    }
+
+    fn header_matches(&self) -> bool {
+        let header = unsafe { &*(self.map.as_ptr() as *const MmapHeader) };
+        (header.anchors, header.totals, header.counts, header.best, header.version) == self.header_fields()
+    }
+}
+
+fn invalid(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+impl OrderBookRead for MmapOrderBook {
+    fn get_spread(&self) -> Option<Price> {
+        self.book.get_spread()
+    }
+
+    fn get_best_bid(&self) -> Option<Price> {
+        self.book.get_best_bid()
+    }
+
+    fn get_best_ask(&self) -> Option<Price> {
+        self.book.get_best_ask()
+    }
+
+    fn get_quantity_at(&self, price: Price, side: Side) -> Option<Quantity> {
+        self.book.get_quantity_at(price, side)
+    }
+
+    fn get_top_levels(&self, side: Side, n: usize) -> Vec<(Price, Quantity)> {
+        self.book.get_top_levels(side, n)
+    }
+
+    fn get_total_quantity(&self, side: Side) -> Quantity {
+        self.book.get_total_quantity(side)
+    }
+}
+
+impl OrderBookWrite for MmapOrderBook {
+    fn apply_update(&mut self, update: Update) {
+        self.book.apply_update(update);
+        self.record_write();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rust-orderbook-{}-{}.mmap", std::process::id(), name))
+    }
+
+    fn fill(book: &mut impl OrderBookWrite) {
+        for (i, price) in [9990, 9995, 9999, 8100].into_iter().enumerate() {
+            book.apply_update(Update::Set { price, quantity: 10 + i as u64, side: Side::Bid });
+        }
+        for (i, price) in [10001, 10004, 11900].into_iter().enumerate() {
+            book.apply_update(Update::Set { price, quantity: 20 + i as u64, side: Side::Ask });
+        }
+        book.apply_update(Update::Remove { price: 9999, side: Side::Bid });
+    }
+
+    #[test]
+    fn closed_books_reopen_as_they_were() {
+        let path = scratch("clean");
+        let mut expected = OrderBookImpl::new();
+        fill(&mut expected);
+        let mut mapped = MmapOrderBook::create(&path, CAP).unwrap();
+        fill(&mut mapped);
+        assert_eq!(mapped.generation(), 8);
+        mapped.close().unwrap();
+
+        let mut reopened = MmapOrderBook::open(&path).unwrap();
+        assert!(!reopened.recovered());
+        assert_eq!(reopened.generation(), 8);
+        for side in [Side::Bid, Side::Ask] {
+            assert!(reopened.book().levels(side).eq(expected.levels(side)));
+            assert_eq!(reopened.get_total_quantity(side), expected.get_total_quantity(side));
+        }
+        assert_eq!(reopened.book().version(), expected.version());
+        assert_eq!(reopened.book().second_best(Side::Bid), Some((9990, 10)));
+        reopened.book().verify_invariants().unwrap();
+
+        reopened.apply_update(Update::Set { price: 10000, quantity: 3, side: Side::Ask });
+        assert_eq!(reopened.get_best_ask(), Some(10000));
+        reopened.close().unwrap();
+        assert_eq!(MmapOrderBook::open(&path).unwrap().get_best_ask(), Some(10000));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn crashed_books_are_rebuilt_from_their_slots() {
+        let path = scratch("crash");
+        let mut mapped = MmapOrderBook::create_at(&path, CAP, 10000).unwrap();
+        fill(&mut mapped);
+        // A crash: the book goes away without `close`.
+        drop(mapped);
+
+        let reopened = MmapOrderBook::open(&path).unwrap();
+        assert!(reopened.recovered());
+        let mut expected = OrderBookImpl::new();
+        fill(&mut expected);
+        for side in [Side::Bid, Side::Ask] {
+            assert!(reopened.book().levels(side).eq(expected.levels(side)));
+        }
+        assert_eq!(reopened.book().get_total_notional(Side::Ask), expected.get_total_notional(Side::Ask));
+        reopened.book().verify_invariants().unwrap();
+        drop(reopened);
+
+        // A crash between a slot write and the header refresh: the header's totals and best
+        // are stale, and the slots win.
+        let mut bytes = fs::read(&path).unwrap();
+        let slot = HEADER_LEN + SIDE_LEN + (10002 - 10000) * size_of::<Quantity>();
+        bytes[slot..slot + 8].copy_from_slice(&7u64.to_ne_bytes());
+        fs::write(&path, &bytes).unwrap();
+        let reopened = MmapOrderBook::open(&path).unwrap();
+        assert!(reopened.recovered());
+        assert_eq!(reopened.get_top_levels(Side::Ask, 2), vec![(10001, 20), (10002, 7)]);
+        assert_eq!(reopened.get_total_quantity(Side::Ask), 20 + 21 + 22 + 7);
+        reopened.book().verify_invariants().unwrap();
+        reopened.close().unwrap();
+
+        // The same edit to a cleanly closed file is corruption.
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[slot..slot + 8].copy_from_slice(&9u64.to_ne_bytes());
+        fs::write(&path, &bytes).unwrap();
+        let err = MmapOrderBook::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn foreign_files_are_refused() {
+        let path = scratch("foreign");
+        assert_eq!(MmapOrderBook::create(&path, CAP * 2).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+
+        fs::write(&path, b"short").unwrap();
+        assert!(MmapOrderBook::open(&path).err().unwrap().to_string().contains("needs"));
+        fs::write(&path, vec![0u8; MMAP_FILE_LEN]).unwrap();
+        assert!(MmapOrderBook::open(&path).err().unwrap().to_string().contains("not a mapped order book"));
+
+        MmapOrderBook::create(&path, CAP).unwrap().close().unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[8..12].copy_from_slice(&2u32.to_ne_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(MmapOrderBook::open(&path).err().unwrap().to_string().contains("layout version 2"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cloned_books_are_detached_from_the_file() {
+        let path = scratch("clone");
+        let mut mapped = MmapOrderBook::create(&path, CAP).unwrap();
+        fill(&mut mapped);
+        let mut copy = mapped.book().clone();
+        copy.apply_update(Update::Remove { price: 9990, side: Side::Bid });
+        assert_eq!(mapped.get_quantity_at(9990, Side::Bid), Some(10));
+        assert_eq!(copy.get_quantity_at(9990, Side::Bid), None);
+        drop(mapped);
+        fs::remove_file(&path).unwrap();
+    }
+}
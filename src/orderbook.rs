@@ -1,136 +1,357 @@
 // orderbook.rs
 
-use std::usize;
-#[allow(unused_imports)]
 use std::collections::BTreeMap;
-use crate::interfaces::{OrderBook, Price, Quantity, Side, Update};
+use crate::alerts::AlertEngine;
+use crate::builder::OrderBookBuilder;
+use crate::checksum::{DecimalStyle, write_decimal};
+use crate::churn::ChurnStats;
+use crate::dirty::DirtyLevels;
+use crate::error::OrderBookError;
+use crate::filter::UpdateFilter;
+use crate::histogram::HistogramStats;
+use crate::interfaces::{Bbo, OrderBookFactory, OrderBookRead, OrderBookWrite, Price, Quantity, Side, TryOrderBook, Update};
+use crate::price::PriceInt;
+use crate::replication::BookEvent;
+use crate::state_ring::StateRing;
+use crate::storage::{InlineLevels, LevelStorage};
+use crate::tape::TradeTape;
+use crate::watch::{LevelEvent, WatchId, Watches};
 
 
-const CAP: usize = 4096;
+pub const CAP: usize = 4096;
 const CAP_MASK: usize = CAP - 1;
 const HALF_CAP: i64 = (CAP / 2) as i64;
 const CAP_I64: i64 = CAP as i64;
+const WORDS: usize = CAP / 64;
+/// Bytes per record read by `OrderBookImpl::fuzz_apply`.
+pub const FUZZ_RECORD_LEN: usize = 7;
 
-pub struct OrderBookImpl {
-    bids: [Quantity; CAP],
-    asks: [Quantity; CAP],
-    anchor_price: Price,
+/// Zero-sized marker: the field following it starts on a fresh cache line.
+#[derive(Clone, Copy, Default)]
+#[repr(align(64))]
+struct CacheLine;
+
+/// Ring-buffer L2 book over a window of `CAP` prices per side: `[anchor - CAP / 2, anchor + CAP / 2 - 1]`.
+///
+/// Every method is total: none panics on any book state (the constructors that validate their
+/// configuration say so). On degenerate states the accessors behave as follows:
+/// - empty side: its best price, `get_bbo`, `get_spread` and anything mid-based are `None`; its
+///   level iterators are empty and its total is 0. The internal best index of an empty side is a
+///   sentinel that no accessor ever converts to a price.
+/// - locked book (best bid == best ask): `get_spread` is `Some(0)`.
+/// - crossed book (best bid > best ask): `get_spread` is negative. Use `try_spread` to get
+///   `OrderBookError::CrossedBook` (or `EmptySide`) instead.
+/// - quantity totals wrap on overflow in `apply_update` (release and debug alike);
+///   `try_apply_update` refuses such updates instead.
+///
+/// Internally prices must stay within `i64` after adding the window offset; spreads saturate.
+///
+/// The price type `P` is any `PriceInt` (`i64` by default; see `with_anchor`). The ring itself is
+/// written once against that trait; the `OrderBookRead` / `OrderBookWrite` API and everything layered on it is `Price` only.
+///
+/// The level storage `S` is inline by default; `with_storage` takes any other `LevelStorage`,
+/// such as slots carved from a shared pool.
+//
+// Layout (repr(C), so field order is the memory order):
+//   line 0      best and second-best indices, totals, level counts and the version (written on
+//               every update)
+//   line 1      per-side anchors and the hot-path config (read on every update, rarely written)
+//   line 2..    bids, then asks; 32 KiB each inline, so both start and end on line boundaries
+//               (a single pointer each with other storage)
+//   after that  occupancy bitmaps, then the colder bookkeeping
+#[derive(Clone)]
+#[repr(C, align(64))]
+pub struct OrderBookImpl<P: PriceInt = Price, S: LevelStorage = InlineLevels> {
     best_bid_idx: usize,
     best_ask_idx: usize,
+    /// Next occupied slot after the best; meaningful only while the side has two levels or more.
+    second_bid_idx: usize,
+    second_ask_idx: usize,
     total_bid_quantity: Quantity,
     total_ask_quantity: Quantity,
+    bid_count: u32,
+    ask_count: u32,
+    version: u64,
+    _config: CacheLine,
+    anchors: [P; 2],
+    checksum_depth: u32,
+    cross_policy: CrossPolicy,
+    max_levels: u16,
+    tick_divisor: i64,
+    _arrays: CacheLine,
+    bids: S,
+    asks: S,
+    bid_bits: [u64; WORDS],
+    ask_bits: [u64; WORDS],
+    #[cfg(feature = "order-counts")]
+    bid_counts: [u32; CAP],
+    #[cfg(feature = "order-counts")]
+    ask_counts: [u32; CAP],
+    /// `price * quantity` summed over each side's levels, kept alongside the quantity totals.
+    total_bid_notional: i128,
+    total_ask_notional: i128,
+    last_undo: Option<UndoRecord>,
+    side_checksums: [u64; 2],
+    checksum_boundary: [Option<Price>; 2],
+    anchor_grid: i64,
+    /// `AnchorMode::FromFirstUpdate`'s rounding multiple until the book is anchored.
+    first_anchor: Option<i64>,
+    /// External fair price that `recenter_anchor_aligned` steers towards; see `set_reference_price`.
+    reference_price: Option<Price>,
+    max_spread: Option<Price>,
+    /// Removals `apply_update` dropped under `max_spread`; see `spread_rejections`.
+    spread_rejections: u64,
+    /// Times `clear` has run; see `clear_count`.
+    clears: u64,
+    qty_divisor: Quantity,
+    price_scale: u32,
+    quote_tick: Price,
+    update_filter: Option<Box<UpdateFilter>>,
+    tape: Option<Box<TradeTape>>,
+    churn: Option<Box<ChurnStats>>,
+    histograms: Option<Box<HistogramStats>>,
+    state_ring: Option<Box<StateRing>>,
+    alerts: Option<Box<AlertEngine>>,
+    event_log: Option<Vec<BookEvent<P>>>,
+    dirty: Option<Box<DirtyLevels>>,
+    watches: Option<Box<Watches<P>>>,
+    now: u64,
+    twa: Option<TwaSpread>,
+    last_seq: Option<u64>,
+    resync_buffer: Option<Vec<(u64, Update)>>,
+    bbo_callback: BboCallback,
+    #[cfg(feature = "metrics")]
+    metrics: BookMetrics,
 }
 
+/// What `apply_update` does with a `Set` that would cross the book
+/// (a bid at or above the best ask, or an ask at or below the best bid).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrossPolicy {
+    /// Apply it as-is (the default, and the fastest path).
+    #[default]
+    Allow,
+    /// Drop it. `try_apply_update` reports it as `OrderBookError::Crossed`.
+    Reject,
+    /// Treat it like a trade through the touch: every opposite level the new price
+    /// crosses is removed, then the update is applied.
+    AutoResolve,
+}
 
+/// Where a book's windows are centred before any data arrives; see `OrderBookBuilder::anchor_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnchorMode {
+    /// The builder's anchors, from construction (the default).
+    #[default]
+    Fixed,
+    /// Unanchored until the first `Set` with a quantity (or depth message with a level), which
+    /// centres both windows on its price rounded to the nearest multiple of `round_to` (1 keeps
+    /// it exact). A `Remove` or `Trade` before then changes nothing.
+    FromFirstUpdate { round_to: i64 },
+}
 
-impl OrderBook for OrderBookImpl {
-    fn new() -> Self {
-        OrderBookImpl {
-            bids: [0; CAP],
-            asks: [0; CAP],
-            anchor_price: 10000,
-            best_bid_idx: 0,
-            best_ask_idx: CAP_MASK,
-            total_ask_quantity: 0,
-            total_bid_quantity: 0,
-        }
+/// Coarse condition of a book, from `book_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookState {
+    /// Waiting for its first update under `AnchorMode::FromFirstUpdate`: empty, and the
+    /// windows are not placed yet.
+    Unanchored,
+    /// Neither side has levels.
+    Empty,
+    /// One side has levels, the other none.
+    OneSided,
+    /// Best bid equals best ask.
+    Locked,
+    /// Best bid above best ask.
+    Crossed,
+    /// Both sides present, best bid below best ask.
+    Normal,
+}
+
+/// Which sides of a book have levels, from `liquidity_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiquidityState {
+    Empty,
+    BidOnly,
+    AskOnly,
+    TwoSided,
+}
+
+/// A `Set` refused under `CrossPolicy::Reject`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrossedUpdate {
+    pub price: Price,
+    pub side: Side,
+    pub opposite_best: Price,
+}
+
+impl std::fmt::Display for CrossedUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} at {} crosses the opposite best {}", self.side, self.price, self.opposite_best)
     }
+}
 
-    #[inline(always)]
-    fn apply_update(&mut self, update: Update) {
-        match update {
-            Update::Set { price, quantity, side } => {
-                let index = (price.wrapping_sub(self.anchor_price) as usize) & CAP_MASK;
+impl std::error::Error for CrossedUpdate {}
 
-                let (book, best_idx, total_qty, is_bid) = match side {
-                    Side::Bid => (&mut self.bids, &mut self.best_bid_idx, &mut self.total_bid_quantity, true),
-                    Side::Ask => (&mut self.asks, &mut self.best_ask_idx, &mut self.total_ask_quantity, false),
-                };
+/// What `apply_depth_message` changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ApplySummary {
+    /// Bid levels whose quantity actually changed.
+    pub bids_changed: usize,
+    /// Ask levels whose quantity actually changed.
+    pub asks_changed: usize,
+    /// Whether the BBO (prices or touch quantities) differs from before the message.
+    pub bbo_moved: bool,
+    /// Whether the book is crossed or locked after the message.
+    pub crossed: bool,
+}
 
-                
-                let old_quantity = unsafe { *book.get_unchecked(index) };
+/// Operational counters, maintained only with the `metrics` feature. See `OrderBookImpl::metrics`.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BookMetrics {
+    /// `apply_update` calls plus levels written by `apply_depth_message`.
+    pub updates: u64,
+    /// Window moves (`recenter_anchor` / `recenter_side` calls that changed an anchor).
+    pub recenters: u64,
+    /// Slots examined while searching for a new second best after the best or second best
+    /// level emptied.
+    pub scan_steps: u64,
+}
 
-                if quantity > 0 {
-                    unsafe { *book.get_unchecked_mut(index) = quantity };
+type BboListener = Box<dyn FnMut(Option<Bbo>) + Send + Sync>;
 
-                    if old_quantity == 0 {
-                        *total_qty += quantity;
-                    } else {
-                        *total_qty = *total_qty - old_quantity + quantity;
-                    }
+/// The registered BBO listener. Clones of the book start without one.
+#[derive(Default)]
+struct BboCallback(Option<BboListener>);
 
-                    
-                    if *total_qty == quantity {
-                        *best_idx = index;
-                    } else if is_bid {
-                         if index.wrapping_sub(*best_idx) & CAP_MASK < (CAP / 2) {
-                             *best_idx = index;
-                         }
-                    } else {
-                         if (*best_idx).wrapping_sub(index) & CAP_MASK < (CAP / 2) {
-                             *best_idx = index;
-                         }
-                    }
-                } else if old_quantity > 0 {
-                    unsafe { *book.get_unchecked_mut(index) = 0 };
-                    *total_qty -= old_quantity;
+impl Clone for BboCallback {
+    fn clone(&self) -> Self {
+        BboCallback(None)
+    }
+}
 
-                    if index == *best_idx {
-                        OrderBookImpl::recalculate_best_index(side, best_idx, book);
-                    }
-                }
-            }
+/// Everything needed to reverse one `apply_update`: the touched slot's previous
+/// quantity plus that side's previous total and best and second-best indices.
+#[derive(Debug, Clone, Copy)]
+struct UndoRecord {
+    side: Side,
+    index: usize,
+    quantity: Quantity,
+    total: Quantity,
+    best_idx: usize,
+    second_idx: usize,
+    #[cfg(feature = "order-counts")]
+    count: u32,
+}
 
-            Update::Remove { price, side } => {
-                let index = (price.wrapping_sub(self.anchor_price) as usize) & CAP_MASK;
-                
-                let (book, best_idx, total_qty) = match side {
-                    Side::Bid => (&mut self.bids, &mut self.best_bid_idx, &mut self.total_bid_quantity),
-                    Side::Ask => (&mut self.asks, &mut self.best_ask_idx, &mut self.total_ask_quantity),
-                };
+/// Running sums for `twa_spread`: spread × time over the intervals between `apply_update_at`
+/// calls during which both sides were quoted.
+#[derive(Debug, Clone, Copy, Default)]
+struct TwaSpread {
+    sum_spread_dt: i128,
+    sum_dt: u64,
+}
 
-                let removed_quantity = unsafe { *book.get_unchecked(index) };
 
-                if removed_quantity > 0 {
-                    unsafe { *book.get_unchecked_mut(index) = 0 };
-                    *total_qty -= removed_quantity;
-                    
-                    if index == *best_idx {
-                        OrderBookImpl::recalculate_best_index(side, best_idx, book);
-                    }
-                }
+impl OrderBookFactory for OrderBookImpl {
+    fn new() -> Self {
+        OrderBookImpl::new()
+    }
+}
+
+impl Default for OrderBookImpl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds a book from `(bids, asks)` maps, as kept by `BTreeOrderBook` and most hand-rolled
+/// books. Both windows are centred on the middle of the combined key range. Fails with
+/// `SpanTooWide` if the keys don't fit in one window, or `ZeroQuantity` for a level holding
+/// nothing (a map that stores empty levels is out of step with its own book). Inverse of
+/// `to_maps`.
+impl TryFrom<(&BTreeMap<Price, Quantity>, &BTreeMap<Price, Quantity>)> for OrderBookImpl {
+    type Error = OrderBookError;
+
+    fn try_from((bids, asks): (&BTreeMap<Price, Quantity>, &BTreeMap<Price, Quantity>)) -> Result<Self, Self::Error> {
+        for (side, map) in [(Side::Bid, bids), (Side::Ask, asks)] {
+            if let Some((&price, _)) = map.iter().find(|&(_, &quantity)| quantity == 0) {
+                return Err(OrderBookError::ZeroQuantity { side, price });
+            }
+        }
+        let keys = || bids.keys().chain(asks.keys()).copied();
+        let (Some(low), Some(high)) = (keys().min(), keys().max()) else {
+            return Ok(Self::new());
+        };
+        if high.abs_diff(low) >= CAP as u64 {
+            return Err(OrderBookError::SpanTooWide { low, high, max: CAP as u64 - 1 });
+        }
+        let mut book = Self::builder().anchor(low + (high - low + 1) / 2).build();
+        for (side, map) in [(Side::Bid, bids), (Side::Ask, asks)] {
+            book.apply_update(Update::SetLevels { side, levels: map.iter().map(|(&price, &quantity)| (price, quantity)).collect() });
+        }
+        Ok(book)
+    }
+}
+
+impl<S: LevelStorage> OrderBookWrite for OrderBookImpl<Price, S> {
+    #[inline(always)]
+    fn apply_update(&mut self, update: Update) {
+        #[cfg(feature = "metrics")]
+        { self.metrics.updates += 1; }
+        let changed = match (&self.alerts, &update) {
+            (None, _) | (_, Update::Trade { .. }) => [false; 2],
+            (Some(_), Update::Set { side, .. } | Update::SetWithCount { side, .. } | Update::Remove { side, .. } | Update::SetLevels { side, .. }) => {
+                [*side == Side::Bid, *side == Side::Ask]
             }
+        };
+        if self.bbo_callback.0.is_none() && self.histograms.is_none() {
+            self.apply_core(update);
+        } else {
+            let before = self.get_bbo();
+            self.apply_core(update);
+            self.notify_bbo(before);
+        }
+        if let Some(mut ring) = self.state_ring.take() {
+            ring.record_apply(self);
+            self.state_ring = Some(ring);
+        }
+        if self.alerts.is_some() {
+            self.run_alerts(changed);
         }
     }
+}
 
+impl<S: LevelStorage> OrderBookRead for OrderBookImpl<Price, S> {
     #[inline(always)]
     fn get_spread(&self) -> Option<Price> {
-        if self.total_bid_quantity == 0 || self.total_ask_quantity == 0 {
+        if self.bid_count == 0 || self.ask_count == 0 {
             None
         } else {
-            let bid = self.index_to_price(self.best_bid_idx);
-            let ask = self.index_to_price(self.best_ask_idx);
-            Some(ask - bid)
+            let bid = self.index_to_price(Side::Bid, self.best_bid_idx);
+            let ask = self.index_to_price(Side::Ask, self.best_ask_idx);
+            Some(ask.saturating_sub(bid))
         }
     }
 
     #[inline(always)]
     fn get_best_bid(&self) -> Option<Price> {
-        if self.total_bid_quantity == 0 { None } else {
-            Some(self.index_to_price(self.best_bid_idx))
+        if self.bid_count == 0 { None } else {
+            Some(self.index_to_price(Side::Bid, self.best_bid_idx))
         }
     }
 
     #[inline(always)]
     fn get_best_ask(&self) -> Option<Price> {
-        if self.total_ask_quantity == 0 { None } else {
-            Some(self.index_to_price(self.best_ask_idx))
+        if self.ask_count == 0 { None } else {
+            Some(self.index_to_price(Side::Ask, self.best_ask_idx))
         }
     }
 
     #[inline(always)]
     fn get_quantity_at(&self, price: Price, side: Side) -> Option<Quantity> {
-        let index = (price.wrapping_sub(self.anchor_price) as usize) & CAP_MASK;
+        let index = self.price_to_index(price, side);
         let qty = unsafe {
             match side {
                 Side::Bid => *self.bids.get_unchecked(index),
@@ -141,28 +362,8 @@ impl OrderBook for OrderBookImpl {
     }
 
     fn get_top_levels(&self, side: Side, n: usize) -> Vec<(Price, Quantity)> {
-        let mut result = Vec::with_capacity(n);
-        let book = match side { Side::Bid => &self.bids, Side::Ask => &self.asks };
-        match side {
-            Side::Bid => {
-                for i in (0..CAP).rev() {
-                    let qty = unsafe { *book.get_unchecked(i) };
-                    if qty > 0 {
-                        result.push((self.index_to_price(i), qty));
-                        if result.len() >= n { break; }
-                    }
-                }
-            }
-            Side::Ask => {
-                for i in 0..CAP {
-                    let qty = unsafe { *book.get_unchecked(i) };
-                    if qty > 0 {
-                        result.push((self.index_to_price(i), qty));
-                        if result.len() >= n { break; }
-                    }
-                }
-            }
-        }
+        let mut result = Vec::with_capacity(n.min(CAP));
+        result.extend(self.levels(side).take(n));
         result
     }
 
@@ -177,40 +378,2316 @@ impl OrderBook for OrderBookImpl {
 
 
 
-impl OrderBookImpl {
+impl<S: LevelStorage> TryOrderBook for OrderBookImpl<Price, S> {
+    /// Fails without touching the book when the price is outside the window, when the update
+    /// filter (if any, see `set_update_filter`) finds it implausible, when a `Set` would open a
+    /// level beyond `max_levels` or overflow the side's total, when a crossing `Set` meets
+    /// `CrossPolicy::Reject`, or when a removal would blow the spread past `max_spread_ticks`.
+    ///
+    /// A `SetLevels` is checked level by level against the window and the update filter only,
+    /// all against the book as it was before; if any level fails, none is applied.
+    fn try_apply_update(&mut self, update: Update) -> Result<(), OrderBookError> {
+        if let Update::SetLevels { side, levels } = &update {
+            self.check_levels(*side, levels)?;
+            self.apply_update(update);
+            return Ok(());
+        }
+        let snapped = if self.tick_divisor != 1 { self.snap_to_tick(update.clone()) } else { update.clone() };
+        let level = match snapped {
+            Update::Set { price, side, .. } | Update::SetWithCount { price, side, .. } | Update::Remove { price, side } => {
+                Some((price, side))
+            }
+            Update::Trade { .. } | Update::SetLevels { .. } => None,
+        };
+        // An unanchored book centres its windows on the update's price, so it cannot be outside.
+        if let Some((price, side)) = level
+            && self.first_anchor.is_none()
+            && !self.in_window(price, side)
+        {
+            return Err(OrderBookError::OutOfWindow { price, anchor: self.anchor(side) });
+        }
+        if self.update_filter.is_some() {
+            let mid_x2 = match (self.get_best_bid(), self.get_best_ask()) {
+                (Some(bid), Some(ask)) => Some(bid as i128 + ask as i128),
+                _ => None,
+            };
+            let resting = level.and_then(|(price, side)| self.get_quantity_at(price, side)).unwrap_or(0);
+            if let Some(filter) = self.update_filter.as_deref_mut() {
+                filter.check(&snapped, mid_x2, resting)?;
+            }
+        }
+        match snapped {
+            Update::Set { price, quantity, side } | Update::SetWithCount { price, quantity, side, .. } if quantity > 0 => {
+                let existing = self.get_quantity_at(price, side);
+                if existing.is_none() && self.level_count(side) >= self.max_levels as usize {
+                    return Err(OrderBookError::LevelLimit { side, max_levels: self.max_levels as usize });
+                }
+                let total = self.get_total_quantity(side) - existing.unwrap_or(0);
+                if total.checked_add(quantity).is_none() {
+                    return Err(OrderBookError::Overflow { side, total, added: quantity });
+                }
+                if self.cross_policy == CrossPolicy::Reject
+                    && let Some(opposite_best) = self.crossed_best(price, side)
+                {
+                    return Err(CrossedUpdate { price, side, opposite_best }.into());
+                }
+            }
+            _ => {}
+        }
+        if let Some(spread) = self.blown_spread(&snapped) {
+            return Err(OrderBookError::SpreadTooWide { spread, max: self.max_spread.unwrap_or(0) });
+        }
+        self.apply_update(update);
+        Ok(())
+    }
+}
+
+impl<P: PriceInt> OrderBookImpl<P> {
+    /// An empty book keyed by `P`, with both windows centred on `anchor`.
+    ///
+    /// Books over a price type other than `Price` get the ring itself (`set_level`,
+    /// `quantity_at`, `best_price`, the level iterators, `clear`); the book traits and
+    /// the optional machinery built on them (tick divisor, cross policy, checksums, tape, churn,
+    /// callbacks, recentering) are implemented for `Price` only.
+    pub fn with_anchor(anchor: P) -> Self {
+        Self::from_parts(anchor, InlineLevels::EMPTY, InlineLevels::EMPTY)
+    }
+}
+
+impl<P: PriceInt, S: LevelStorage> OrderBookImpl<P, S> {
+    /// An empty book like `with_anchor`, keeping its levels in `bids` and `asks` rather than
+    /// inline. Both are zeroed first, so storage handed back by a pool needs no cleaning.
+    ///
+    /// The book owns the storage from here on; clones of the book clone it.
+    pub fn with_storage(anchor: P, mut bids: S, mut asks: S) -> Self {
+        bids.fill(0);
+        asks.fill(0);
+        Self::from_parts(anchor, bids, asks)
+    }
+
+    fn from_parts(anchor: P, bids: S, asks: S) -> Self {
+        OrderBookImpl {
+            best_bid_idx: 0,
+            best_ask_idx: CAP_MASK,
+            second_bid_idx: 0,
+            second_ask_idx: CAP_MASK,
+            total_ask_quantity: 0,
+            total_bid_quantity: 0,
+            bid_count: 0,
+            ask_count: 0,
+            version: 0,
+            _config: CacheLine,
+            anchors: [anchor; 2],
+            checksum_depth: 0,
+            cross_policy: CrossPolicy::Allow,
+            max_levels: CAP as u16,
+            tick_divisor: 1,
+            _arrays: CacheLine,
+            bids,
+            asks,
+            bid_bits: [0; WORDS],
+            ask_bits: [0; WORDS],
+            #[cfg(feature = "order-counts")]
+            bid_counts: [0; CAP],
+            #[cfg(feature = "order-counts")]
+            ask_counts: [0; CAP],
+            total_bid_notional: 0,
+            total_ask_notional: 0,
+            last_undo: None,
+            side_checksums: [0; 2],
+            checksum_boundary: [None; 2],
+            anchor_grid: 256,
+            first_anchor: None,
+            reference_price: None,
+            max_spread: None,
+            spread_rejections: 0,
+            clears: 0,
+            qty_divisor: 1,
+            price_scale: 0,
+            quote_tick: 1,
+            update_filter: None,
+            tape: None,
+            churn: None,
+            histograms: None,
+            state_ring: None,
+            alerts: None,
+            event_log: None,
+            dirty: None,
+            watches: None,
+            now: 0,
+            twa: None,
+            last_seq: None,
+            resync_buffer: None,
+            bbo_callback: BboCallback(None),
+            #[cfg(feature = "metrics")]
+            metrics: BookMetrics::default(),
+        }
+    }
+
+    /// Stores `quantity` at `price` on `side` (0 removes the level). The price must be in the
+    /// window; see `in_window`.
+    pub fn set_level(&mut self, price: P, quantity: Quantity, side: Side) {
+        let index = self.price_to_index(price, side);
+        self.write_slot(side, index, quantity);
+    }
+
+    /// The quantity resting at `price` on `side`, or `None` if the level is empty.
+    pub fn quantity_at(&self, price: P, side: Side) -> Option<Quantity> {
+        let index = self.price_to_index(price, side);
+        let qty = match side {
+            Side::Bid => self.bids[index],
+            Side::Ask => self.asks[index],
+        };
+        (qty > 0).then_some(qty)
+    }
+
+    /// Best bid or best ask, `None` if that side is empty.
+    #[inline(always)]
+    pub fn best_price(&self, side: Side) -> Option<P> {
+        match side {
+            Side::Bid if self.bid_count > 0 => Some(self.index_to_price(side, self.best_bid_idx)),
+            Side::Ask if self.ask_count > 0 => Some(self.index_to_price(side, self.best_ask_idx)),
+            _ => None,
+        }
+    }
+
+    /// The occupied level furthest from the touch on `side` (the lowest bid, the highest ask),
+    /// `None` if the side is empty. Not maintained incrementally: a scan of the occupancy
+    /// bitmap from the window edge inwards, so at most `CAP / 64` words and usually one or two.
+    pub fn worst_price(&self, side: Side) -> Option<P> {
+        let (bits, count) = match side {
+            Side::Bid => (&self.bid_bits, self.bid_count),
+            Side::Ask => (&self.ask_bits, self.ask_count),
+        };
+        if count == 0 {
+            return None;
+        }
+        // Walking in from the far edge is the opposite side's walk away from its touch.
+        let (opposite, edge) = match side {
+            Side::Bid => (Side::Ask, HALF_CAP as usize),
+            Side::Ask => (Side::Bid, HALF_CAP as usize - 1),
+        };
+        let mut worst = None;
+        scan_occupied(bits, opposite, edge, |index| {
+            worst = Some(index);
+            false
+        });
+        worst.map(|index| self.index_to_price(side, index))
+    }
+
+    /// The level just behind the best on `side`, kept up to date by every write, so unlike
+    /// `nth_level(side, 1)` this costs no scan. `None` if the side has fewer than two levels.
+    #[inline(always)]
+    pub fn second_best(&self, side: Side) -> Option<(P, Quantity)> {
+        let (book, index, count) = match side {
+            Side::Bid => (&self.bids, self.second_bid_idx, self.bid_count),
+            Side::Ask => (&self.asks, self.second_ask_idx, self.ask_count),
+        };
+        (count >= 2).then(|| (self.index_to_price(side, index), book[index]))
+    }
+
+    /// What `undo_last` needs to put slot `index` on `side` back as it is now.
+    #[inline(always)]
+    fn undo_record(&self, side: Side, index: usize) -> UndoRecord {
+        let (book, best_idx, second_idx, total) = match side {
+            Side::Bid => (&self.bids, self.best_bid_idx, self.second_bid_idx, self.total_bid_quantity),
+            Side::Ask => (&self.asks, self.best_ask_idx, self.second_ask_idx, self.total_ask_quantity),
+        };
+        UndoRecord {
+            side,
+            index,
+            quantity: unsafe { *book.get_unchecked(index) },
+            total,
+            best_idx,
+            second_idx,
+            #[cfg(feature = "order-counts")]
+            count: self.slot_count(side, index),
+        }
+    }
+
+    /// Writes one slot and keeps the bitmap, totals, count, best and second-best indices, version
+    /// and undo record in step. Shared by every price type; `apply_update` lands here after its policies.
+    #[inline(always)]
+    fn write_slot(&mut self, side: Side, index: usize, quantity: Quantity) {
+        prefetch_slot(match side { Side::Bid => &self.bids, Side::Ask => &self.asks }, index);
+        let undo = self.undo_record(side, index);
+        let (book, bits, best_idx, second_idx, total_qty, level_count, is_bid) = match side {
+            Side::Bid => (&mut self.bids, &mut self.bid_bits, &mut self.best_bid_idx, &mut self.second_bid_idx, &mut self.total_bid_quantity, &mut self.bid_count, true),
+            Side::Ask => (&mut self.asks, &mut self.ask_bits, &mut self.best_ask_idx, &mut self.second_ask_idx, &mut self.total_ask_quantity, &mut self.ask_count, false),
+        };
+        let old_quantity = unsafe { *book.get_unchecked(index) };
+        if quantity > 0 && old_quantity == 0 && *level_count >= self.max_levels as u32 {
+            self.last_undo = None;
+            return;
+        }
+        self.last_undo = Some(undo);
+
+        if quantity > 0 {
+            unsafe { *book.get_unchecked_mut(index) = quantity };
+            self.version += (old_quantity != quantity) as u64;
+
+            if old_quantity == 0 {
+                *total_qty = total_qty.wrapping_add(quantity);
+                *level_count += 1;
+                set_bit(bits, index);
+                rank_new_level(is_bid, index, *level_count, best_idx, second_idx);
+            } else {
+                *total_qty = total_qty.wrapping_sub(old_quantity).wrapping_add(quantity);
+            }
+        } else if old_quantity > 0 {
+            unsafe { *book.get_unchecked_mut(index) = 0 };
+            self.version += 1;
+            *total_qty = total_qty.wrapping_sub(old_quantity);
+            *level_count -= 1;
+            clear_bit(bits, index);
+
+            // The second best moves up into the vacated rank and the walk only has to find the
+            // level after it.
+            let vacated = if index == *best_idx {
+                *best_idx = *second_idx;
+                Some(*best_idx)
+            } else {
+                (index == *second_idx).then_some(index)
+            };
+            if let Some(from) = vacated
+                && *level_count >= 2
+            {
+                *second_idx = if is_bid { from.wrapping_sub(1) & CAP_MASK } else { (from + 1) & CAP_MASK };
+                let _steps = Self::recalculate_best_index(side, second_idx, book);
+                #[cfg(feature = "metrics")]
+                { self.metrics.scan_steps += _steps as u64; }
+            } else if *level_count == 0 {
+                *best_idx = if is_bid { 0 } else { CAP_MASK };
+            }
+        }
+        if old_quantity != quantity {
+            self.level_changed(side, index, old_quantity, quantity);
+        }
+    }
+
+    /// Quote-denominated size at `price`: `price * quantity`, or `None` if the level is empty.
+    pub fn get_quote_quantity_at(&self, price: P, side: Side) -> Option<i128> {
+        self.quantity_at(price, side).map(|quantity| price.to_i128() * quantity as i128)
+    }
+
+    /// Sum of `get_quote_quantity_at` over `side`'s levels, maintained by every write like
+    /// `get_total_quantity`, so a per-order risk check can read it in O(1). Levels a recenter
+    /// drops leave the total with them. Wraps on overflow, like the quantity totals.
     #[inline(always)]
-    fn index_to_price(&self, index: usize) -> Price {
-        
-        let offset = index as i64;
-        let adjustment = if offset > HALF_CAP { -CAP_I64 } else { 0 };
-        self.anchor_price.wrapping_add(offset).wrapping_add(adjustment)
+    pub fn get_total_notional(&self, side: Side) -> i128 {
+        match side {
+            Side::Bid => self.total_bid_notional,
+            Side::Ask => self.total_ask_notional,
+        }
+    }
+
+    #[inline(always)]
+    fn track_notional(&mut self, side: Side, index: usize, old: Quantity, new: Quantity) {
+        let price = slot_price(self.anchors[side as usize], index).to_i128();
+        let total = match side {
+            Side::Bid => &mut self.total_bid_notional,
+            Side::Ask => &mut self.total_ask_notional,
+        };
+        *total = total.wrapping_add(price.wrapping_mul(new as i128 - old as i128));
+    }
+
+    /// Starts recording every level change as a `BookEvent` (see `replication`), discarding
+    /// anything recorded so far.
+    pub fn enable_event_log(&mut self) {
+        self.event_log = Some(Vec::new());
+    }
+
+    pub fn disable_event_log(&mut self) {
+        self.event_log = None;
+    }
+
+    /// Drains the events recorded since the last call (empty if logging is off).
+    pub fn take_events(&mut self) -> Vec<BookEvent<P>> {
+        self.event_log.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Starts recording which levels change, for `take_dirty_levels`. More than `limit`
+    /// distinct levels between polls (or a clear / recenter) degrade to a full-refresh flag.
+    pub fn enable_dirty_tracking(&mut self, limit: usize) {
+        self.dirty = Some(Box::new(DirtyLevels::new(limit)));
+    }
+
+    pub fn disable_dirty_tracking(&mut self) {
+        self.dirty = None;
+    }
+
+    /// Replaces `out` with each level changed since the last call, as `(side, price, quantity
+    /// now)` (0 for a removed level), in order of first change. Returns `true` instead, leaving
+    /// `out` empty, when too many levels changed to track and the consumer must re-read the
+    /// whole book. Levels changed and changed back are still reported. Empty if tracking is off.
+    pub fn take_dirty_levels(&mut self, out: &mut Vec<(Side, P, Quantity)>) -> bool {
+        out.clear();
+        let Some(dirty) = self.dirty.as_deref_mut() else {
+            return false;
+        };
+        let full_refresh = dirty.overflowed();
+        for &(side, index) in dirty.slots() {
+            let quantity = match side {
+                Side::Bid => self.bids[index],
+                Side::Ask => self.asks[index],
+            };
+            out.push((side, slot_price(self.anchors[side as usize], index), quantity));
+        }
+        dirty.reset();
+        full_refresh
+    }
+
+    /// Starts queueing a `LevelEvent` whenever the quantity at (`side`, `price`) changes,
+    /// including to 0 when a recenter drops the price out of the window. Watching a level
+    /// costs unwatched updates one bit test. Returns `None` once `watch::MAX_WATCHES` levels
+    /// are watched; `unwatch` one to make room.
+    pub fn watch_level(&mut self, side: Side, price: P) -> Option<WatchId> {
+        let (slot, quantity) = self.locate_watch(side, price);
+        self.watches.get_or_insert_with(|| Box::new(Watches::new())).add(side, price, slot, quantity)
+    }
+
+    /// Stops watching; returns whether `id` was being watched. Queued events are kept.
+    pub fn unwatch(&mut self, id: WatchId) -> bool {
+        self.watches.as_deref_mut().is_some_and(|watches| watches.remove(id))
+    }
+
+    /// Drains the watched-level events queued since the last call, oldest first.
+    pub fn take_level_events(&mut self) -> Vec<LevelEvent<P>> {
+        self.watches.as_deref_mut().map(Watches::take_events).unwrap_or_default()
+    }
+
+    fn locate_watch(&self, side: Side, price: P) -> (Option<usize>, Quantity) {
+        if self.in_window(price, side) {
+            (Some(self.price_to_index(price, side)), self.quantity_at(price, side).unwrap_or(0))
+        } else {
+            (None, 0)
+        }
+    }
+
+    /// Re-reads every watched level after a wholesale change (clear, recenter).
+    fn refresh_watches(&mut self) {
+        if let Some(mut watches) = self.watches.take() {
+            watches.relocate(|side, price| self.locate_watch(side, price));
+            self.watches = Some(watches);
+        }
+    }
+
+    /// Orders resting at slot `index`: 0 for an empty level, one whose count was never reported,
+    /// or any level without the `order-counts` feature.
+    #[inline(always)]
+    pub(crate) fn slot_count(&self, side: Side, index: usize) -> u32 {
+        #[cfg(feature = "order-counts")]
+        {
+            match side {
+                Side::Bid => self.bid_counts[index],
+                Side::Ask => self.ask_counts[index],
+            }
+        }
+        #[cfg(not(feature = "order-counts"))]
+        {
+            let _ = (side, index);
+            0
+        }
+    }
+
+    #[cfg(feature = "order-counts")]
+    #[inline(always)]
+    fn set_slot_count(&mut self, side: Side, index: usize, count: u32) {
+        match side {
+            Side::Bid => self.bid_counts[index] = count,
+            Side::Ask => self.ask_counts[index] = count,
+        }
+    }
+
+    /// Bookkeeping shared by every path that changes a level's quantity.
+    #[inline(always)]
+    fn level_changed(&mut self, side: Side, index: usize, old: Quantity, new: Quantity) {
+        self.track_notional(side, index, old, new);
+        #[cfg(feature = "order-counts")]
+        if new == 0 {
+            self.set_slot_count(side, index, 0);
+        }
+        if let Some(dirty) = self.dirty.as_deref_mut() {
+            dirty.mark(side, index);
+        }
+        if let Some(watches) = self.watches.as_deref_mut() {
+            watches.changed(side, index, new);
+        }
+        if let Some(log) = self.event_log.as_mut() {
+            log.push(BookEvent::Level { side, price: slot_price(self.anchors[side as usize], index), quantity: new });
+        }
+    }
+
+    /// Raw view of one side for embedding / FFI consumers: the slot array and that side's anchor.
+    ///
+    /// Slot `i` holds the quantity resting at:
+    /// - `anchor + i`        when `i <  CAP / 2`
+    /// - `anchor + i - CAP`  when `i >= CAP / 2`
+    ///
+    /// Going the other way, a price maps to slot `(price - anchor) & (CAP - 1)`.
+    /// A quantity of 0 means the level is empty. The reference is only valid until the next mutation.
+    #[inline(always)]
+    pub fn raw_side(&self, side: Side) -> (&[Quantity; CAP], P) {
+        match side {
+            Side::Bid => (&self.bids, self.anchors[0]),
+            Side::Ask => (&self.asks, self.anchors[1]),
+        }
+    }
+
+    /// Read-only view of `side`'s slots with its window; see `RawLevels` for the invariants.
+    #[inline(always)]
+    pub fn levels_raw(&self, side: Side) -> RawLevels<'_, P> {
+        let (quantities, anchor) = self.raw_side(side);
+        let best_index = match side {
+            Side::Bid => (self.bid_count > 0).then_some(self.best_bid_idx),
+            Side::Ask => (self.ask_count > 0).then_some(self.best_ask_idx),
+        };
+        RawLevels { quantities, anchor, best_index }
+    }
+
+    #[inline(always)]
+    fn index_to_price(&self, side: Side, index: usize) -> P {
+        slot_price(self.anchor(side), index)
     }
 
-    fn recalculate_best_index(side: Side, best_idx: &mut usize, book: &[Quantity; CAP]) {
+    /// Walks from `*best_idx` away from the touch in price order (never across the window edge)
+    /// to the first occupied slot, leaving it there. Called to find the next second best after
+    /// the best or second best level was emptied. Returns the slots examined.
+    fn recalculate_best_index(side: Side, best_idx: &mut usize, book: &[Quantity; CAP]) -> usize {
+        let from = *best_idx;
         match side {
             Side::Bid => {
-                for i in (0..CAP).rev() {
-                    if unsafe { *book.get_unchecked(i) } > 0 { *best_idx = i; return; }
+                let steps = window_rank(from) + 1;
+                for step in 0..steps {
+                    let i = from.wrapping_sub(step) & CAP_MASK;
+                    if unsafe { *book.get_unchecked(i) } > 0 { *best_idx = i; return step + 1; }
                 }
                 *best_idx = 0;
+                steps
             }
             Side::Ask => {
-                for i in 0..CAP {
-                    if unsafe { *book.get_unchecked(i) } > 0 { *best_idx = i; return; }
+                let steps = CAP - window_rank(from);
+                for step in 0..steps {
+                    let i = (from + step) & CAP_MASK;
+                    if unsafe { *book.get_unchecked(i) } > 0 { *best_idx = i; return step + 1; }
                 }
                 *best_idx = CAP_MASK;
+                steps
             }
         }
     }
-    
-    #[allow(dead_code)]
-    fn price_to_index(&self, price: Price) -> usize {
-        (price.wrapping_sub(self.anchor_price) as usize) & CAP_MASK
+
+    /// Occupied levels on `side`, best price first.
+    pub fn levels(&self, side: Side) -> Levels<'_, P> {
+        let (book, best, count) = match side {
+            Side::Bid => (&self.bids, self.best_bid_idx, self.bid_count),
+            Side::Ask => (&self.asks, self.best_ask_idx, self.ask_count),
+        };
+        let remaining = match (count, side) {
+            (0, _) => 0,
+            (_, Side::Bid) => window_rank(best) + 1,
+            (_, Side::Ask) => CAP - window_rank(best),
+        };
+        Levels { book, anchor: self.anchor(side), side, next: best, remaining }
+    }
+
+    /// Whether both books hold the same levels on both sides, whatever their anchors: the
+    /// market view of equality, for checking that a recenter or a rebuild changed nothing.
+    /// Config, version and the optional trackers are not compared.
+    pub fn market_eq(&self, other: &Self) -> bool {
+        [Side::Bid, Side::Ask].into_iter().all(|side| {
+            let count = |book: &Self| match side {
+                Side::Bid => book.bid_count,
+                Side::Ask => book.ask_count,
+            };
+            count(self) == count(other) && self.levels(side).eq(other.levels(side))
+        })
+    }
+
+    /// `side`'s occupied levels with prices in `[low, high]`, best first, without allocating.
+    /// The walk starts at whichever of `high` (bids) / `low` (asks) and the best is nearer the
+    /// back of the book and stops at the band's far edge, so a narrow band costs only its width.
+    /// Prices outside the window hold nothing and are skipped.
+    pub fn levels_in_range(&self, side: Side, low: P, high: P) -> Levels<'_, P> {
+        let (book, best, count) = match side {
+            Side::Bid => (&self.bids, self.best_bid_idx, self.bid_count),
+            Side::Ask => (&self.asks, self.best_ask_idx, self.ask_count),
+        };
+        let anchor = self.anchor(side);
+        // Exact offsets from the anchor; the window is -HALF_CAP..HALF_CAP.
+        let offset = |price: P| price.to_i128() - anchor.to_i128();
+        let best_offset = window_rank(best) as i128 - HALF_CAP as i128;
+        let (start, end) = match side {
+            Side::Bid => (offset(high).min(best_offset), offset(low).max(-HALF_CAP as i128)),
+            Side::Ask => (offset(low).max(best_offset), offset(high).min(HALF_CAP as i128 - 1)),
+        };
+        let remaining = match side {
+            Side::Bid if count > 0 && start >= end => start.abs_diff(end) as usize + 1,
+            Side::Ask if count > 0 && start <= end => start.abs_diff(end) as usize + 1,
+            _ => 0,
+        };
+        Levels { book, anchor, side, next: start as i64 as usize & CAP_MASK, remaining }
+    }
+
+    /// Folds `f` over `side`'s occupied levels, best first. Skips empty slots a bitmap word at a
+    /// time like `nth_level`, so it suits one-pass analytics (notional, entropy, centre of mass)
+    /// over sparse books.
+    pub fn fold_levels<B>(&self, side: Side, init: B, mut f: impl FnMut(B, P, Quantity) -> B) -> B {
+        let (book, bits, best, count) = match side {
+            Side::Bid => (&self.bids, &self.bid_bits, self.best_bid_idx, self.bid_count),
+            Side::Ask => (&self.asks, &self.ask_bits, self.best_ask_idx, self.ask_count),
+        };
+        if count == 0 {
+            return init;
+        }
+        let mut acc = Some(init);
+        scan_occupied(bits, side, best, |index| {
+            acc = acc.take().map(|acc| f(acc, self.index_to_price(side, index), book[index]));
+            true
+        });
+        acc.expect("the fold always puts the accumulator back")
+    }
+
+    /// The `n`-th occupied level from the best (0 = best), or `None` if the side has at most `n` levels.
+    /// Walks the occupancy bitmap a word at a time, so empty stretches cost one load per 64 slots.
+    pub fn nth_level(&self, side: Side, n: usize) -> Option<(P, Quantity)> {
+        let (book, bits, best, count) = match side {
+            Side::Bid => (&self.bids, &self.bid_bits, self.best_bid_idx, self.bid_count),
+            Side::Ask => (&self.asks, &self.ask_bits, self.best_ask_idx, self.ask_count),
+        };
+        if n >= count as usize {
+            return None;
+        }
+        let mut remaining = n;
+        let mut found = None;
+        scan_occupied(bits, side, best, |index| {
+            if remaining == 0 {
+                found = Some((self.index_to_price(side, index), book[index]));
+                false
+            } else {
+                remaining -= 1;
+                true
+            }
+        });
+        found
+    }
+
+    /// Number of occupied levels on `side`. This counter, not the quantity total, is what
+    /// every emptiness check uses.
+    #[inline(always)]
+    pub fn level_count(&self, side: Side) -> usize {
+        match side {
+            Side::Bid => self.bid_count as usize,
+            Side::Ask => self.ask_count as usize,
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn price_to_index(&self, price: P, side: Side) -> usize {
+        (price.offset_from(self.anchor(side)) as usize) & CAP_MASK
+    }
+
+    /// Centre of `side`'s window. Both sides share one anchor unless configured apart
+    /// (`OrderBookBuilder::bid_anchor` / `ask_anchor`, `recenter_side`). While the book is
+    /// `BookState::Unanchored` this is the configured anchor the first update will replace.
+    #[inline(always)]
+    pub fn anchor(&self, side: Side) -> P {
+        self.anchors[side as usize]
+    }
+
+    /// Whether `price` is in `side`'s window, `[anchor - CAP / 2, anchor + CAP / 2 - 1]`, and so
+    /// has its own slot (see `raw_side` for the mapping). A price outside shares a slot with one inside.
+    #[inline(always)]
+    pub fn in_window(&self, price: P, side: Side) -> bool {
+        let offset = price.offset_from(self.anchor(side));
+        (-HALF_CAP..HALF_CAP).contains(&offset)
+    }
+
+    /// Whether `a` is a more aggressive price than `b` on `side` (higher for bids, lower for
+    /// asks), comparing signed distances from the side's anchor so that prices wrapping past
+    /// the type's bounds (a `u32` book anchored near 0 or `u32::MAX`) still order correctly.
+    /// Exact for any two prices within half the type's range of the anchor, in the window or not.
+    #[inline(always)]
+    pub fn is_better(&self, side: Side, a: P, b: P) -> bool {
+        let anchor = self.anchor(side);
+        let (a, b) = (a.offset_from(anchor), b.offset_from(anchor));
+        match side {
+            Side::Bid => a > b,
+            Side::Ask => a < b,
+        }
+    }
+
+    /// Whether an update at `price` needs a recenter first: `price` is outside at least one
+    /// side's window (so, depending on its side, it would alias another slot or be refused by
+    /// `try_apply_update`). Lets a consumer move the window before a burst rather than mid-burst.
+    #[inline(always)]
+    pub fn would_recenter(&self, price: P) -> bool {
+        !self.in_window(price, Side::Bid) || !self.in_window(price, Side::Ask)
+    }
+
+    /// Empties both sides and resets `version`. Configuration (anchor, policies, divisor, checksum depth, tape) is kept.
+    pub fn clear(&mut self) {
+        self.clears += 1;
+        self.bids.fill(0);
+        self.asks.fill(0);
+        self.bid_bits = [0; WORDS];
+        self.ask_bits = [0; WORDS];
+        #[cfg(feature = "order-counts")]
+        {
+            self.bid_counts = [0; CAP];
+            self.ask_counts = [0; CAP];
+        }
+        self.best_bid_idx = 0;
+        self.best_ask_idx = CAP_MASK;
+        self.second_bid_idx = 0;
+        self.second_ask_idx = CAP_MASK;
+        self.total_bid_quantity = 0;
+        self.total_ask_quantity = 0;
+        self.bid_count = 0;
+        self.ask_count = 0;
+        self.version = 0;
+        self.last_undo = None;
+        self.side_checksums = [0; 2];
+        self.checksum_boundary = [None; 2];
+        self.total_bid_notional = 0;
+        self.total_ask_notional = 0;
+        if let Some(dirty) = self.dirty.as_deref_mut() {
+            dirty.overflow();
+        }
+        self.refresh_watches();
+        if let Some(log) = self.event_log.as_mut() {
+            log.push(BookEvent::Clear);
+        }
+    }
+}
+
+// Constructors for the default, inline storage; see `with_storage` for the others.
+impl OrderBookImpl {
+    /// An empty book with both windows centred on 10000 and every option at its default.
+    pub fn new() -> Self {
+        Self::with_anchor(10000)
+    }
+
+    /// Starts a builder for a preconfigured book; `new()` is the all-defaults shortcut.
+    pub fn builder() -> OrderBookBuilder {
+        OrderBookBuilder::default()
+    }
+
+    pub(crate) fn from_builder(options: &OrderBookBuilder) -> Self {
+        let mut book = Self::new();
+        book.anchors = [options.bid_anchor, options.ask_anchor];
+        book.tick_divisor = options.tick_size;
+        book.max_levels = options.max_levels.min(CAP) as u16;
+        book.cross_policy = options.on_cross;
+        book.anchor_grid = options.anchor_grid;
+        if let AnchorMode::FromFirstUpdate { round_to } = options.anchor_mode {
+            book.first_anchor = Some(round_to);
+        }
+        book.max_spread = options.max_spread;
+        book.qty_divisor = options.qty_divisor;
+        book.price_scale = options.price_scale;
+        book.quote_tick = options.quote_tick;
+        book.enable_checksum(options.checksum_depth);
+        book
+    }
+
+    /// Empty book enforcing `policy` on crossing `Set`s.
+    pub fn with_cross_policy(policy: CrossPolicy) -> Self {
+        let mut book = Self::new();
+        book.cross_policy = policy;
+        book
     }
-    #[allow(dead_code)]
-    fn is_in_range(&self, price: Price) -> bool {
-        (price - self.anchor_price).abs() < HALF_CAP
+
+    /// Empty book whose `apply_update` takes prices in units `divisor` times finer than a tick.
+    ///
+    /// Incoming prices are divided by `divisor`, rounding toward negative infinity (so a price
+    /// snaps to the tick at or below it, for negative prices too). Everything stored and every
+    /// read accessor is in whole ticks. Panics if `divisor` is not positive.
+    pub fn with_tick_divisor(divisor: i64) -> Self {
+        assert!(divisor > 0, "tick divisor must be positive");
+        let mut book = Self::new();
+        book.tick_divisor = divisor;
+        book
     }
-    #[allow(dead_code)]
-    fn recenter_anchor(&mut self, _new_price: Price) {}
-}
\ No newline at end of file
+}
+
+impl<S: LevelStorage> OrderBookImpl<Price, S> {
+    /// `apply_update` minus the BBO callback; the cross policy, undo record, churn and
+    /// checksum are all handled here.
+    #[inline(always)]
+    fn apply_core(&mut self, update: Update) {
+        if let Update::SetLevels { side, levels } = &update {
+            self.apply_levels(*side, levels);
+            return;
+        }
+        let update = if self.tick_divisor != 1 { self.snap_to_tick(update) } else { update };
+        if self.first_anchor.is_some()
+            && let Update::Set { price, quantity, .. } | Update::SetWithCount { price, quantity, .. } = update
+            && quantity > 0
+        {
+            self.anchor_first(price);
+        }
+        let (touched_side, touched_price) = match update {
+            Update::Set { price, side, .. } | Update::SetWithCount { price, side, .. } | Update::Remove { price, side } => {
+                (side, price)
+            }
+            Update::Trade { price, quantity, aggressor, timestamp } => {
+                // Trades carry no level change; nothing to undo either.
+                self.last_undo = None;
+                if let Some(tape) = self.tape.as_deref_mut() {
+                    tape.record_trade(timestamp, price, quantity, aggressor);
+                }
+                if let Some(churn) = self.churn.as_deref_mut() {
+                    churn.record_trade(price, quantity, aggressor);
+                }
+                return;
+            }
+            Update::SetLevels { .. } => return,
+        };
+
+        // A refused update changes nothing, so the one before it is no longer the last to undo.
+        if self.cross_policy != CrossPolicy::Allow && !self.resolve_cross(&update) {
+            self.last_undo = None;
+            return;
+        }
+        if self.max_spread.is_some() && self.blown_spread(&update).is_some() {
+            self.spread_rejections += 1;
+            self.last_undo = None;
+            return;
+        }
+
+        let churn_before = self
+            .churn
+            .is_some()
+            .then(|| (self.get_quantity_at(touched_price, touched_side).unwrap_or(0), self.touch(touched_side)));
+
+        let quantity = match update {
+            Update::Set { quantity, .. } | Update::SetWithCount { quantity, .. } => quantity,
+            Update::Remove { .. } | Update::Trade { .. } | Update::SetLevels { .. } => 0,
+        };
+        let index = self.price_to_index(touched_price, touched_side);
+        self.write_slot(touched_side, index, quantity);
+        // A plain `Set` leaves the count alone; an emptied level's was zeroed by the write.
+        #[cfg(feature = "order-counts")]
+        if let Update::SetWithCount { order_count, .. } = update
+            && quantity > 0
+            && self.get_quantity_at(touched_price, touched_side).is_some()
+        {
+            self.set_slot_count(touched_side, index, order_count);
+        }
+
+        if let Some((old, touch)) = churn_before {
+            let new = self.get_quantity_at(touched_price, touched_side).unwrap_or(0);
+            if let Some(churn) = self.churn.as_deref_mut() {
+                churn.record_change(self.now, touched_side, touched_price, touch, old, new);
+            }
+        }
+
+        // The slot's own price: an out-of-window update lands on an aliased slot.
+        if self.checksum_depth != 0 {
+            self.refresh_checksum(touched_side, self.index_to_price(touched_side, index));
+        }
+    }
+
+    /// `apply_update(Set)` for a level the caller knows is empty, skipping the old-quantity
+    /// branch and the removal path. Falls back to `apply_update` when the slot is occupied after
+    /// all, `quantity` is 0, the side is at `max_levels`, or the book has anything configured
+    /// that the general path consults (see `plain_write_path`), so the result is always the
+    /// same as `apply_update`'s.
+    #[inline(always)]
+    pub fn insert_new(&mut self, price: Price, quantity: Quantity, side: Side) {
+        let index = self.price_to_index(price, side);
+        let (occupied, count) = match side {
+            Side::Bid => (self.bids[index] != 0, self.bid_count),
+            Side::Ask => (self.asks[index] != 0, self.ask_count),
+        };
+        if occupied || quantity == 0 || count >= self.max_levels as u32 || !self.plain_write_path() {
+            return self.apply_update(Update::Set { price, quantity, side });
+        }
+        #[cfg(feature = "metrics")]
+        { self.metrics.updates += 1; }
+        self.last_undo = Some(self.undo_record(side, index));
+        let (book, bits, best_idx, second_idx, total_qty, level_count, is_bid) = match side {
+            Side::Bid => (&mut self.bids, &mut self.bid_bits, &mut self.best_bid_idx, &mut self.second_bid_idx, &mut self.total_bid_quantity, &mut self.bid_count, true),
+            Side::Ask => (&mut self.asks, &mut self.ask_bits, &mut self.best_ask_idx, &mut self.second_ask_idx, &mut self.total_ask_quantity, &mut self.ask_count, false),
+        };
+        book[index] = quantity;
+        *total_qty = total_qty.wrapping_add(quantity);
+        *level_count += 1;
+        set_bit(bits, index);
+        rank_new_level(is_bid, index, *level_count, best_idx, second_idx);
+        self.version += 1;
+        self.level_changed(side, index, 0, quantity);
+    }
+
+    /// `apply_update(Set)` for a level the caller knows is occupied, to a non-zero quantity:
+    /// no count, bitmap or best-index work at all. Falls back to `apply_update` like
+    /// `insert_new` (the level empty after all, `quantity` 0, or a non-plain book).
+    #[inline(always)]
+    pub fn modify(&mut self, price: Price, quantity: Quantity, side: Side) {
+        let index = self.price_to_index(price, side);
+        let old = match side {
+            Side::Bid => self.bids[index],
+            Side::Ask => self.asks[index],
+        };
+        if old == 0 || quantity == 0 || !self.plain_write_path() {
+            return self.apply_update(Update::Set { price, quantity, side });
+        }
+        #[cfg(feature = "metrics")]
+        { self.metrics.updates += 1; }
+        self.last_undo = Some(self.undo_record(side, index));
+        let (book, total_qty) = match side {
+            Side::Bid => (&mut self.bids, &mut self.total_bid_quantity),
+            Side::Ask => (&mut self.asks, &mut self.total_ask_quantity),
+        };
+        book[index] = quantity;
+        *total_qty = total_qty.wrapping_sub(old).wrapping_add(quantity);
+        if old != quantity {
+            self.version += 1;
+            self.level_changed(side, index, old, quantity);
+        }
+    }
+
+    /// Whether a `Set` goes straight to the slot write: no tick divisor, cross policy, spread
+    /// limit, pending first anchor, checksum, churn, BBO callback, histograms, state ring or
+    /// alerts.
+    #[inline(always)]
+    fn plain_write_path(&self) -> bool {
+        self.tick_divisor == 1
+            && self.cross_policy == CrossPolicy::Allow
+            && self.max_spread.is_none()
+            && self.first_anchor.is_none()
+            && self.checksum_depth == 0
+            && self.churn.is_none()
+            && self.bbo_callback.0.is_none()
+            && self.histograms.is_none()
+            && self.state_ring.is_none()
+            && self.alerts.is_none()
+    }
+
+    /// Every occupied level as `(bids, asks)` maps; `OrderBookImpl::try_from` takes them back.
+    pub fn to_maps(&self) -> (BTreeMap<Price, Quantity>, BTreeMap<Price, Quantity>) {
+        (self.levels(Side::Bid).collect(), self.levels(Side::Ask).collect())
+    }
+
+    /// Lowest occupied bid; see `worst_price` for the cost.
+    pub fn get_worst_bid(&self) -> Option<Price> {
+        self.worst_price(Side::Bid)
+    }
+
+    /// Highest occupied ask; see `worst_price` for the cost.
+    pub fn get_worst_ask(&self) -> Option<Price> {
+        self.worst_price(Side::Ask)
+    }
+
+    /// Ticks between `side`'s best and worst levels (0 with a single level), `None` if the
+    /// side is empty. A span pinned at the venue's advertised depth suggests a truncated feed.
+    pub fn get_book_span(&self, side: Side) -> Option<i64> {
+        let (best, worst) = (self.best_price(side)?, self.worst_price(side)?);
+        Some(best.abs_diff(worst) as i64)
+    }
+
+    /// Best ask minus best bid, or why there is no meaningful spread: `EmptySide` if a side has
+    /// no levels, `CrossedBook` if the best bid is above the best ask. A locked book is `Ok(0)`.
+    pub fn try_spread(&self) -> Result<Price, OrderBookError> {
+        let bid = self.get_best_bid().ok_or(OrderBookError::EmptySide { side: Side::Bid })?;
+        let ask = self.get_best_ask().ok_or(OrderBookError::EmptySide { side: Side::Ask })?;
+        if bid > ask {
+            return Err(OrderBookError::CrossedBook { bid, ask });
+        }
+        Ok(ask.saturating_sub(bid))
+    }
+
+    /// `get_spread` in units of the quoting tick (`OrderBookBuilder::quote_tick`, 1 by default).
+    /// Fractional when the stored price grid is finer than the tick, e.g. levels aggregated
+    /// from sub-tick prices; negative on a crossed book. `None` if either side is empty.
+    pub fn spread_in_ticks_f64(&self) -> Option<f64> {
+        Some(self.get_spread()? as f64 / self.quote_tick as f64)
+    }
+
+    /// Whether the book is unanchored, empty, one-sided, locked, crossed or normal.
+    pub fn book_state(&self) -> BookState {
+        if self.first_anchor.is_some() {
+            return BookState::Unanchored;
+        }
+        match (self.get_best_bid(), self.get_best_ask()) {
+            (None, None) => BookState::Empty,
+            (Some(_), None) | (None, Some(_)) => BookState::OneSided,
+            (Some(bid), Some(ask)) if bid == ask => BookState::Locked,
+            (Some(bid), Some(ask)) if bid > ask => BookState::Crossed,
+            _ => BookState::Normal,
+        }
+    }
+
+    /// Which sides have levels; a halted or illiquid market shows up as `BidOnly` / `AskOnly`.
+    #[inline(always)]
+    pub fn liquidity_state(&self) -> LiquidityState {
+        match (self.bid_count > 0, self.ask_count > 0) {
+            (false, false) => LiquidityState::Empty,
+            (true, false) => LiquidityState::BidOnly,
+            (false, true) => LiquidityState::AskOnly,
+            (true, true) => LiquidityState::TwoSided,
+        }
+    }
+
+    /// Best bid and ask with their quantities; `None` if either side is empty.
+    #[inline(always)]
+    pub fn get_bbo(&self) -> Option<Bbo> {
+        if self.bid_count == 0 || self.ask_count == 0 {
+            return None;
+        }
+        Some(Bbo {
+            bid_price: self.index_to_price(Side::Bid, self.best_bid_idx),
+            bid_quantity: self.bids[self.best_bid_idx],
+            ask_price: self.index_to_price(Side::Ask, self.best_ask_idx),
+            ask_quantity: self.asks[self.best_ask_idx],
+            version: self.version,
+        })
+    }
+
+    /// Orders resting at `price` on `side`, or `None` if the level is empty. Counts come from
+    /// `Update::SetWithCount`; a plain `Set` keeps the level's last reported count, so 0 means
+    /// none was ever reported. Maintained through removes, undo, recenters and `clear`.
+    #[cfg(feature = "order-counts")]
+    pub fn get_order_count_at(&self, price: Price, side: Side) -> Option<u32> {
+        self.get_quantity_at(price, side).map(|_| self.slot_count(side, self.price_to_index(price, side)))
+    }
+
+    /// `get_top_levels` with each level's order count (see `get_order_count_at`).
+    #[cfg(feature = "order-counts")]
+    pub fn get_top_levels_with_counts(&self, side: Side, n: usize) -> Vec<(Price, Quantity, u32)> {
+        self.levels(side)
+            .take(n)
+            .map(|(price, qty)| (price, qty, self.slot_count(side, self.price_to_index(price, side))))
+            .collect()
+    }
+
+    /// Base units per whole unit for the `_scaled` getters (`OrderBookBuilder::qty_divisor`,
+    /// 1 by default). Everything else, `apply_update` included, is in base units.
+    pub fn qty_divisor(&self) -> Quantity {
+        self.qty_divisor
+    }
+
+    /// Quoting tick in stored price units (`OrderBookBuilder::quote_tick`, 1 by default).
+    pub fn quote_tick(&self) -> Price {
+        self.quote_tick
+    }
+
+    /// Decimal places of the incoming prices (`OrderBookBuilder::price_scale`, 0 by default).
+    pub fn price_scale(&self) -> u32 {
+        self.price_scale
+    }
+
+    /// A stored tick price as the decimal the feed would quote it: multiplied back by the tick
+    /// divisor and rendered with exactly `price_scale` decimal places, so tick 10000 at scale
+    /// 2 is `"100.00"`. For UIs and logs; saturates on overflow.
+    pub fn format_price(&self, index_price: Price) -> String {
+        let price = index_price.saturating_mul(self.tick_divisor);
+        let mut out = String::new();
+        write_decimal(&mut out, price < 0, price.unsigned_abs(), self.price_scale, DecimalStyle::Fixed);
+        out
+    }
+
+    /// `get_total_quantity` in whole units: divided by `qty_divisor`, rounded to the nearest
+    /// unit with halves rounded up (so 1.5 units is 2 and 2.4 is 2).
+    pub fn get_total_quantity_scaled(&self, side: Side) -> Quantity {
+        self.scale_quantity(self.get_total_quantity(side))
+    }
+
+    /// `get_top_levels` with quantities in whole units, rounded as in `get_total_quantity_scaled`.
+    /// Each level is rounded on its own, so the sum may differ from the scaled total.
+    pub fn top_levels_scaled(&self, side: Side, n: usize) -> Vec<(Price, Quantity)> {
+        self.levels(side).take(n).map(|(price, qty)| (price, self.scale_quantity(qty))).collect()
+    }
+
+    fn scale_quantity(&self, quantity: Quantity) -> Quantity {
+        let divisor = self.qty_divisor;
+        let remainder = quantity % divisor;
+        quantity / divisor + (remainder >= divisor - remainder) as Quantity
+    }
+
+    /// Up to `N` best levels of `side` in a stack array, plus how many were filled; slots past
+    /// the count are `(0, 0)`. The allocation-free counterpart to `get_top_levels`.
+    pub fn top_levels_array<const N: usize>(&self, side: Side) -> ([(Price, Quantity); N], usize) {
+        let mut out = [(0, 0); N];
+        let mut filled = 0;
+        for (slot, level) in out.iter_mut().zip(self.levels(side)) {
+            *slot = level;
+            filled += 1;
+        }
+        (out, filled)
+    }
+
+    /// `apply_update` followed by `get_bbo`, for loops that need the new touch after every update.
+    #[inline(always)]
+    pub fn apply_update_bbo(&mut self, update: Update) -> Option<Bbo> {
+        self.apply_update(update);
+        self.get_bbo()
+    }
+
+    /// Sets the book's clock to `timestamp` and applies `update`. Time-windowed components
+    /// (e.g. `ChurnStats` with a time window) read this clock.
+    pub fn apply_update_at(&mut self, timestamp: u64, update: Update) {
+        let spread = self.twa.and_then(|_| self.get_spread());
+        if let (Some(twa), Some(spread)) = (self.twa.as_mut(), spread) {
+            // The spread before this update held from the previous timestamp until now.
+            let dt = timestamp.saturating_sub(self.now);
+            twa.sum_spread_dt += spread as i128 * dt as i128;
+            twa.sum_dt += dt;
+        }
+        self.now = timestamp;
+        self.apply_update(update);
+    }
+
+    /// Starts accumulating the time-weighted average spread over `apply_update_at` calls,
+    /// from the current clock (`now`). Intervals with either side empty do not count.
+    pub fn enable_twa_spread(&mut self) {
+        self.twa = Some(TwaSpread::default());
+    }
+
+    /// Time-weighted average spread in ticks since `enable_twa_spread` or the last
+    /// `reset_twa`; `None` if that is off or no quoted time has elapsed yet.
+    pub fn twa_spread(&self) -> Option<f64> {
+        self.twa.filter(|twa| twa.sum_dt > 0).map(|twa| twa.sum_spread_dt as f64 / twa.sum_dt as f64)
+    }
+
+    /// Starts a new averaging interval at the current clock.
+    pub fn reset_twa(&mut self) {
+        if let Some(twa) = self.twa.as_mut() {
+            *twa = TwaSpread::default();
+        }
+    }
+
+    /// The last timestamp passed to `apply_update_at`.
+    #[inline(always)]
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    #[cfg(feature = "async")]
+    #[inline(always)]
+    pub(crate) fn set_clock(&mut self, timestamp: u64) {
+        self.now = timestamp;
+    }
+
+    /// Calls `callback` with the new BBO whenever an update changes it (`None` once either side
+    /// is empty). Replaces any previous callback; clones of the book do not inherit it.
+    pub fn on_bbo_change(&mut self, callback: impl FnMut(Option<Bbo>) + Send + Sync + 'static) {
+        self.bbo_callback = BboCallback(Some(Box::new(callback)));
+    }
+
+    pub fn remove_bbo_callback(&mut self) {
+        self.bbo_callback = BboCallback(None);
+    }
+
+    fn notify_bbo(&mut self, before: Option<Bbo>) {
+        let after = self.get_bbo();
+        if !touch_moved(before, after) {
+            return;
+        }
+        if let (Some(histograms), Some(bbo)) = (self.histograms.as_deref_mut(), after.as_ref()) {
+            histograms.record(bbo);
+        }
+        if let Some(callback) = self.bbo_callback.0.as_mut() {
+            callback(after);
+        }
+    }
+
+    /// Applies one exchange depth message atomically: both sides' level changes (quantity 0
+    /// removes the level) under sequence number `seq`.
+    ///
+    /// The first message sets the sequence; after that `seq` must be exactly one past the last
+    /// accepted one. A gap, or any price outside its side's window, fails the whole message
+    /// before anything is touched. The best indices are fixed once at the end and the BBO
+    /// callback fires at most once, after both sides are in. The cross policy is not applied
+    /// level by level (the message's intermediate states are meaningless); check `crossed` in
+    /// the summary instead. Sets opening a level beyond `max_levels` are dropped as in
+    /// `apply_update`. Not undoable: clears the `undo_last` record.
+    pub fn apply_depth_message(
+        &mut self,
+        seq: u64,
+        bids: &[(Price, Quantity)],
+        asks: &[(Price, Quantity)],
+    ) -> Result<ApplySummary, OrderBookError> {
+        if let Some(last) = self.last_seq
+            && seq != last.wrapping_add(1)
+        {
+            return Err(OrderBookError::SequenceGap { expected: last.wrapping_add(1), received: seq });
+        }
+        let first_anchor = self.first_anchor.and_then(|round_to| self.first_depth_anchor(bids, asks, round_to));
+        for (side, levels) in [(Side::Bid, bids), (Side::Ask, asks)] {
+            let anchor = first_anchor.unwrap_or(self.anchor(side));
+            for &(price, _) in levels {
+                let price = self.tick_price(price);
+                if !(-HALF_CAP..HALF_CAP).contains(&price.wrapping_sub(anchor)) {
+                    return Err(OrderBookError::OutOfWindow { price, anchor });
+                }
+            }
+        }
+
+        if let Some(anchor) = first_anchor {
+            self.recenter([anchor; 2]);
+        }
+        self.last_seq = Some(seq);
+        self.last_undo = None;
+        let before = self.get_bbo();
+        let touches = [self.touch(Side::Bid), self.touch(Side::Ask)];
+        let mut summary = ApplySummary::default();
+        for (side, levels) in [(Side::Bid, bids), (Side::Ask, asks)] {
+            let mut changed = 0;
+            for &(price, quantity) in levels {
+                #[cfg(feature = "metrics")]
+                { self.metrics.updates += 1; }
+                changed += self.write_level(side, self.tick_price(price), quantity, touches[side as usize]) as usize;
+            }
+            if changed > 0 {
+                self.reset_best_index(side);
+            }
+            match side {
+                Side::Bid => summary.bids_changed = changed,
+                Side::Ask => summary.asks_changed = changed,
+            }
+        }
+
+        if self.checksum_depth != 0 {
+            self.enable_checksum(self.checksum_depth as usize);
+        }
+        let after = self.get_bbo();
+        summary.bbo_moved = touch_moved(before, after);
+        summary.crossed = after.is_some_and(|bbo| bbo.bid_price >= bbo.ask_price);
+        self.notify_bbo(before);
+        if self.alerts.is_some() {
+            self.run_alerts([summary.bids_changed > 0, summary.asks_changed > 0]);
+        }
+        Ok(summary)
+    }
+
+    /// Removes the levels at `prices` on `side` (taken like `apply_update`'s, before the tick
+    /// divisor) and returns how many were occupied and so actually removed; empty and
+    /// out-of-window prices are skipped. The best index is recomputed once at the end and the
+    /// BBO callback fires at most once. Under `max_spread` each removal is checked as a
+    /// `Remove` would be, one by one. Not undoable: clears the `undo_last` record.
+    pub fn remove_many(&mut self, side: Side, prices: &[Price]) -> usize {
+        let before = self.get_bbo();
+        let mut removed = 0;
+        if self.max_spread.is_some() {
+            for &price in prices {
+                let tick = self.tick_price(price);
+                if self.in_window(tick, side) && self.get_quantity_at(tick, side).is_some() {
+                    self.apply_core(Update::Remove { price, side });
+                    removed += self.get_quantity_at(tick, side).is_none() as usize;
+                }
+            }
+        } else {
+            let touch = self.touch(side);
+            for &price in prices {
+                let price = self.tick_price(price);
+                if self.in_window(price, side) {
+                    removed += self.write_level(side, price, 0, touch) as usize;
+                }
+            }
+            if removed > 0 {
+                self.reset_best_index(side);
+            }
+        }
+        self.last_undo = None;
+        if removed > 0 && self.checksum_depth != 0 {
+            self.enable_checksum(self.checksum_depth as usize);
+        }
+        self.notify_bbo(before);
+        if self.alerts.is_some() {
+            self.run_alerts([removed > 0 && side == Side::Bid, removed > 0 && side == Side::Ask]);
+        }
+        removed
+    }
+
+    /// Applies `data` as a stream of fixed 7-byte records, for fuzz targets: any input decodes
+    /// to valid updates, and none of them panics. Each record is:
+    ///
+    /// | bytes | field                                                                  |
+    /// |-------|------------------------------------------------------------------------|
+    /// | 0     | bits 0-1 kind: 0 = Set, 1 = Remove, 2 = Trade, 3 = SetWithCount; bit 2 side; bits 3-7 order count |
+    /// | 1..3  | price as an `i16` offset from the side's anchor, wrapping              |
+    /// | 3..7  | quantity, `u32`                                                        |
+    ///
+    /// The offsets reach well past the window on both sides, so aliased slots and crossing
+    /// updates come up quickly. A trailing partial record is ignored. Pair with
+    /// `verify_invariants` to catch state corruption.
+    pub fn fuzz_apply(&mut self, data: &[u8]) {
+        for record in data.chunks_exact(FUZZ_RECORD_LEN) {
+            let side = if record[0] & 0b100 == 0 { Side::Bid } else { Side::Ask };
+            let offset = i16::from_le_bytes([record[1], record[2]]);
+            let price = self.anchor(side).wrapping_add(offset as Price);
+            let quantity = u32::from_le_bytes([record[3], record[4], record[5], record[6]]) as Quantity;
+            let update = match record[0] & 0b11 {
+                0 => Update::Set { price, quantity, side },
+                1 => Update::Remove { price, side },
+                2 => Update::Trade { price, quantity, aggressor: side, timestamp: self.now },
+                _ => Update::SetWithCount { price, quantity, order_count: (record[0] >> 3) as u32, side },
+            };
+            self.apply_update(update);
+        }
+    }
+
+    /// A book over slots that already hold levels (a reopened memory mapping, say), windows
+    /// centred on `anchors` (bid, ask), with `version` carried over. Everything else that is
+    /// derived from the slots (bitmaps, counts, totals, notionals, best and second-best indices)
+    /// is rebuilt from them; options are at their defaults.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn from_slots(anchors: [Price; 2], bids: S, asks: S, version: u64) -> Self {
+        let mut book = Self::from_parts(anchors[0], bids, asks);
+        book.anchors = anchors;
+        book.version = version;
+        for side in [Side::Bid, Side::Ask] {
+            let anchor = book.anchor(side);
+            let (slots, bits, count, total, notional) = match side {
+                Side::Bid => (&book.bids, &mut book.bid_bits, &mut book.bid_count, &mut book.total_bid_quantity, &mut book.total_bid_notional),
+                Side::Ask => (&book.asks, &mut book.ask_bits, &mut book.ask_count, &mut book.total_ask_quantity, &mut book.total_ask_notional),
+            };
+            for (index, &quantity) in slots.iter().enumerate().filter(|&(_, &quantity)| quantity > 0) {
+                set_bit(bits, index);
+                *count += 1;
+                *total = total.wrapping_add(quantity);
+                *notional = notional.wrapping_add((slot_price(anchor, index) as i128).wrapping_mul(quantity as i128));
+            }
+            book.reset_best_index(side);
+        }
+        book
+    }
+
+    /// Checks the book's derived state against its slots, returning a description of the first
+    /// inconsistency: the occupancy bitmaps, level counts, quantity and notional totals, best
+    /// and second-best indices, `max_levels`, order counts, and the rolling checksum. A full
+    /// scan of both windows, for tests and fuzzing rather than the hot path.
+    pub fn verify_invariants(&self) -> Result<(), String> {
+        for side in [Side::Bid, Side::Ask] {
+            let (book, bits, best, second, count, total, notional) = match side {
+                Side::Bid => (&self.bids, &self.bid_bits, self.best_bid_idx, self.second_bid_idx, self.bid_count, self.total_bid_quantity, self.total_bid_notional),
+                Side::Ask => (&self.asks, &self.ask_bits, self.best_ask_idx, self.second_ask_idx, self.ask_count, self.total_ask_quantity, self.total_ask_notional),
+            };
+            let mut occupied = Vec::new();
+            let (mut sum, mut sum_notional) = (0 as Quantity, 0i128);
+            for index in 0..CAP {
+                let quantity = book[index];
+                if (bits[index >> 6] >> (index & 63) & 1 == 1) != (quantity > 0) {
+                    return Err(format!("{side:?} slot {index} holds {quantity} but its occupancy bit disagrees"));
+                }
+                if quantity == 0 && self.slot_count(side, index) != 0 {
+                    return Err(format!("{side:?} slot {index} is empty but has an order count"));
+                }
+                if quantity > 0 {
+                    occupied.push(index);
+                    sum = sum.wrapping_add(quantity);
+                    sum_notional = sum_notional.wrapping_add((self.index_to_price(side, index) as i128).wrapping_mul(quantity as i128));
+                }
+            }
+            if occupied.len() != count as usize {
+                return Err(format!("{side:?} count is {count} but {} slots are occupied", occupied.len()));
+            }
+            if occupied.len() > self.max_levels as usize {
+                return Err(format!("{side:?} holds {} levels, above max_levels {}", occupied.len(), self.max_levels));
+            }
+            if sum != total {
+                return Err(format!("{side:?} total is {total} but the levels sum to {sum}"));
+            }
+            if sum_notional != notional {
+                return Err(format!("{side:?} notional is {notional} but the levels sum to {sum_notional}"));
+            }
+            // Best first: highest window rank for bids, lowest for asks.
+            occupied.sort_unstable_by_key(|&index| window_rank(index));
+            if side == Side::Bid {
+                occupied.reverse();
+            }
+            if let Some(&expected) = occupied.first()
+                && best != expected
+            {
+                return Err(format!("{side:?} best index is {best}, expected {expected}"));
+            }
+            if let Some(&expected) = occupied.get(1)
+                && second != expected
+            {
+                return Err(format!("{side:?} second-best index is {second}, expected {expected}"));
+            }
+        }
+        if self.checksum_depth != 0 && self.current_checksum() != self.top_levels_checksum(self.checksum_depth as usize) {
+            return Err(format!("rolling checksum differs from a rescan of the top {} levels", self.checksum_depth));
+        }
+        Ok(())
+    }
+
+    /// Applies one sequenced update. `seq` follows the same rule as `apply_depth_message` (and
+    /// shares its counter): the first one sets the sequence, then each must be exactly one past
+    /// the last, or the update is refused with `SequenceGap` and nothing changes. In
+    /// `buffer_until_snapshot` mode the update is queued instead and always accepted.
+    pub fn apply_sequenced(&mut self, seq: u64, update: Update) -> Result<(), OrderBookError> {
+        if let Some(buffer) = self.resync_buffer.as_mut() {
+            buffer.push((seq, update));
+            return Ok(());
+        }
+        if let Some(last) = self.last_seq
+            && seq != last.wrapping_add(1)
+        {
+            return Err(OrderBookError::SequenceGap { expected: last.wrapping_add(1), received: seq });
+        }
+        self.last_seq = Some(seq);
+        self.apply_update(update);
+        Ok(())
+    }
+
+    /// Starts queueing `apply_sequenced` updates instead of applying them, for the window in
+    /// which a snapshot is being fetched while deltas already stream in. Load the snapshot
+    /// (e.g. `clear` then `apply_update`s), then call `set_snapshot_baseline`.
+    pub fn buffer_until_snapshot(&mut self) {
+        self.resync_buffer.get_or_insert_with(Vec::new);
+    }
+
+    /// Whether `apply_sequenced` is currently queueing.
+    pub fn is_buffering(&self) -> bool {
+        self.resync_buffer.is_some()
+    }
+
+    /// Ends buffering: the book now holds the snapshot taken as of `seq`. Buffered updates at or
+    /// below `seq` are already reflected in it and are dropped; the rest are replayed in sequence
+    /// order. Returns how many were replayed.
+    ///
+    /// The replayed updates must run contiguously from `seq + 1`. If they do not (a delta was
+    /// lost), replay stops at the gap with `SequenceGap`, the updates after it are discarded and
+    /// the book needs another resync. Does nothing but set the sequence when not buffering.
+    pub fn set_snapshot_baseline(&mut self, seq: u64) -> Result<usize, OrderBookError> {
+        let mut buffered = self.resync_buffer.take().unwrap_or_default();
+        buffered.retain(|&(s, _)| s > seq);
+        buffered.sort_by_key(|&(s, _)| s);
+        self.last_seq = Some(seq);
+        let mut replayed = 0;
+        for (s, update) in buffered {
+            self.apply_sequenced(s, update)?;
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+
+    /// Brings this book to a primary's state by replaying the primary's `take_events` output.
+    ///
+    /// The replica must start where the primary was when logging began (typically both fresh with
+    /// the same anchors) and must not cap levels below the primary's `max_levels`; its own
+    /// policies are not consulted. Levels, totals, anchors and `version` then match the
+    /// primary's after every batch. Like `apply_depth_message`, this clears the undo record and
+    /// notifies the BBO callback once (a `Recenter` event notifies on its own as well).
+    pub fn apply_events(&mut self, events: &[BookEvent]) {
+        self.last_undo = None;
+        let before = self.get_bbo();
+        let touches = [self.touch(Side::Bid), self.touch(Side::Ask)];
+        let mut dirty = [false; 2];
+        for event in events {
+            match *event {
+                BookEvent::Level { side, price, quantity } => {
+                    // The primary's anchors are the replica's from here on.
+                    self.first_anchor = None;
+                    dirty[side as usize] |= self.write_level(side, price, quantity, touches[side as usize]);
+                }
+                BookEvent::Clear => {
+                    self.clear();
+                    dirty = [false; 2];
+                }
+                BookEvent::Recenter { anchors } => {
+                    self.reset_dirty_sides(&mut dirty);
+                    self.recenter(anchors);
+                }
+            }
+        }
+        self.reset_dirty_sides(&mut dirty);
+        if self.checksum_depth != 0 {
+            self.enable_checksum(self.checksum_depth as usize);
+        }
+        self.notify_bbo(before);
+    }
+
+    fn reset_dirty_sides(&mut self, dirty: &mut [bool; 2]) {
+        for side in [Side::Bid, Side::Ask] {
+            if std::mem::take(&mut dirty[side as usize]) {
+                self.reset_best_index(side);
+            }
+        }
+    }
+
+    /// Counters since construction; survives `clear` and recentring.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> BookMetrics {
+        self.metrics
+    }
+
+    /// Bumped by every mutation that changes the levels (no-op `Set`s, `Remove`s of absent
+    /// levels and trades leave it alone) and by recentring; reset to 0 only by `clear`.
+    #[inline(always)]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Removals dropped by `apply_update` (and the batch paths built on it) because they would
+    /// have widened the spread past `max_spread_ticks`; `try_apply_update` returns
+    /// `SpreadTooWide` instead and does not count. Each one leaves a level the feed has pulled
+    /// on the book, so a rising count means the book has parted from the feed and needs a
+    /// resync. Not reset by `clear`.
+    pub fn spread_rejections(&self) -> u64 {
+        self.spread_rejections
+    }
+
+    /// Times `clear` has run. `version` starts again from 0 after a clear, so a version number
+    /// can come back with different levels; the pair `(clear_count, version)` never does.
+    pub fn clear_count(&self) -> u64 {
+        self.clears
+    }
+
+    /// Whether the book has changed since it was at version `v`.
+    #[inline(always)]
+    pub fn changed_since(&self, v: u64) -> bool {
+        self.version != v
+    }
+
+    /// Sequence number of the last accepted `apply_depth_message`.
+    pub fn last_sequence(&self) -> Option<u64> {
+        self.last_seq
+    }
+
+    /// Forgets the sequence so the next depth message is accepted whatever its number (after a resync).
+    pub fn reset_sequence(&mut self) {
+        self.last_seq = None;
+    }
+
+    /// Stores `quantity` at `price` without touching the best index; returns whether the slot changed.
+    /// `touch` is the side's best before the batch, for churn classification.
+    fn write_level(&mut self, side: Side, price: Price, quantity: Quantity, touch: Option<Price>) -> bool {
+        let index = self.price_to_index(price, side);
+        let (book, bits, total_qty, level_count) = match side {
+            Side::Bid => (&mut self.bids, &mut self.bid_bits, &mut self.total_bid_quantity, &mut self.bid_count),
+            Side::Ask => (&mut self.asks, &mut self.ask_bits, &mut self.total_ask_quantity, &mut self.ask_count),
+        };
+        let old = book[index];
+        if old == quantity || (old == 0 && *level_count >= self.max_levels as u32) {
+            return false;
+        }
+        match (old, quantity) {
+            (0, _) => {
+                *level_count += 1;
+                set_bit(bits, index);
+            }
+            (_, 0) => {
+                *level_count -= 1;
+                clear_bit(bits, index);
+            }
+            _ => {}
+        }
+        *total_qty = total_qty.wrapping_sub(old).wrapping_add(quantity);
+        book[index] = quantity;
+        self.version += 1;
+        self.level_changed(side, index, old, quantity);
+        if let Some(churn) = self.churn.as_deref_mut() {
+            churn.record_change(self.now, side, price, touch, old, quantity);
+        }
+        true
+    }
+
+    /// Recomputes `side`'s best and second-best indices from the occupancy bitmap, starting at
+    /// the window edge.
+    fn reset_best_index(&mut self, side: Side) {
+        let (bits, count) = match side {
+            Side::Bid => (&self.bid_bits, self.bid_count),
+            Side::Ask => (&self.ask_bits, self.ask_count),
+        };
+        let empty = match side { Side::Bid => 0, Side::Ask => CAP_MASK };
+        let mut found = [empty; 2];
+        if count > 0 {
+            let edge = match side { Side::Bid => HALF_CAP as usize - 1, Side::Ask => HALF_CAP as usize };
+            let mut seen = 0;
+            scan_occupied(bits, side, edge, |index| {
+                found[seen] = index;
+                seen += 1;
+                seen < 2
+            });
+        }
+        match side {
+            Side::Bid => [self.best_bid_idx, self.second_bid_idx] = found,
+            Side::Ask => [self.best_ask_idx, self.second_ask_idx] = found,
+        }
+    }
+
+    /// Starts classifying every level change into add/cancel churn; see `ChurnStats`.
+    pub fn enable_churn(&mut self, stats: ChurnStats) {
+        self.churn = Some(Box::new(stats));
+    }
+
+    pub fn churn(&self) -> Option<&ChurnStats> {
+        self.churn.as_deref()
+    }
+
+    /// Times the level at `price` was set or removed over the last `window` of the book clock.
+    /// 0 unless churn is enabled with `ChurnStats::with_level_flips`.
+    pub fn churn_rate(&self, side: Side, price: Price, window: u64) -> u32 {
+        self.churn.as_deref().map_or(0, |churn| churn.flip_count(side, price, self.now, window))
+    }
+
+    /// Starts recording spread and touch-size distributions on every BBO change; see
+    /// `HistogramStats`. Like the BBO callback, this puts `apply_update` on its slower path.
+    pub fn enable_histograms(&mut self, stats: HistogramStats) {
+        self.histograms = Some(Box::new(stats));
+    }
+
+    pub fn histograms(&self) -> Option<&HistogramStats> {
+        self.histograms.as_deref()
+    }
+
+    pub fn disable_histograms(&mut self) -> Option<HistogramStats> {
+        self.histograms.take().map(|stats| *stats)
+    }
+
+    /// Best price on `side`, if any.
+    #[inline(always)]
+    fn touch(&self, side: Side) -> Option<Price> {
+        match side {
+            Side::Bid => self.get_best_bid(),
+            Side::Ask => self.get_best_ask(),
+        }
+    }
+
+    /// Installs `filter` on the fallible path: `try_apply_update` refuses updates it finds
+    /// implausible with `OrderBookError::Implausible`. `apply_update` is not filtered.
+    pub fn set_update_filter(&mut self, filter: UpdateFilter) {
+        self.update_filter = Some(Box::new(filter));
+    }
+
+    /// The installed filter, with its rejection counters.
+    pub fn update_filter(&self) -> Option<&UpdateFilter> {
+        self.update_filter.as_deref()
+    }
+
+    pub fn remove_update_filter(&mut self) -> Option<UpdateFilter> {
+        self.update_filter.take().map(|filter| *filter)
+    }
+
+    /// Starts recording periodic snapshots of this book into `ring`; see `StateRing`.
+    /// Attaches `engine`, re-evaluated after every update from now on (see `AlertEngine`). Its
+    /// staleness clocks start at the book's current clock.
+    pub fn enable_alerts(&mut self, mut engine: AlertEngine) {
+        engine.attach(self.now);
+        self.alerts = Some(Box::new(engine));
+    }
+
+    pub fn alerts(&self) -> Option<&AlertEngine> {
+        self.alerts.as_deref()
+    }
+
+    /// For draining queued alerts or adding rules while attached.
+    pub fn alerts_mut(&mut self) -> Option<&mut AlertEngine> {
+        self.alerts.as_deref_mut()
+    }
+
+    pub fn disable_alerts(&mut self) -> Option<AlertEngine> {
+        self.alerts.take().map(|engine| *engine)
+    }
+
+    /// Moves the clock to `timestamp` and re-evaluates the alert rules without an update, so
+    /// duration and staleness rules come due while the feed is quiet.
+    pub fn poll_alerts(&mut self, timestamp: u64) {
+        self.now = timestamp;
+        if self.alerts.is_some() {
+            self.run_alerts([false; 2]);
+        }
+    }
+
+    fn run_alerts(&mut self, changed: [bool; 2]) {
+        if let Some(mut engine) = self.alerts.take() {
+            engine.evaluate(self.now, self, changed);
+            self.alerts = Some(engine);
+        }
+    }
+
+    pub fn enable_state_ring(&mut self, ring: StateRing) {
+        self.state_ring = Some(Box::new(ring));
+    }
+
+    pub fn state_ring(&self) -> Option<&StateRing> {
+        self.state_ring.as_deref()
+    }
+
+    /// Captures immediately into the attached ring, if any (for use when a check fails).
+    pub fn capture_state(&mut self) {
+        if let Some(mut ring) = self.state_ring.take() {
+            ring.capture(self);
+            self.state_ring = Some(ring);
+        }
+    }
+
+    /// Starts forwarding `Update::Trade`s from `apply_update` into `tape`.
+    pub fn attach_tape(&mut self, tape: TradeTape) {
+        self.tape = Some(Box::new(tape));
+    }
+
+    pub fn tape(&self) -> Option<&TradeTape> {
+        self.tape.as_deref()
+    }
+
+    pub fn detach_tape(&mut self) -> Option<TradeTape> {
+        self.tape.take().map(|tape| *tape)
+    }
+
+    #[inline(always)]
+    fn snap_to_tick(&self, update: Update) -> Update {
+        match update {
+            Update::Set { price, quantity, side } => Update::Set { price: price.div_euclid(self.tick_divisor), quantity, side },
+            Update::SetWithCount { price, quantity, order_count, side } => {
+                Update::SetWithCount { price: price.div_euclid(self.tick_divisor), quantity, order_count, side }
+            }
+            Update::Remove { price, side } => Update::Remove { price: price.div_euclid(self.tick_divisor), side },
+            Update::Trade { price, quantity, aggressor, timestamp } => {
+                Update::Trade { price: price.div_euclid(self.tick_divisor), quantity, aggressor, timestamp }
+            }
+            Update::SetLevels { side, mut levels } => {
+                for level in &mut levels {
+                    level.0 = level.0.div_euclid(self.tick_divisor);
+                }
+                Update::SetLevels { side, levels }
+            }
+        }
+    }
+
+    /// `Update::SetLevels`: writes every level, then fixes the best indices and the checksum
+    /// once. The cross policy and `max_spread` look at the book between levels, so with either
+    /// in force the levels go through `apply_core` one by one instead. As in
+    /// `apply_depth_message`, churn classifies every level against the touch before the bulk,
+    /// and the undo record is cleared.
+    fn apply_levels(&mut self, side: Side, levels: &[(Price, Quantity)]) {
+        if self.cross_policy != CrossPolicy::Allow || self.max_spread.is_some() {
+            for &(price, quantity) in levels {
+                self.apply_core(Update::Set { price, quantity, side });
+            }
+            return;
+        }
+        if self.first_anchor.is_some()
+            && let Some(&(price, _)) = levels.iter().find(|&&(_, quantity)| quantity > 0)
+        {
+            self.anchor_first(self.tick_price(price));
+        }
+        self.last_undo = None;
+        let touch = self.touch(side);
+        let mut changed = false;
+        for &(price, quantity) in levels {
+            changed |= self.write_level(side, self.tick_price(price), quantity, touch);
+        }
+        if changed {
+            self.reset_best_index(side);
+        }
+        if self.checksum_depth != 0 {
+            self.enable_checksum(self.checksum_depth as usize);
+        }
+    }
+
+    /// `try_apply_update`'s checks for a `SetLevels`.
+    fn check_levels(&mut self, side: Side, levels: &[(Price, Quantity)]) -> Result<(), OrderBookError> {
+        let mid_x2 = match (self.get_best_bid(), self.get_best_ask()) {
+            (Some(bid), Some(ask)) => Some(bid as i128 + ask as i128),
+            _ => None,
+        };
+        for &(price, quantity) in levels {
+            let price = self.tick_price(price);
+            if self.first_anchor.is_none() && !self.in_window(price, side) {
+                return Err(OrderBookError::OutOfWindow { price, anchor: self.anchor(side) });
+            }
+            let resting = self.get_quantity_at(price, side).unwrap_or(0);
+            if let Some(filter) = self.update_filter.as_deref_mut() {
+                filter.check(&Update::Set { price, quantity, side }, mid_x2, resting)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The spread `update` (already snapped) would leave, if it is over `max_spread`. Only
+    /// emptying a side's best level can widen it, and only while both sides stay quoted.
+    fn blown_spread(&self, update: &Update) -> Option<Price> {
+        let max = self.max_spread?;
+        let (price, side) = match *update {
+            Update::Set { price, side, quantity: 0 } | Update::SetWithCount { price, side, quantity: 0, .. } | Update::Remove { price, side } => {
+                (price, side)
+            }
+            _ => return None,
+        };
+        if self.best_price(side) != Some(price) {
+            return None;
+        }
+        let (next, _) = self.nth_level(side, 1)?;
+        let spread = match side {
+            Side::Bid => self.get_best_ask()? - next,
+            Side::Ask => next - self.get_best_bid()?,
+        };
+        (spread > max).then_some(spread)
+    }
+
+    /// The opposite best that a new level at `price` on `side` would cross, if any.
+    #[inline(always)]
+    fn crossed_best(&self, price: Price, side: Side) -> Option<Price> {
+        match side {
+            Side::Bid => self.get_best_ask().filter(|&ask| price >= ask),
+            Side::Ask => self.get_best_bid().filter(|&bid| price <= bid),
+        }
+    }
+
+    /// Applies the cross policy ahead of `update`; returns false if the update must be dropped.
+    /// Under `AutoResolve` the trimmed opposite levels are not covered by `undo_last`.
+    fn resolve_cross(&mut self, update: &Update) -> bool {
+        let (Update::Set { price, quantity, side } | Update::SetWithCount { price, quantity, side, .. }) = *update else {
+            return true;
+        };
+        if quantity == 0 {
+            return true;
+        }
+        match self.cross_policy {
+            CrossPolicy::Allow => true,
+            CrossPolicy::Reject => self.crossed_best(price, side).is_none(),
+            CrossPolicy::AutoResolve => {
+                let opposite = match side { Side::Bid => Side::Ask, Side::Ask => Side::Bid };
+                while let Some(crossed) = self.crossed_best(price, side) {
+                    self.apply_core(Update::Remove { price: crossed, side: opposite });
+                }
+                true
+            }
+        }
+    }
+
+    /// Reverses the most recent `apply_update`. Only one level of undo is kept:
+    /// returns `false` if there is nothing to undo (no update yet, already undone, or the last
+    /// one was refused by a policy or dropped by `max_levels`).
+    pub fn undo_last(&mut self) -> bool {
+        let Some(record) = self.last_undo.take() else { return false };
+        let (book, bits, best_idx, second_idx, total_qty, level_count) = match record.side {
+            Side::Bid => (&mut self.bids, &mut self.bid_bits, &mut self.best_bid_idx, &mut self.second_bid_idx, &mut self.total_bid_quantity, &mut self.bid_count),
+            Side::Ask => (&mut self.asks, &mut self.ask_bits, &mut self.best_ask_idx, &mut self.second_ask_idx, &mut self.total_ask_quantity, &mut self.ask_count),
+        };
+        match (book[record.index] > 0, record.quantity > 0) {
+            (false, true) => *level_count += 1,
+            (true, false) => *level_count -= 1,
+            _ => {}
+        }
+        let previous = book[record.index];
+        let changed = previous != record.quantity;
+        self.version += changed as u64;
+        book[record.index] = record.quantity;
+        if record.quantity > 0 { set_bit(bits, record.index) } else { clear_bit(bits, record.index) }
+        *total_qty = record.total;
+        *best_idx = record.best_idx;
+        *second_idx = record.second_idx;
+        if changed {
+            self.level_changed(record.side, record.index, previous, record.quantity);
+        }
+        #[cfg(feature = "order-counts")]
+        self.set_slot_count(record.side, record.index, record.count);
+        if self.checksum_depth != 0 {
+            let (sum, boundary) = self.side_checksum(record.side, self.checksum_depth as usize);
+            self.side_checksums[record.side as usize] = sum;
+            self.checksum_boundary[record.side as usize] = boundary;
+        }
+        true
+    }
+
+    /// Order-independent hash of the best `n` levels on both sides.
+    /// Full recomputation; `current_checksum` is the incrementally maintained equivalent.
+    pub fn top_levels_checksum(&self, n: usize) -> u64 {
+        self.side_checksum(Side::Bid, n).0.wrapping_add(self.side_checksum(Side::Ask, n).0)
+    }
+
+    /// Starts maintaining the top-`depth` checksum inside `apply_update` (0 disables it).
+    pub fn enable_checksum(&mut self, depth: usize) {
+        let depth = depth.min(CAP);
+        self.checksum_depth = depth as u32;
+        self.side_checksums = [0; 2];
+        self.checksum_boundary = [None; 2];
+        if depth != 0 {
+            for side in [Side::Bid, Side::Ask] {
+                let (sum, boundary) = self.side_checksum(side, depth);
+                self.side_checksums[side as usize] = sum;
+                self.checksum_boundary[side as usize] = boundary;
+            }
+        }
+    }
+
+    /// The rolling top-N checksum; equals `top_levels_checksum(depth)` after every update.
+    #[inline(always)]
+    pub fn current_checksum(&self) -> u64 {
+        self.side_checksums[0].wrapping_add(self.side_checksums[1])
+    }
+
+    /// Rescans the top-N of `side` only when `price` could be one of them: either the side has
+    /// fewer than N levels, or `price` is at or better than the current N-th level.
+    fn refresh_checksum(&mut self, side: Side, price: Price) {
+        let within = match (self.checksum_boundary[side as usize], side) {
+            (None, _) => true,
+            (Some(edge), Side::Bid) => price >= edge,
+            (Some(edge), Side::Ask) => price <= edge,
+        };
+        if within {
+            let (sum, boundary) = self.side_checksum(side, self.checksum_depth as usize);
+            self.side_checksums[side as usize] = sum;
+            self.checksum_boundary[side as usize] = boundary;
+        }
+    }
+
+    /// Returns the side's checksum contribution and, if the side has at least `n` levels, the N-th price.
+    fn side_checksum(&self, side: Side, n: usize) -> (u64, Option<Price>) {
+        let mut sum = 0u64;
+        let mut count = 0;
+        let mut last = None;
+        for (price, qty) in self.levels(side).take(n) {
+            sum = sum.wrapping_add(level_hash(side, price, qty));
+            count += 1;
+            last = Some(price);
+        }
+        (sum, if count == n { last } else { None })
+    }
+
+    /// `price` as `apply_update` will store it (after the tick divisor, if any).
+    #[inline(always)]
+    pub(crate) fn tick_price(&self, price: Price) -> Price {
+        if self.tick_divisor != 1 { price.div_euclid(self.tick_divisor) } else { price }
+    }
+
+    /// Moves both windows to be centred on `new_anchor`. Levels that fall outside the new
+    /// window are dropped; returns the quantity dropped as `(bid, ask)`, so lost liquidity can
+    /// be logged. Not undoable: clears the `undo_last` record.
+    pub fn recenter_anchor(&mut self, new_anchor: Price) -> (Quantity, Quantity) {
+        self.recenter([new_anchor; 2])
+    }
+
+    /// `recenter_anchor` on the largest multiple of the anchor grid (`OrderBookBuilder::anchor_grid`,
+    /// 256 ticks by default) at or below `near`. Following a slowly trending price with this
+    /// moves the window once per grid step instead of on every tick; a call that lands on the
+    /// current anchor does nothing.
+    ///
+    /// With a reference price set (`set_reference_price`), the anchor is instead the grid
+    /// multiple nearest the reference among those whose window still holds `near`, so the
+    /// window ends up centred on where activity is expected rather than on the price that
+    /// happened to fall outside it.
+    pub fn recenter_anchor_aligned(&mut self, near: Price) -> (Quantity, Quantity) {
+        let grid = self.anchor_grid;
+        let anchor = match self.reference_price {
+            None => near.div_euclid(grid).saturating_mul(grid),
+            Some(reference) => {
+                // Anchors whose window holds `near` run from near - CAP/2 + 1 to near + CAP/2;
+                // the grid is at most CAP/2, so both bounds below are in that range.
+                let low = near.saturating_sub(HALF_CAP - 1).saturating_add(grid - 1).div_euclid(grid).saturating_mul(grid);
+                let high = near.saturating_add(HALF_CAP).div_euclid(grid).saturating_mul(grid);
+                round_to_nearest(reference, grid).clamp(low, high)
+            }
+        };
+        self.recenter_anchor(anchor)
+    }
+
+    /// Sets the external fair price (in ticks, like the anchors) that recentering steers
+    /// towards; see `recenter_anchor_aligned` and `recenter_to_reference`. Replaces any earlier
+    /// one and moves nothing by itself.
+    pub fn set_reference_price(&mut self, price: Price) {
+        self.reference_price = Some(price);
+    }
+
+    pub fn clear_reference_price(&mut self) {
+        self.reference_price = None;
+    }
+
+    pub fn reference_price(&self) -> Option<Price> {
+        self.reference_price
+    }
+
+    /// Recenters both windows on the grid multiple nearest the reference price, for keeping
+    /// the window on expected activity through quiet periods when no update forces a move.
+    /// Does nothing without a reference price, or when the anchors are already there.
+    /// Returns the dropped quantity like `recenter_anchor`.
+    pub fn recenter_to_reference(&mut self) -> (Quantity, Quantity) {
+        match self.reference_price {
+            Some(reference) => self.recenter_anchor(round_to_nearest(reference, self.anchor_grid)),
+            None => (0, 0),
+        }
+    }
+
+    /// Anchors an `AnchorMode::FromFirstUpdate` book on the first price it sees.
+    #[cold]
+    fn anchor_first(&mut self, price: Price) {
+        if let Some(round_to) = self.first_anchor {
+            self.recenter([round_to_nearest(price, round_to); 2]);
+        }
+    }
+
+    /// Anchor for an unanchored book's first depth message: the mid of its best bid and best
+    /// ask, or the one of them it has. `None` if it opens no level.
+    fn first_depth_anchor(&self, bids: &[(Price, Quantity)], asks: &[(Price, Quantity)], round_to: i64) -> Option<Price> {
+        fn opened(levels: &[(Price, Quantity)]) -> impl Iterator<Item = Price> + '_ {
+            levels.iter().filter(|&&(_, qty)| qty > 0).map(|&(price, _)| price)
+        }
+        let bid = opened(bids).max().map(|price| self.tick_price(price));
+        let ask = opened(asks).min().map(|price| self.tick_price(price));
+        let price = match (bid, ask) {
+            (Some(bid), Some(ask)) => ((bid as i128 + ask as i128).div_euclid(2)) as Price,
+            (bid, ask) => bid.or(ask)?,
+        };
+        Some(round_to_nearest(price, round_to))
+    }
+
+    /// Like `recenter_anchor`, for one side's window only; the other side keeps its anchor
+    /// (and drops nothing).
+    pub fn recenter_side(&mut self, side: Side, new_anchor: Price) -> (Quantity, Quantity) {
+        let mut anchors = self.anchors;
+        anchors[side as usize] = new_anchor;
+        self.recenter(anchors)
+    }
+
+    fn recenter(&mut self, anchors: [Price; 2]) -> (Quantity, Quantity) {
+        self.first_anchor = None;
+        if anchors == self.anchors {
+            return (0, 0);
+        }
+        // Logged as one event: a replica recenters itself and drops the same levels. Watches
+        // are compared before and after instead of seeing the clear and the re-insertion, and
+        // churn sees neither: no level was added, cancelled or flipped.
+        let log = self.event_log.take();
+        let watches = self.watches.take();
+        let churn = self.churn.take();
+        #[cfg(feature = "metrics")]
+        { self.metrics.recenters += 1; }
+        let with_counts = |book: &Self, side: Side| -> Vec<(Price, Quantity, u32)> {
+            book.levels(side).map(|(price, qty)| (price, qty, book.slot_count(side, book.price_to_index(price, side)))).collect()
+        };
+        let bids = with_counts(self, Side::Bid);
+        let asks = with_counts(self, Side::Ask);
+        let before = self.get_bbo();
+        let (version, clears) = (self.version, self.clears);
+        let (policy, divisor) = (self.cross_policy, self.tick_divisor);
+        self.cross_policy = CrossPolicy::Allow;
+        self.tick_divisor = 1;
+
+        self.clear();
+        self.anchors = anchors;
+        let mut dropped: [Quantity; 2] = [0; 2];
+        for (side, levels) in [(Side::Bid, bids), (Side::Ask, asks)] {
+            for (price, quantity, order_count) in levels {
+                if self.in_window(price, side) {
+                    self.apply_core(Update::SetWithCount { price, quantity, order_count, side });
+                } else {
+                    dropped[side as usize] = dropped[side as usize].wrapping_add(quantity);
+                }
+            }
+        }
+
+        self.cross_policy = policy;
+        self.tick_divisor = divisor;
+        self.last_undo = None;
+        self.version = version + 1;
+        self.clears = clears;
+        self.enable_checksum(self.checksum_depth as usize);
+        self.event_log = log;
+        if let Some(log) = self.event_log.as_mut() {
+            log.push(BookEvent::Recenter { anchors });
+        }
+        self.watches = watches;
+        self.refresh_watches();
+        self.churn = churn;
+        self.notify_bbo(before);
+        (dropped[0], dropped[1])
+    }
+}
+
+
+/// Whether the BBO prices or quantities differ (the version is ignored).
+#[inline(always)]
+fn touch_moved(before: Option<Bbo>, after: Option<Bbo>) -> bool {
+    match (before, after) {
+        (Some(before), Some(after)) => !before.same_touch(&after),
+        (before, after) => before.is_some() != after.is_some(),
+    }
+}
+
+/// Exact inverse of `price_to_index` over the window: the slot index read as a signed
+/// `log2(CAP)`-bit offset from the anchor.
+#[inline(always)]
+fn slot_price<P: PriceInt>(anchor: P, index: usize) -> P {
+    let offset = index as i64;
+    let adjustment = if offset >= HALF_CAP { -CAP_I64 } else { 0 };
+    anchor.offset_by(offset + adjustment)
+}
+
+/// Prefetch hint for the slot `apply_update` is about to write; a no-op unless the
+/// `prefetch` feature is enabled on x86_64.
+#[inline(always)]
+#[allow(unused_variables)]
+fn prefetch_slot(book: &[Quantity; CAP], index: usize) {
+    #[cfg(all(feature = "prefetch", target_arch = "x86_64"))]
+    unsafe {
+        use std::arch::x86_64::{_MM_HINT_T0, _mm_prefetch};
+        _mm_prefetch::<_MM_HINT_T0>(book.as_ptr().add(index) as *const i8);
+    }
+}
+
+#[inline(always)]
+fn set_bit(bits: &mut [u64; WORDS], index: usize) {
+    unsafe { *bits.get_unchecked_mut(index >> 6) |= 1 << (index & 63) };
+}
+
+#[inline(always)]
+fn clear_bit(bits: &mut [u64; WORDS], index: usize) {
+    unsafe { *bits.get_unchecked_mut(index >> 6) &= !(1 << (index & 63)) };
+}
+
+/// Visits occupied slots in price order starting at `from` and moving away from the touch
+/// (down for bids, up for asks) until the window edge, or until `visit` returns false.
+fn scan_occupied(bits: &[u64; WORDS], side: Side, from: usize, mut visit: impl FnMut(usize) -> bool) {
+    let bottom = HALF_CAP as usize; // lowest price in the window
+    let top = bottom - 1; // highest price in the window
+    // Split the walk into at most two index-contiguous runs.
+    let runs = match side {
+        Side::Ask if from >= bottom => [(from, CAP_MASK), (0, top)],
+        Side::Ask => [(from, top), (1, 0)],
+        Side::Bid if from <= top => [(0, from), (bottom, CAP_MASK)],
+        Side::Bid => [(bottom, from), (1, 0)],
+    };
+    for &(lo, hi) in &runs {
+        if lo > hi {
+            continue;
+        }
+        let keep_going = match side {
+            Side::Ask => scan_up(bits, lo, hi, &mut visit),
+            Side::Bid => scan_down(bits, lo, hi, &mut visit),
+        };
+        if !keep_going {
+            return;
+        }
+    }
+}
+
+fn scan_up(bits: &[u64; WORDS], lo: usize, hi: usize, visit: &mut impl FnMut(usize) -> bool) -> bool {
+    for (word_idx, &word) in bits.iter().enumerate().take((hi >> 6) + 1).skip(lo >> 6) {
+        let mut word = word;
+        if word_idx == lo >> 6 { word &= !0u64 << (lo & 63); }
+        if word_idx == hi >> 6 { word &= !0u64 >> (63 - (hi & 63)); }
+        while word != 0 {
+            let index = (word_idx << 6) | word.trailing_zeros() as usize;
+            if !visit(index) { return false; }
+            word &= word - 1;
+        }
+    }
+    true
+}
+
+fn scan_down(bits: &[u64; WORDS], lo: usize, hi: usize, visit: &mut impl FnMut(usize) -> bool) -> bool {
+    for (word_idx, &word) in bits.iter().enumerate().take((hi >> 6) + 1).skip(lo >> 6).rev() {
+        let mut word = word;
+        if word_idx == lo >> 6 { word &= !0u64 << (lo & 63); }
+        if word_idx == hi >> 6 { word &= !0u64 >> (63 - (hi & 63)); }
+        while word != 0 {
+            let bit = 63 - word.leading_zeros() as usize;
+            if !visit((word_idx << 6) | bit) { return false; }
+            word &= !(1u64 << bit);
+        }
+    }
+    true
+}
+
+/// `price` rounded to the nearest multiple of `multiple` (halves up), saturating.
+fn round_to_nearest(price: Price, multiple: i64) -> Price {
+    price.saturating_add(multiple / 2).div_euclid(multiple).saturating_mul(multiple)
+}
+
+/// Places a just-opened level at `index` in the best / second-best indices; `count` includes it.
+#[inline(always)]
+fn rank_new_level(is_bid: bool, index: usize, count: u32, best_idx: &mut usize, second_idx: &mut usize) {
+    let better = |a: usize, b: usize| if is_bid { window_rank(a) > window_rank(b) } else { window_rank(a) < window_rank(b) };
+    if count == 1 {
+        *best_idx = index;
+    } else if better(index, *best_idx) {
+        *second_idx = *best_idx;
+        *best_idx = index;
+    } else if count == 2 || better(index, *second_idx) {
+        *second_idx = index;
+    }
+}
+
+/// Position of a slot in ascending price order within the window (0 = lowest price).
+#[inline(always)]
+fn window_rank(index: usize) -> usize {
+    index.wrapping_sub(HALF_CAP as usize) & CAP_MASK
+}
+
+#[inline(always)]
+fn level_hash(side: Side, price: Price, quantity: Quantity) -> u64 {
+    // splitmix64 finalizer over the packed level
+    let mut x = (price as u64) ^ quantity.rotate_left(32) ^ ((side as u64) << 63);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Every occupied level of the book as `(side, price, quantity)`: the asks in ascending price,
+/// then the bids in descending price, each side best first as in `levels`. Exact-sized, from
+/// the level counts.
+impl<'a, P: PriceInt, S: LevelStorage> IntoIterator for &'a OrderBookImpl<P, S> {
+    type Item = (Side, P, Quantity);
+    type IntoIter = BookLevels<'a, P>;
+
+    fn into_iter(self) -> BookLevels<'a, P> {
+        BookLevels {
+            asks: self.levels(Side::Ask),
+            bids: self.levels(Side::Bid),
+            asks_left: self.level_count(Side::Ask),
+            bids_left: self.level_count(Side::Bid),
+        }
+    }
+}
+
+/// Iterator over both sides' levels; see `IntoIterator for &OrderBookImpl`.
+pub struct BookLevels<'a, P: PriceInt = Price> {
+    asks: Levels<'a, P>,
+    bids: Levels<'a, P>,
+    asks_left: usize,
+    bids_left: usize,
+}
+
+impl<P: PriceInt> Iterator for BookLevels<'_, P> {
+    type Item = (Side, P, Quantity);
+
+    #[inline]
+    fn next(&mut self) -> Option<(Side, P, Quantity)> {
+        if self.asks_left > 0 {
+            self.asks_left -= 1;
+            return self.asks.next().map(|(price, quantity)| (Side::Ask, price, quantity));
+        }
+        if self.bids_left > 0 {
+            self.bids_left -= 1;
+            return self.bids.next().map(|(price, quantity)| (Side::Bid, price, quantity));
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.asks_left + self.bids_left;
+        (len, Some(len))
+    }
+}
+
+impl<P: PriceInt> ExactSizeIterator for BookLevels<'_, P> {}
+
+/// Iterator over the occupied levels of one side, best first. See `OrderBookImpl::levels`.
+pub struct Levels<'a, P: PriceInt = Price> {
+    book: &'a [Quantity; CAP],
+    anchor: P,
+    side: Side,
+    next: usize,
+    remaining: usize,
+}
+
+impl<P: PriceInt> Iterator for Levels<'_, P> {
+    type Item = (P, Quantity);
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<(P, Quantity)> {
+        while self.remaining > 0 {
+            let index = self.next;
+            self.remaining -= 1;
+            self.next = match self.side {
+                Side::Bid => index.wrapping_sub(1) & CAP_MASK,
+                Side::Ask => (index + 1) & CAP_MASK,
+            };
+            let qty = unsafe { *self.book.get_unchecked(index) };
+            if qty > 0 {
+                return Some((slot_price(self.anchor, index), qty));
+            }
+        }
+        None
+    }
+}
+
+/// Read-only view of one side's ring, from `OrderBookImpl::levels_raw`: the quantity slots plus
+/// what is needed to interpret them. Borrowing the book keeps it immutable for the view's life.
+///
+/// Invariants a consumer may rely on:
+/// - slot `i` holds the quantity resting at `index_to_price(i)`; 0 means the level is empty;
+/// - every price in the window maps to exactly one slot (`price_to_index` is its inverse), and
+///   the window is `[anchor - CAP / 2, anchor + CAP / 2 - 1]`;
+/// - `best_index` is the best occupied slot, `None` exactly when every slot is 0;
+/// - the sum of the slots is `get_total_quantity` (unless the total wrapped on overflow).
+///
+/// ```
+/// use rust_3::interfaces::{OrderBookRead, OrderBookWrite, Side, Update};
+/// use rust_3::orderbook::OrderBookImpl;
+///
+/// let mut ob = OrderBookImpl::new();
+/// ob.apply_update(Update::Set { price: 9990, quantity: 5, side: Side::Bid });
+/// ob.apply_update(Update::Set { price: 9985, quantity: 7, side: Side::Bid });
+///
+/// let raw = ob.levels_raw(Side::Bid);
+/// let total: u64 = raw.quantities().iter().sum();
+/// assert_eq!(total, ob.get_total_quantity(Side::Bid));
+/// assert_eq!(raw.best_index().map(|i| raw.index_to_price(i)), ob.get_best_bid());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RawLevels<'a, P: PriceInt = Price> {
+    quantities: &'a [Quantity; CAP],
+    anchor: P,
+    best_index: Option<usize>,
+}
+
+impl<'a, P: PriceInt> RawLevels<'a, P> {
+    #[inline(always)]
+    pub fn quantities(&self) -> &'a [Quantity; CAP] {
+        self.quantities
+    }
+
+    #[inline(always)]
+    pub fn anchor(&self) -> P {
+        self.anchor
+    }
+
+    #[inline(always)]
+    pub fn best_index(&self) -> Option<usize> {
+        self.best_index
+    }
+
+    /// Price of slot `index` (taken modulo `CAP`).
+    #[inline(always)]
+    pub fn index_to_price(&self, index: usize) -> P {
+        slot_price(self.anchor, index & CAP_MASK)
+    }
+
+    /// Slot of `price`, or `None` outside the window.
+    #[inline(always)]
+    pub fn price_to_index(&self, price: P) -> Option<usize> {
+        let offset = price.offset_from(self.anchor);
+        (-HALF_CAP..HALF_CAP).contains(&offset).then_some(offset as usize & CAP_MASK)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_and_write_mappings_are_exact_inverses() {
+        let mut ob = OrderBookImpl::new();
+        ob.recenter_side(Side::Ask, -7);
+        let unsigned = OrderBookImpl::<u32>::with_anchor(1000);
+        for index in 0..CAP {
+            for side in [Side::Bid, Side::Ask] {
+                assert_eq!(ob.price_to_index(ob.index_to_price(side, index), side), index);
+                assert!(ob.in_window(ob.index_to_price(side, index), side));
+            }
+            assert_eq!(unsigned.price_to_index(unsigned.index_to_price(Side::Bid, index), Side::Bid), index);
+        }
+        for side in [Side::Bid, Side::Ask] {
+            let anchor = ob.anchor(side);
+            for price in anchor - HALF_CAP..anchor + HALF_CAP {
+                assert_eq!(ob.index_to_price(side, ob.price_to_index(price, side)), price);
+            }
+            assert!(!ob.in_window(anchor - HALF_CAP - 1, side));
+            assert!(!ob.in_window(anchor + HALF_CAP, side));
+        }
+        // The unsigned window wraps through 0: [u32::MAX - 1047, 3047].
+        for price in (0..CAP as u32).map(|k| 1000u32.wrapping_sub(HALF_CAP as u32).wrapping_add(k)) {
+            assert!(unsigned.in_window(price, Side::Bid));
+            assert_eq!(unsigned.index_to_price(Side::Bid, unsigned.price_to_index(price, Side::Bid)), price);
+        }
+        assert!(!unsigned.in_window(3048, Side::Bid));
+    }
+}
@@ -0,0 +1,413 @@
+// persist.rs
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::checksum::crc32;
+use crate::error::OrderBookError;
+use crate::interfaces::{BulkLevels, Price, Quantity, Side, Update};
+use crate::orderbook::{CAP, OrderBookImpl};
+
+/// Snapshot file layout, all little-endian. A 64-byte header:
+///
+/// | bytes  | field                                                      |
+/// |--------|------------------------------------------------------------|
+/// | 0..8   | magic, `SNAPSHOT_MAGIC`                                    |
+/// | 8..12  | format version, `u32` (`SNAPSHOT_VERSION`)                 |
+/// | 12..16 | flags, `u32`: bit 0 set if a sequence was recorded         |
+/// | 16..24 | symbol tag, `u64`, chosen by the caller                    |
+/// | 24..32 | bid anchor, `i64`                                          |
+/// | 32..40 | ask anchor, `i64`                                          |
+/// | 40..48 | last sequence, `u64` (0 when the flag is clear)            |
+/// | 48..56 | payload length, `u64`                                      |
+/// | 56..60 | CRC-32 of bytes 0..56 and the payload                      |
+/// | 60..64 | reserved, written as zero and ignored                      |
+///
+/// then the payload: the book clock `u64`, the bid and ask level counts as `u32`s, and every
+/// level as price `i64`, quantity `u64`: the bids best first, then the asks best first.
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"RSOBSNAP";
+pub const SNAPSHOT_VERSION: u32 = 1;
+pub const SNAPSHOT_HEADER_LEN: usize = 64;
+const LEVEL_LEN: usize = 16;
+const HAS_SEQUENCE: u32 = 1;
+
+/// Why a snapshot file was refused. `load_from` and `read_snapshot_header` return it inside an
+/// `io::Error` of kind `InvalidData`; get it back with `get_ref()` and `downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotFileError {
+    /// The file does not start with `SNAPSHOT_MAGIC`.
+    NotASnapshot,
+    /// Written in a format version this build does not read.
+    UnsupportedVersion { found: u32, supported: u32 },
+    /// The file is shorter than its header says.
+    Truncated { expected: u64, found: u64 },
+    /// The stored CRC does not match the contents: the file is corrupt.
+    ChecksumMismatch { stored: u32, computed: u32 },
+    /// Saved for a different symbol than the one asked for.
+    SymbolMismatch { expected: u64, found: u64 },
+    /// The checksum matched but the contents are inconsistent.
+    Malformed(&'static str),
+}
+
+impl fmt::Display for SnapshotFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotFileError::NotASnapshot => write!(f, "not an order book snapshot file"),
+            SnapshotFileError::UnsupportedVersion { found, supported } => {
+                write!(f, "snapshot format version {} is not supported (this build reads version {})", found, supported)
+            }
+            SnapshotFileError::Truncated { expected, found } => {
+                write!(f, "snapshot file is truncated: expected {} bytes, found {}", expected, found)
+            }
+            SnapshotFileError::ChecksumMismatch { stored, computed } => {
+                write!(f, "snapshot checksum mismatch: stored {:08x}, computed {:08x}", stored, computed)
+            }
+            SnapshotFileError::SymbolMismatch { expected, found } => {
+                write!(f, "snapshot is for symbol {}, expected {}", found, expected)
+            }
+            SnapshotFileError::Malformed(reason) => write!(f, "malformed snapshot: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotFileError {}
+
+/// `offset` is that of the header field at fault: the end of the data for a truncated file, the
+/// start of the payload for a malformed one.
+impl From<SnapshotFileError> for OrderBookError {
+    fn from(err: SnapshotFileError) -> Self {
+        let (offset, reason) = match err {
+            SnapshotFileError::NotASnapshot => (0, "not an order book snapshot file"),
+            SnapshotFileError::UnsupportedVersion { .. } => (8, "unsupported snapshot format version"),
+            SnapshotFileError::Truncated { found, .. } => (found as usize, "snapshot file is truncated"),
+            SnapshotFileError::ChecksumMismatch { .. } => (56, "snapshot checksum mismatch"),
+            SnapshotFileError::SymbolMismatch { .. } => (16, "snapshot is for another symbol"),
+            SnapshotFileError::Malformed(reason) => (SNAPSHOT_HEADER_LEN, reason),
+        };
+        OrderBookError::Decode { offset, reason }
+    }
+}
+
+impl From<SnapshotFileError> for io::Error {
+    fn from(err: SnapshotFileError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// The fixed fields of a snapshot file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub version: u32,
+    pub symbol: u64,
+    /// Bid and ask window anchors, in that order.
+    pub anchors: [Price; 2],
+    /// `last_sequence` at save time.
+    pub sequence: Option<u64>,
+    pub payload_len: u64,
+    pub crc: u32,
+}
+
+impl SnapshotHeader {
+    fn parse(bytes: &[u8; SNAPSHOT_HEADER_LEN]) -> Result<Self, SnapshotFileError> {
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        if bytes[..8] != SNAPSHOT_MAGIC {
+            return Err(SnapshotFileError::NotASnapshot);
+        }
+        let version = u32_at(8);
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotFileError::UnsupportedVersion { found: version, supported: SNAPSHOT_VERSION });
+        }
+        Ok(SnapshotHeader {
+            version,
+            symbol: u64_at(16),
+            anchors: [u64_at(24) as Price, u64_at(32) as Price],
+            sequence: (u32_at(12) & HAS_SEQUENCE != 0).then(|| u64_at(40)),
+            payload_len: u64_at(48),
+            crc: u32_at(56),
+        })
+    }
+}
+
+impl OrderBookImpl {
+    /// Writes the book's levels, anchors, clock and `last_sequence` to `path` in the format
+    /// described at `SNAPSHOT_MAGIC`, tagged with `symbol`. The file is written under a
+    /// temporary name beside `path`, synced, then renamed over it, so a crash mid-save leaves
+    /// either the old file or the new one, never a torn one.
+    pub fn save_to(&self, path: impl AsRef<Path>, symbol: u64) -> io::Result<()> {
+        let path = path.as_ref();
+        let bytes = self.snapshot_bytes(symbol);
+        let temp = temp_path(path);
+        let written = File::create(&temp).and_then(|mut file| {
+            file.write_all(&bytes)?;
+            file.sync_all()
+        });
+        if let Err(err) = written.and_then(|()| fs::rename(&temp, path)) {
+            let _ = fs::remove_file(&temp);
+            return Err(err);
+        }
+        // Make the rename itself durable where directories can be synced.
+        #[cfg(unix)]
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds a book saved by `save_to` for `symbol`: the same anchors, levels, clock and
+    /// `last_sequence`, with every other option at its default (`version` restarts too).
+    /// Refuses, as `InvalidData` carrying a `SnapshotFileError`, a file that is not a snapshot,
+    /// has another format version, is truncated, fails its CRC, or was saved for another symbol.
+    pub fn load_from(path: impl AsRef<Path>, symbol: u64) -> io::Result<OrderBookImpl> {
+        let bytes = fs::read(path)?;
+        let (header, payload) = split_snapshot(&bytes)?;
+        if header.symbol != symbol {
+            return Err(SnapshotFileError::SymbolMismatch { expected: symbol, found: header.symbol }.into());
+        }
+        Ok(book_from_payload(&header, payload)?)
+    }
+
+    /// The whole snapshot file, header and payload, as `save_to` writes it.
+    pub(crate) fn snapshot_bytes(&self, symbol: u64) -> Vec<u8> {
+        let (bids, asks) = (self.level_count(Side::Bid), self.level_count(Side::Ask));
+        let payload_len = 16 + (bids + asks) * LEVEL_LEN;
+        let mut out = Vec::with_capacity(SNAPSHOT_HEADER_LEN + payload_len);
+        out.extend_from_slice(&SNAPSHOT_MAGIC);
+        out.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        let sequence = self.last_sequence();
+        out.extend_from_slice(&(if sequence.is_some() { HAS_SEQUENCE } else { 0 }).to_le_bytes());
+        out.extend_from_slice(&symbol.to_le_bytes());
+        out.extend_from_slice(&self.anchor(Side::Bid).to_le_bytes());
+        out.extend_from_slice(&self.anchor(Side::Ask).to_le_bytes());
+        out.extend_from_slice(&sequence.unwrap_or(0).to_le_bytes());
+        out.extend_from_slice(&(payload_len as u64).to_le_bytes());
+        out.extend_from_slice(&[0; 8]);
+
+        out.extend_from_slice(&self.now().to_le_bytes());
+        out.extend_from_slice(&(bids as u32).to_le_bytes());
+        out.extend_from_slice(&(asks as u32).to_le_bytes());
+        for side in [Side::Bid, Side::Ask] {
+            for (price, quantity) in self.levels(side) {
+                out.extend_from_slice(&price.to_le_bytes());
+                out.extend_from_slice(&quantity.to_le_bytes());
+            }
+        }
+        let crc = snapshot_crc(&out[..56], &out[SNAPSHOT_HEADER_LEN..]);
+        out[56..60].copy_from_slice(&crc.to_le_bytes());
+        out
+    }
+}
+
+/// The header of the snapshot at `path`, checked for magic and format version only, so a
+/// caller can see which symbol and sequence a file holds before loading it.
+pub fn read_snapshot_header(path: impl AsRef<Path>) -> io::Result<SnapshotHeader> {
+    let mut bytes = Vec::with_capacity(SNAPSHOT_HEADER_LEN);
+    io::Read::read_to_end(&mut io::Read::take(File::open(path)?, SNAPSHOT_HEADER_LEN as u64), &mut bytes)?;
+    let Some(header) = bytes.first_chunk::<SNAPSHOT_HEADER_LEN>() else {
+        return Err(SnapshotFileError::Truncated { expected: SNAPSHOT_HEADER_LEN as u64, found: bytes.len() as u64 }.into());
+    };
+    Ok(SnapshotHeader::parse(header)?)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+fn snapshot_crc(header: &[u8], payload: &[u8]) -> u32 {
+    let mut bytes = Vec::with_capacity(header.len() + payload.len());
+    bytes.extend_from_slice(header);
+    bytes.extend_from_slice(payload);
+    crc32(&bytes)
+}
+
+/// The book in `bytes` (a snapshot as `snapshot_bytes` produced it), checked like `load_from`
+/// but whatever its symbol.
+pub(crate) fn book_from_snapshot(bytes: &[u8]) -> Result<OrderBookImpl, SnapshotFileError> {
+    let (header, payload) = split_snapshot(bytes)?;
+    book_from_payload(&header, payload)
+}
+
+/// Checks length and CRC and returns the header with the payload.
+fn split_snapshot(bytes: &[u8]) -> Result<(SnapshotHeader, &[u8]), SnapshotFileError> {
+    let Some(header_bytes) = bytes.first_chunk::<SNAPSHOT_HEADER_LEN>() else {
+        return Err(SnapshotFileError::Truncated { expected: SNAPSHOT_HEADER_LEN as u64, found: bytes.len() as u64 });
+    };
+    let header = SnapshotHeader::parse(header_bytes)?;
+    let expected = (SNAPSHOT_HEADER_LEN as u64).saturating_add(header.payload_len);
+    if (bytes.len() as u64) < expected {
+        return Err(SnapshotFileError::Truncated { expected, found: bytes.len() as u64 });
+    }
+    if bytes.len() as u64 > expected {
+        return Err(SnapshotFileError::Malformed("trailing bytes after the payload"));
+    }
+    let payload = &bytes[SNAPSHOT_HEADER_LEN..];
+    let computed = snapshot_crc(&bytes[..56], payload);
+    if computed != header.crc {
+        return Err(SnapshotFileError::ChecksumMismatch { stored: header.crc, computed });
+    }
+    Ok((header, payload))
+}
+
+fn book_from_payload(header: &SnapshotHeader, payload: &[u8]) -> Result<OrderBookImpl, SnapshotFileError> {
+    let Some((fixed, levels)) = payload.split_first_chunk::<16>() else {
+        return Err(SnapshotFileError::Malformed("payload shorter than its fixed fields"));
+    };
+    let now = u64::from_le_bytes(fixed[..8].try_into().unwrap());
+    let counts = [u32::from_le_bytes(fixed[8..12].try_into().unwrap()), u32::from_le_bytes(fixed[12..16].try_into().unwrap())];
+    if counts.iter().any(|&count| count as usize > CAP) {
+        return Err(SnapshotFileError::Malformed("more levels than a window holds"));
+    }
+    if levels.len() != (counts[0] as usize + counts[1] as usize) * LEVEL_LEN {
+        return Err(SnapshotFileError::Malformed("level counts disagree with the payload length"));
+    }
+
+    let mut book = OrderBookImpl::builder().bid_anchor(header.anchors[0]).ask_anchor(header.anchors[1]).build();
+    let mut records = levels.chunks_exact(LEVEL_LEN).map(|record| {
+        (Price::from_le_bytes(record[..8].try_into().unwrap()), Quantity::from_le_bytes(record[8..].try_into().unwrap()))
+    });
+    for side in [Side::Bid, Side::Ask] {
+        let side_levels: BulkLevels = records.by_ref().take(counts[side as usize] as usize).collect();
+        if side_levels.iter().any(|&(price, quantity)| quantity == 0 || !book.in_window(price, side)) {
+            return Err(SnapshotFileError::Malformed("level empty or outside its window"));
+        }
+        let best_first = side_levels.windows(2).all(|pair| match side {
+            Side::Bid => pair[0].0 > pair[1].0,
+            Side::Ask => pair[0].0 < pair[1].0,
+        });
+        if !best_first {
+            return Err(SnapshotFileError::Malformed("levels out of order"));
+        }
+        book.apply_update_at(now, Update::SetLevels { side, levels: side_levels });
+    }
+    if let Some(sequence) = header.sequence {
+        book.set_snapshot_baseline(sequence).expect("nothing is buffered on a fresh book");
+    }
+    Ok(book)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+    use crate::interfaces::OrderBookWrite;
+
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rust-orderbook-{}-{}.snap", process::id(), name))
+    }
+
+    fn sample_book() -> OrderBookImpl {
+        let mut ob = OrderBookImpl::builder().bid_anchor(50_000).ask_anchor(50_500).build();
+        for (i, price) in [49_990, 49_995, 50_000, 48_500].into_iter().enumerate() {
+            ob.apply_update(Update::Set { price, quantity: 10 + i as u64, side: Side::Bid });
+        }
+        for (i, price) in [50_010, 50_011, 51_900].into_iter().enumerate() {
+            ob.apply_update(Update::Set { price, quantity: 20 + i as u64, side: Side::Ask });
+        }
+        ob.apply_sequenced(7_001, Update::Remove { price: 49_995, side: Side::Bid }).unwrap();
+        ob.apply_update_at(1_700_000_123, Update::Set { price: 50_012, quantity: 4, side: Side::Ask });
+        ob
+    }
+
+    fn load_err(path: &Path, symbol: u64) -> io::Error {
+        match OrderBookImpl::load_from(path, symbol) {
+            Ok(_) => panic!("{} loaded", path.display()),
+            Err(err) => err,
+        }
+    }
+
+    fn snapshot_error(err: &io::Error) -> &SnapshotFileError {
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{err}");
+        err.get_ref().and_then(|inner| inner.downcast_ref()).expect("a SnapshotFileError")
+    }
+
+    #[test]
+    fn saved_books_load_back() {
+        let path = scratch("round-trip");
+        let ob = sample_book();
+        ob.save_to(&path, 42).unwrap();
+        assert!(!temp_path(&path).exists());
+
+        let loaded = OrderBookImpl::load_from(&path, 42).unwrap();
+        assert!(loaded.market_eq(&ob));
+        assert_eq!((loaded.anchor(Side::Bid), loaded.anchor(Side::Ask)), (50_000, 50_500));
+        assert_eq!(loaded.last_sequence(), Some(7_001));
+        assert_eq!(loaded.now(), 1_700_000_123);
+        assert_eq!(loaded.get_total_notional(Side::Ask), ob.get_total_notional(Side::Ask));
+
+        let header = read_snapshot_header(&path).unwrap();
+        assert_eq!((header.symbol, header.sequence, header.anchors), (42, Some(7_001), [50_000, 50_500]));
+
+        let err = load_err(&path, 43);
+        assert_eq!(snapshot_error(&err), &SnapshotFileError::SymbolMismatch { expected: 43, found: 42 });
+        assert!(err.to_string().contains("symbol 42"));
+
+        // Saving again replaces the file; an empty book without a sequence round-trips too.
+        OrderBookImpl::new().save_to(&path, 42).unwrap();
+        let empty = OrderBookImpl::load_from(&path, 42).unwrap();
+        assert_eq!((empty.level_count(Side::Bid), empty.last_sequence()), (0, None));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_files_are_refused() {
+        let path = scratch("truncated");
+        sample_book().save_to(&path, 1).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        fs::write(&path, &bytes[..bytes.len() - 5]).unwrap();
+        let err = load_err(&path, 1);
+        let expected = bytes.len() as u64;
+        assert_eq!(snapshot_error(&err), &SnapshotFileError::Truncated { expected, found: expected - 5 });
+
+        fs::write(&path, &bytes[..20]).unwrap();
+        let err = load_err(&path, 1);
+        assert_eq!(snapshot_error(&err), &SnapshotFileError::Truncated { expected: 64, found: 20 });
+        let err = read_snapshot_header(&path).unwrap_err();
+        assert_eq!(snapshot_error(&err), &SnapshotFileError::Truncated { expected: 64, found: 20 });
+        assert_eq!(
+            OrderBookError::from(snapshot_error(&err).clone()),
+            OrderBookError::Decode { offset: 20, reason: "snapshot file is truncated" }
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_files_are_refused() {
+        let path = scratch("corrupt");
+        sample_book().save_to(&path, 1).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        // Every single-bit flip in the payload or the covered header fields is caught.
+        for at in (0..bytes.len()).filter(|&at| !(8..12).contains(&at) && !(56..64).contains(&at) && at >= 8) {
+            let mut flipped = bytes.clone();
+            flipped[at] ^= 1 << (at % 8);
+            fs::write(&path, &flipped).unwrap();
+            let err = load_err(&path, 1);
+            assert!(
+                matches!(snapshot_error(&err), SnapshotFileError::ChecksumMismatch { .. } | SnapshotFileError::Truncated { .. } | SnapshotFileError::Malformed(_)),
+                "byte {at}: {err}"
+            );
+        }
+
+        let mut flipped = bytes.clone();
+        flipped[SNAPSHOT_HEADER_LEN + 20] ^= 0x10;
+        fs::write(&path, &flipped).unwrap();
+        let err = load_err(&path, 1);
+        assert!(matches!(snapshot_error(&err), SnapshotFileError::ChecksumMismatch { .. }));
+        assert!(err.to_string().contains("checksum mismatch"));
+
+        let mut newer = bytes.clone();
+        newer[8..12].copy_from_slice(&2u32.to_le_bytes());
+        fs::write(&path, &newer).unwrap();
+        let err = load_err(&path, 1);
+        assert_eq!(snapshot_error(&err), &SnapshotFileError::UnsupportedVersion { found: 2, supported: 1 });
+
+        fs::write(&path, b"definitely not a snapshot, but long enough to hold a header......").unwrap();
+        let err = load_err(&path, 1);
+        assert_eq!(snapshot_error(&err), &SnapshotFileError::NotASnapshot);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,56 @@
+// price.rs
+
+use std::fmt::Debug;
+
+/// Integer type a book can key its levels by. `Price` (`i64`) is the default; `u32` / `u64`
+/// suit instruments whose prices are never negative, `i32` / `i64` those that can be.
+///
+/// The ring only ever needs a price's signed distance from the anchor and the price at a given
+/// distance, both modulo the type's width, so that is all the trait asks for. Within a window
+/// (well inside the type's range) wrapping and exact arithmetic agree, signed or not.
+pub trait PriceInt: Copy + Ord + Debug + Send + Sync + 'static {
+    /// `self - anchor`, as a signed distance; wraps like the type's own subtraction.
+    fn offset_from(self, anchor: Self) -> i64;
+    /// `self + offset`, wrapping.
+    fn offset_by(self, offset: i64) -> Self;
+    /// Exact widening, for notional arithmetic.
+    fn to_i128(self) -> i128;
+}
+
+macro_rules! price_int {
+    ($($t:ty => $signed:ty),*) => {$(
+        impl PriceInt for $t {
+            #[inline(always)]
+            fn offset_from(self, anchor: Self) -> i64 {
+                self.wrapping_sub(anchor) as $signed as i64
+            }
+
+            #[inline(always)]
+            fn offset_by(self, offset: i64) -> Self {
+                self.wrapping_add(offset as $t)
+            }
+
+            #[inline(always)]
+            fn to_i128(self) -> i128 {
+                self as i128
+            }
+        }
+    )*};
+}
+
+price_int!(i32 => i32, i64 => i64, u32 => i32, u64 => i64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsigned_offsets_are_signed_distances() {
+        assert_eq!(5u32.offset_from(10), -5);
+        assert_eq!(10u32.offset_by(-5), 5);
+        assert_eq!(0u64.offset_from(u64::MAX), 1);
+        assert_eq!(u64::MAX.offset_by(1), 0);
+        assert_eq!((-3i32).offset_from(4), -7);
+        assert_eq!(i64::MIN.offset_by(-1), i64::MAX);
+    }
+}
@@ -0,0 +1,117 @@
+// profile.rs
+
+use std::collections::BTreeMap;
+use crate::interfaces::{OrderBookRead, Price, Quantity, Side};
+use crate::orderbook::{CAP, OrderBookImpl};
+
+/// Resting liquidity inside one price band `[low, high]` (inclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileBucket {
+    pub low: Price,
+    pub high: Price,
+    pub bid_quantity: Quantity,
+    pub ask_quantity: Quantity,
+}
+
+impl OrderBookImpl {
+    /// Histogram of resting liquidity in bands of `bucket_ticks` prices, lowest band first.
+    ///
+    /// Band `k` covers `[anchor + k * bucket_ticks, anchor + (k + 1) * bucket_ticks - 1]` (bid anchor), so band
+    /// edges stay put between calls. Only the occupied span (lowest to highest occupied price on
+    /// either side) is covered, but empty bands inside it are kept, unless the span holds more
+    /// than `2 * CAP` bands (sides anchored far apart): then only occupied bands are returned.
+    /// Returns an empty vec for an empty book or `bucket_ticks == 0`.
+    pub fn volume_profile(&self, bucket_ticks: usize) -> Vec<ProfileBucket> {
+        if bucket_ticks == 0 {
+            return Vec::new();
+        }
+        let width = i64::try_from(bucket_ticks).unwrap_or(i64::MAX) as i128;
+        let anchor = self.anchor(Side::Bid) as i128;
+        let band = |price: Price| (price as i128 - anchor).div_euclid(width);
+        let bucket = |k: i128| {
+            let edge = |k: i128| (anchor + k * width).clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+            ProfileBucket { low: edge(k), high: edge(k + 1).saturating_sub(1), bid_quantity: 0, ask_quantity: 0 }
+        };
+
+        let occupied = self.levels(Side::Bid).chain(self.levels(Side::Ask)).map(|(price, _)| price);
+        let Some((low, high)) = occupied.fold(None, |span: Option<(Price, Price)>, price| match span {
+            None => Some((price, price)),
+            Some((lo, hi)) => Some((lo.min(price), hi.max(price))),
+        }) else {
+            return Vec::new();
+        };
+
+        let (first, last) = (band(low), band(high));
+        if last - first >= 2 * CAP as i128 {
+            let mut sparse: BTreeMap<i128, ProfileBucket> = BTreeMap::new();
+            for (price, qty) in self.levels(Side::Bid) {
+                let b = sparse.entry(band(price)).or_insert_with_key(|&k| bucket(k));
+                b.bid_quantity = b.bid_quantity.saturating_add(qty);
+            }
+            for (price, qty) in self.levels(Side::Ask) {
+                let b = sparse.entry(band(price)).or_insert_with_key(|&k| bucket(k));
+                b.ask_quantity = b.ask_quantity.saturating_add(qty);
+            }
+            return sparse.into_values().collect();
+        }
+
+        let mut buckets: Vec<ProfileBucket> = (first..=last).map(bucket).collect();
+        for (price, qty) in self.levels(Side::Bid) {
+            let b = &mut buckets[(band(price) - first) as usize];
+            b.bid_quantity = b.bid_quantity.saturating_add(qty);
+        }
+        for (price, qty) in self.levels(Side::Ask) {
+            let b = &mut buckets[(band(price) - first) as usize];
+            b.ask_quantity = b.ask_quantity.saturating_add(qty);
+        }
+        buckets
+    }
+
+    /// Quantity of `side` grouped into bands of `band_ticks` prices, up to `n_bands` of them,
+    /// starting with the band holding the best price and moving away from the touch.
+    ///
+    /// Bands are aligned to absolute multiples of `band_ticks` (band `k` is
+    /// `[k * band_ticks, (k + 1) * band_ticks - 1]`), so the output is stable while the touch
+    /// moves inside a band. Each band is reported at its edge nearest the touch (its highest
+    /// price for bids, lowest for asks); empty bands in between are included with quantity 0.
+    /// Bands are clipped to the window: the last one may only partly lie in it, and none lie
+    /// beyond it, so fewer than `n_bands` can come back. Empty for an empty side or
+    /// `band_ticks == 0`.
+    pub fn aggregate_levels(&self, side: Side, band_ticks: usize, n_bands: usize) -> Vec<(Price, Quantity)> {
+        let Some(best) = (match side {
+            Side::Bid => self.get_best_bid(),
+            Side::Ask => self.get_best_ask(),
+        }) else {
+            return Vec::new();
+        };
+        if band_ticks == 0 {
+            return Vec::new();
+        }
+        let width = i64::try_from(band_ticks).unwrap_or(i64::MAX);
+        let first = best.div_euclid(width);
+        // Distance in bands from the first one, moving away from the touch.
+        let distance = |price: Price| match side {
+            Side::Bid => first.abs_diff(price.div_euclid(width)),
+            Side::Ask => price.div_euclid(width).abs_diff(first),
+        };
+        let anchor = self.anchor(side);
+        let far_edge = match side {
+            Side::Bid => anchor.saturating_sub(CAP as i64 / 2),
+            Side::Ask => anchor.saturating_add(CAP as i64 / 2 - 1),
+        };
+        let count = (distance(far_edge).saturating_add(1)).min(n_bands as u64) as usize;
+
+        let mut bands: Vec<(Price, Quantity)> = (0..count as i64)
+            .map(|i| match side {
+                Side::Bid => first.saturating_sub(i).saturating_mul(width).saturating_add(width - 1),
+                Side::Ask => first.saturating_add(i).saturating_mul(width),
+            })
+            .map(|price| (price, 0))
+            .collect();
+        for (price, qty) in self.levels(side) {
+            let Some(band) = bands.get_mut(distance(price) as usize) else { break };
+            band.1 = band.1.saturating_add(qty);
+        }
+        bands
+    }
+}
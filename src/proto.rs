@@ -0,0 +1,404 @@
+// proto.rs
+
+use std::fmt;
+use crate::analytics::DepthSnapshot;
+use crate::error::OrderBookError;
+use crate::interfaces::{Bbo, Price, Quantity, Side, Update};
+
+/// `schema_version` written into every message, and the only one accepted on the way in.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The messages of `proto/orderbook.proto`, as prost types. Written out by hand rather than
+/// generated at build time (so the feature needs no `protoc`); keep the two in step field for
+/// field. `wire_format_is_stable` in the tests catches a tag or type that drifts.
+pub mod pb {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Side {
+        Unspecified = 0,
+        Bid = 1,
+        Ask = 2,
+    }
+
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct Level {
+        #[prost(sint64, tag = "1")]
+        pub price: i64,
+        #[prost(int64, tag = "2")]
+        pub quantity: i64,
+    }
+
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct SetLevel {
+        #[prost(sint64, tag = "1")]
+        pub price: i64,
+        #[prost(int64, tag = "2")]
+        pub quantity: i64,
+        #[prost(enumeration = "Side", tag = "3")]
+        pub side: i32,
+        #[prost(uint32, optional, tag = "4")]
+        pub order_count: Option<u32>,
+    }
+
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct RemoveLevel {
+        #[prost(sint64, tag = "1")]
+        pub price: i64,
+        #[prost(enumeration = "Side", tag = "2")]
+        pub side: i32,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SetLevels {
+        #[prost(enumeration = "Side", tag = "1")]
+        pub side: i32,
+        #[prost(message, repeated, tag = "2")]
+        pub levels: Vec<Level>,
+    }
+
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct Trade {
+        #[prost(sint64, tag = "1")]
+        pub price: i64,
+        #[prost(int64, tag = "2")]
+        pub quantity: i64,
+        #[prost(enumeration = "Side", tag = "3")]
+        pub aggressor: i32,
+        #[prost(uint64, tag = "4")]
+        pub timestamp: u64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Update {
+        #[prost(uint32, tag = "1")]
+        pub schema_version: u32,
+        #[prost(uint32, tag = "2")]
+        pub price_scale: u32,
+        #[prost(oneof = "update::Kind", tags = "3, 4, 5, 6")]
+        pub kind: Option<update::Kind>,
+    }
+
+    pub mod update {
+        #[derive(Clone, PartialEq, ::prost::Oneof)]
+        pub enum Kind {
+            #[prost(message, tag = "3")]
+            Set(super::SetLevel),
+            #[prost(message, tag = "4")]
+            Remove(super::RemoveLevel),
+            #[prost(message, tag = "5")]
+            SetLevels(super::SetLevels),
+            #[prost(message, tag = "6")]
+            Trade(super::Trade),
+        }
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct DepthSnapshot {
+        #[prost(uint32, tag = "1")]
+        pub schema_version: u32,
+        #[prost(uint32, tag = "2")]
+        pub price_scale: u32,
+        #[prost(uint64, tag = "3")]
+        pub timestamp: u64,
+        #[prost(uint64, tag = "4")]
+        pub version: u64,
+        #[prost(message, repeated, tag = "5")]
+        pub bids: Vec<Level>,
+        #[prost(message, repeated, tag = "6")]
+        pub asks: Vec<Level>,
+    }
+
+    #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+    pub struct Bbo {
+        #[prost(uint32, tag = "1")]
+        pub schema_version: u32,
+        #[prost(uint32, tag = "2")]
+        pub price_scale: u32,
+        #[prost(sint64, tag = "3")]
+        pub bid_price: i64,
+        #[prost(int64, tag = "4")]
+        pub bid_quantity: i64,
+        #[prost(sint64, tag = "5")]
+        pub ask_price: i64,
+        #[prost(int64, tag = "6")]
+        pub ask_quantity: i64,
+        #[prost(uint64, tag = "7")]
+        pub version: u64,
+    }
+}
+
+/// Why a conversion to or from the wire types failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtoError {
+    /// The message's `schema_version` is not `SCHEMA_VERSION` (0: the sender did not set it).
+    UnsupportedSchema(u32),
+    /// A side that is neither bid nor ask, including `SIDE_UNSPECIFIED`.
+    UnknownSide(i32),
+    NegativeQuantity(i64),
+    /// A quantity above `i64::MAX`, which the signed wire field cannot carry.
+    QuantityTooLarge(Quantity),
+    /// An `Update` with none of its `kind` fields set.
+    MissingKind,
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtoError::UnsupportedSchema(version) => write!(f, "unsupported schema version {version} (expected {SCHEMA_VERSION})"),
+            ProtoError::UnknownSide(side) => write!(f, "unknown side {side}"),
+            ProtoError::NegativeQuantity(quantity) => write!(f, "negative quantity {quantity}"),
+            ProtoError::QuantityTooLarge(quantity) => write!(f, "quantity {quantity} does not fit the wire format"),
+            ProtoError::MissingKind => write!(f, "update carries no kind"),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
+/// The message was decoded by prost before these checks ran, so there is no byte offset to
+/// report: `offset` is 0.
+impl From<ProtoError> for OrderBookError {
+    fn from(err: ProtoError) -> Self {
+        let reason = match err {
+            ProtoError::UnsupportedSchema(_) => "unsupported schema version",
+            ProtoError::UnknownSide(_) => "unknown side",
+            ProtoError::NegativeQuantity(_) => "negative quantity",
+            ProtoError::QuantityTooLarge(_) => "quantity does not fit the wire format",
+            ProtoError::MissingKind => "update carries no kind",
+        };
+        OrderBookError::Decode { offset: 0, reason }
+    }
+}
+
+fn side_to_wire(side: Side) -> i32 {
+    match side {
+        Side::Bid => pb::Side::Bid as i32,
+        Side::Ask => pb::Side::Ask as i32,
+    }
+}
+
+fn side_from_wire(side: i32) -> Result<Side, ProtoError> {
+    match pb::Side::try_from(side) {
+        Ok(pb::Side::Bid) => Ok(Side::Bid),
+        Ok(pb::Side::Ask) => Ok(Side::Ask),
+        _ => Err(ProtoError::UnknownSide(side)),
+    }
+}
+
+fn quantity_to_wire(quantity: Quantity) -> Result<i64, ProtoError> {
+    i64::try_from(quantity).map_err(|_| ProtoError::QuantityTooLarge(quantity))
+}
+
+fn quantity_from_wire(quantity: i64) -> Result<Quantity, ProtoError> {
+    Quantity::try_from(quantity).map_err(|_| ProtoError::NegativeQuantity(quantity))
+}
+
+fn check_schema(version: u32) -> Result<(), ProtoError> {
+    if version == SCHEMA_VERSION { Ok(()) } else { Err(ProtoError::UnsupportedSchema(version)) }
+}
+
+fn levels_to_wire(levels: &[(Price, Quantity)]) -> Result<Vec<pb::Level>, ProtoError> {
+    levels.iter().map(|&(price, quantity)| Ok(pb::Level { price, quantity: quantity_to_wire(quantity)? })).collect()
+}
+
+fn levels_from_wire<C: FromIterator<(Price, Quantity)>>(levels: &[pb::Level]) -> Result<C, ProtoError> {
+    levels.iter().map(|level| Ok((level.price, quantity_from_wire(level.quantity)?))).collect()
+}
+
+impl pb::Update {
+    /// `update` for the wire, its prices tagged with `price_scale` decimal places.
+    pub fn from_update(update: &Update, price_scale: u32) -> Result<Self, ProtoError> {
+        use pb::update::Kind;
+        let kind = match *update {
+            Update::Set { price, quantity, side } => {
+                Kind::Set(pb::SetLevel { price, quantity: quantity_to_wire(quantity)?, side: side_to_wire(side), order_count: None })
+            }
+            Update::SetWithCount { price, quantity, order_count, side } => Kind::Set(pb::SetLevel {
+                price,
+                quantity: quantity_to_wire(quantity)?,
+                side: side_to_wire(side),
+                order_count: Some(order_count),
+            }),
+            Update::Remove { price, side } => Kind::Remove(pb::RemoveLevel { price, side: side_to_wire(side) }),
+            Update::SetLevels { side, ref levels } => Kind::SetLevels(pb::SetLevels { side: side_to_wire(side), levels: levels_to_wire(levels)? }),
+            Update::Trade { price, quantity, aggressor, timestamp } => Kind::Trade(pb::Trade {
+                price,
+                quantity: quantity_to_wire(quantity)?,
+                aggressor: side_to_wire(aggressor),
+                timestamp,
+            }),
+        };
+        Ok(pb::Update { schema_version: SCHEMA_VERSION, price_scale, kind: Some(kind) })
+    }
+}
+
+/// Validates and converts; the price scale is left for the receiver to check against its book.
+impl TryFrom<&pb::Update> for Update {
+    type Error = ProtoError;
+
+    fn try_from(message: &pb::Update) -> Result<Self, ProtoError> {
+        use pb::update::Kind;
+        check_schema(message.schema_version)?;
+        Ok(match message.kind.as_ref().ok_or(ProtoError::MissingKind)? {
+            Kind::Set(set) => {
+                let (price, quantity, side) = (set.price, quantity_from_wire(set.quantity)?, side_from_wire(set.side)?);
+                match set.order_count {
+                    Some(order_count) => Update::SetWithCount { price, quantity, order_count, side },
+                    None => Update::Set { price, quantity, side },
+                }
+            }
+            Kind::Remove(remove) => Update::Remove { price: remove.price, side: side_from_wire(remove.side)? },
+            Kind::SetLevels(set) => Update::SetLevels { side: side_from_wire(set.side)?, levels: levels_from_wire(&set.levels)? },
+            Kind::Trade(trade) => Update::Trade {
+                price: trade.price,
+                quantity: quantity_from_wire(trade.quantity)?,
+                aggressor: side_from_wire(trade.aggressor)?,
+                timestamp: trade.timestamp,
+            },
+        })
+    }
+}
+
+impl pb::DepthSnapshot {
+    pub fn from_snapshot(snapshot: &DepthSnapshot, price_scale: u32) -> Result<Self, ProtoError> {
+        Ok(pb::DepthSnapshot {
+            schema_version: SCHEMA_VERSION,
+            price_scale,
+            timestamp: snapshot.timestamp,
+            version: snapshot.version,
+            bids: levels_to_wire(&snapshot.bids)?,
+            asks: levels_to_wire(&snapshot.asks)?,
+        })
+    }
+}
+
+impl TryFrom<&pb::DepthSnapshot> for DepthSnapshot {
+    type Error = ProtoError;
+
+    fn try_from(message: &pb::DepthSnapshot) -> Result<Self, ProtoError> {
+        check_schema(message.schema_version)?;
+        Ok(DepthSnapshot {
+            timestamp: message.timestamp,
+            version: message.version,
+            bids: levels_from_wire(&message.bids)?,
+            asks: levels_from_wire(&message.asks)?,
+        })
+    }
+}
+
+impl pb::Bbo {
+    pub fn from_bbo(bbo: &Bbo, price_scale: u32) -> Result<Self, ProtoError> {
+        Ok(pb::Bbo {
+            schema_version: SCHEMA_VERSION,
+            price_scale,
+            bid_price: bbo.bid_price,
+            bid_quantity: quantity_to_wire(bbo.bid_quantity)?,
+            ask_price: bbo.ask_price,
+            ask_quantity: quantity_to_wire(bbo.ask_quantity)?,
+            version: bbo.version,
+        })
+    }
+}
+
+impl TryFrom<&pb::Bbo> for Bbo {
+    type Error = ProtoError;
+
+    fn try_from(message: &pb::Bbo) -> Result<Self, ProtoError> {
+        check_schema(message.schema_version)?;
+        Ok(Bbo {
+            bid_price: message.bid_price,
+            bid_quantity: quantity_from_wire(message.bid_quantity)?,
+            ask_price: message.ask_price,
+            ask_quantity: quantity_from_wire(message.ask_quantity)?,
+            version: message.version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+    use smallvec::smallvec;
+
+    fn updates() -> Vec<Update> {
+        vec![
+            Update::Set { price: 12345, quantity: 7, side: Side::Bid },
+            Update::SetWithCount { price: -3, quantity: 1 << 40, order_count: 12, side: Side::Ask },
+            Update::Remove { price: 12340, side: Side::Ask },
+            Update::SetLevels { side: Side::Bid, levels: smallvec![(12345, 0), (12344, 9)] },
+            Update::Trade { price: 12346, quantity: 2, aggressor: Side::Bid, timestamp: 1_700_000_000_000 },
+        ]
+    }
+
+    #[test]
+    fn updates_round_trip() {
+        for update in updates() {
+            let bytes = pb::Update::from_update(&update, 2).unwrap().encode_to_vec();
+            let message = pb::Update::decode(bytes.as_slice()).unwrap();
+            assert_eq!(message.price_scale, 2);
+            assert_eq!(Update::try_from(&message).unwrap(), update);
+        }
+    }
+
+    #[test]
+    fn snapshots_and_bbos_round_trip() {
+        let snapshot = DepthSnapshot { timestamp: 5, version: 9, bids: vec![(100, 3), (99, 1)], asks: vec![(101, 4)] };
+        let message = pb::DepthSnapshot::decode(pb::DepthSnapshot::from_snapshot(&snapshot, 4).unwrap().encode_to_vec().as_slice()).unwrap();
+        assert_eq!(DepthSnapshot::try_from(&message).unwrap(), snapshot);
+
+        let bbo = Bbo { bid_price: 100, bid_quantity: 3, ask_price: 101, ask_quantity: 4, version: 9 };
+        let message = pb::Bbo::decode(pb::Bbo::from_bbo(&bbo, 4).unwrap().encode_to_vec().as_slice()).unwrap();
+        assert_eq!((Bbo::try_from(&message).unwrap(), message.price_scale), (bbo, 4));
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected() {
+        let set = |side, quantity| pb::Update {
+            schema_version: SCHEMA_VERSION,
+            price_scale: 0,
+            kind: Some(pb::update::Kind::Set(pb::SetLevel { price: 1, quantity, side, order_count: None })),
+        };
+        assert_eq!(Update::try_from(&set(3, 1)).unwrap_err(), ProtoError::UnknownSide(3));
+        assert_eq!(Update::try_from(&set(0, 1)).unwrap_err(), ProtoError::UnknownSide(0));
+        assert_eq!(Update::try_from(&set(1, -5)).unwrap_err(), ProtoError::NegativeQuantity(-5));
+        assert_eq!(Update::try_from(&pb::Update { kind: None, ..set(1, 1) }).unwrap_err(), ProtoError::MissingKind);
+        assert_eq!(Update::try_from(&pb::Update { schema_version: 0, ..set(1, 1) }).unwrap_err(), ProtoError::UnsupportedSchema(0));
+
+        let mut snapshot = pb::DepthSnapshot { schema_version: SCHEMA_VERSION, ..Default::default() };
+        snapshot.asks.push(pb::Level { price: 1, quantity: -1 });
+        assert_eq!(DepthSnapshot::try_from(&snapshot).unwrap_err(), ProtoError::NegativeQuantity(-1));
+
+        let huge = Update::Set { price: 1, quantity: u64::MAX, side: Side::Bid };
+        assert_eq!(pb::Update::from_update(&huge, 0).unwrap_err(), ProtoError::QuantityTooLarge(u64::MAX));
+        assert_eq!(OrderBookError::from(ProtoError::UnknownSide(3)), OrderBookError::Decode { offset: 0, reason: "unknown side" });
+    }
+
+    /// Encodings of `updates()` (price scale 2), a snapshot and a BBO as of schema version 1.
+    /// A mismatch means the wire format changed: existing receivers would misread the new
+    /// bytes. Only ever append cases here; never edit the bytes to make a change pass.
+    #[test]
+    fn wire_format_is_stable() {
+        let golden_updates: [&[u8]; 5] = [
+            &[0x08, 0x01, 0x10, 0x02, 0x1a, 0x08, 0x08, 0xf2, 0xc0, 0x01, 0x10, 0x07, 0x18, 0x01],
+            &[0x08, 0x01, 0x10, 0x02, 0x1a, 0x0d, 0x08, 0x05, 0x10, 0x80, 0x80, 0x80, 0x80, 0x80, 0x20, 0x18, 0x02, 0x20, 0x0c],
+            &[0x08, 0x01, 0x10, 0x02, 0x22, 0x06, 0x08, 0xe8, 0xc0, 0x01, 0x10, 0x02],
+            // The zero-quantity level encodes as its price alone (proto3 omits default values).
+            &[0x08, 0x01, 0x10, 0x02, 0x2a, 0x10, 0x08, 0x01, 0x12, 0x04, 0x08, 0xf2, 0xc0, 0x01, 0x12, 0x06, 0x08, 0xf0, 0xc0, 0x01, 0x10, 0x09],
+            &[0x08, 0x01, 0x10, 0x02, 0x32, 0x0f, 0x08, 0xf4, 0xc0, 0x01, 0x10, 0x02, 0x18, 0x01, 0x20, 0x80, 0xd0, 0x95, 0xff, 0xbc, 0x31],
+        ];
+        for (update, golden) in updates().iter().zip(golden_updates) {
+            assert_eq!(pb::Update::from_update(update, 2).unwrap().encode_to_vec(), golden, "{update:?}");
+            let decoded = Update::try_from(&pb::Update::decode(golden).unwrap()).unwrap();
+            assert_eq!(&decoded, update);
+        }
+
+        let snapshot = DepthSnapshot { timestamp: 5, version: 9, bids: vec![(100, 3)], asks: vec![(-101, 4)] };
+        let golden: &[u8] = &[0x08, 0x01, 0x10, 0x04, 0x18, 0x05, 0x20, 0x09, 0x2a, 0x05, 0x08, 0xc8, 0x01, 0x10, 0x03, 0x32, 0x05, 0x08, 0xc9, 0x01, 0x10, 0x04];
+        assert_eq!(pb::DepthSnapshot::from_snapshot(&snapshot, 4).unwrap().encode_to_vec(), golden);
+
+        let bbo = Bbo { bid_price: 100, bid_quantity: 3, ask_price: 101, ask_quantity: 4, version: 9 };
+        let golden: &[u8] = &[0x08, 0x01, 0x10, 0x04, 0x18, 0xc8, 0x01, 0x20, 0x03, 0x28, 0xca, 0x01, 0x30, 0x04, 0x38, 0x09];
+        assert_eq!(pb::Bbo::from_bbo(&bbo, 4).unwrap().encode_to_vec(), golden);
+    }
+}
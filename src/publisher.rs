@@ -0,0 +1,222 @@
+// publisher.rs
+
+use std::sync::{Arc, Mutex};
+use crate::analytics::DepthSnapshot;
+use crate::orderbook::OrderBookImpl;
+
+/// Where a `DepthPublisher` hands its snapshots. The snapshot is lent, not given: the publisher
+/// reuses its buffers, so a sink that keeps it has to copy it (`clone_from` reuses the sink's
+/// own buffers too). Closures are sinks, which covers callbacks and serializers; `DepthMailbox`
+/// hands off to another thread.
+pub trait DepthSink {
+    fn publish(&mut self, snapshot: &DepthSnapshot);
+}
+
+impl<F: FnMut(&DepthSnapshot)> DepthSink for F {
+    fn publish(&mut self, snapshot: &DepthSnapshot) {
+        self(snapshot)
+    }
+}
+
+/// Single-snapshot mailbox between threads: publishing overwrites an unread snapshot, so a
+/// consumer that falls behind gets the latest one and never a queue. Clones share the mailbox.
+#[derive(Debug, Clone, Default)]
+pub struct DepthMailbox {
+    latest: Arc<Mutex<Option<DepthSnapshot>>>,
+}
+
+impl DepthMailbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The snapshot published since the last `take`, if any.
+    pub fn take(&self) -> Option<DepthSnapshot> {
+        self.latest.lock().unwrap().take()
+    }
+}
+
+impl DepthSink for DepthMailbox {
+    fn publish(&mut self, snapshot: &DepthSnapshot) {
+        match &mut *self.latest.lock().unwrap() {
+            Some(unread) => unread.clone_from(snapshot),
+            empty => *empty = Some(snapshot.clone()),
+        }
+    }
+}
+
+/// Throttles depth snapshots of a book to at most one per `min_interval`, always the latest.
+///
+/// Call `maybe_publish` from the feed loop after applying updates (or let
+/// `events::BookEvents::depth_stream` do it). A snapshot goes to the sink when `min_interval`
+/// has passed since the last one and the top `depth` levels differ from it. Changes inside the
+/// interval are not queued: the first call after it captures the book as it is then. A book
+/// whose version (and `clear_count`) has not moved since the last capture is not even read.
+///
+/// Captures go into two buffers that are swapped, never reallocated once they have grown to
+/// `depth` levels.
+#[derive(Debug, Clone)]
+pub struct DepthPublisher<S: DepthSink> {
+    depth: usize,
+    min_interval: u64,
+    sink: S,
+    /// The last snapshot handed to the sink (meaningful once `sent_at` is set).
+    published: DepthSnapshot,
+    scratch: DepthSnapshot,
+    sent_at: Option<u64>,
+    /// `clear_count` and version of the book at the last capture, published or not.
+    captured_version: Option<(u64, u64)>,
+}
+
+impl<S: DepthSink> DepthPublisher<S> {
+    /// Publishes the top `depth` levels per side to `sink`, at most once per `min_interval`
+    /// (in the units of the `now` passed to `maybe_publish`).
+    pub fn new(depth: usize, min_interval: u64, sink: S) -> Self {
+        let empty = || DepthSnapshot { timestamp: 0, version: 0, bids: Vec::with_capacity(depth), asks: Vec::with_capacity(depth) };
+        DepthPublisher { depth, min_interval, sink, published: empty(), scratch: empty(), sent_at: None, captured_version: None }
+    }
+
+    /// Publishes `book`'s depth if the interval has passed since the last snapshot at `now`
+    /// and the depth has changed since. Returns whether it did. The snapshot is stamped like
+    /// `depth_snapshot`, with the book's clock and version.
+    pub fn maybe_publish(&mut self, book: &OrderBookImpl, now: u64) -> bool {
+        if let Some(sent_at) = self.sent_at
+            && now < sent_at.saturating_add(self.min_interval)
+        {
+            return false;
+        }
+        let version = (book.clear_count(), book.version());
+        if self.captured_version == Some(version) {
+            return false;
+        }
+        self.captured_version = Some(version);
+        book.depth_snapshot_into(self.depth, &mut self.scratch);
+        if self.sent_at.is_some() && self.scratch.bids == self.published.bids && self.scratch.asks == self.published.asks {
+            return false;
+        }
+        std::mem::swap(&mut self.scratch, &mut self.published);
+        self.sink.publish(&self.published);
+        self.sent_at = Some(now);
+        true
+    }
+
+    /// The last snapshot published, if any.
+    pub fn last_published(&self) -> Option<&DepthSnapshot> {
+        self.sent_at.map(|_| &self.published)
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::{Side, Update};
+    use crate::testing::set;
+
+    /// Applies `script` (clock, update) to a book, calling `maybe_publish` after each step at
+    /// the step's time; returns whether each call published.
+    fn run(publisher: &mut DepthPublisher<impl DepthSink>, script: &[(u64, Option<Update>)]) -> Vec<bool> {
+        let mut book = OrderBookImpl::new();
+        script
+            .iter()
+            .map(|(now, update)| {
+                if let Some(update) = update {
+                    book.apply_update_at(*now, update.clone());
+                }
+                publisher.maybe_publish(&book, *now)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn latest_wins_within_the_interval() {
+        let mut seen = Vec::new();
+        let mut publisher = DepthPublisher::new(2, 100, |snapshot: &DepthSnapshot| seen.push(snapshot.clone()));
+        let published = run(
+            &mut publisher,
+            &[
+                (0, Some(set(9990, 1, Side::Bid))),
+                (10, Some(set(9991, 2, Side::Bid))),
+                (20, Some(set(9992, 3, Side::Bid))),
+                (99, None),
+                (100, None), // interval over: the latest state, once
+                (150, Some(set(9993, 4, Side::Bid))),
+                (250, Some(set(9994, 5, Side::Bid))),
+            ],
+        );
+        assert_eq!(published, [true, false, false, false, true, false, true]);
+        drop(publisher);
+
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[0].bids, [(9990, 1)]);
+        assert_eq!((seen[1].timestamp, seen[1].version, &seen[1].bids[..]), (20, 3, &[(9992, 3), (9991, 2)][..]));
+        assert_eq!(seen[2].bids, [(9994, 5), (9993, 4)]);
+    }
+
+    #[test]
+    fn unchanged_depth_is_skipped() {
+        let mut count = 0;
+        let mut publisher = DepthPublisher::new(1, 10, |_: &DepthSnapshot| count += 1);
+        let published = run(
+            &mut publisher,
+            &[
+                (0, Some(set(9990, 1, Side::Bid))),
+                (50, None), // nothing changed
+                (60, Some(set(9980, 7, Side::Bid))), // changed below the top level only
+                (70, Some(set(9990, 1, Side::Bid))), // a no-op Set leaves the version alone
+                (80, Some(set(9990, 2, Side::Bid))),
+            ],
+        );
+        assert_eq!(published, [true, false, false, false, true]);
+        assert_eq!(publisher.last_published().unwrap().bids, [(9990, 2)]);
+        drop(publisher);
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn mailbox_holds_only_the_latest_snapshot() {
+        let mailbox = DepthMailbox::new();
+        let mut publisher = DepthPublisher::new(3, 0, mailbox.clone());
+        assert!(mailbox.take().is_none());
+        assert_eq!(run(&mut publisher, &[(0, Some(set(9990, 1, Side::Bid))), (1, Some(set(9991, 1, Side::Bid))), (2, Some(set(9992, 1, Side::Bid)))]), [true; 3]);
+
+        // Three published, one unread: the last.
+        let latest = mailbox.take().unwrap();
+        assert_eq!(latest.bids, [(9992, 1), (9991, 1), (9990, 1)]);
+        assert!(mailbox.take().is_none());
+        assert_eq!(publisher.sink().take(), None);
+    }
+
+    #[test]
+    fn a_clear_does_not_hide_the_rebuilt_book() {
+        let mut seen = Vec::new();
+        let mut publisher = DepthPublisher::new(2, 0, |snapshot: &DepthSnapshot| seen.push(snapshot.bids.clone()));
+        let mut book = OrderBookImpl::new();
+        book.apply_update_at(0, set(9990, 1, Side::Bid));
+        assert!(publisher.maybe_publish(&book, 0));
+
+        // Back at version 1 after the clear, with other levels.
+        book.clear();
+        book.apply_update_at(10, set(9980, 4, Side::Bid));
+        assert_eq!(book.version(), 1);
+        assert!(publisher.maybe_publish(&book, 10));
+
+        // Rebuilt to exactly what was last published: read, but not published again.
+        book.clear();
+        book.apply_update_at(20, set(9980, 4, Side::Bid));
+        assert!(!publisher.maybe_publish(&book, 20));
+        drop(publisher);
+        assert_eq!(seen, [vec![(9990, 1)], vec![(9980, 4)]]);
+    }
+}
@@ -0,0 +1,356 @@
+// recorder.rs
+
+use std::io::{self, Write};
+use crate::feed::{BinaryFeedParser, FeedParser, ParseError};
+use crate::interfaces::Update;
+use crate::orderbook::OrderBookImpl;
+use crate::persist::book_from_snapshot;
+
+/// Bytes before each update in a recorded log: timestamp `u64`, sequence `u64`, little-endian.
+/// The update follows as a `BinaryFeedParser` record.
+pub const FRAME_HEADER_LEN: usize = 16;
+/// Bytes before each snapshot in a checkpoint index: timestamp, sequence, log offset and
+/// snapshot length, all `u64` little-endian. The snapshot follows in the `save_to` file format
+/// (see `persist::SNAPSHOT_MAGIC`), CRC and all.
+pub const CHECKPOINT_HEADER_LEN: usize = 32;
+
+/// How often a `Recorder` writes a checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointEvery {
+    /// After every `n` recorded updates.
+    Updates(u64),
+    /// At the first update at least this much later than the last checkpoint (or the first
+    /// update), in the units of the recorded timestamps.
+    Time(u64),
+}
+
+/// Writes an update log that a `Replayer` can read back, and optionally a sidecar index of
+/// checkpoints (a full snapshot of the book with the log offset and timestamp it belongs to),
+/// so that seeking into a long log only replays the updates since the nearest checkpoint.
+///
+/// Updates get consecutive sequence numbers from 1; timestamps must not go backwards.
+pub struct Recorder<W: Write, I: Write = io::Sink> {
+    log: W,
+    index: Option<(I, CheckpointEvery)>,
+    offset: u64,
+    seq: u64,
+    last_timestamp: u64,
+    /// Sequence and timestamp the next checkpoint is counted from.
+    since: Option<(u64, u64)>,
+    frame: Vec<u8>,
+}
+
+impl<W: Write> Recorder<W> {
+    /// A recorder without checkpoints.
+    pub fn new(log: W) -> Self {
+        Recorder { log, index: None, offset: 0, seq: 0, last_timestamp: 0, since: None, frame: Vec::new() }
+    }
+}
+
+impl<W: Write, I: Write> Recorder<W, I> {
+    /// A recorder that also writes a checkpoint to `index` as often as `every` says.
+    pub fn with_checkpoints(log: W, index: I, every: CheckpointEvery) -> Self {
+        Recorder { log, index: Some((index, every)), offset: 0, seq: 0, last_timestamp: 0, since: None, frame: Vec::new() }
+    }
+
+    /// Appends `update`, stamped `timestamp`, and returns its sequence number. `book` must be
+    /// the book with `update` (and every update before it) already applied: it is what a
+    /// checkpoint due now captures, and it is not read otherwise.
+    pub fn record(&mut self, timestamp: u64, update: &Update, book: &OrderBookImpl) -> io::Result<u64> {
+        if timestamp < self.last_timestamp {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("timestamp {timestamp} is before the last one, {}", self.last_timestamp)));
+        }
+        self.seq += 1;
+        self.last_timestamp = timestamp;
+        self.frame.clear();
+        self.frame.extend_from_slice(&timestamp.to_le_bytes());
+        self.frame.extend_from_slice(&self.seq.to_le_bytes());
+        BinaryFeedParser::encode(update, &mut self.frame);
+        self.log.write_all(&self.frame)?;
+        self.offset += self.frame.len() as u64;
+
+        let (since_seq, since_timestamp) = *self.since.get_or_insert((0, timestamp));
+        if let Some((index, every)) = self.index.as_mut() {
+            let due = match *every {
+                CheckpointEvery::Updates(n) => self.seq - since_seq >= n.max(1),
+                CheckpointEvery::Time(elapsed) => timestamp - since_timestamp >= elapsed,
+            };
+            if due {
+                let snapshot = book.snapshot_bytes(0);
+                for word in [timestamp, self.seq, self.offset, snapshot.len() as u64] {
+                    index.write_all(&word.to_le_bytes())?;
+                }
+                index.write_all(&snapshot)?;
+                self.since = Some((self.seq, timestamp));
+            }
+        }
+        Ok(self.seq)
+    }
+
+    /// Sequence number of the last recorded update (0 before any).
+    pub fn sequence(&self) -> u64 {
+        self.seq
+    }
+
+    /// Flushes both writers and hands them back.
+    pub fn finish(mut self) -> io::Result<(W, Option<I>)> {
+        self.log.flush()?;
+        if let Some((index, _)) = self.index.as_mut() {
+            index.flush()?;
+        }
+        Ok((self.log, self.index.map(|(index, _)| index)))
+    }
+}
+
+/// Where a checkpoint is, in the index and in the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Checkpoint {
+    timestamp: u64,
+    seq: u64,
+    log_offset: usize,
+    /// Range of the snapshot in the index.
+    snapshot: (usize, usize),
+}
+
+/// Replays a log written by `Recorder` into a book, forwards one update at a time or by
+/// seeking to a timestamp or sequence number. With the checkpoint index, a seek restores the
+/// nearest checkpoint at or before the target and replays only the updates after it; without
+/// one (or before the first checkpoint) it replays from the start.
+///
+/// A restored checkpoint brings back the levels, anchors and clock; every other option of the
+/// book is at its default, so replays should use default-configured books throughout.
+pub struct Replayer<'a> {
+    log: &'a [u8],
+    index: &'a [u8],
+    checkpoints: Vec<Checkpoint>,
+    // Boxed: a replayer is moved around whole, and the books are most of it.
+    initial: Box<OrderBookImpl>,
+    book: Box<OrderBookImpl>,
+    offset: usize,
+    seq: u64,
+    /// Timestamp of the last update applied.
+    timestamp: u64,
+}
+
+impl<'a> Replayer<'a> {
+    /// A replayer positioned before the first update of `log`, starting from `initial` (which
+    /// must be the book the recording started from). `index` is the recorder's checkpoint
+    /// index, or empty; its entries are located here and their snapshots checked when used.
+    pub fn new(log: &'a [u8], index: &'a [u8], initial: OrderBookImpl) -> Result<Self, ParseError> {
+        let mut checkpoints = Vec::new();
+        let mut at = 0;
+        while at < index.len() {
+            let Some(header) = index.get(at..at + CHECKPOINT_HEADER_LEN) else {
+                return Err(ParseError { offset: index.len(), reason: "truncated checkpoint" });
+            };
+            let word = |i: usize| u64::from_le_bytes(header[i * 8..i * 8 + 8].try_into().unwrap());
+            let start = at + CHECKPOINT_HEADER_LEN;
+            let end = usize::try_from(word(3)).ok().and_then(|len| start.checked_add(len)).filter(|&end| end <= index.len());
+            let (Some(end), Ok(log_offset)) = (end, usize::try_from(word(2))) else {
+                return Err(ParseError { offset: index.len(), reason: "truncated checkpoint" });
+            };
+            if log_offset > log.len() || checkpoints.last().is_some_and(|last: &Checkpoint| last.seq >= word(1)) {
+                return Err(ParseError { offset: at, reason: "checkpoint out of order or past the log" });
+            }
+            checkpoints.push(Checkpoint { timestamp: word(0), seq: word(1), log_offset, snapshot: (start, end) });
+            at = end;
+        }
+        let initial = Box::new(initial);
+        Ok(Replayer { log, index, checkpoints, book: initial.clone(), initial, offset: 0, seq: 0, timestamp: 0 })
+    }
+
+    /// The book as of the current position.
+    pub fn book(&self) -> &OrderBookImpl {
+        &self.book
+    }
+
+    /// Sequence number of the last update applied (0 at the start).
+    pub fn sequence(&self) -> u64 {
+        self.seq
+    }
+
+    /// Applies the next update; returns its timestamp and sequence, or `None` at the end.
+    pub fn step(&mut self) -> Option<Result<(u64, u64), ParseError>> {
+        let (timestamp, seq, update, len) = match self.peek()? {
+            Ok(frame) => frame,
+            Err(err) => return Some(Err(err)),
+        };
+        self.book.apply_update_at(timestamp, update);
+        self.offset += len;
+        self.seq = seq;
+        self.timestamp = timestamp;
+        Some(Ok((timestamp, seq)))
+    }
+
+    /// Moves to just after the last update stamped at or before `timestamp` (the start, if
+    /// none is). Returns the sequence number reached.
+    pub fn seek_to_time(&mut self, timestamp: u64) -> Result<u64, ParseError> {
+        let checkpoint = self.checkpoints.iter().rposition(|checkpoint| checkpoint.timestamp <= timestamp);
+        let past = self.seq > 0 && self.timestamp > timestamp;
+        if past || checkpoint.is_some_and(|i| self.checkpoints[i].seq > self.seq) {
+            self.restore(checkpoint)?;
+        }
+        while self.peek_timestamp()?.is_some_and(|next| next <= timestamp) {
+            self.step().expect("a frame was peeked")?;
+        }
+        Ok(self.seq)
+    }
+
+    /// Moves to just after update `seq` (0 is the start; past the end stops at the end).
+    /// Returns the sequence number reached.
+    pub fn seek_to_seq(&mut self, seq: u64) -> Result<u64, ParseError> {
+        let checkpoint = self.checkpoints.iter().rposition(|checkpoint| checkpoint.seq <= seq);
+        if self.seq > seq || checkpoint.is_some_and(|i| self.checkpoints[i].seq > self.seq) {
+            self.restore(checkpoint)?;
+        }
+        while self.seq < seq {
+            match self.step() {
+                Some(result) => result?,
+                None => break,
+            };
+        }
+        Ok(self.seq)
+    }
+
+    /// Resets to checkpoint `at`, or to the start for `None`.
+    fn restore(&mut self, at: Option<usize>) -> Result<(), ParseError> {
+        match at {
+            None => {
+                self.book.clone_from(&self.initial);
+                self.offset = 0;
+                self.seq = 0;
+                self.timestamp = 0;
+            }
+            Some(i) => {
+                let checkpoint = self.checkpoints[i];
+                let (start, end) = checkpoint.snapshot;
+                *self.book = book_from_snapshot(&self.index[start..end])
+                    .map_err(|_| ParseError { offset: start, reason: "corrupt checkpoint snapshot" })?;
+                self.offset = checkpoint.log_offset;
+                self.seq = checkpoint.seq;
+                self.timestamp = checkpoint.timestamp;
+            }
+        }
+        Ok(())
+    }
+
+    fn peek_timestamp(&self) -> Result<Option<u64>, ParseError> {
+        self.peek().transpose().map(|frame| frame.map(|(timestamp, ..)| timestamp))
+    }
+
+    /// The frame at the current offset: timestamp, sequence, update and length.
+    fn peek(&self) -> Option<Result<(u64, u64, Update, usize), ParseError>> {
+        let data = &self.log[self.offset..];
+        if data.is_empty() {
+            return None;
+        }
+        let Some(header) = data.get(..FRAME_HEADER_LEN) else {
+            return Some(Err(ParseError { offset: self.log.len(), reason: "truncated frame" }));
+        };
+        let timestamp = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let seq = u64::from_le_bytes(header[8..].try_into().unwrap());
+        Some(
+            BinaryFeedParser
+                .parse(&data[FRAME_HEADER_LEN..])
+                .map(|(update, len)| (timestamp, seq, update, FRAME_HEADER_LEN + len))
+                .map_err(|err| ParseError { offset: self.offset + FRAME_HEADER_LEN + err.offset, reason: err.reason }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::Side;
+    use crate::sim::{MarketSimulator, SimConfig};
+
+    /// `updates` simulated updates, three to a timestamp (10 apart), recorded with checkpoints
+    /// as often as `every` says.
+    fn session(updates: usize, every: CheckpointEvery) -> (Vec<u8>, Vec<u8>) {
+        let mut book = OrderBookImpl::new();
+        let mut recorder = Recorder::with_checkpoints(Vec::new(), Vec::new(), every);
+        for (i, update) in MarketSimulator::new(SimConfig::default()).take(updates).enumerate() {
+            let timestamp = i as u64 / 3 * 10;
+            book.apply_update_at(timestamp, update.clone());
+            recorder.record(timestamp, &update, &book).unwrap();
+        }
+        let (log, index) = recorder.finish().unwrap();
+        (log, index.unwrap())
+    }
+
+    /// The book after stepping a replayer without checkpoints through the first `seq` updates.
+    fn from_scratch(log: &[u8], seq: u64) -> OrderBookImpl {
+        let mut replayer = Replayer::new(log, &[], OrderBookImpl::new()).unwrap();
+        while replayer.sequence() < seq {
+            replayer.step().unwrap().unwrap();
+        }
+        replayer.book().clone()
+    }
+
+    #[test]
+    fn seeks_match_a_replay_from_the_start() {
+        let (log, index) = session(20_000, CheckpointEvery::Updates(1_000));
+        let mut replayer = Replayer::new(&log, &index, OrderBookImpl::new()).unwrap();
+        assert_eq!(replayer.checkpoints.len(), 20);
+
+        // Three updates to a timestamp: 10_000 to 10_002 are stamped 33_330, the last before 33_335.
+        assert_eq!(replayer.seek_to_time(33_335).unwrap(), 10_002);
+        assert!(replayer.book().market_eq(&from_scratch(&log, 10_002)));
+        assert_eq!(replayer.book().now(), 33_330);
+
+        // Backwards, forwards within a checkpoint interval, then before the first checkpoint.
+        for seq in [7_777, 7_900, 12_345, 999, 0] {
+            assert_eq!(replayer.seek_to_seq(seq).unwrap(), seq);
+            assert!(replayer.book().market_eq(&from_scratch(&log, seq)), "seq {seq}");
+        }
+        assert_eq!(replayer.seek_to_seq(u64::MAX).unwrap(), 20_000);
+        assert!(replayer.book().market_eq(&from_scratch(&log, 20_000)));
+        assert!(replayer.step().is_none());
+    }
+
+    #[test]
+    fn checkpoints_by_time() {
+        let (log, index) = session(3_000, CheckpointEvery::Time(2_500));
+        let mut replayer = Replayer::new(&log, &index, OrderBookImpl::new()).unwrap();
+        // The first update is at 0; checkpoints fall at 2_500, 5_000, 7_500.
+        let stamps: Vec<u64> = replayer.checkpoints.iter().map(|checkpoint| checkpoint.timestamp).collect();
+        assert_eq!(stamps, [2_500, 5_000, 7_500]);
+
+        assert_eq!(replayer.seek_to_time(6_000).unwrap(), 1_803);
+        assert!(replayer.book().market_eq(&from_scratch(&log, 1_803)));
+        assert_eq!(replayer.seek_to_time(0).unwrap(), 3);
+    }
+
+    #[test]
+    fn recording_rejects_time_going_backwards() {
+        let book = OrderBookImpl::new();
+        let update = Update::Set { price: 10_000, quantity: 5, side: Side::Bid };
+        let mut recorder = Recorder::new(Vec::new());
+        assert_eq!(recorder.record(20, &update, &book).unwrap(), 1);
+        let err = recorder.record(19, &update, &book).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(recorder.sequence(), 1);
+        let (log, index) = recorder.finish().unwrap();
+        assert_eq!(log.len(), FRAME_HEADER_LEN + 26);
+        assert!(index.is_none());
+    }
+
+    #[test]
+    fn damaged_logs_and_indexes_are_errors() {
+        let (log, mut index) = session(300, CheckpointEvery::Updates(100));
+
+        let mut replayer = Replayer::new(&log[..log.len() - 5], &[], OrderBookImpl::new()).unwrap();
+        assert_eq!(replayer.seek_to_seq(299).unwrap(), 299);
+        assert_eq!(replayer.step().unwrap().unwrap_err().reason, "truncated record");
+
+        let truncated = Replayer::new(&log, &index[..index.len() - 1], OrderBookImpl::new());
+        assert_eq!(truncated.err().unwrap().reason, "truncated checkpoint");
+
+        // A flipped byte in the second checkpoint's snapshot fails its CRC, but only once used.
+        let (start, _) = Replayer::new(&log, &index, OrderBookImpl::new()).unwrap().checkpoints[1].snapshot;
+        index[start + 70] ^= 1;
+        let mut replayer = Replayer::new(&log, &index, OrderBookImpl::new()).unwrap();
+        assert_eq!(replayer.seek_to_seq(150).unwrap(), 150);
+        assert_eq!(replayer.seek_to_seq(250).unwrap_err().reason, "corrupt checkpoint snapshot");
+    }
+}
@@ -0,0 +1,100 @@
+// reference.rs
+
+use std::collections::BTreeMap;
+use crate::error::OrderBookError;
+use crate::interfaces::{OrderBookFactory, OrderBookRead, OrderBookWrite, Price, Quantity, Side, TryOrderBook, Update};
+
+/// The v1 `BTreeMap` book. Slow but obviously correct; kept as the oracle that the
+/// array implementation is cross-checked against.
+#[derive(Debug, Clone, Default)]
+pub struct BTreeOrderBook {
+    bids: BTreeMap<Price, Quantity>,
+    asks: BTreeMap<Price, Quantity>,
+}
+
+impl BTreeOrderBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn side(&self, side: Side) -> &BTreeMap<Price, Quantity> {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+}
+
+impl OrderBookFactory for BTreeOrderBook {
+    fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl OrderBookWrite for BTreeOrderBook {
+    fn apply_update(&mut self, update: Update) {
+        match update {
+            Update::Set { price, quantity, side } | Update::SetWithCount { price, quantity, side, .. } => {
+                let book = match side { Side::Bid => &mut self.bids, Side::Ask => &mut self.asks };
+                if quantity > 0 {
+                    book.insert(price, quantity);
+                } else {
+                    book.remove(&price);
+                }
+            }
+            Update::Remove { price, side } => {
+                match side { Side::Bid => self.bids.remove(&price), Side::Ask => self.asks.remove(&price) };
+            }
+            Update::Trade { .. } => {}
+            Update::SetLevels { side, levels } => {
+                for (price, quantity) in levels {
+                    self.apply_update(Update::Set { price, quantity, side });
+                }
+            }
+        }
+    }
+}
+
+impl OrderBookRead for BTreeOrderBook {
+    fn get_spread(&self) -> Option<Price> {
+        Some(self.get_best_ask()? - self.get_best_bid()?)
+    }
+
+    fn get_best_bid(&self) -> Option<Price> {
+        self.bids.keys().next_back().copied()
+    }
+
+    fn get_best_ask(&self) -> Option<Price> {
+        self.asks.keys().next().copied()
+    }
+
+    fn get_quantity_at(&self, price: Price, side: Side) -> Option<Quantity> {
+        self.side(side).get(&price).copied()
+    }
+
+    fn get_top_levels(&self, side: Side, n: usize) -> Vec<(Price, Quantity)> {
+        match side {
+            Side::Bid => self.bids.iter().rev().take(n).map(|(p, q)| (*p, *q)).collect(),
+            Side::Ask => self.asks.iter().take(n).map(|(p, q)| (*p, *q)).collect(),
+        }
+    }
+
+    /// Wraps on overflow, like `OrderBookImpl`'s running totals.
+    fn get_total_quantity(&self, side: Side) -> Quantity {
+        self.side(side).values().fold(0, |total, &quantity| total.wrapping_add(quantity))
+    }
+}
+
+impl TryOrderBook for BTreeOrderBook {
+    /// The map has no window and no cross policy; only total overflow can fail.
+    fn try_apply_update(&mut self, update: Update) -> Result<(), OrderBookError> {
+        if let Update::Set { price, quantity, side } | Update::SetWithCount { price, quantity, side, .. } = update {
+            let total = self.get_total_quantity(side).wrapping_sub(self.get_quantity_at(price, side).unwrap_or(0));
+            if total.checked_add(quantity).is_none() {
+                return Err(OrderBookError::Overflow { side, total, added: quantity });
+            }
+        }
+        self.apply_update(update);
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+// replication.rs
+
+use crate::interfaces::{Price, Quantity, Side};
+
+/// One effective change to a book, as recorded by `OrderBookImpl::enable_event_log` and
+/// replayed by `OrderBookImpl::apply_events`.
+///
+/// Events describe results, not requests: the tick divisor, cross policy and level limit have
+/// already been applied, trades and refused updates leave no trace, and an auto-resolved cross
+/// shows up as the removals it caused. A replica therefore needs none of the primary's
+/// configuration, and the wire format is free to differ from `Update`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookEvent<P = Price> {
+    /// The level at `price` on `side` now holds `quantity` (0 = removed).
+    Level { side: Side, price: P, quantity: Quantity },
+    /// Both sides were emptied (`clear`, `take_snapshot_and_reset`).
+    Clear,
+    /// The windows moved to these `[bid, ask]` anchors, dropping the levels outside them.
+    Recenter { anchors: [P; 2] },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::{OrderBookRead, OrderBookWrite, Update};
+    use crate::orderbook::{CrossPolicy, OrderBookImpl};
+    use crate::sim::SimRng;
+    use crate::testing::{random_price, random_side, random_update};
+
+    fn assert_same(primary: &OrderBookImpl, replica: &OrderBookImpl) {
+        for side in [Side::Bid, Side::Ask] {
+            assert!(primary.levels(side).eq(replica.levels(side)), "{side:?} levels differ");
+            assert_eq!(primary.get_total_quantity(side), replica.get_total_quantity(side));
+            assert_eq!(primary.anchor(side), replica.anchor(side));
+        }
+        assert_eq!(primary.get_bbo(), replica.get_bbo());
+        assert_eq!(primary.version(), replica.version());
+    }
+
+    #[test]
+    fn replica_tracks_a_randomly_driven_primary() {
+        let mut primary = OrderBookImpl::builder().on_cross(CrossPolicy::AutoResolve).max_levels(200).build();
+        let mut replica = OrderBookImpl::builder().max_levels(200).build();
+        primary.enable_event_log();
+
+        let mut rng = SimRng::new(0x9e37_79b9_7f4a_7c15);
+        for batch in 0..200 {
+            for _ in 0..50 {
+                // Between the two anchors, which part after the ask-side recenters.
+                let center = (primary.anchor(Side::Bid) + primary.anchor(Side::Ask)) / 2;
+                let update = match rng.below(10) {
+                    0..=7 => random_update(&mut rng, center, 600),
+                    8 => Update::Trade { price: random_price(&mut rng, center, 600), quantity: 1, aggressor: random_side(&mut rng), timestamp: 0 },
+                    _ => {
+                        primary.undo_last();
+                        continue;
+                    }
+                };
+                primary.apply_update(update);
+            }
+            match batch % 50 {
+                17 => {
+                    primary.recenter_anchor(primary.anchor(Side::Bid) + rng.below(400) as i64 - 200);
+                }
+                31 => {
+                    primary.recenter_side(Side::Ask, primary.anchor(Side::Ask) + 150);
+                }
+                49 => primary.clear(),
+                _ => {}
+            }
+            let events = primary.take_events();
+            replica.apply_events(&events);
+            assert_same(&primary, &replica);
+        }
+        assert!(primary.take_events().is_empty());
+    }
+
+    #[test]
+    fn events_are_effective_changes() {
+        let mut ob = OrderBookImpl::with_tick_divisor(10);
+        ob.enable_event_log();
+        ob.apply_update(Update::Set { price: 99_990, quantity: 5, side: Side::Bid });
+        ob.apply_update(Update::Set { price: 99_995, quantity: 5, side: Side::Bid });
+        ob.apply_update(Update::Remove { price: 10_000, side: Side::Ask });
+        ob.apply_update(Update::Trade { price: 99_990, quantity: 1, aggressor: Side::Ask, timestamp: 0 });
+        assert_eq!(ob.take_events(), vec![BookEvent::Level { side: Side::Bid, price: 9_999, quantity: 5 }]);
+        ob.disable_event_log();
+        ob.clear();
+        assert!(ob.take_events().is_empty());
+    }
+}
@@ -0,0 +1,404 @@
+// sim.rs
+
+use std::collections::{BTreeMap, VecDeque};
+use crate::interfaces::{Price, Quantity, Side, Update};
+
+/// How resting size varies with distance from the touch; see `SimConfig::depth_shape`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthShape {
+    /// The same typical size at every level.
+    Flat,
+    /// Each tick further out holds `ratio` times the size of the one before (below 1 thins out).
+    Decaying { ratio: f64 },
+    /// Size grows up to `peak` ticks from the touch and falls off beyond it, as on books where
+    /// the liquidity waits behind the first few levels.
+    Humped { peak: u32 },
+}
+
+/// The seeded generator behind `MarketSimulator`: xorshift64* over a splitmix64 of the seed, so
+/// neighbouring seeds diverge at once and 0 is usable. Not for anything but simulations and
+/// randomized tests.
+#[derive(Debug, Clone)]
+pub struct SimRng {
+    state: u64,
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        let mut state = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        state ^= state >> 31;
+        SimRng { state: state | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// In `0..bound`, with the modulo bias a test can ignore.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+/// Parameters for `MarketSimulator`. `SimConfig::default()` is a quiet, liquid market around
+/// 10000 that stays well inside a default book's window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimConfig {
+    /// Same seed, same stream.
+    pub seed: u64,
+    /// The first mid.
+    pub start_price: Price,
+    /// Typical size of the mid's move per update, in ticks.
+    pub volatility: f64,
+    /// Mean move of the mid per update, in ticks (positive drifts up).
+    pub drift: f64,
+    /// Relative frequencies of adds, cancels and trades.
+    pub add_weight: u32,
+    pub cancel_weight: u32,
+    pub trade_weight: u32,
+    /// How far from the touch quotes go, in ticks. Levels the mid leaves further out than this
+    /// are cancelled.
+    pub depth: u32,
+    pub depth_shape: DepthShape,
+    /// Typical size at the touch.
+    pub base_quantity: Quantity,
+    /// Chance per step of a burst: several updates in a row to one touch price.
+    pub burst_probability: f64,
+    /// Chance per step that a best level is pulled and re-quoted.
+    pub touch_churn_probability: f64,
+    /// Chance per step that the mid jumps `jump_ticks` up or down. With `jump_ticks` beyond half
+    /// a window, a fixed-window book fed the stream has to recenter to follow.
+    pub jump_probability: f64,
+    pub jump_ticks: i64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            seed: 1,
+            start_price: 10000,
+            volatility: 0.2,
+            drift: 0.0,
+            add_weight: 60,
+            cancel_weight: 30,
+            trade_weight: 10,
+            depth: 50,
+            depth_shape: DepthShape::Decaying { ratio: 0.97 },
+            base_quantity: 100,
+            burst_probability: 0.01,
+            touch_churn_probability: 0.02,
+            jump_probability: 0.0,
+            jump_ticks: 3000,
+        }
+    }
+}
+
+/// Endless, seeded stream of `Update`s with the texture of real market data: a mid that drifts
+/// and diffuses (and optionally jumps), quotes added and cancelled around it with a configurable
+/// depth profile, trades against the touch, bursts at a single price and touch turnover.
+///
+/// The simulator keeps its own copy of the book, so every `Remove` and every trade's level
+/// change hits an occupied level, and the book is never crossed: levels the mid moves through
+/// are removed before anything is quoted on the far side of them. A `Trade` is followed by
+/// the update that takes its quantity off the level.
+#[derive(Debug, Clone)]
+pub struct MarketSimulator {
+    config: SimConfig,
+    rng: SimRng,
+    mid: f64,
+    bids: BTreeMap<Price, Quantity>,
+    asks: BTreeMap<Price, Quantity>,
+    pending: VecDeque<Update>,
+    timestamp: u64,
+}
+
+impl MarketSimulator {
+    pub fn new(config: SimConfig) -> Self {
+        MarketSimulator {
+            mid: config.start_price as f64,
+            rng: SimRng::new(config.seed),
+            config,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            pending: VecDeque::new(),
+            timestamp: 0,
+        }
+    }
+
+    pub fn config(&self) -> &SimConfig {
+        &self.config
+    }
+
+    /// The mid the quotes are currently placed around.
+    pub fn mid(&self) -> f64 {
+        self.mid
+    }
+
+    /// The simulator's own view of the book, which already includes any updates it has queued
+    /// but not yet yielded.
+    pub fn quantity_at(&self, price: Price, side: Side) -> Option<Quantity> {
+        self.side(side).get(&price).copied()
+    }
+
+    /// Best price in the simulator's own view; see `quantity_at`.
+    pub fn best(&self, side: Side) -> Option<Price> {
+        match side {
+            Side::Bid => self.bids.keys().next_back().copied(),
+            Side::Ask => self.asks.keys().next().copied(),
+        }
+    }
+
+    fn side(&self, side: Side) -> &BTreeMap<Price, Quantity> {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    /// Uniform in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.unit() < probability
+    }
+
+    fn random_side(&mut self) -> Side {
+        if self.next_u64() & 1 == 0 { Side::Bid } else { Side::Ask }
+    }
+
+    /// Best possible bid and ask for the current mid, a tick apart.
+    fn touch(&self) -> (Price, Price) {
+        let bid = (self.mid - 0.5).floor() as Price;
+        (bid, bid + 1)
+    }
+
+    /// Ticks from the touch for a new quote or a cancel: mostly near it, never past `depth`.
+    fn distance(&mut self) -> u32 {
+        let scale = (self.config.depth as f64 / 4.0).max(1.0);
+        ((-(1.0 - self.unit()).ln() * scale) as u32).min(self.config.depth)
+    }
+
+    fn quantity(&mut self, distance: u32) -> Quantity {
+        let factor = match self.config.depth_shape {
+            DepthShape::Flat => 1.0,
+            DepthShape::Decaying { ratio } => ratio.powi(distance as i32),
+            DepthShape::Humped { peak } if distance <= peak => (distance + 1) as f64 / (peak + 1) as f64,
+            DepthShape::Humped { peak } => (peak + 1) as f64 / (distance + 1) as f64,
+        };
+        let jitter = 0.5 + self.unit();
+        ((self.config.base_quantity as f64 * factor * jitter).round() as Quantity).max(1)
+    }
+
+    /// Records `quantity` at `price` (0 removes) and queues the update saying so.
+    fn write(&mut self, side: Side, price: Price, quantity: Quantity) {
+        let book = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+        if quantity == 0 {
+            book.remove(&price);
+            self.pending.push_back(Update::Remove { price, side });
+        } else {
+            book.insert(price, quantity);
+            self.pending.push_back(Update::Set { price, quantity, side });
+        }
+    }
+
+    fn step(&mut self) {
+        self.timestamp += 1_000;
+        let noise = (self.unit() + self.unit() + self.unit() - 1.5) * 2.0 * self.config.volatility;
+        self.mid += self.config.drift + noise;
+        if self.chance(self.config.jump_probability) {
+            let jump = if self.next_u64() & 1 == 0 { self.config.jump_ticks } else { -self.config.jump_ticks };
+            self.mid += jump as f64;
+        }
+        self.retire_stale_levels();
+
+        if self.chance(self.config.touch_churn_probability) {
+            let side = self.random_side();
+            if let Some(price) = self.best(side) {
+                self.write(side, price, 0);
+                let quantity = self.quantity(0);
+                self.write(side, price, quantity);
+                return;
+            }
+        }
+        if self.chance(self.config.burst_probability) {
+            let side = self.random_side();
+            let price = self.best(side).unwrap_or(match side {
+                Side::Bid => self.touch().0,
+                Side::Ask => self.touch().1,
+            });
+            for _ in 0..2 + self.next_u64() % 5 {
+                let quantity = self.quantity(0);
+                self.write(side, price, quantity);
+            }
+            return;
+        }
+
+        let config = &self.config;
+        let total = (config.add_weight + config.cancel_weight + config.trade_weight).max(1) as u64;
+        let (adds, cancels) = (config.add_weight as u64, config.cancel_weight as u64);
+        let pick = self.next_u64() % total;
+        let side = self.random_side();
+        let done = if pick < adds {
+            false
+        } else if pick < adds + cancels {
+            self.cancel(side)
+        } else {
+            self.trade(side)
+        };
+        if !done {
+            self.add(side);
+        }
+    }
+
+    /// Removes levels the mid has moved through or left more than `depth` ticks behind.
+    fn retire_stale_levels(&mut self) {
+        let (bid, ask) = self.touch();
+        let depth = self.config.depth as Price;
+        let stale = |book: &BTreeMap<Price, Quantity>, keep: &dyn Fn(Price) -> bool| -> Vec<Price> {
+            book.keys().copied().filter(|&price| !keep(price)).collect()
+        };
+        let bids = stale(&self.bids, &|price| price <= bid && bid - price <= depth);
+        let asks = stale(&self.asks, &|price| price >= ask && price - ask <= depth);
+        for price in bids {
+            self.write(Side::Bid, price, 0);
+        }
+        for price in asks {
+            self.write(Side::Ask, price, 0);
+        }
+    }
+
+    fn add(&mut self, side: Side) {
+        let distance = self.distance();
+        let (bid, ask) = self.touch();
+        let price = match side {
+            Side::Bid => bid - distance as Price,
+            Side::Ask => ask + distance as Price,
+        };
+        let quantity = self.quantity(distance);
+        let resting = self.quantity_at(price, side).unwrap_or(0);
+        self.write(side, price, resting.saturating_add(quantity));
+    }
+
+    /// Shrinks or pulls a level, mostly one near the touch. False if the side is empty.
+    fn cancel(&mut self, side: Side) -> bool {
+        let levels = self.side(side).len();
+        if levels == 0 {
+            return false;
+        }
+        let nth = self.distance() as usize % levels;
+        let (price, resting) = match side {
+            Side::Bid => self.bids.iter().rev().nth(nth),
+            Side::Ask => self.asks.iter().nth(nth),
+        }
+        .map(|(&price, &quantity)| (price, quantity))
+        .expect("nth is below the level count");
+        let left = if self.next_u64() & 1 == 0 { 0 } else { resting / 2 };
+        self.write(side, price, left);
+        true
+    }
+
+    /// A marketable order from `aggressor` against the opposite best. False if there is none.
+    fn trade(&mut self, aggressor: Side) -> bool {
+        let opposite = match aggressor {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let Some(price) = self.best(opposite) else { return false };
+        let resting = self.quantity_at(price, opposite).unwrap_or(0);
+        let wanted = self.quantity(0);
+        let quantity = wanted.min(resting);
+        self.pending.push_back(Update::Trade { price, quantity, aggressor, timestamp: self.timestamp });
+        self.write(opposite, price, resting - quantity);
+        true
+    }
+}
+
+impl Iterator for MarketSimulator {
+    type Item = Update;
+
+    fn next(&mut self) -> Option<Update> {
+        while self.pending.is_empty() {
+            self.step();
+        }
+        self.pending.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::OrderBookError;
+    use crate::interfaces::{OrderBookRead, TryOrderBook};
+    use crate::orderbook::OrderBookImpl;
+
+    #[test]
+    fn same_seed_same_stream() {
+        let config = SimConfig { seed: 42, jump_probability: 0.001, ..SimConfig::default() };
+        let stream = |config: SimConfig| MarketSimulator::new(config).take(5_000).collect::<Vec<_>>();
+        let first = stream(config.clone());
+        assert_eq!(first, stream(config.clone()));
+        let other = stream(SimConfig { seed: 43, ..config });
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn stream_keeps_a_consistent_book_through_recenters() {
+        let config = SimConfig { seed: 7, drift: 0.05, jump_probability: 0.0005, ..SimConfig::default() };
+        let mut sim = MarketSimulator::new(config);
+        let mut book = OrderBookImpl::new();
+        let (mut recenters, mut trades, mut bursts, mut last_set) = (0, 0, 0, None);
+        for _ in 0..50_000 {
+            let update = sim.next().unwrap();
+            match update {
+                Update::Remove { price, side } => assert!(book.get_quantity_at(price, side).is_some(), "{update:?}"),
+                Update::Trade { price, aggressor, .. } => {
+                    let opposite = match aggressor { Side::Bid => Side::Ask, Side::Ask => Side::Bid };
+                    let best = match opposite { Side::Bid => book.get_best_bid(), Side::Ask => book.get_best_ask() };
+                    assert_eq!(best, Some(price));
+                    trades += 1;
+                }
+                Update::Set { price, side, .. } => {
+                    bursts += (last_set == Some((price, side))) as u32;
+                    last_set = Some((price, side));
+                }
+                _ => {}
+            }
+            if let Err(OrderBookError::OutOfWindow { price, .. }) = book.try_apply_update(update.clone()) {
+                book.recenter_anchor_aligned(price);
+                recenters += 1;
+                book.try_apply_update(update).unwrap();
+            }
+            if let (Some(bid), Some(ask)) = (book.get_best_bid(), book.get_best_ask()) {
+                assert!(bid < ask, "crossed at {bid} / {ask}");
+            }
+        }
+        assert!(recenters > 0 && trades > 1_000 && bursts > 100, "{recenters} {trades} {bursts}");
+        for side in [Side::Bid, Side::Ask] {
+            assert_eq!(book.levels(side).collect::<BTreeMap<_, _>>(), *sim.side(side));
+        }
+    }
+
+    #[test]
+    fn depth_shapes_set_the_size_profile() {
+        let mut sim = MarketSimulator::new(SimConfig { depth_shape: DepthShape::Humped { peak: 10 }, ..SimConfig::default() });
+        let average = |sim: &mut MarketSimulator, distance| (0..1_000).map(|_| sim.quantity(distance)).sum::<u64>() / 1_000;
+        assert!(average(&mut sim, 10) > 3 * average(&mut sim, 0));
+        assert!(average(&mut sim, 10) > 3 * average(&mut sim, 40));
+        sim.config.depth_shape = DepthShape::Flat;
+        assert!(average(&mut sim, 40).abs_diff(average(&mut sim, 0)) < 10);
+    }
+}
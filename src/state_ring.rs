@@ -0,0 +1,190 @@
+// state_ring.rs
+
+use std::fmt::Write;
+use crate::interfaces::{OrderBookRead, OrderBookWrite, Price, Quantity, Side, Update};
+use crate::orderbook::OrderBookImpl;
+use crate::reference::BTreeOrderBook;
+use crate::storage::LevelStorage;
+
+/// One captured book state. See `StateRing`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingEntry {
+    /// `apply_update` calls seen by the ring when this was captured.
+    pub applies: u64,
+    pub version: u64,
+    pub last_sequence: Option<u64>,
+    /// `[bid, ask]` anchors.
+    pub anchors: [Price; 2],
+    /// `[bid, ask]` total quantities.
+    pub totals: [Quantity; 2],
+    /// Top levels, best first.
+    pub bids: Vec<(Price, Quantity)>,
+    pub asks: Vec<(Price, Quantity)>,
+}
+
+/// Flight recorder for post-mortems: every `every` applies, the top `depth` levels, totals,
+/// version and sequence go into a ring of the last `capacity` captures.
+///
+/// Attach with `OrderBookImpl::enable_state_ring`. All entries are allocated up front and
+/// overwritten in place, so steady-state capture allocates nothing.
+#[derive(Debug, Clone)]
+pub struct StateRing {
+    every: u64,
+    depth: usize,
+    applies: u64,
+    entries: Vec<RingEntry>,
+    next: usize,
+    filled: usize,
+}
+
+impl StateRing {
+    /// Panics if `every` is 0.
+    pub fn new(every: u64, capacity: usize, depth: usize) -> Self {
+        assert!(every > 0, "capture interval must be positive");
+        // Built one by one: cloning a template would drop the reserved level capacity.
+        let entries = (0..capacity)
+            .map(|_| RingEntry {
+                applies: 0,
+                version: 0,
+                last_sequence: None,
+                anchors: [0; 2],
+                totals: [0; 2],
+                bids: Vec::with_capacity(depth),
+                asks: Vec::with_capacity(depth),
+            })
+            .collect();
+        StateRing { every, depth, applies: 0, entries, next: 0, filled: 0 }
+    }
+
+    pub(crate) fn record_apply<S: LevelStorage>(&mut self, book: &OrderBookImpl<Price, S>) {
+        self.applies += 1;
+        if self.applies.is_multiple_of(self.every) {
+            self.capture(book);
+        }
+    }
+
+    /// Captures `book` now, regardless of the interval (e.g. right when an invariant check fails).
+    pub fn capture<S: LevelStorage>(&mut self, book: &OrderBookImpl<Price, S>) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let entry = &mut self.entries[self.next];
+        entry.applies = self.applies;
+        entry.version = book.version();
+        entry.last_sequence = book.last_sequence();
+        entry.anchors = [book.anchor(Side::Bid), book.anchor(Side::Ask)];
+        entry.totals = [book.get_total_quantity(Side::Bid), book.get_total_quantity(Side::Ask)];
+        entry.bids.clear();
+        entry.bids.extend(book.levels(Side::Bid).take(self.depth));
+        entry.asks.clear();
+        entry.asks.extend(book.levels(Side::Ask).take(self.depth));
+        self.next = (self.next + 1) % self.entries.len();
+        self.filled = (self.filled + 1).min(self.entries.len());
+    }
+
+    /// Captured entries, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &RingEntry> {
+        let start = (self.next + self.entries.len() - self.filled) % self.entries.len().max(1);
+        (0..self.filled).map(move |i| &self.entries[(start + i) % self.entries.len()])
+    }
+
+    /// Plain-text dump of every entry, oldest first, one block per entry, for pasting into a bug report.
+    pub fn dump_ring(&self) -> String {
+        let mut out = String::new();
+        for entry in self.entries() {
+            let _ = writeln!(
+                out,
+                "applies={} version={} seq={:?} anchors={:?} totals={:?}",
+                entry.applies, entry.version, entry.last_sequence, entry.anchors, entry.totals
+            );
+            let _ = writeln!(out, "  bids {:?}", entry.bids);
+            let _ = writeln!(out, "  asks {:?}", entry.asks);
+        }
+        out
+    }
+}
+
+/// Where `locate_divergence` first found a disagreement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Divergence {
+    /// After journal update `i` the array book and the `BTreeOrderBook` reference disagree:
+    /// `OrderBookImpl` itself mishandled that update.
+    Update(usize),
+    /// The replay is self-consistent but does not end at `to`: the journal does not match what
+    /// the live book actually applied (missing, extra or reordered updates).
+    Endpoint,
+}
+
+/// Replays `journal` (the updates applied between `from` and `to`, in order) from `from`'s
+/// levels into both an `OrderBookImpl` and the reference book, comparing their top levels after
+/// every update, then compares the result with `to`. `None` if everything agrees.
+///
+/// Entries only hold the top levels, so this is exact as long as the replay does not need a
+/// level from below the captured depth; the live book's tick divisor is not reapplied.
+pub fn locate_divergence(from: &RingEntry, to: &RingEntry, journal: &[Update]) -> Option<Divergence> {
+    let depth = from.bids.len().max(from.asks.len()).max(to.bids.len()).max(to.asks.len());
+    let mut book = OrderBookImpl::builder().bid_anchor(from.anchors[0]).ask_anchor(from.anchors[1]).build();
+    let mut reference = BTreeOrderBook::new();
+    for (side, levels) in [(Side::Bid, &from.bids), (Side::Ask, &from.asks)] {
+        for &(price, quantity) in levels {
+            book.apply_update(Update::Set { price, quantity, side });
+            reference.apply_update(Update::Set { price, quantity, side });
+        }
+    }
+    for (i, update) in journal.iter().enumerate() {
+        book.apply_update(update.clone());
+        reference.apply_update(update.clone());
+        let agree = |side| book.get_top_levels(side, depth) == reference.get_top_levels(side, depth);
+        if !agree(Side::Bid) || !agree(Side::Ask) {
+            return Some(Divergence::Update(i));
+        }
+    }
+    let replayed = (book.get_top_levels(Side::Bid, to.bids.len()), book.get_top_levels(Side::Ask, to.asks.len()));
+    (replayed != (to.bids.clone(), to.asks.clone())).then_some(Divergence::Endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::set;
+
+    #[test]
+    fn ring_keeps_the_latest_captures_without_reallocating() {
+        let mut ob = OrderBookImpl::new();
+        ob.enable_state_ring(StateRing::new(2, 3, 2));
+        for i in 0..10 {
+            ob.apply_update(set(9990 + i, 1, Side::Bid));
+        }
+        let ring = ob.state_ring().unwrap();
+        let applies: Vec<u64> = ring.entries().map(|e| e.applies).collect();
+        assert_eq!(applies, vec![6, 8, 10]);
+        let last = ring.entries().last().unwrap();
+        assert_eq!(last.bids, vec![(9999, 1), (9998, 1)]);
+        assert_eq!(last.totals, [10, 0]);
+        assert_eq!(last.version, 10);
+        assert!(ring.entries().all(|e| e.bids.capacity() == 2));
+
+        let dump = ring.dump_ring();
+        assert_eq!(dump.lines().count(), 9);
+        assert!(dump.starts_with("applies=6 version=6"));
+    }
+
+    #[test]
+    fn consistent_journal_has_no_divergence() {
+        let mut ob = OrderBookImpl::new();
+        let mut ring = StateRing::new(1, 2, 5);
+        ob.apply_update(set(9990, 5, Side::Bid));
+        ob.apply_update(set(10010, 5, Side::Ask));
+        ring.capture(&ob);
+        let journal = [set(9995, 3, Side::Bid), Update::Remove { price: 10010, side: Side::Ask }, set(10004, 1, Side::Ask)];
+        for update in &journal {
+            ob.apply_update(update.clone());
+        }
+        ring.capture(&ob);
+        let entries: Vec<&RingEntry> = ring.entries().collect();
+        assert_eq!(locate_divergence(entries[0], entries[1], &journal), None);
+
+        // A journal missing an update the live book applied cannot reach the captured end state.
+        assert_eq!(locate_divergence(entries[0], entries[1], &journal[..2]), Some(Divergence::Endpoint));
+    }
+}
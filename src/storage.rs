@@ -0,0 +1,187 @@
+// storage.rs
+
+use std::ops::{Deref, DerefMut};
+use crate::interfaces::Quantity;
+use crate::orderbook::CAP;
+
+/// Where a book keeps one side's `CAP` quantity slots: the `S` of `OrderBookImpl<P, S>`.
+///
+/// The default, `InlineLevels`, embeds the array in the book itself, as it always has. Any other
+/// implementation puts it behind a pointer, typically one handed out by a pool or arena shared
+/// by many books (see `OrderBookImpl::with_storage`); `BoxedLevels` is the global-allocator one.
+/// The slot count is fixed by the array type, so an implementation only has to hand out the
+/// same array every time. `Clone` must produce an independent copy, as cloning a book copies its
+/// levels.
+///
+/// Books over a custom storage get the ring and the `OrderBookRead` / `OrderBookWrite` API; the
+/// analytics and exchange-format layers built on top are implemented for `InlineLevels` only.
+pub trait LevelStorage: Deref<Target = [Quantity; CAP]> + DerefMut + Clone + Send + Sync + 'static {}
+
+impl<T: Deref<Target = [Quantity; CAP]> + DerefMut + Clone + Send + Sync + 'static> LevelStorage for T {}
+
+/// The slots embedded in the book (32 KiB per side). The default storage.
+#[derive(Clone)]
+pub struct InlineLevels([Quantity; CAP]);
+
+impl InlineLevels {
+    pub(crate) const EMPTY: InlineLevels = InlineLevels([0; CAP]);
+}
+
+impl Deref for InlineLevels {
+    type Target = [Quantity; CAP];
+
+    #[inline(always)]
+    fn deref(&self) -> &[Quantity; CAP] {
+        &self.0
+    }
+}
+
+impl DerefMut for InlineLevels {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut [Quantity; CAP] {
+        &mut self.0
+    }
+}
+
+/// Slots on the heap, for books that move around a lot or would otherwise blow a small stack.
+#[derive(Clone)]
+pub struct BoxedLevels(Box<[Quantity; CAP]>);
+
+impl BoxedLevels {
+    pub fn new() -> Self {
+        BoxedLevels(Box::new([0; CAP]))
+    }
+}
+
+impl Default for BoxedLevels {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for BoxedLevels {
+    type Target = [Quantity; CAP];
+
+    #[inline(always)]
+    fn deref(&self) -> &[Quantity; CAP] {
+        &self.0
+    }
+}
+
+impl DerefMut for BoxedLevels {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut [Quantity; CAP] {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::interfaces::{OrderBookRead, OrderBookWrite, Side, Update};
+    use crate::orderbook::OrderBookImpl;
+    use crate::sim::SimRng;
+    use crate::testing::random_update;
+
+    /// A fixed run of arrays handed out front to back and never reclaimed.
+    struct BumpPool {
+        base: *mut [Quantity; CAP],
+        chunks: usize,
+        used: AtomicUsize,
+    }
+
+    // Each chunk is handed out once, so no two `PoolLevels` ever alias.
+    unsafe impl Send for BumpPool {}
+    unsafe impl Sync for BumpPool {}
+
+    impl BumpPool {
+        fn leak(chunks: usize) -> &'static BumpPool {
+            let arrays: &'static mut [[Quantity; CAP]] = Vec::leak(vec![[Quantity::MAX; CAP]; chunks]);
+            Box::leak(Box::new(BumpPool { base: arrays.as_mut_ptr(), chunks, used: AtomicUsize::new(0) }))
+        }
+
+        fn alloc(&'static self) -> PoolLevels {
+            let chunk = self.used.fetch_add(1, Ordering::Relaxed);
+            assert!(chunk < self.chunks, "pool exhausted");
+            PoolLevels { pool: self, slots: unsafe { &mut *self.base.add(chunk) } }
+        }
+
+        fn owns(&self, slots: &[Quantity; CAP]) -> bool {
+            let offset = (slots as *const [Quantity; CAP] as usize).wrapping_sub(self.base as usize);
+            offset < self.chunks * size_of::<[Quantity; CAP]>()
+        }
+    }
+
+    struct PoolLevels {
+        pool: &'static BumpPool,
+        slots: &'static mut [Quantity; CAP],
+    }
+
+    impl Clone for PoolLevels {
+        fn clone(&self) -> Self {
+            let copy = self.pool.alloc();
+            copy.slots.copy_from_slice(&self.slots[..]);
+            copy
+        }
+    }
+
+    impl Deref for PoolLevels {
+        type Target = [Quantity; CAP];
+
+        fn deref(&self) -> &[Quantity; CAP] {
+            self.slots
+        }
+    }
+
+    impl DerefMut for PoolLevels {
+        fn deref_mut(&mut self) -> &mut [Quantity; CAP] {
+            self.slots
+        }
+    }
+
+    #[test]
+    fn pooled_book_matches_inline_book() {
+        let pool = BumpPool::leak(4);
+        let mut pooled = OrderBookImpl::with_storage(10000, pool.alloc(), pool.alloc());
+        let mut inline = OrderBookImpl::new();
+        assert!(pool.owns(pooled.levels_raw(Side::Bid).quantities()));
+        assert!(pool.owns(pooled.levels_raw(Side::Ask).quantities()));
+        // The pool starts out dirty; `with_storage` zeroed both chunks.
+        assert_eq!(pooled.get_bbo(), None);
+
+        let mut rng = SimRng::new(0x9e37_79b9_7f4a_7c15);
+        for round in 0..5000 {
+            let update = random_update(&mut rng, 10000, 200);
+            pooled.apply_update(update.clone());
+            inline.apply_update(update);
+            if round % 500 == 0 {
+                pooled.recenter_anchor(10000 - 50 + rng.below(100) as i64);
+                inline.recenter_anchor(pooled.anchor(Side::Bid));
+            }
+        }
+        for side in [Side::Bid, Side::Ask] {
+            assert!(pooled.levels(side).eq(inline.levels(side)), "{side:?}");
+            assert_eq!(pooled.get_total_quantity(side), inline.get_total_quantity(side));
+        }
+        assert_eq!(pooled.get_bbo(), inline.get_bbo());
+
+        // Cloning takes fresh chunks and copies the levels into them.
+        let mut copy = pooled.clone();
+        assert_eq!(pool.used.load(Ordering::Relaxed), 4);
+        assert!(copy.levels(Side::Ask).eq(pooled.levels(Side::Ask)));
+        copy.clear();
+        assert_eq!(copy.get_bbo(), None);
+        assert_eq!(pooled.get_bbo(), inline.get_bbo());
+    }
+
+    #[test]
+    fn boxed_levels_start_zeroed() {
+        let mut dirty = BoxedLevels::new();
+        dirty.fill(7);
+        let mut ob = OrderBookImpl::with_storage(10000, dirty, BoxedLevels::default());
+        assert_eq!(ob.get_total_quantity(Side::Bid), 0);
+        ob.apply_update(Update::Set { price: 9999, quantity: 3, side: Side::Bid });
+        assert_eq!(ob.get_top_levels(Side::Bid, 5), vec![(9999, 3)]);
+    }
+}
@@ -0,0 +1,140 @@
+// tape.rs
+
+use std::collections::VecDeque;
+use crate::interfaces::{Price, Quantity, Side};
+
+/// One trade print. `aggressor` is the initiating side (Bid = buyer lifted the offer).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trade {
+    pub timestamp: u64,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub aggressor: Side,
+}
+
+/// Bounded ring of recent trades with rolling-window statistics.
+///
+/// Trades are evicted once there are more than `capacity` of them or once they are older than
+/// `max_age` relative to the newest trade. Timestamps are caller-defined units (the crate uses
+/// nanoseconds) and must be non-decreasing. Window queries take `(now, window)` and cover trades
+/// with `timestamp >= now - window`; a window longer than `max_age` only sees what was retained.
+#[derive(Debug, Clone)]
+pub struct TradeTape {
+    trades: VecDeque<Trade>,
+    capacity: usize,
+    max_age: u64,
+}
+
+impl TradeTape {
+    pub fn new(capacity: usize, max_age: u64) -> Self {
+        TradeTape { trades: VecDeque::with_capacity(capacity), capacity, max_age }
+    }
+
+    pub fn record_trade(&mut self, timestamp: u64, price: Price, quantity: Quantity, aggressor: Side) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.trades.len() == self.capacity {
+            self.trades.pop_front();
+        }
+        self.trades.push_back(Trade { timestamp, price, quantity, aggressor });
+        let horizon = timestamp.saturating_sub(self.max_age);
+        while self.trades.front().is_some_and(|t| t.timestamp < horizon) {
+            self.trades.pop_front();
+        }
+    }
+
+    pub fn last_trade(&self) -> Option<Trade> {
+        self.trades.back().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.trades.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trades.is_empty()
+    }
+
+    /// Traded quantity in `[now - window, now]`.
+    pub fn volume(&self, now: u64, window: u64) -> Quantity {
+        self.in_window(now, window).fold(0, |sum: Quantity, t| sum.saturating_add(t.quantity))
+    }
+
+    /// Volume-weighted average price over the window, `None` if no trade falls in it.
+    pub fn vwap(&self, now: u64, window: u64) -> Option<f64> {
+        let (notional, volume) = self
+            .in_window(now, window)
+            .fold((0i128, 0u128), |(n, v), t| (n + t.price as i128 * t.quantity as i128, v + t.quantity as u128));
+        (volume > 0).then(|| notional as f64 / volume as f64)
+    }
+
+    /// (buyer-initiated, seller-initiated) volume over the window.
+    pub fn volume_by_aggressor(&self, now: u64, window: u64) -> (Quantity, Quantity) {
+        self.in_window(now, window).fold((0, 0), |(buy, sell), t| match t.aggressor {
+            Side::Bid => (buy.saturating_add(t.quantity), sell),
+            Side::Ask => (buy, sell.saturating_add(t.quantity)),
+        })
+    }
+
+    fn in_window(&self, now: u64, window: u64) -> impl Iterator<Item = &Trade> {
+        let from = now.saturating_sub(window);
+        self.trades.iter().rev().take_while(move |t| t.timestamp >= from).filter(move |t| t.timestamp <= now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::{OrderBookRead, OrderBookWrite, Update};
+    use crate::orderbook::OrderBookImpl;
+
+    const SEC: u64 = 1_000_000_000;
+
+    #[test]
+    fn windowed_statistics() {
+        let mut tape = TradeTape::new(100, 60 * SEC);
+        tape.record_trade(SEC, 10000, 10, Side::Bid);
+        tape.record_trade(2 * SEC, 10002, 30, Side::Ask);
+        tape.record_trade(5 * SEC, 10004, 20, Side::Bid);
+
+        assert_eq!(tape.last_trade().map(|t| t.price), Some(10004));
+        assert_eq!(tape.volume(5 * SEC, 10 * SEC), 60);
+        assert_eq!(tape.volume(5 * SEC, 3 * SEC), 50);
+        assert_eq!(tape.vwap(5 * SEC, 3 * SEC), Some((10002.0 * 30.0 + 10004.0 * 20.0) / 50.0));
+        assert_eq!(tape.volume_by_aggressor(5 * SEC, 10 * SEC), (30, 30));
+
+        // Nothing traded in the last second before t=9s.
+        assert_eq!(tape.volume(9 * SEC, SEC), 0);
+        assert_eq!(tape.vwap(9 * SEC, SEC), None);
+        assert_eq!(tape.volume_by_aggressor(9 * SEC, SEC), (0, 0));
+    }
+
+    #[test]
+    fn evicts_by_count_and_age() {
+        let mut tape = TradeTape::new(2, 10 * SEC);
+        tape.record_trade(SEC, 1, 1, Side::Bid);
+        tape.record_trade(2 * SEC, 2, 2, Side::Bid);
+        tape.record_trade(3 * SEC, 3, 3, Side::Bid);
+        assert_eq!(tape.len(), 2);
+        assert_eq!(tape.volume(3 * SEC, 100 * SEC), 5);
+
+        tape.record_trade(14 * SEC, 4, 4, Side::Ask);
+        assert_eq!(tape.len(), 1);
+        assert_eq!(tape.volume_by_aggressor(14 * SEC, 100 * SEC), (0, 4));
+    }
+
+    #[test]
+    fn book_forwards_trades_to_attached_tape() {
+        let mut ob = OrderBookImpl::new();
+        ob.apply_update(Update::Set { price: 10000, quantity: 5, side: Side::Ask });
+        // Without a tape a trade is a no-op.
+        ob.apply_update(Update::Trade { price: 10000, quantity: 1, aggressor: Side::Bid, timestamp: SEC });
+
+        ob.attach_tape(TradeTape::new(16, 60 * SEC));
+        ob.apply_update(Update::Trade { price: 10000, quantity: 2, aggressor: Side::Bid, timestamp: 2 * SEC });
+        assert_eq!(ob.get_quantity_at(10000, Side::Ask), Some(5));
+        assert_eq!(ob.tape().map(|t| t.volume(2 * SEC, SEC)), Some(2));
+        assert!(!ob.undo_last());
+    }
+}
@@ -0,0 +1,28 @@
+// testing.rs
+
+// Shorthands and random update generators shared by the crate's tests (the binary's included,
+// hence public). The randomness comes from `sim::SimRng`, so every test is reproducible.
+
+use crate::interfaces::{Price, Quantity, Side, Update};
+use crate::sim::SimRng;
+
+pub fn set(price: Price, quantity: Quantity, side: Side) -> Update {
+    Update::Set { price, quantity, side }
+}
+
+pub fn random_side(rng: &mut SimRng) -> Side {
+    if rng.below(2) == 0 { Side::Bid } else { Side::Ask }
+}
+
+/// Uniform over the `span` prices starting `span / 2` below `center`.
+pub fn random_price(rng: &mut SimRng, center: Price, span: u64) -> Price {
+    center - (span / 2) as Price + rng.below(span) as Price
+}
+
+/// A `Set` of a quantity below 50 (sometimes 0) or, one time in four, a `Remove`, on a random
+/// side at a `random_price`.
+pub fn random_update(rng: &mut SimRng, center: Price, span: u64) -> Update {
+    let side = random_side(rng);
+    let price = random_price(rng, center, span);
+    if rng.below(4) == 0 { Update::Remove { price, side } } else { Update::Set { price, quantity: rng.below(50), side } }
+}
@@ -0,0 +1,179 @@
+// timed.rs
+
+use std::ops::Deref;
+use crate::interfaces::{OrderBookRead, OrderBookWrite, Price, Quantity, Side, Update};
+use crate::orderbook::{CAP, OrderBookImpl};
+
+/// `OrderBookImpl` plus the time each slot last changed quantity.
+///
+/// Opt-in: the two timestamp arrays double the book's memory, so the plain book carries none of
+/// this. Read access goes through `Deref`; mutation only through the methods below so the
+/// timestamps can never fall out of step with the levels.
+pub struct TimedOrderBook {
+    book: OrderBookImpl,
+    bid_times: Box<[u64; CAP]>,
+    ask_times: Box<[u64; CAP]>,
+}
+
+impl TimedOrderBook {
+    pub fn new() -> Self {
+        Self::from_book(OrderBookImpl::new())
+    }
+
+    /// Wraps an existing (typically empty, preconfigured) book. Its current levels start at time 0.
+    pub fn from_book(book: OrderBookImpl) -> Self {
+        TimedOrderBook { book, bid_times: Box::new([0; CAP]), ask_times: Box::new([0; CAP]) }
+    }
+
+    /// Applies `update` and stamps the touched slots with `timestamp` where the quantity changed.
+    pub fn apply_update_at(&mut self, timestamp: u64, update: Update) {
+        let touched = match update {
+            Update::Set { price, side, .. } | Update::SetWithCount { price, side, .. } | Update::Remove { price, side } => {
+                Some((side, self.book.tick_price(price)))
+            }
+            Update::Trade { .. } => None,
+            Update::SetLevels { side, ref levels } => {
+                let before: Vec<_> = levels
+                    .iter()
+                    .map(|&(price, _)| {
+                        let price = self.book.tick_price(price);
+                        (price, self.book.get_quantity_at(price, side))
+                    })
+                    .collect();
+                self.book.apply_update(update);
+                for (price, before) in before {
+                    self.stamp_if_changed(timestamp, side, price, before);
+                }
+                return;
+            }
+        };
+        let before = touched.map(|(side, price)| self.book.get_quantity_at(price, side));
+        self.book.apply_update(update);
+
+        if let (Some((side, price)), Some(before)) = (touched, before) {
+            self.stamp_if_changed(timestamp, side, price, before);
+        }
+    }
+
+    fn stamp_if_changed(&mut self, timestamp: u64, side: Side, price: Price, before: Option<Quantity>) {
+        if self.book.get_quantity_at(price, side) != before {
+            let index = self.book.price_to_index(price, side);
+            self.times_mut(side)[index] = timestamp;
+        }
+    }
+
+    /// How long the current quantity at `price` has been resting, `None` if the level is empty.
+    pub fn level_age(&self, price: Price, side: Side, now: u64) -> Option<u64> {
+        self.book.get_quantity_at(price, side)?;
+        Some(now.saturating_sub(self.last_modified(price, side)))
+    }
+
+    /// Occupied levels best-first with the time each last changed.
+    pub fn timed_levels(&self, side: Side) -> impl Iterator<Item = (Price, Quantity, u64)> + '_ {
+        self.book.levels(side).map(move |(price, qty)| (price, qty, self.last_modified(price, side)))
+    }
+
+    /// Empties the book and forgets every timestamp.
+    pub fn clear(&mut self) {
+        self.book.clear();
+        self.bid_times.fill(0);
+        self.ask_times.fill(0);
+    }
+
+    /// Recentres the book; surviving levels keep their timestamps, dropped ones lose them.
+    /// Returns the dropped quantity as `OrderBookImpl::recenter_anchor` does.
+    pub fn recenter_anchor(&mut self, new_anchor: Price) -> (Quantity, Quantity) {
+        let mut kept: Vec<(Side, Price, u64)> = Vec::new();
+        for side in [Side::Bid, Side::Ask] {
+            kept.extend(self.timed_levels(side).map(|(price, _, time)| (side, price, time)));
+        }
+        let dropped = self.book.recenter_anchor(new_anchor);
+        self.bid_times.fill(0);
+        self.ask_times.fill(0);
+        for (side, price, time) in kept {
+            if self.book.get_quantity_at(price, side).is_some() && self.book.in_window(price, side) {
+                let index = self.book.price_to_index(price, side);
+                self.times_mut(side)[index] = time;
+            }
+        }
+        dropped
+    }
+
+    fn last_modified(&self, price: Price, side: Side) -> u64 {
+        let index = self.book.price_to_index(price, side);
+        match side {
+            Side::Bid => self.bid_times[index],
+            Side::Ask => self.ask_times[index],
+        }
+    }
+
+    fn times_mut(&mut self, side: Side) -> &mut [u64; CAP] {
+        match side {
+            Side::Bid => &mut self.bid_times,
+            Side::Ask => &mut self.ask_times,
+        }
+    }
+}
+
+impl Default for TimedOrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for TimedOrderBook {
+    type Target = OrderBookImpl;
+
+    fn deref(&self) -> &OrderBookImpl {
+        &self.book
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::set;
+
+    #[test]
+    fn ages_follow_quantity_changes() {
+        let mut ob = TimedOrderBook::new();
+        ob.apply_update_at(100, set(9999, 5, Side::Bid));
+        ob.apply_update_at(200, set(10001, 5, Side::Ask));
+        assert_eq!(ob.level_age(9999, Side::Bid, 250), Some(150));
+
+        // Re-sending the same quantity is not a modification.
+        ob.apply_update_at(300, set(9999, 5, Side::Bid));
+        assert_eq!(ob.level_age(9999, Side::Bid, 350), Some(250));
+        ob.apply_update_at(400, set(9999, 6, Side::Bid));
+        assert_eq!(ob.level_age(9999, Side::Bid, 450), Some(50));
+
+        ob.apply_update_at(500, Update::Remove { price: 9999, side: Side::Bid });
+        assert_eq!(ob.level_age(9999, Side::Bid, 550), None);
+        assert_eq!(ob.timed_levels(Side::Ask).collect::<Vec<_>>(), vec![(10001, 5, 200)]);
+    }
+
+    #[test]
+    fn recenter_and_clear_keep_timestamps_coherent() {
+        let mut ob = TimedOrderBook::new();
+        ob.apply_update_at(10, set(9000, 1, Side::Bid));
+        ob.apply_update_at(20, set(10500, 2, Side::Ask));
+
+        // 9000 falls out of a window centred on 11500; 10500 moves to a new slot but keeps its time.
+        ob.recenter_anchor(11500);
+        assert_eq!(ob.get_quantity_at(9000, Side::Bid), None);
+        assert_eq!(ob.timed_levels(Side::Ask).collect::<Vec<_>>(), vec![(10500, 2, 20)]);
+
+        // Under the new anchor a bid at 10500 lands on the slot 9000 used before; it must not
+        // inherit that timestamp.
+        ob.apply_update_at(30, set(10500, 3, Side::Bid));
+        assert_eq!(ob.level_age(10500, Side::Bid, 40), Some(10));
+
+        ob.clear();
+        assert_eq!(ob.level_age(10500, Side::Ask, 40), None);
+        ob.apply_update_at(50, set(11000, 1, Side::Ask));
+        assert_eq!(ob.timed_levels(Side::Ask).collect::<Vec<_>>(), vec![(11000, 1, 50)]);
+        ob.apply_update_at(60, Update::Remove { price: 11000, side: Side::Ask });
+        assert!(ob.timed_levels(Side::Ask).next().is_none());
+        assert_eq!(ob.get_total_quantity(Side::Ask), 0);
+    }
+}
@@ -0,0 +1,119 @@
+// watch.rs
+
+use crate::interfaces::{Price, Quantity, Side};
+use crate::orderbook::CAP;
+
+/// Most watches a book holds at once; `OrderBookImpl::watch_level` refuses more.
+pub const MAX_WATCHES: usize = 64;
+
+/// Handle returned by `OrderBookImpl::watch_level`, for `unwatch` and for telling
+/// `LevelEvent`s apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WatchId(u32);
+
+/// A watched level's quantity changed (0 = empty), queued for `OrderBookImpl::take_level_events`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelEvent<P = Price> {
+    pub watch_id: WatchId,
+    pub side: Side,
+    pub price: P,
+    pub old_qty: Quantity,
+    pub new_qty: Quantity,
+}
+
+#[derive(Debug, Clone)]
+struct Watch<P> {
+    id: WatchId,
+    side: Side,
+    price: P,
+    /// Ring slot while the price is inside the side's window, `None` otherwise.
+    slot: Option<usize>,
+    /// Last quantity reported for the level.
+    quantity: Quantity,
+}
+
+impl<P> Watch<P> {
+    /// Sort key: by side, then slot, out-of-window watches last.
+    fn key(&self) -> (usize, usize) {
+        (self.side as usize, self.slot.unwrap_or(usize::MAX))
+    }
+}
+
+/// The book's watched levels: a short array sorted by (side, slot) plus a bitmap of watched
+/// slots, so an update to an unwatched level costs one bit test.
+#[derive(Debug, Clone)]
+pub(crate) struct Watches<P> {
+    entries: Vec<Watch<P>>,
+    watched: [[u64; CAP / 64]; 2],
+    events: Vec<LevelEvent<P>>,
+    next_id: u32,
+}
+
+impl<P: Copy> Watches<P> {
+    pub(crate) fn new() -> Self {
+        Watches { entries: Vec::new(), watched: [[0; CAP / 64]; 2], events: Vec::new(), next_id: 0 }
+    }
+
+    /// `None` if `MAX_WATCHES` are already active.
+    pub(crate) fn add(&mut self, side: Side, price: P, slot: Option<usize>, quantity: Quantity) -> Option<WatchId> {
+        if self.entries.len() >= MAX_WATCHES {
+            return None;
+        }
+        let id = WatchId(self.next_id);
+        self.next_id += 1;
+        self.entries.push(Watch { id, side, price, slot, quantity });
+        self.reindex();
+        Some(id)
+    }
+
+    /// Whether `id` was being watched.
+    pub(crate) fn remove(&mut self, id: WatchId) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|watch| watch.id != id);
+        self.reindex();
+        self.entries.len() != before
+    }
+
+    /// A level changed through the normal write path; `slot` is in the current window.
+    #[inline(always)]
+    pub(crate) fn changed(&mut self, side: Side, slot: usize, new: Quantity) {
+        if self.watched[side as usize][slot / 64] & (1 << (slot % 64)) == 0 {
+            return;
+        }
+        let key = (side as usize, slot);
+        let start = self.entries.partition_point(|watch| watch.key() < key);
+        for watch in self.entries[start..].iter_mut().take_while(|watch| watch.key() == key) {
+            self.events.push(LevelEvent { watch_id: watch.id, side, price: watch.price, old_qty: watch.quantity, new_qty: new });
+            watch.quantity = new;
+        }
+    }
+
+    /// Re-reads every watch after levels changed wholesale (a clear, or a recenter moving the
+    /// windows): `locate` gives each price's slot, if in the window, and current quantity.
+    /// Queues an event for each watch whose quantity differs from the last one reported.
+    pub(crate) fn relocate(&mut self, locate: impl Fn(Side, P) -> (Option<usize>, Quantity)) {
+        for watch in &mut self.entries {
+            let (slot, quantity) = locate(watch.side, watch.price);
+            watch.slot = slot;
+            if quantity != watch.quantity {
+                self.events.push(LevelEvent { watch_id: watch.id, side: watch.side, price: watch.price, old_qty: watch.quantity, new_qty: quantity });
+                watch.quantity = quantity;
+            }
+        }
+        self.reindex();
+    }
+
+    pub(crate) fn take_events(&mut self) -> Vec<LevelEvent<P>> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn reindex(&mut self) {
+        self.entries.sort_by_key(|watch| (watch.key(), watch.id));
+        self.watched = [[0; CAP / 64]; 2];
+        for watch in &self.entries {
+            if let Some(slot) = watch.slot {
+                self.watched[watch.side as usize][slot / 64] |= 1 << (slot % 64);
+            }
+        }
+    }
+}